@@ -1,4 +1,4 @@
-use bf_exec::Interpreter;
+use bf_exec::{Engine, Interpreter};
 use common::execution::memory::config::{Config as MemoryConfig, *};
 use common::execution::stream::config::{Config as StreamConfig, *};
 use criterion::{criterion_group, criterion_main, Criterion};
@@ -715,7 +715,7 @@ const BRAINFUCK_CODE: &str =
     >+<]>[[-<+>]<<<[-]+>>>]<<<]<<<<+>>>]<<]>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
     ";
 
-fn interpret() {
+fn interpret(engine: Engine) {
     let memory_config = MemoryConfig {
         len: 32768,
         addr: Addr::Unsigned,
@@ -728,11 +728,23 @@ fn interpret() {
         output: Output::Null,
     };
     let mut interpreter = Interpreter::new(memory_config, stream_config);
+    interpreter.set_engine(engine);
     interpreter.run(BRAINFUCK_CODE).unwrap();
 }
 
 fn benchmark(c: &mut Criterion) {
-    c.bench_function("hanoi", |b| b.iter(interpret));
+    let mut group = c.benchmark_group("hanoi");
+    let engines = [
+        ("interpreter", Engine::Interpreter),
+        ("threaded", Engine::Threaded),
+        ("jit", Engine::Jit),
+    ];
+
+    for (name, engine) in engines {
+        group.bench_function(name, |b| b.iter(|| interpret(engine)));
+    }
+
+    group.finish();
 }
 
 criterion_group!(benches, benchmark);
@@ -1,4 +1,4 @@
-use bf_exec::Interpreter;
+use bf_exec::{Engine, Interpreter};
 use common::execution::memory::config::{Config as MemoryConfig, *};
 use common::execution::stream::config::{Config as StreamConfig, *};
 use criterion::{criterion_group, criterion_main, Criterion};
@@ -149,7 +149,7 @@ const BRAINFUCK_CODE: &str =
     +[-[->>>>>>>>>+<<<<<<<<<]>>>>>>>>>]>>>>>->>>>>>>>>>>>>>>>>>>>>>>>>>>-<<<<<<[<<<<
     <<<<<]]>>>]";
 
-fn interpret() {
+fn interpret(engine: Engine) {
     let memory_config = MemoryConfig {
         len: 32768,
         addr: Addr::Unsigned,
@@ -162,11 +162,23 @@ fn interpret() {
         output: Output::Null,
     };
     let mut interpreter = Interpreter::new(memory_config, stream_config);
+    interpreter.set_engine(engine);
     interpreter.run(BRAINFUCK_CODE).unwrap();
 }
 
 fn benchmark(c: &mut Criterion) {
-    c.bench_function("mandelbrot", |b| b.iter(interpret));
+    let mut group = c.benchmark_group("mandelbrot");
+    let engines = [
+        ("interpreter", Engine::Interpreter),
+        ("threaded", Engine::Threaded),
+        ("jit", Engine::Jit),
+    ];
+
+    for (name, engine) in engines {
+        group.bench_function(name, |b| b.iter(|| interpret(engine)));
+    }
+
+    group.finish();
 }
 
 criterion_group! {
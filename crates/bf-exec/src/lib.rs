@@ -1,31 +1,107 @@
-use common::compiler::{Compiler, ParseError};
+use common::compiler::{
+    Compiler, Config as CompilerConfig, InstructionList, OptimizerStats, ParseError,
+};
 use common::execution::context::Context;
+use common::execution::jit::{Jit, JitError};
 use common::execution::memory::config::Config as MemoryConfig;
 use common::execution::processor::{Processor, ProcessorError};
 use common::execution::stream::config::Config as StreamConfig;
+use common::execution::threaded::Threaded;
 
 use snafu::prelude::*;
 
 type Result<T> = std::result::Result<T, InterpreterError>;
 
+/// How the compiled programs are run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Run the instructions one by one with `Processor`.
+    #[default]
+    Interpreter,
+    /// Compile the instructions into closures with `Threaded` first, which run
+    /// without decoding the instructions again. It falls back to `Interpreter`
+    /// where the jumps don't form loops.
+    Threaded,
+    /// Compile the instructions into machine code with `Jit`, which falls back to
+    /// `Interpreter` where `Jit` isn't supported.
+    Jit,
+}
+
 pub struct Interpreter {
     context: Context,
+    compiler_config: CompilerConfig,
+    engine: Engine,
+    stats: Option<OptimizerStats>,
 }
 
 impl Interpreter {
     pub fn new(memory_config: MemoryConfig, stream_config: StreamConfig) -> Self {
+        Self::with_config(memory_config, stream_config, CompilerConfig::default())
+    }
+
+    /// The code is always compiled for the memory of the interpreter, whatever
+    /// the `target` of `compiler_config` is.
+    pub fn with_config(
+        memory_config: MemoryConfig,
+        stream_config: StreamConfig,
+        compiler_config: CompilerConfig,
+    ) -> Self {
         Self {
+            compiler_config: CompilerConfig {
+                target: Some(memory_config.clone()),
+                ..compiler_config
+            },
             context: Context::new(memory_config, stream_config),
+            engine: Engine::default(),
+            stats: None,
         }
     }
 
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
     pub fn run(&mut self, code: &str) -> Result<()> {
-        let compiler = Compiler::new();
-        let instructions = compiler.compile(code)?;
-        let mut processor = Processor::new(instructions);
-        processor.run(&mut self.context)?;
+        let compiler = Compiler::with_config(self.compiler_config.clone());
+        let (instructions, stats) = compiler.compile_with_stats(code)?;
+        self.stats = Some(stats);
+        self.run_instructions(instructions)
+    }
+
+    /// Run the program in the IR text form. See `Compiler::assemble`.
+    pub fn run_assembly(&mut self, code: &str) -> Result<()> {
+        let compiler = Compiler::with_config(self.compiler_config.clone());
+        let instructions = compiler.assemble(code)?;
+        self.stats = None;
+        self.run_instructions(instructions)
+    }
+
+    /// Run the compiled program, which should be compiled for the memory of the
+    /// interpreter.
+    pub fn run_instructions(&mut self, instructions: InstructionList) -> Result<()> {
+        let memory = self.compiler_config.target.as_ref().unwrap();
+
+        match self.engine {
+            Engine::Jit if Jit::supports(memory) => {
+                let jit = Jit::compile(instructions, memory).context(JitSnafu)?;
+                jit.run(&mut self.context)?;
+            }
+            Engine::Threaded if Threaded::supports(&instructions) => {
+                Threaded::compile(instructions, memory).run(&mut self.context)?
+            }
+            _ => {
+                let mut processor = Processor::new(instructions);
+                processor.run(&mut self.context)?;
+            }
+        }
+
         Ok(())
     }
+
+    /// Return what the optimizer did to the code last compiled.
+    pub fn stats(&self) -> Option<&OptimizerStats> {
+        self.stats.as_ref()
+    }
 }
 
 #[derive(Snafu, Debug, PartialEq, Eq)]
@@ -34,6 +110,8 @@ pub enum InterpreterError {
     Parse { source: ParseError },
     #[snafu(display("an error occurred when running the code"))]
     Runtime { source: ProcessorError },
+    #[snafu(display("couldn't compile the code into machine code"))]
+    Jit { source: JitError },
     #[snafu(display("the program hasn't been loaded yet"))]
     Uninitialized,
 }
@@ -9,21 +9,246 @@
 //!
 
 use std::error::Error;
-use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 
-use bf_exec::Interpreter;
-use clap::{builder::PathBufValueParser, command, value_parser, Arg, ArgMatches};
+use bf_exec::{Engine, Interpreter, InterpreterError};
+use clap::{
+    builder::PathBufValueParser, command, parser::ValueSource, value_parser, Arg, ArgAction,
+    ArgMatches, Command,
+};
+use common::compiler::{
+    Bytecode, BytecodeError, CBackend, Compiler, Config as CompilerConfig, Diagnostic, ElfBackend,
+    InstructionList, Level, OptLevel, OptimizerStats, ParseError, PointerBounds, RustBackend,
+    SourceMap, Termination, WasmBackend,
+};
 use common::execution::memory::config::{self as memory_config, Config as MemoryConfig};
 use common::execution::stream::config::{self as stream_config, Config as StreamConfig};
 
 fn main() {
     let matches = input();
-    let (memory_config, stream_config, path) = parse(&matches);
 
-    let code = match std::fs::read_to_string(path) {
-        Ok(code) => code,
+    if let Some(("check", matches)) = matches.subcommand() {
+        let (memory_config, _, compiler_config, path) = parse(matches);
+        let bytes = read(path);
+
+        let (diagnostics, path, code) = if Bytecode::is_bytecode(&bytes) {
+            let bytecode = load_bytecode(path, &bytes);
+            let (path, code) = source_of(&bytecode, path);
+            let diagnostics = analyze(&bytecode.instructions, &bytecode.memory);
+            (diagnostics, path, code)
+        } else {
+            let code = text(path, bytes);
+
+            match check(memory_config, compiler_config, path, &code) {
+                Ok(diagnostics) => (diagnostics, path.clone(), code),
+                Err(e) => {
+                    print_diagnostics(&e.diagnostics(), path, &code);
+                    process::exit(1);
+                }
+            }
+        };
+
+        print_diagnostics(&diagnostics, &path, &code);
+
+        if diagnostics.iter().any(|d| d.level == Level::Error) {
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    if let Some(("compile", matches)) = matches.subcommand() {
+        let (memory_config, stream_config, compiler_config, path) = parse(matches);
+        let bytes = read(path);
+
+        if Bytecode::is_bytecode(&bytes) {
+            eprintln!("error: {} is already compiled", path.display());
+            process::exit(1);
+        }
+
+        let code = text(path, bytes);
+        let output = match matches.get_one::<PathBuf>("OUTPUT_FILE") {
+            Some(output) => output.clone(),
+            None => path.with_extension("bfc"),
+        };
+        let source_map = !matches.get_flag("STRIP");
+
+        match compile(
+            memory_config,
+            stream_config,
+            compiler_config,
+            source_map,
+            path,
+            &code,
+        ) {
+            Ok(bytes) => {
+                if let Err(e) = std::fs::write(&output, bytes) {
+                    eprintln!("error: couldn't write {}", output.display());
+                    eprintln!("caused by: {e}");
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                print_diagnostics(&e.diagnostics(), path, &code);
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    if let Some(("build", matches)) = matches.subcommand() {
+        let (memory_config, stream_config, compiler_config, path) = parse(matches);
+        let bytes = read(path);
+        let output = match matches.get_one::<PathBuf>("OUTPUT_FILE") {
+            Some(output) => output.clone(),
+            None if path.extension().is_none() => path.with_extension("out"),
+            None => path.with_extension(""),
+        };
+
+        if same_file(path, &output) {
+            eprintln!("error: {} would overwrite the source", output.display());
+            process::exit(1);
+        }
+
+        let res = if Bytecode::is_bytecode(&bytes) {
+            let bytecode = load_bytecode(path, &bytes);
+            ElfBackend::new(bytecode.memory, bytecode.stream).generate(&bytecode.instructions)
+        } else {
+            let code = text(path, bytes);
+            let compiler = Compiler::with_config(CompilerConfig {
+                target: Some(memory_config.clone()),
+                ..compiler_config
+            });
+
+            match load(&compiler, path, &code) {
+                Ok(list) => ElfBackend::new(memory_config, stream_config).generate(&list),
+                Err(e) => {
+                    print_diagnostics(&e.diagnostics(), path, &code);
+                    process::exit(1);
+                }
+            }
+        };
+
+        match res {
+            Ok(binary) => {
+                if let Err(e) = write_executable(&output, &binary) {
+                    eprintln!("error: couldn't write {}", output.display());
+                    eprintln!("caused by: {e}");
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("error: {e}");
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let (memory_config, stream_config, compiler_config, path) = parse(&matches);
+    let opt_stats = matches.get_flag("OPT_STATS");
+    let engine = match matches.get_one::<String>("ENGINE").unwrap().as_str() {
+        "interpreter" => Engine::Interpreter,
+        "threaded" => Engine::Threaded,
+        "jit" => Engine::Jit,
+        _ => unreachable!(),
+    };
+    let bytes = read(path);
+
+    if Bytecode::is_bytecode(&bytes) {
+        let bytecode = load_bytecode(path, &bytes);
+        let (path, code) = source_of(&bytecode, path);
+
+        match matches.get_one::<String>("EMIT").map(String::as_str) {
+            Some("ir") => print!("{:#}", bytecode.instructions),
+            Some("c") => print_c(bytecode.memory, bytecode.stream, &bytecode.instructions),
+            Some("rust") => print_rust(bytecode.memory, &bytecode.instructions),
+            Some("wat") => print_wasm(bytecode.memory, &bytecode.instructions, false),
+            Some("wasm") => print_wasm(bytecode.memory, &bytecode.instructions, true),
+            Some(stage) => {
+                eprintln!("error: couldn't emit {stage} for a bytecode file");
+                process::exit(1);
+            }
+            None => {
+                if let Err(e) = run_bytecode(&matches, bytecode, compiler_config, engine) {
+                    print_interpreter_error(e, &path, &code);
+                    process::exit(1);
+                }
+            }
+        }
+
+        return;
+    }
+
+    let code = text(path, bytes);
+
+    if let Some(stage) = matches.get_one::<String>("EMIT") {
+        if is_assembly(path) && !matches!(stage.as_str(), "ir" | "c" | "rust" | "wat" | "wasm") {
+            eprintln!("error: couldn't emit {stage} for an assembly file");
+            process::exit(1);
+        }
+
+        if let Err(e) = emit(
+            memory_config,
+            stream_config,
+            compiler_config,
+            stage,
+            path,
+            &code,
+        ) {
+            print_diagnostics(&e.diagnostics(), path, &code);
+            process::exit(1);
+        }
+
+        return;
+    }
+
+    if let Err(e) = run(
+        memory_config,
+        stream_config,
+        compiler_config,
+        engine,
+        opt_stats,
+        path,
+        &code,
+    ) {
+        print_interpreter_error(e, path, &code);
+        process::exit(1);
+    }
+}
+
+fn print_interpreter_error(e: InterpreterError, path: &Path, code: &str) {
+    match e {
+        InterpreterError::Parse { source } => print_diagnostics(&source.diagnostics(), path, code),
+        InterpreterError::Runtime { source } if source.diagnostic().is_some() => {
+            print_diagnostics(&[source.diagnostic().unwrap()], path, code)
+        }
+        e => print_error(Box::new(e)),
+    }
+}
+
+/// Whether the file contains the IR text form of a program.
+fn is_assembly(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "bfasm")
+}
+
+/// Compile the code, or assemble it if it's read from an assembly file.
+fn load(compiler: &Compiler, path: &Path, code: &str) -> Result<InstructionList, ParseError> {
+    if is_assembly(path) {
+        compiler.assemble(code)
+    } else {
+        compiler.compile(code)
+    }
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(bytes) => bytes,
         Err(e) => {
             match e.kind() {
                 ErrorKind::NotFound => eprintln!("error: couldn't find {}", path.display()),
@@ -35,14 +260,95 @@ fn main() {
 
             process::exit(1);
         }
-    };
+    }
+}
 
-    if let Err(e) = run(memory_config, stream_config, code) {
-        print_error(e);
-        process::exit(1);
+fn text(path: &Path, bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(code) => code,
+        Err(_) => {
+            eprintln!("error: {} isn't valid UTF-8", path.display());
+            process::exit(1);
+        }
     }
 }
 
+fn load_bytecode(path: &Path, bytes: &[u8]) -> Bytecode {
+    match Bytecode::decode(bytes) {
+        Ok(bytecode) => bytecode,
+        Err(e) => {
+            eprintln!("error: couldn't load {}", path.display());
+            eprintln!("caused by: {e}");
+
+            if let BytecodeError::UnsupportedVersion { .. } = e {
+                eprintln!("note: compile the program again with `bf-exec compile`");
+            }
+
+            process::exit(1);
+        }
+    }
+}
+
+/// Return the path and the source code of a compiled program for the
+/// diagnostics, which are read from its source map if possible. The source
+/// code is empty if it's unavailable.
+fn source_of(bytecode: &Bytecode, path: &Path) -> (PathBuf, String) {
+    match &bytecode.source_map {
+        Some(source_map) => {
+            let code = std::fs::read_to_string(&source_map.name).unwrap_or_default();
+            (PathBuf::from(&source_map.name), code)
+        }
+        None => (path.to_path_buf(), String::new()),
+    }
+}
+
+fn print_diagnostics(diagnostics: &[Diagnostic], path: &Path, code: &str) {
+    let name = path.display().to_string();
+
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(&name, code));
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.level == Level::Error)
+        .count();
+
+    let warnings = diagnostics.len() - errors;
+
+    match (errors, warnings) {
+        (0, 0) => {}
+        (0, 1) => eprintln!("warning: 1 warning emitted"),
+        (0, n) => eprintln!("warning: {n} warnings emitted"),
+        (1, _) => eprintln!("error: aborting due to the previous error"),
+        (n, _) => eprintln!("error: aborting due to {n} previous errors"),
+    }
+}
+
+fn print_stats(stats: &OptimizerStats) {
+    let width = stats
+        .passes
+        .iter()
+        .map(|pass| pass.name.len())
+        .chain(["pass".len()])
+        .max()
+        .unwrap();
+
+    eprintln!("{:width$}  rewrites  before   after", "pass");
+
+    for pass in &stats.passes {
+        eprintln!(
+            "{:width$}  {:>8}  {:>6}  {:>6}",
+            pass.name, pass.rewrites, pass.before, pass.after
+        );
+    }
+
+    eprintln!(
+        "instructions: {} -> {} in {} iteration(s)",
+        stats.before, stats.after, stats.iterations
+    );
+}
+
 fn print_error(e: Box<dyn Error>) {
     eprintln!("error: {e}");
     let mut e = e.source();
@@ -59,6 +365,7 @@ fn input() -> ArgMatches {
         Arg::new("LEN")
             .long("len")
             .required(false)
+            .global(true)
             .value_parser(value_parser!(usize))
             .default_value("32768")
             .next_line_help(true)
@@ -69,6 +376,7 @@ fn input() -> ArgMatches {
         Arg::new("ADDR")
             .long("addr")
             .required(false)
+            .global(true)
             .value_parser(["unsigned", "signed"])
             .default_value("unsigned")
             .next_line_help(true)
@@ -86,6 +394,7 @@ fn input() -> ArgMatches {
         Arg::new("CELL")
             .long("cell")
             .required(false)
+            .global(true)
             .value_parser(["int8", "int32"])
             .default_value("int8")
             .next_line_help(true)
@@ -96,6 +405,7 @@ fn input() -> ArgMatches {
         Arg::new("OVERFLOW")
             .long("overflow")
             .required(false)
+            .global(true)
             .value_parser(["wrap", "error"])
             .default_value("wrap")
             .next_line_help(true)
@@ -117,6 +427,7 @@ fn input() -> ArgMatches {
         Arg::new("EOF")
             .long("eof")
             .required(false)
+            .global(true)
             .value_parser(["zero", "keep", "ignore"])
             .default_value("ignore")
             .next_line_help(true)
@@ -135,6 +446,7 @@ fn input() -> ArgMatches {
         Arg::new("INPUT")
             .long("input")
             .required(false)
+            .global(true)
             .value_parser(["null", "std"])
             .default_value("std")
             .next_line_help(true)
@@ -145,6 +457,7 @@ fn input() -> ArgMatches {
         Arg::new("OUTPUT")
             .long("output")
             .required(false)
+            .global(true)
             .value_parser(["char-std", "int-std"])
             .default_value("char-std")
             .next_line_help(true)
@@ -152,18 +465,177 @@ fn input() -> ArgMatches {
             .long_help("the output stream type."),
     );
     let cmd = cmd.arg(
-        Arg::new("SOURCE")
-            .required(true)
-            .value_parser(PathBufValueParser::new())
+        Arg::new("OPT_LEVEL")
+            .long("opt-level")
+            .short('O')
+            .required(false)
+            .global(true)
+            .value_parser(["0", "1", "2", "3"])
+            .default_value("3")
             .next_line_help(true)
-            .help("the path of the brainfuck program source code file.\n")
-            .long_help("the path of the brainfuck program source code file."),
+            .help("the optimization level of the compiler.\n")
+            .long_help({
+                let mut h = String::new();
+                h.push_str("the optimization level of the compiler.\n");
+                h.push('\n');
+                h.push_str(" - 0: no optimization\n");
+                h.push_str(" - 1: turn clearing and scanning loops into single instructions\n");
+                h.push_str(
+                    " - 2: also rewrite multiplication loops and straight-line code, coalesce outputs and remove dead code\n",
+                );
+                h.push_str(
+                    " - 3: also fuse the writes to the same cell, and run the code before reading input at compile time",
+                );
+                h
+            }),
+    );
+    let cmd = cmd.arg(
+        Arg::new("DISABLE_PASS")
+            .long("disable-pass")
+            .required(false)
+            .global(true)
+            .action(ArgAction::Append)
+            .value_parser([
+                "clear",
+                "add-until-zero",
+                "scan",
+                "offset",
+                "set",
+                "output",
+                "constant",
+                "dead-code",
+            ])
+            .next_line_help(true)
+            .help("disable an optimization pass, which can be used more than once.\n")
+            .long_help("disable an optimization pass, which can be used more than once."),
+    );
+    let cmd = cmd.arg(
+        Arg::new("OPT_STATS")
+            .long("opt-stats")
+            .required(false)
+            .global(true)
+            .action(ArgAction::SetTrue)
+            .next_line_help(true)
+            .help("print what each optimization pass did to stderr.\n")
+            .long_help("print what each optimization pass did to stderr."),
+    );
+    let cmd = cmd.arg(
+        Arg::new("EMIT")
+            .long("emit")
+            .required(false)
+            .value_parser(["tokens", "ast", "ir", "c", "rust", "wat", "wasm"])
+            .next_line_help(true)
+            .help("print a stage of the compiler instead of running the program.\n")
+            .long_help({
+                let mut h = String::new();
+                h.push_str("print a stage of the compiler instead of running the program.\n");
+                h.push('\n');
+                h.push_str(" - tokens: the tokens, where the same adjacent ones are combined\n");
+                h.push_str(" - ast: the optimized syntax tree\n");
+                h.push_str(" - ir: the instructions to run\n");
+                h.push_str(" - c: a C program doing the same on the memory and the streams\n");
+                h.push_str(" - rust: a Rust module doing the same on the memory, whose `run` takes the streams\n");
+                h.push_str(" - wat: a WebAssembly module in the text format, which imports `env.read`, `env.write`\n");
+                h.push_str("   and `env.fail`, and exports `run` and `memory`\n");
+                h.push_str(" - wasm: the same module in the binary format");
+                h
+            }),
+    );
+    let cmd = cmd.arg(
+        Arg::new("ENGINE")
+            .long("engine")
+            .required(false)
+            .value_parser(["interpreter", "threaded", "jit"])
+            .default_value("interpreter")
+            .next_line_help(true)
+            .help("how the program is run.\n")
+            .long_help({
+                let mut h = String::new();
+                h.push_str("how the program is run.\n");
+                h.push('\n');
+                h.push_str(" - interpreter: run the instructions one by one\n");
+                h.push_str(" - threaded: compile the instructions into closures first, which run faster\n");
+                h.push_str(
+                    " - jit: compile the instructions into machine code first, which is only supported on Linux x86-64 and falls back to the interpreter elsewhere",
+                );
+                h
+            }),
+    );
+    let cmd = cmd.arg(source());
+    let cmd = cmd.subcommand_negates_reqs(true).subcommand(
+        Command::new("check")
+            .about("check the program for possible errors without running it")
+            .arg(source()),
+    );
+    let cmd = cmd.subcommand(
+        Command::new("compile")
+            .about("compile the program into bytecode, which can be run directly")
+            .arg(
+                Arg::new("OUTPUT_FILE")
+                    .long("output-file")
+                    .short('o')
+                    .required(false)
+                    .value_parser(PathBufValueParser::new())
+                    .next_line_help(true)
+                    .help(
+                        "the path of the bytecode file, which is SOURCE with `.bfc` by default.\n",
+                    )
+                    .long_help(
+                        "the path of the bytecode file, which is SOURCE with `.bfc` by default.",
+                    ),
+            )
+            .arg(
+                Arg::new("STRIP")
+                    .long("strip")
+                    .required(false)
+                    .action(ArgAction::SetTrue)
+                    .next_line_help(true)
+                    .help("don't save the source map for the diagnostics.\n")
+                    .long_help("don't save the source map for the diagnostics."),
+            )
+            .arg(source()),
+    );
+    let cmd = cmd.subcommand(
+        Command::new("build")
+            .about("build the program into a static executable for Linux x86-64, which needs no runtime")
+            .arg(
+                Arg::new("OUTPUT_FILE")
+                    .long("output-file")
+                    .short('o')
+                    .required(false)
+                    .value_parser(PathBufValueParser::new())
+                    .next_line_help(true)
+                    .help("the path of the executable, which is SOURCE without the extension, or with `.out` if it has none, by default.\n")
+                    .long_help(
+                        "the path of the executable, which is SOURCE without the extension, or with `.out` if it has none, by default.",
+                    ),
+            )
+            .arg(source()),
     );
 
     cmd.get_matches()
 }
 
-fn parse(matches: &ArgMatches) -> (MemoryConfig, StreamConfig, &PathBuf) {
+/// Check whether two paths name the same file, following the links if it exists.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn source() -> Arg {
+    Arg::new("SOURCE")
+        .required(true)
+        .value_parser(PathBufValueParser::new())
+        .next_line_help(true)
+        .help("the path of the brainfuck program source code file.\n")
+        .long_help(
+            "the path of the brainfuck program source code file, the IR text file if it ends with `.bfasm`, or the bytecode file.",
+        )
+}
+
+fn parse(matches: &ArgMatches) -> (MemoryConfig, StreamConfig, CompilerConfig, &PathBuf) {
     let memory_config = MemoryConfig {
         len: *matches.get_one::<usize>("LEN").unwrap(),
         addr: match matches.get_one::<String>("ADDR").unwrap().as_str() {
@@ -202,16 +674,217 @@ fn parse(matches: &ArgMatches) -> (MemoryConfig, StreamConfig, &PathBuf) {
         },
     };
 
+    let compiler_config = CompilerConfig {
+        opt_level: match matches.get_one::<String>("OPT_LEVEL").unwrap().as_str() {
+            "0" => OptLevel::O0,
+            "1" => OptLevel::O1,
+            "2" => OptLevel::O2,
+            "3" => OptLevel::O3,
+            _ => unreachable!(),
+        },
+        disable: matches
+            .get_many::<String>("DISABLE_PASS")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+
     let source = matches.get_one::<PathBuf>("SOURCE").unwrap();
-    (memory_config, stream_config, source)
+    (memory_config, stream_config, compiler_config, source)
 }
 
 fn run(
     memory_config: MemoryConfig,
     stream_config: StreamConfig,
-    code: String,
-) -> Result<(), Box<dyn Error>> {
-    let mut interpreter = Interpreter::new(memory_config, stream_config);
-    interpreter.run(&code)?;
+    compiler_config: CompilerConfig,
+    engine: Engine,
+    opt_stats: bool,
+    path: &Path,
+    code: &str,
+) -> Result<(), InterpreterError> {
+    let mut interpreter = Interpreter::with_config(memory_config, stream_config, compiler_config);
+    interpreter.set_engine(engine);
+    let res = if is_assembly(path) {
+        interpreter.run_assembly(code)
+    } else {
+        interpreter.run(code)
+    };
+
+    if let (true, Some(stats)) = (opt_stats, interpreter.stats()) {
+        print_stats(stats);
+    }
+
+    res
+}
+
+/// Run a compiled program with the memory and stream it was compiled for. The
+/// memory and stream options are ignored with a warning.
+fn run_bytecode(
+    matches: &ArgMatches,
+    bytecode: Bytecode,
+    compiler_config: CompilerConfig,
+    engine: Engine,
+) -> Result<(), InterpreterError> {
+    let ignored: Vec<_> = ["LEN", "ADDR", "CELL", "OVERFLOW", "EOF", "INPUT", "OUTPUT"]
+        .into_iter()
+        .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .map(|id| format!("--{}", id.to_lowercase()))
+        .collect();
+
+    if !ignored.is_empty() {
+        eprintln!(
+            "warning: {} ignored, using the options the program was compiled with",
+            ignored.join(", ")
+        );
+    }
+
+    let mut interpreter =
+        Interpreter::with_config(bytecode.memory, bytecode.stream, compiler_config);
+    interpreter.set_engine(engine);
+    interpreter.run_instructions(bytecode.instructions)
+}
+
+/// Write an executable file.
+fn write_executable(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    std::fs::write(path, bytes)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+/// Compile the code for the memory and stream into bytecode.
+fn compile(
+    memory_config: MemoryConfig,
+    stream_config: StreamConfig,
+    compiler_config: CompilerConfig,
+    source_map: bool,
+    path: &Path,
+    code: &str,
+) -> Result<Vec<u8>, ParseError> {
+    let compiler = Compiler::with_config(CompilerConfig {
+        target: Some(memory_config.clone()),
+        ..compiler_config
+    });
+    let instructions = load(&compiler, path, code)?;
+    let source_map = source_map.then(|| SourceMap {
+        name: path.display().to_string(),
+        spans: instructions.spans.clone(),
+    });
+    let bytecode = Bytecode {
+        instructions,
+        memory: memory_config,
+        stream: stream_config,
+        source_map,
+    };
+
+    // The streams from the command line can always be saved.
+    Ok(bytecode.encode().unwrap())
+}
+
+/// Compile the code for the memory, and report where the pointer may go out of
+/// it and the loops which may never terminate. Dead code is kept, for removing
+/// it may hide the errors the program raises.
+fn check(
+    memory_config: MemoryConfig,
+    mut compiler_config: CompilerConfig,
+    path: &Path,
+    code: &str,
+) -> Result<Vec<Diagnostic>, ParseError> {
+    compiler_config.disable.push("dead-code".to_string());
+    let compiler = Compiler::with_config(CompilerConfig {
+        target: Some(memory_config.clone()),
+        ..compiler_config
+    });
+    let list = load(&compiler, path, code)?;
+    Ok(analyze(&list, &memory_config))
+}
+
+/// Report where the pointer may go out of the memory and the loops which may
+/// never terminate.
+fn analyze(list: &InstructionList, memory_config: &MemoryConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = PointerBounds::analyze(list).diagnostics(list, memory_config);
+    diagnostics.extend(Termination::analyze(list, memory_config).diagnostics(list));
+    diagnostics
+}
+
+/// Print a stage of compiling the code for the memory.
+fn emit(
+    memory_config: MemoryConfig,
+    stream_config: StreamConfig,
+    compiler_config: CompilerConfig,
+    stage: &str,
+    path: &Path,
+    code: &str,
+) -> Result<(), ParseError> {
+    let compiler = Compiler::with_config(CompilerConfig {
+        target: Some(memory_config.clone()),
+        ..compiler_config
+    });
+
+    match stage {
+        "tokens" => print!("{:#}", compiler.tokenize(code)),
+        "ast" => print!("{:#}", compiler.parse(code)?),
+        "ir" => print!("{:#}", load(&compiler, path, code)?),
+        "c" => print_c(memory_config, stream_config, &load(&compiler, path, code)?),
+        "rust" => print_rust(memory_config, &load(&compiler, path, code)?),
+        "wat" => print_wasm(memory_config, &load(&compiler, path, code)?, false),
+        "wasm" => print_wasm(memory_config, &load(&compiler, path, code)?, true),
+        _ => unreachable!(),
+    }
+
     Ok(())
 }
+
+fn print_rust(memory_config: MemoryConfig, list: &InstructionList) {
+    match RustBackend::new(memory_config).generate(list) {
+        Ok(code) => print!("{code}"),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Print the WebAssembly module in the binary format if `binary` is true, or in
+/// the text format otherwise.
+fn print_wasm(memory_config: MemoryConfig, list: &InstructionList, binary: bool) {
+    let backend = WasmBackend::new(memory_config);
+    let res = if binary {
+        backend
+            .generate_binary(list)
+            .map(|module| io::stdout().write_all(&module))
+    } else {
+        backend.generate_text(list).map(|module| {
+            print!("{module}");
+            Ok(())
+        })
+    };
+
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("error: couldn't write the module: {e}");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn print_c(memory_config: MemoryConfig, stream_config: StreamConfig, list: &InstructionList) {
+    match CBackend::new(memory_config, stream_config).generate(list) {
+        Ok(code) => print!("{code}"),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
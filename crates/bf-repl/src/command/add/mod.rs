@@ -1,4 +1,4 @@
-use common::execution::memory::{Memory, MemoryError};
+use common::execution::memory::{DynMemory, MemoryError};
 
 use snafu::prelude::*;
 
@@ -12,7 +12,7 @@ pub enum AddError {
     Overflow { source: MemoryError },
 }
 
-pub fn execute(memory: &mut Memory, addr: isize, val: i32) -> Result<()> {
+pub fn execute(memory: &mut DynMemory, addr: isize, val: i32) -> Result<()> {
     match memory.add_at(addr, val) {
         Ok(()) => Ok(()),
         Err(e) => match e {
@@ -29,7 +29,7 @@ mod tests {
 
     #[test]
     fn add() {
-        let mut memory: Memory = Default::default();
+        let mut memory: DynMemory = Default::default();
         assert_eq!(execute(&mut memory, 0, 1), Ok(()));
         assert_eq!(memory.get_at(0), Ok(1));
 
@@ -39,7 +39,7 @@ mod tests {
 
     #[test]
     fn add_out_of_bound() {
-        let mut memory: Memory = Default::default();
+        let mut memory: DynMemory = Default::default();
         assert!(matches!(
             execute(&mut memory, -1, 0),
             Err(AddError::OutOfBound { source: _ })
@@ -48,7 +48,7 @@ mod tests {
 
     #[test]
     fn add_overflow() {
-        let mut memory: Memory = Default::default();
+        let mut memory: DynMemory = Default::default();
         assert!(matches!(
             execute(&mut memory, 1, 100000),
             Err(AddError::Overflow { source: _ })
@@ -1,4 +1,4 @@
-use common::execution::memory::{Memory, MemoryError};
+use common::execution::memory::{DynMemory, MemoryError};
 use snafu::prelude::*;
 
 type Result<T> = std::result::Result<T, GetError>;
@@ -9,7 +9,7 @@ pub enum GetError {
     OutOfBound { source: MemoryError },
 }
 
-pub fn execute(memory: &Memory, addr: isize) -> Result<i32> {
+pub fn execute(memory: &DynMemory, addr: isize) -> Result<i32> {
     match memory.get_at(addr) {
         Ok(res) => Ok(res),
         Err(e) => Err(GetError::OutOfBound { source: e }),
@@ -22,7 +22,7 @@ mod tests {
 
     #[test]
     fn get() {
-        let mut memory: Memory = Default::default();
+        let mut memory: DynMemory = Default::default();
         memory.set_at(0, 1).unwrap();
         memory.set_at(1, 2).unwrap();
         assert_eq!(execute(&memory, 0), Ok(1));
@@ -31,7 +31,7 @@ mod tests {
 
     #[test]
     fn get_out_of_bound() {
-        let memory: Memory = Default::default();
+        let memory: DynMemory = Default::default();
         assert!(matches!(
             execute(&memory, -1),
             Err(GetError::OutOfBound { .. })
@@ -1,5 +1,5 @@
-use common::execution::memory::Memory;
+use common::execution::memory::DynMemory;
 
-pub fn execute(memory: &Memory) -> isize {
+pub fn execute(memory: &DynMemory) -> isize {
     memory.position()
 }
@@ -1,10 +1,10 @@
-use common::execution::memory::{AddrRange, Memory};
+use common::execution::memory::{AddrRange, DynMemory};
 use snafu::prelude::*;
 use std::fmt::{Display, Formatter};
 
 pub type Result<T> = std::result::Result<T, ViewError>;
 pub struct MemoryView<'a> {
-    memory: &'a Memory,
+    memory: &'a DynMemory,
     range: AddrRange,
 }
 
@@ -57,7 +57,7 @@ pub enum ViewError {
     InvalidRange { range: AddrRange },
 }
 
-pub fn execute(memory: &Memory, range: AddrRange) -> Result<MemoryView> {
+pub fn execute(memory: &DynMemory, range: AddrRange) -> Result<MemoryView<'_>> {
     ensure!(range.left <= range.right, InvalidRangeSnafu { range });
 
     let AddrRange { left, right } = memory.range();
@@ -77,7 +77,7 @@ mod tests {
 
     #[test]
     fn display() {
-        let mut memory: Memory = Default::default();
+        let mut memory: DynMemory = Default::default();
         memory.add(48).unwrap();
         memory.seek(1).unwrap();
         memory.add(2).unwrap();
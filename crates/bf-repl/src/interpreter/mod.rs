@@ -1,6 +1,6 @@
-use common::compiler::{Compiler, ParseError};
+use common::compiler::{Compiler, Config as CompilerConfig, ParseError};
 use common::execution::context::Context;
-use common::execution::memory::{config::Config as MemoryConfig, Memory};
+use common::execution::memory::{config::Config as MemoryConfig, DynMemory};
 use common::execution::processor::{Processor, ProcessorError};
 use common::execution::stream::config::Config as StreamConfig;
 use snafu::prelude::*;
@@ -19,18 +19,22 @@ impl Interpreter {
     }
 
     pub fn run(&mut self, code: &str) -> Result<()> {
-        let compiler = Compiler::new();
+        // The memory is kept between lines, so the code isn't run alone.
+        let compiler = Compiler::with_config(CompilerConfig {
+            standalone: false,
+            ..Default::default()
+        });
         let instructions = compiler.compile(code)?;
         let mut processor = Processor::new(instructions);
         processor.run(&mut self.context)?;
         Ok(())
     }
 
-    pub fn memory(&self) -> &Memory {
+    pub fn memory(&self) -> &DynMemory {
         &self.context.memory
     }
 
-    pub fn memory_mut(&mut self) -> &mut Memory {
+    pub fn memory_mut(&mut self) -> &mut DynMemory {
         &mut self.context.memory
     }
 }
@@ -41,6 +45,7 @@ pub enum InterpreterError {
     Parse { source: ParseError },
     #[snafu(display("an error occurred when running the code"))]
     Runtime { source: ProcessorError },
+    #[allow(dead_code)]
     #[snafu(display("the program hasn't been loaded yet"))]
     Uninitialized,
 }
@@ -0,0 +1,337 @@
+mod termination;
+
+pub use termination::{Hang, LoopHang, Termination};
+
+use crate::compiler::diagnostic::Diagnostic;
+use crate::compiler::instruction::{Instruction, InstructionList};
+use crate::compiler::parser::OutputItem;
+use crate::execution::memory::config::{Addr, Config as MemoryConfig};
+
+/// A range of cells relative to where the pointer starts. A bound is `None` if
+/// the range is unbounded on that side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub left: Option<isize>,
+    pub right: Option<isize>,
+}
+
+impl Interval {
+    pub fn exact(cell: isize) -> Self {
+        Self {
+            left: Some(cell),
+            right: Some(cell),
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.left.is_some() && self.left == self.right
+    }
+
+    fn shift(self, offset: isize) -> Self {
+        Self {
+            left: self.left.map(|left| left + offset),
+            right: self.right.map(|right| right + offset),
+        }
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            left: self.left.zip(other.left).map(|(a, b)| a.min(b)),
+            right: self.right.zip(other.right).map(|(a, b)| a.max(b)),
+        }
+    }
+}
+
+/// The cells an instruction may move the pointer to or access. It's `certain`
+/// if the instruction always runs and reaches exactly `cells`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reach {
+    pub pc: usize,
+    pub cells: Interval,
+    pub certain: bool,
+}
+
+/// Where the pointer may go in a program, found without running it. The result
+/// is exact for the programs whose loops always move the pointer back to where
+/// they start, and becomes unbounded in the direction an unbalanced loop moves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerBounds {
+    /// The instructions reaching any cell, in the order of their addresses.
+    pub reaches: Vec<Reach>,
+}
+
+impl PointerBounds {
+    pub fn analyze(list: &InstructionList) -> Self {
+        let mut reaches = vec![];
+        let mut current = Interval::exact(0);
+        // Where the pointer was when each enclosing loop started.
+        let mut loops = vec![];
+
+        for (pc, instruction) in list.instructions.iter().enumerate() {
+            let cells = match instruction {
+                Instruction::Add { offset, .. }
+                | Instruction::Set { offset, .. }
+                | Instruction::Input { offset }
+                | Instruction::Output { offset }
+                | Instruction::OutputRepeat { offset, .. } => Some(current.shift(*offset)),
+                Instruction::OutputSeq { items } => items
+                    .iter()
+                    .filter_map(|item| match item {
+                        OutputItem::Cell { offset } => Some(current.shift(*offset)),
+                        OutputItem::Byte { .. } => None,
+                    })
+                    .reduce(Interval::union),
+                Instruction::Clear => Some(current),
+                Instruction::Seek { offset } => {
+                    current = current.shift(*offset);
+                    Some(current)
+                }
+                Instruction::AddUntilZero { target, .. } => Some(
+                    target
+                        .iter()
+                        .fold(current, |cells, arg| cells.union(current.shift(arg.offset))),
+                ),
+                Instruction::Scan { stride } => {
+                    current = if *stride > 0 {
+                        Interval {
+                            right: None,
+                            ..current
+                        }
+                    } else {
+                        Interval {
+                            left: None,
+                            ..current
+                        }
+                    };
+                    Some(current)
+                }
+                Instruction::JumpIfZero { .. } => {
+                    loops.push(current);
+                    Some(current)
+                }
+                Instruction::Jump { .. } => {
+                    let start = loops.pop().unwrap_or(current);
+
+                    if current != start {
+                        current = Self::repeat(start, current);
+                        Some(current)
+                    } else {
+                        None
+                    }
+                }
+                Instruction::OutputBytes { .. } | Instruction::Halt => None,
+            };
+
+            if let Some(cells) = cells {
+                // Only the counter of `AddUntilZero` is always accessed.
+                let certain = loops.is_empty()
+                    && cells.is_exact()
+                    && !matches!(instruction, Instruction::AddUntilZero { .. });
+                reaches.push(Reach { pc, cells, certain });
+            }
+        }
+
+        Self { reaches }
+    }
+
+    /// Return where the pointer may be after a loop runs repeatedly, which starts
+    /// at `start` and ends at `end` in the first iteration.
+    fn repeat(start: Interval, end: Interval) -> Interval {
+        let moves_left = match (start.left, end.left) {
+            (Some(start), Some(end)) => end < start,
+            (_, None) => true,
+            (None, Some(_)) => false,
+        };
+        let moves_right = match (start.right, end.right) {
+            (Some(start), Some(end)) => end > start,
+            (_, None) => true,
+            (None, Some(_)) => false,
+        };
+        let cells = start.union(end);
+
+        Interval {
+            left: cells.left.filter(|_| !moves_left),
+            right: cells.right.filter(|_| !moves_right),
+        }
+    }
+
+    /// Return all the cells the program may reach.
+    pub fn cells(&self) -> Interval {
+        self.reaches
+            .iter()
+            .map(|reach| reach.cells)
+            .fold(Interval::exact(0), Interval::union)
+    }
+
+    /// Warn about the first instructions which may reach the cells out of the
+    /// memory described by `target`, on each side of it.
+    pub fn diagnostics(&self, list: &InstructionList, target: &MemoryConfig) -> Vec<Diagnostic> {
+        let range = target.range();
+        let cells = self.cells();
+        let mut res = vec![];
+        let help = self.help(target);
+
+        let left = self.reaches.iter().find(|reach| match reach.cells.left {
+            Some(left) => left < range.left,
+            None => true,
+        });
+
+        if let Some(reach) = left {
+            let (message, label) = match reach.cells.left {
+                Some(cell) if reach.certain => (
+                    "the pointer moves past the start of the memory",
+                    format!("this reaches cell {cell}"),
+                ),
+                Some(cell) => (
+                    "the pointer may move past the start of the memory",
+                    format!("this may reach cell {cell}"),
+                ),
+                None => (
+                    "the pointer may move past the start of the memory",
+                    "this may move the pointer left without a bound".to_string(),
+                ),
+            };
+            let diagnostic = Diagnostic::warning(message, list.span(reach.pc)).label(label);
+            res.push(help.iter().fold(diagnostic, |d, help| d.note(help)));
+        }
+
+        let right = self.reaches.iter().find(|reach| match reach.cells.right {
+            Some(right) => right > range.right,
+            None => true,
+        });
+
+        if let Some(reach) = right {
+            let (message, label) = match reach.cells.right {
+                Some(cell) if reach.certain => (
+                    "the pointer moves past the end of the memory",
+                    format!("this reaches cell {cell}"),
+                ),
+                Some(cell) => (
+                    "the pointer may move past the end of the memory",
+                    format!("this may reach cell {cell}"),
+                ),
+                None => (
+                    "the pointer may move past the end of the memory",
+                    "this may move the pointer right without a bound".to_string(),
+                ),
+            };
+            let diagnostic = Diagnostic::warning(message, list.span(reach.pc)).label(label);
+            let diagnostic = match Self::min_len(target, cells) {
+                Some(len) => diagnostic.note(format!("consider using `--len {len}` or more")),
+                None => diagnostic,
+            };
+            res.push(diagnostic);
+        }
+
+        res
+    }
+
+    /// Suggest how to make the memory cover the cells on the left.
+    fn help(&self, target: &MemoryConfig) -> Vec<String> {
+        let cells = self.cells();
+        let mut res = vec![];
+
+        if let Addr::Unsigned = target.addr {
+            res.push("consider using signed addresses with `--addr signed`".to_string());
+        }
+
+        if cells.left.is_some() {
+            if let Some(len) = Self::min_len(
+                &MemoryConfig {
+                    addr: Addr::Signed,
+                    ..target.clone()
+                },
+                cells,
+            ) {
+                res.push(format!(
+                    "the program needs `--addr signed --len {len}` at least"
+                ));
+            }
+        }
+
+        res
+    }
+
+    /// Return the minimum length of the memory with the address strategy of
+    /// `target` to cover `cells`, or `None` if it's impossible.
+    fn min_len(target: &MemoryConfig, cells: Interval) -> Option<usize> {
+        let (left, right) = (cells.left?, cells.right?);
+
+        match target.addr {
+            Addr::Unsigned if left < 0 => None,
+            Addr::Unsigned => Some(right as usize + 1),
+            Addr::Signed => Some(2 * (-left).max(right + 1) as usize),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn analyze(code: &str) -> (InstructionList, PointerBounds) {
+        let list = Compiler::new().compile(code).unwrap();
+        let bounds = PointerBounds::analyze(&list);
+        (list, bounds)
+    }
+
+    #[test]
+    fn balanced_loops() {
+        let (_, bounds) = analyze(",[>>+<<-]>.<<,[>+++<-]");
+
+        let expected = Interval {
+            left: Some(-1),
+            right: Some(2),
+        };
+        assert_eq!(bounds.cells(), expected);
+    }
+
+    #[test]
+    fn unbalanced_loops() {
+        let (_, bounds) = analyze(",[>,]<+");
+
+        let expected = Interval {
+            left: Some(-1),
+            right: None,
+        };
+        assert_eq!(bounds.cells(), expected);
+    }
+
+    #[test]
+    fn left_seek_warnings() {
+        let target = MemoryConfig {
+            len: 10,
+            ..Default::default()
+        };
+
+        let (list, bounds) = analyze(",<.>");
+        let diagnostics = bounds.diagnostics(&list, &target);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "the pointer moves past the start of the memory"
+        );
+        assert_eq!(diagnostics[0].span, list.span(1));
+        assert_eq!(
+            diagnostics[0].notes[1].message,
+            "the program needs `--addr signed --len 2` at least"
+        );
+
+        let (list, bounds) = analyze(",[<<.>>-]");
+        let diagnostics = bounds.diagnostics(&list, &target);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "the pointer may move past the start of the memory"
+        );
+
+        let (list, bounds) = analyze(",[>,]>>>>>>>>>>>>>.");
+        let diagnostics = bounds.diagnostics(&list, &target);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "the pointer may move past the end of the memory"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use crate::compiler::diagnostic::Diagnostic;
+use crate::compiler::instruction::{Instruction, InstructionList};
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Overflow};
+
+/// Why a loop fails to terminate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hang {
+    /// The counter never changes after an iteration, so the loop never
+    /// terminates once it starts.
+    Always,
+    /// The counter wraps around before reaching zero, unless it's a multiple of
+    /// the number when the loop starts.
+    UnlessMultipleOf(i64),
+}
+
+/// A loop which may never terminate. `step` is how much the counter changes in
+/// each iteration, and `start` is the value of the counter when the loop starts
+/// if it's known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopHang {
+    pub pc: usize,
+    pub step: i64,
+    pub hang: Hang,
+    pub start: Option<i64>,
+}
+
+impl LoopHang {
+    /// Whether the loop surely runs and never terminates.
+    pub fn is_certain(&self) -> bool {
+        match (self.hang, self.start) {
+            (Hang::Always, Some(_)) => true,
+            (Hang::UnlessMultipleOf(divisor), Some(start)) => start % divisor != 0,
+            (_, None) => false,
+        }
+    }
+}
+
+/// The loops which may never terminate in a program, found without running it.
+/// Only the loops moving the pointer back to where they start and changing the
+/// counter by a constant are checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Termination {
+    /// The loops in the order of their addresses.
+    pub hangs: Vec<LoopHang>,
+}
+
+impl Termination {
+    /// Find the loops in a program, which starts with all cells being zero.
+    pub fn analyze(list: &InstructionList, target: &MemoryConfig) -> Self {
+        let mut hangs = vec![];
+        // The values of the cells before the first loop, relative to the pointer.
+        let mut known = KnownCells::Zeroed(HashMap::new());
+        let mut depth = 0;
+
+        for (pc, instruction) in list.instructions.iter().enumerate() {
+            match instruction {
+                Instruction::JumpIfZero { target: end } => {
+                    let start = if depth == 0 { known.get(0) } else { None };
+                    depth += 1;
+
+                    let step = Self::step(&list.instructions[pc + 1..end - 1]);
+                    hangs.extend(step.and_then(|step| Self::check(pc, step, start, target)));
+                }
+                // It runs a loop at once, which never terminates either if the
+                // counter never reaches zero.
+                Instruction::AddUntilZero { target: args, step } => {
+                    let start = if depth == 0 { known.get(0) } else { None };
+                    hangs.extend(Self::check(pc, *step as i64, start, target));
+
+                    if depth == 0 {
+                        for arg in args {
+                            known.insert(arg.offset, None);
+                        }
+                        known.insert(0, Some(0));
+                    }
+                }
+                Instruction::Jump { .. } => {
+                    depth -= 1;
+                    // Only the counter is known after the loop ends.
+                    known = KnownCells::Only(HashMap::from([(0, 0)]));
+                }
+                _ if depth > 0 => {}
+                Instruction::Add { offset, val } => {
+                    let after = known
+                        .get(*offset)
+                        .and_then(|before| Self::normalize(before + *val as i64, target));
+                    known.insert(*offset, after);
+                }
+                Instruction::Set { offset, val } => {
+                    known.insert(*offset, Self::normalize(*val as i64, target));
+                }
+                Instruction::Clear => known.insert(0, Some(0)),
+                Instruction::Input { offset } => known.insert(*offset, None),
+                Instruction::Seek { offset } => known.seek(*offset),
+                Instruction::Scan { .. } => known = KnownCells::Only(HashMap::from([(0, 0)])),
+                Instruction::Output { .. }
+                | Instruction::OutputBytes { .. }
+                | Instruction::OutputRepeat { .. }
+                | Instruction::OutputSeq { .. }
+                | Instruction::Halt => {}
+            }
+        }
+
+        Self { hangs }
+    }
+
+    /// Return the loop at `pc` if it may never terminate, where the counter
+    /// changes by `step` and starts with `start` if it's known.
+    fn check(pc: usize, step: i64, start: Option<i64>, target: &MemoryConfig) -> Option<LoopHang> {
+        let hang = LoopHang {
+            pc,
+            step,
+            hang: Self::hang(step, target)?,
+            start,
+        };
+
+        // The loop never runs, or terminates with the known counter.
+        if start == Some(0) || start.is_some() && !hang.is_certain() {
+            None
+        } else {
+            Some(hang)
+        }
+    }
+
+    /// Return how much the counter changes in each iteration of a loop, or
+    /// `None` if it's unknown.
+    fn step(body: &[Instruction]) -> Option<i64> {
+        let mut offset = 0;
+        let mut step = 0;
+        // Where the pointer was when each nested loop started.
+        let mut loops = vec![];
+
+        for instruction in body {
+            match instruction {
+                Instruction::Add { offset: o, val } if offset + o == 0 => {
+                    // The counter changes as many times as the nested loop runs.
+                    if !loops.is_empty() {
+                        return None;
+                    }
+                    step += *val as i64;
+                }
+                Instruction::Set { offset: o, .. } | Instruction::Input { offset: o }
+                    if offset + o == 0 =>
+                {
+                    return None
+                }
+                Instruction::Clear if offset == 0 => return None,
+                Instruction::AddUntilZero { target, .. }
+                    if offset == 0 || target.iter().any(|arg| offset + arg.offset == 0) =>
+                {
+                    return None
+                }
+                Instruction::Scan { stride } if *stride != 0 => return None,
+                Instruction::Seek { offset: o } => offset += o,
+                Instruction::JumpIfZero { .. } => loops.push(offset),
+                // The nested loop must be balanced, or the counter moves.
+                Instruction::Jump { .. } if loops.pop() != Some(offset) => return None,
+                _ => {}
+            }
+        }
+
+        (offset == 0).then_some(step)
+    }
+
+    /// Return why a loop whose counter changes by `step` may never terminate,
+    /// or `None` if it always does.
+    fn hang(step: i64, target: &MemoryConfig) -> Option<Hang> {
+        match target.overflow {
+            // The counter either reaches zero or overflows.
+            Overflow::Error => (step == 0).then_some(Hang::Always),
+            Overflow::Wrap => {
+                let modulus = 1i64 << Self::bits(target);
+                let divisor = gcd(step.rem_euclid(modulus), modulus);
+
+                match divisor {
+                    1 => None,
+                    divisor if divisor == modulus => Some(Hang::Always),
+                    divisor => Some(Hang::UnlessMultipleOf(divisor)),
+                }
+            }
+        }
+    }
+
+    fn bits(target: &MemoryConfig) -> u32 {
+        match target.cell {
+            Cell::I8 => 8,
+            Cell::I32 => 32,
+        }
+    }
+
+    /// Return the value stored in a cell, or `None` if storing it fails.
+    fn normalize(val: i64, target: &MemoryConfig) -> Option<i64> {
+        let (min, max) = match target.cell {
+            Cell::I8 => (i8::MIN as i64, i8::MAX as i64),
+            Cell::I32 => (i32::MIN as i64, i32::MAX as i64),
+        };
+
+        match target.overflow {
+            _ if (min..=max).contains(&val) => Some(val),
+            Overflow::Error => None,
+            Overflow::Wrap => {
+                let modulus = 1i64 << Self::bits(target);
+                Some((val - min).rem_euclid(modulus) + min)
+            }
+        }
+    }
+
+    /// Report the loops which surely never terminate as errors, and the others as
+    /// warnings.
+    pub fn diagnostics(&self, list: &InstructionList) -> Vec<Diagnostic> {
+        self.hangs
+            .iter()
+            .map(|hang| {
+                let span = list.span(hang.pc);
+                let diagnostic = if hang.is_certain() {
+                    Diagnostic::error("this loop never terminates", span)
+                } else if let Hang::Always = hang.hang {
+                    Diagnostic::warning("this loop never terminates once it starts", span)
+                } else {
+                    Diagnostic::warning("this loop may never terminate", span)
+                };
+
+                let diagnostic = match hang.step {
+                    0 => diagnostic.label("the loop never changes the current cell"),
+                    step => diagnostic.label(format!(
+                        "the loop changes the current cell by {step} in each iteration"
+                    )),
+                };
+
+                let diagnostic = match hang.hang {
+                    Hang::Always if hang.step != 0 => {
+                        diagnostic.note("the current cell wraps around to the same value")
+                    }
+                    Hang::Always => diagnostic,
+                    Hang::UnlessMultipleOf(divisor) => diagnostic.note(format!(
+                        "it only terminates if the current cell starts with a multiple of {divisor}"
+                    )),
+                };
+
+                match hang.start {
+                    Some(start) => diagnostic.note(format!("the current cell starts with {start}")),
+                    None => diagnostic,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The cells whose values are known, whose offsets are relative to the pointer.
+enum KnownCells {
+    /// The cells not in the map are zero, and `None` means it's unknown.
+    Zeroed(HashMap<isize, Option<i64>>),
+    /// Only the cells in the map are known.
+    Only(HashMap<isize, i64>),
+}
+
+impl KnownCells {
+    fn get(&self, offset: isize) -> Option<i64> {
+        match self {
+            KnownCells::Zeroed(cells) => cells.get(&offset).copied().unwrap_or(Some(0)),
+            KnownCells::Only(cells) => cells.get(&offset).copied(),
+        }
+    }
+
+    fn insert(&mut self, offset: isize, val: Option<i64>) {
+        match self {
+            KnownCells::Zeroed(cells) => {
+                cells.insert(offset, val);
+            }
+            KnownCells::Only(cells) => match val {
+                Some(val) => {
+                    cells.insert(offset, val);
+                }
+                None => {
+                    cells.remove(&offset);
+                }
+            },
+        }
+    }
+
+    /// Move the pointer by `offset`.
+    fn seek(&mut self, offset: isize) {
+        match self {
+            KnownCells::Zeroed(cells) => {
+                *cells = cells
+                    .drain()
+                    .map(|(cell, val)| (cell - offset, val))
+                    .collect()
+            }
+            KnownCells::Only(cells) => {
+                *cells = cells
+                    .drain()
+                    .map(|(cell, val)| (cell - offset, val))
+                    .collect()
+            }
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::diagnostic::Level;
+    use crate::compiler::{Compiler, Config, OptLevel};
+
+    fn analyze(code: &str, target: &MemoryConfig) -> (InstructionList, Termination) {
+        let compiler = Compiler::with_config(Config {
+            opt_level: OptLevel::O0,
+            ..Default::default()
+        });
+        let list = compiler.compile(code).unwrap();
+        let termination = Termination::analyze(&list, target);
+        (list, termination)
+    }
+
+    #[test]
+    fn unchanged_counter() {
+        let target = MemoryConfig::default();
+
+        let (list, termination) = analyze(",[]", &target);
+        assert_eq!(termination.hangs.len(), 1);
+        assert_eq!(termination.hangs[0].hang, Hang::Always);
+        let diagnostics = termination.diagnostics(&list);
+        assert_eq!(diagnostics[0].level, Level::Warning);
+        assert_eq!(diagnostics[0].span, list.span(1));
+
+        let (_, termination) = analyze(",[>+<[>]<.]", &target);
+        assert!(termination.hangs.is_empty());
+
+        let (_, termination) = analyze(",[>+<[>+<-]]", &target);
+        assert!(termination.hangs.is_empty());
+
+        let (_, termination) = analyze(",[>+<-]", &target);
+        assert!(termination.hangs.is_empty());
+    }
+
+    #[test]
+    fn wrapping_counter() {
+        let target = MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+
+        let (_, termination) = analyze(",[--]", &target);
+        assert_eq!(termination.hangs[0].hang, Hang::UnlessMultipleOf(2));
+
+        let (_, termination) = analyze(",[---]", &target);
+        assert!(termination.hangs.is_empty());
+
+        let code = format!(",[{}]", "+".repeat(256));
+        let (_, termination) = analyze(&code, &target);
+        assert_eq!(termination.hangs[0].hang, Hang::Always);
+
+        let target = MemoryConfig {
+            cell: Cell::I32,
+            ..target
+        };
+        let (_, termination) = analyze(&code, &target);
+        assert_eq!(termination.hangs[0].hang, Hang::UnlessMultipleOf(256));
+
+        // The loop is rewritten into `AddUntilZero`, which hangs all the same.
+        let compiler = Compiler::with_config(Config {
+            target: Some(target.clone()),
+            ..Default::default()
+        });
+        let list = compiler.compile(",[-->+<]").unwrap();
+        assert!(matches!(
+            list.instructions[1],
+            Instruction::AddUntilZero { step: -2, .. }
+        ));
+        let termination = Termination::analyze(&list, &target);
+        assert_eq!(termination.hangs[0].pc, 1);
+        assert_eq!(termination.hangs[0].hang, Hang::UnlessMultipleOf(2));
+    }
+
+    #[test]
+    fn known_counter() {
+        let target = MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+
+        let (list, termination) = analyze("+[>+<]", &target);
+        let diagnostics = termination.diagnostics(&list);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, Level::Error);
+        assert_eq!(diagnostics[0].message, "this loop never terminates");
+
+        let (_, termination) = analyze("[]++++[--]", &target);
+        assert!(termination.hangs.is_empty());
+
+        let (_, termination) = analyze(",[-]+++[--]", &target);
+        assert!(termination.hangs[0].is_certain());
+
+        let (_, termination) = analyze(",[-]>,<[]", &target);
+        assert!(termination.hangs.is_empty());
+
+        let (_, termination) = analyze(",[-]>,[]", &target);
+        assert!(!termination.hangs[0].is_certain());
+    }
+}
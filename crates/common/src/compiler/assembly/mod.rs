@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use snafu::prelude::*;
+
+use crate::compiler::diagnostic::Diagnostic;
+use crate::compiler::instruction::{Instruction, InstructionList, MAX_REPEAT_COUNT};
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem};
+use crate::compiler::span::{Position, Span};
+
+pub type Result<T> = std::result::Result<T, Vec<AssemblyError>>;
+
+/// Build an `InstructionList` from its text form, which is what `InstructionList`
+/// writes with `Display`. For example:
+///
+/// ```text
+///     input 0
+/// L0:
+///     jz L1                ; loop while the current cell isn't zero
+///     add_until_zero -1, [1: 2, 2: =3]
+///     output_seq [@1, 10]
+///     seek 1
+///     jmp L0
+/// L1:
+///     halt
+/// ```
+///
+/// Each line contains an optional label definition `name:`, followed by an
+/// optional instruction. Everything after `;` is a comment. Offsets and values
+/// are decimal integers, and the instructions are:
+///
+/// - `add offset, val`, `set offset, val`
+/// - `seek offset`, `scan stride`, `clear`, where `stride` isn't zero
+/// - `input offset`, `output offset`, `output_repeat offset, count`, where
+///   `count` is at most 2^20
+/// - `output_bytes [val, ...]`
+/// - `output_seq [item, ...]`, where an item is either a constant `val` or a cell
+///   `@offset`
+/// - `add_until_zero step, [arg, ...]`, where `step` isn't zero, and an arg is
+///   `offset: times`, or `offset: =times` if the cell is cleared in each
+///   iteration
+/// - `jz label`, `jmp label`, `halt`
+///
+/// The jumps must form loops like what the compiler generates: `jz` jumps to the
+/// instruction right after the `jmp` which jumps back to it, and the program ends
+/// with `halt`. The span of an instruction is where it's written in `code`.
+pub fn assemble(code: &str) -> Result<InstructionList> {
+    let mut assembler = Assembler::default();
+
+    for (line, start) in lines(code) {
+        if let Err(e) = assembler.line(line, start) {
+            assembler.errors.push(e);
+        }
+    }
+
+    assembler.finish(code)
+}
+
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum AssemblyError {
+    #[snafu(display("found an unexpected character `{ch}` at {span}"))]
+    UnexpectedChar { ch: char, span: Span },
+    #[snafu(display("expected {expected} at {span}"))]
+    Expected { expected: &'static str, span: Span },
+    #[snafu(display("the number at {span} is out of range"))]
+    OutOfRange { span: Span },
+    #[snafu(display("unknown instruction `{name}` at {span}"))]
+    UnknownInstruction { name: String, span: Span },
+    #[snafu(display("unknown label `{name}` at {span}"))]
+    UnknownLabel { name: String, span: Span },
+    /// `first` is where the label is defined for the first time.
+    #[snafu(display("the label `{name}` at {span} is already defined"))]
+    DuplicateLabel {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    #[snafu(display("the stride at {span} is zero"))]
+    ZeroStride { span: Span },
+    #[snafu(display("the step at {span} is zero"))]
+    ZeroStep { span: Span },
+    #[snafu(display("the jump at {span} doesn't form a loop"))]
+    UnpairedJump { span: Span },
+    #[snafu(display("the program doesn't end with `halt`"))]
+    MissingHalt { span: Span },
+}
+
+impl AssemblyError {
+    pub fn span(&self) -> Span {
+        match self {
+            AssemblyError::UnexpectedChar { span, .. }
+            | AssemblyError::Expected { span, .. }
+            | AssemblyError::OutOfRange { span }
+            | AssemblyError::UnknownInstruction { span, .. }
+            | AssemblyError::UnknownLabel { span, .. }
+            | AssemblyError::DuplicateLabel { span, .. }
+            | AssemblyError::ZeroStride { span }
+            | AssemblyError::ZeroStep { span }
+            | AssemblyError::UnpairedJump { span }
+            | AssemblyError::MissingHalt { span } => *span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            AssemblyError::UnexpectedChar { ch, span } => {
+                Diagnostic::error(format!("unexpected character `{ch}`"), *span)
+            }
+            AssemblyError::Expected { expected, span } => {
+                Diagnostic::error(format!("expected {expected}"), *span)
+            }
+            AssemblyError::OutOfRange { span } => {
+                Diagnostic::error("number out of range", *span).label("this number")
+            }
+            AssemblyError::UnknownInstruction { name, span } => {
+                Diagnostic::error(format!("unknown instruction `{name}`"), *span)
+            }
+            AssemblyError::UnknownLabel { name, span } => {
+                Diagnostic::error(format!("unknown label `{name}`"), *span)
+                    .label("this label is never defined")
+            }
+            AssemblyError::DuplicateLabel { name, span, first } => Diagnostic::error(
+                format!("the label `{name}` is defined more than once"),
+                *span,
+            )
+            .note_at("it's first defined here", *first),
+            AssemblyError::ZeroStride { span } => {
+                Diagnostic::error("the stride is zero", *span).label("this never moves the pointer")
+            }
+            AssemblyError::ZeroStep { span } => {
+                Diagnostic::error("the step is zero", *span).label("this never changes the counter")
+            }
+            AssemblyError::UnpairedJump { span } => {
+                Diagnostic::error("the jumps don't form a loop", *span)
+                    .label("this jump isn't paired with another one")
+                    .note("`jz` must jump to right after the `jmp` which jumps back to it")
+            }
+            AssemblyError::MissingHalt { span } => {
+                Diagnostic::error("the program doesn't end with `halt`", *span)
+            }
+        }
+    }
+}
+
+/// Split the code into lines without the comments, and pair them with their
+/// starting positions.
+fn lines(code: &str) -> impl Iterator<Item = (&str, Position)> {
+    let mut start = Position::new(0, 1, 1);
+
+    code.split_inclusive('\n').map(move |line| {
+        let position = start;
+        start = line.chars().fold(start, Position::advance);
+        let line = line
+            .split(';')
+            .next()
+            .unwrap()
+            .trim_end_matches(['\n', '\r']);
+        (line, position)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Ident(String),
+    Number(String),
+    Punct(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+fn tokenize(line: &str, start: Position) -> std::result::Result<Vec<Token>, AssemblyError> {
+    let mut res = vec![];
+    let mut chars = line.chars().peekable();
+    let mut position = start;
+
+    while let Some(&ch) = chars.peek() {
+        let begin = position;
+        let kind = match ch {
+            ' ' | '\t' => {
+                chars.next();
+                position = position.advance(ch);
+                continue;
+            }
+            ':' | ',' | '[' | ']' | '@' | '=' => {
+                chars.next();
+                position = position.advance(ch);
+                TokenKind::Punct(ch)
+            }
+            '-' | '0'..='9' => {
+                let mut number = String::new();
+
+                while let Some(&ch) = chars.peek() {
+                    if !(ch.is_ascii_digit() || ch == '-' && number.is_empty()) {
+                        break;
+                    }
+                    number.push(ch);
+                    chars.next();
+                    position = position.advance(ch);
+                }
+
+                TokenKind::Number(number)
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut ident = String::new();
+
+                while let Some(&ch) = chars.peek() {
+                    if !(ch.is_ascii_alphanumeric() || ch == '_') {
+                        break;
+                    }
+                    ident.push(ch);
+                    chars.next();
+                    position = position.advance(ch);
+                }
+
+                TokenKind::Ident(ident)
+            }
+            _ => {
+                return Err(AssemblyError::UnexpectedChar {
+                    ch,
+                    span: Span::of_char(begin, ch),
+                })
+            }
+        };
+
+        res.push(Token {
+            kind,
+            span: Span::new(begin, position),
+        });
+    }
+
+    Ok(res)
+}
+
+/// The tokens of a line, which are consumed from the beginning.
+struct Line {
+    tokens: Vec<Token>,
+    index: usize,
+    /// The span right after the last token, for the errors at the end of line.
+    end: Span,
+}
+
+impl Line {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.index += 1;
+        self.tokens.get(self.index - 1)
+    }
+
+    /// Return the span of the next token, or the end of line.
+    fn next_span(&self) -> Span {
+        self.peek().map_or(self.end, |token| token.span)
+    }
+
+    /// Return the span from the token at `index` to the last consumed one.
+    fn span_from(&self, index: usize) -> Span {
+        self.tokens[index]
+            .span
+            .merge(self.tokens[self.index - 1].span)
+    }
+
+    fn is_punct(&self, ch: char) -> bool {
+        matches!(self.peek(), Some(Token { kind: TokenKind::Punct(c), .. }) if *c == ch)
+    }
+
+    fn punct(
+        &mut self,
+        ch: char,
+        expected: &'static str,
+    ) -> std::result::Result<(), AssemblyError> {
+        if self.is_punct(ch) {
+            self.next();
+            Ok(())
+        } else {
+            Err(AssemblyError::Expected {
+                expected,
+                span: self.next_span(),
+            })
+        }
+    }
+
+    fn comma(&mut self) -> std::result::Result<(), AssemblyError> {
+        self.punct(',', "`,`")
+    }
+
+    fn number<T: FromStr>(&mut self) -> std::result::Result<T, AssemblyError> {
+        let span = self.next_span();
+
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Number(number),
+                ..
+            }) => {
+                let res = number
+                    .parse()
+                    .map_err(|_| AssemblyError::OutOfRange { span });
+                self.next();
+                res
+            }
+            _ => Err(AssemblyError::Expected {
+                expected: "a number",
+                span,
+            }),
+        }
+    }
+
+    /// Offsets and strides are limited to `i32` like the counts in the source code,
+    /// so moving the pointer by one never overflows.
+    fn offset(&mut self) -> std::result::Result<isize, AssemblyError> {
+        self.number::<i32>().map(|offset| offset as isize)
+    }
+
+    fn ident(
+        &mut self,
+        expected: &'static str,
+    ) -> std::result::Result<(String, Span), AssemblyError> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Ident(ident),
+                span,
+            }) => {
+                let res = (ident.clone(), *span);
+                self.next();
+                Ok(res)
+            }
+            _ => Err(AssemblyError::Expected {
+                expected,
+                span: self.next_span(),
+            }),
+        }
+    }
+
+    /// Parse a list `[item, ...]` whose items are parsed by `item`.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> std::result::Result<T, AssemblyError>,
+    ) -> std::result::Result<Vec<T>, AssemblyError> {
+        let mut res = vec![];
+        self.punct('[', "`[`")?;
+
+        if !self.is_punct(']') {
+            res.push(item(self)?);
+
+            while self.is_punct(',') {
+                self.next();
+                res.push(item(self)?);
+            }
+        }
+
+        self.punct(']', "`,` or `]`")?;
+        Ok(res)
+    }
+}
+
+/// A jump whose target hasn't been resolved.
+struct Jump {
+    addr: usize,
+    label: String,
+    span: Span,
+}
+
+#[derive(Default)]
+struct Assembler {
+    instructions: Vec<Instruction>,
+    spans: Vec<Span>,
+    labels: HashMap<String, (usize, Span)>,
+    jumps: Vec<Jump>,
+    errors: Vec<AssemblyError>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: &str, start: Position) -> std::result::Result<(), AssemblyError> {
+        let tokens = tokenize(line, start)?;
+        let end = line.chars().fold(start, Position::advance);
+        let mut line = Line {
+            tokens,
+            index: 0,
+            end: Span::of_char(end, ' '),
+        };
+
+        if let (
+            Some(Token {
+                kind: TokenKind::Ident(name),
+                span,
+            }),
+            Some(colon),
+        ) = (line.tokens.first(), line.tokens.get(1))
+        {
+            if colon.kind == TokenKind::Punct(':') {
+                let (name, span) = (name.clone(), *span);
+                line.index = 2;
+
+                match self.labels.get(&name) {
+                    Some(&(_, first)) => {
+                        return Err(AssemblyError::DuplicateLabel { name, span, first })
+                    }
+                    None => {
+                        self.labels.insert(name, (self.instructions.len(), span));
+                    }
+                }
+            }
+        }
+
+        if line.peek().is_none() {
+            return Ok(());
+        }
+
+        let first = line.index;
+        let instruction = self.instruction(&mut line)?;
+
+        if line.peek().is_some() {
+            return Err(AssemblyError::Expected {
+                expected: "the end of line",
+                span: line.next_span(),
+            });
+        }
+
+        self.instructions.push(instruction);
+        self.spans.push(line.span_from(first));
+        Ok(())
+    }
+
+    fn instruction(&mut self, line: &mut Line) -> std::result::Result<Instruction, AssemblyError> {
+        let (name, span) = line.ident("an instruction")?;
+
+        let instruction = match name.as_str() {
+            "add" => {
+                let offset = line.offset()?;
+                line.comma()?;
+                Instruction::Add {
+                    offset,
+                    val: line.number()?,
+                }
+            }
+            "set" => {
+                let offset = line.offset()?;
+                line.comma()?;
+                Instruction::Set {
+                    offset,
+                    val: line.number()?,
+                }
+            }
+            "seek" => Instruction::Seek {
+                offset: line.offset()?,
+            },
+            "scan" => {
+                let span = line.next_span();
+                let stride = line.offset()?;
+                ensure!(stride != 0, ZeroStrideSnafu { span });
+                Instruction::Scan { stride }
+            }
+            "clear" => Instruction::Clear,
+            "input" => Instruction::Input {
+                offset: line.offset()?,
+            },
+            "output" => Instruction::Output {
+                offset: line.offset()?,
+            },
+            "output_repeat" => {
+                let offset = line.offset()?;
+                line.comma()?;
+                let span = line.next_span();
+                let count = line.number()?;
+                ensure!(count <= MAX_REPEAT_COUNT, OutOfRangeSnafu { span });
+                Instruction::OutputRepeat { offset, count }
+            }
+            "output_bytes" => Instruction::OutputBytes {
+                bytes: line.list(Line::number)?,
+            },
+            "output_seq" => Instruction::OutputSeq {
+                items: line.list(|line| {
+                    if line.is_punct('@') {
+                        line.next();
+                        Ok(OutputItem::Cell {
+                            offset: line.offset()?,
+                        })
+                    } else {
+                        Ok(OutputItem::Byte {
+                            val: line.number()?,
+                        })
+                    }
+                })?,
+            },
+            "add_until_zero" => {
+                let span = line.next_span();
+                let step: i32 = line.number()?;
+                ensure!(step != 0, ZeroStepSnafu { span });
+                line.comma()?;
+                let target = line.list(|line| {
+                    let offset = line.offset()?;
+                    line.punct(':', "`:`")?;
+
+                    if line.is_punct('=') {
+                        line.next();
+                        Ok(AddUntilZeroArg::cleared(offset, line.number()?))
+                    } else {
+                        Ok(AddUntilZeroArg::new(offset, line.number()?))
+                    }
+                })?;
+                Instruction::AddUntilZero { target, step }
+            }
+            "jz" | "jmp" => {
+                let (label, span) = line.ident("a label")?;
+                self.jumps.push(Jump {
+                    addr: self.instructions.len(),
+                    label,
+                    span,
+                });
+
+                // The target is resolved after all labels are defined.
+                if name == "jz" {
+                    Instruction::JumpIfZero { target: 0 }
+                } else {
+                    Instruction::Jump { target: 0 }
+                }
+            }
+            "halt" => Instruction::Halt,
+            _ => return Err(AssemblyError::UnknownInstruction { name, span }),
+        };
+
+        Ok(instruction)
+    }
+
+    /// Resolve the jumps and check the structure of the program.
+    fn finish(mut self, code: &str) -> Result<InstructionList> {
+        for jump in &self.jumps {
+            match self.labels.get(&jump.label) {
+                Some(&(addr, _)) => match &mut self.instructions[jump.addr] {
+                    Instruction::Jump { target } | Instruction::JumpIfZero { target } => {
+                        *target = addr
+                    }
+                    _ => unreachable!(),
+                },
+                None => self.errors.push(AssemblyError::UnknownLabel {
+                    name: jump.label.clone(),
+                    span: jump.span,
+                }),
+            }
+        }
+
+        if !self.errors.is_empty() {
+            self.errors.sort_by_key(|e| e.span().start.offset);
+            return Err(self.errors);
+        }
+
+        let list = InstructionList {
+            instructions: self.instructions,
+            spans: self.spans,
+        };
+        let mut errors: Vec<_> = list
+            .unpaired_jumps()
+            .into_iter()
+            .map(|addr| AssemblyError::UnpairedJump {
+                span: list.span(addr),
+            })
+            .collect();
+
+        if list.instructions.last() != Some(&Instruction::Halt) {
+            let end = code.chars().fold(Position::new(0, 1, 1), Position::advance);
+            errors.push(AssemblyError::MissingHalt {
+                span: Span::of_char(end, ' '),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(list)
+        } else {
+            errors.sort_by_key(|e| e.span().start.offset);
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{Compiler, Config, OptLevel};
+
+    #[test]
+    fn assemble_instructions() {
+        let code = "\
+    input 0
+L0: jz L1 ; comment
+    add_until_zero -1, [1: 2, -2: =3]
+    output_seq [@1, 10]
+    output_bytes []
+    seek -1
+    jmp L0
+L1:
+    halt
+";
+        let list = assemble(code).unwrap();
+
+        let expected = vec![
+            Instruction::Input { offset: 0 },
+            Instruction::JumpIfZero { target: 7 },
+            Instruction::AddUntilZero {
+                target: vec![AddUntilZeroArg::new(1, 2), AddUntilZeroArg::cleared(-2, 3)],
+                step: -1,
+            },
+            Instruction::OutputSeq {
+                items: vec![OutputItem::Cell { offset: 1 }, OutputItem::Byte { val: 10 }],
+            },
+            Instruction::OutputBytes { bytes: vec![] },
+            Instruction::Seek { offset: -1 },
+            Instruction::Jump { target: 1 },
+            Instruction::Halt,
+        ];
+        assert_eq!(list.instructions, expected);
+
+        let jz = Span::new(Position::new(12, 2, 5), Position::new(17, 2, 10));
+        assert_eq!(list.span(1), jz);
+    }
+
+    #[test]
+    fn round_trip() {
+        for code in [
+            include_str!("../../../../../examples/hanoi.bf"),
+            include_str!("../../../../../examples/squares.bf"),
+        ] {
+            for opt_level in [OptLevel::O0, OptLevel::O3] {
+                let compiler = Compiler::with_config(Config {
+                    opt_level,
+                    ..Default::default()
+                });
+                let list = compiler.compile(code).unwrap();
+                let text = list.to_string();
+                let assembled = assemble(&text).unwrap();
+
+                assert_eq!(assembled.instructions, list.instructions);
+                assert_eq!(assembled.to_string(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn assembly_errors() {
+        let errors = assemble("L0:\n    jz L1\n    add 1\n    foo\nL0:\n    jmp L2\n").unwrap_err();
+        let expected = vec![
+            AssemblyError::UnknownLabel {
+                name: "L1".to_string(),
+                span: Span::new(Position::new(11, 2, 8), Position::new(13, 2, 10)),
+            },
+            AssemblyError::Expected {
+                expected: "`,`",
+                span: Span::new(Position::new(23, 3, 10), Position::new(24, 3, 11)),
+            },
+            AssemblyError::UnknownInstruction {
+                name: "foo".to_string(),
+                span: Span::new(Position::new(28, 4, 5), Position::new(31, 4, 8)),
+            },
+            AssemblyError::DuplicateLabel {
+                name: "L0".to_string(),
+                span: Span::new(Position::new(32, 5, 1), Position::new(34, 5, 3)),
+                first: Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3)),
+            },
+            AssemblyError::UnknownLabel {
+                name: "L2".to_string(),
+                span: Span::new(Position::new(44, 6, 9), Position::new(46, 6, 11)),
+            },
+        ];
+        assert_eq!(errors, expected);
+
+        let errors = assemble("L0:\n    jmp L0\n    halt\n").unwrap_err();
+        assert!(matches!(errors[..], [AssemblyError::UnpairedJump { .. }]));
+
+        let errors = assemble("    seek 1\n").unwrap_err();
+        assert!(matches!(errors[..], [AssemblyError::MissingHalt { .. }]));
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let errors = assemble(
+            "    scan 0\n    add_until_zero 0, [1: 1]\n    output_repeat 0, 1048577\n    add 2147483648, 1\n    halt\n",
+        )
+        .unwrap_err();
+        let expected = vec![
+            AssemblyError::ZeroStride {
+                span: Span::new(Position::new(9, 1, 10), Position::new(10, 1, 11)),
+            },
+            AssemblyError::ZeroStep {
+                span: Span::new(Position::new(30, 2, 20), Position::new(31, 2, 21)),
+            },
+            AssemblyError::OutOfRange {
+                span: Span::new(Position::new(61, 3, 22), Position::new(68, 3, 29)),
+            },
+            AssemblyError::OutOfRange {
+                span: Span::new(Position::new(77, 4, 9), Position::new(87, 4, 19)),
+            },
+        ];
+        assert_eq!(errors, expected);
+
+        let list = assemble("    add_until_zero -2, []\n    halt\n").unwrap();
+        assert_eq!(
+            list.instructions[0],
+            Instruction::AddUntilZero {
+                target: vec![],
+                step: -2,
+            }
+        );
+
+        let list = assemble("    output_repeat 0, 1048576\n    halt\n").unwrap();
+        assert_eq!(
+            list.instructions[0],
+            Instruction::OutputRepeat {
+                offset: 0,
+                count: MAX_REPEAT_COUNT,
+            }
+        );
+    }
+}
@@ -0,0 +1,665 @@
+use snafu::prelude::*;
+
+use crate::compiler::instruction::{Instruction, InstructionList, MAX_REPEAT_COUNT};
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem};
+use crate::compiler::span::{Position, Span};
+use crate::execution::memory::config::{Addr, Cell, Config as MemoryConfig, Eof, Overflow};
+use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+
+pub type Result<T> = std::result::Result<T, BytecodeError>;
+
+/// The bytes every bytecode file starts with. A brainfuck program never starts
+/// with `\0`, so they can be told apart.
+pub const MAGIC: &[u8; 4] = b"\0bfc";
+/// The version of the format written by `Bytecode::encode`, which changes
+/// whenever the format changes.
+pub const VERSION: u16 = 1;
+
+/// Where a compiled program comes from. `name` is the path of the source code,
+/// and `spans[i]` is the span of the instruction at `i`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    pub name: String,
+    pub spans: Vec<Span>,
+}
+
+/// A compiled program with the memory and stream it was compiled for. Its
+/// binary form is:
+///
+/// - `MAGIC` and `VERSION` as a little-endian `u16`
+/// - the CRC-32 checksum of the rest of the bytes as a little-endian `u32`
+/// - the memory and stream configuration
+/// - the instructions, each of which is an opcode and its operands
+/// - an optional `SourceMap`
+///
+/// The spans in `instructions` are only kept in the source map.
+pub struct Bytecode {
+    pub instructions: InstructionList,
+    pub memory: MemoryConfig,
+    pub stream: StreamConfig,
+    pub source_map: Option<SourceMap>,
+}
+
+impl Bytecode {
+    /// Whether `bytes` look like a bytecode file, whose version may be
+    /// unsupported.
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut payload = Writer::default();
+        payload.memory(&self.memory);
+        payload.stream(&self.stream)?;
+        payload.u64(self.instructions.len() as u64);
+
+        for instruction in &self.instructions.instructions {
+            payload.instruction(instruction);
+        }
+
+        match &self.source_map {
+            Some(source_map) => {
+                payload.u8(1);
+                payload.str(&source_map.name);
+
+                for span in &source_map.spans {
+                    payload.span(*span);
+                }
+            }
+            None => payload.u8(0),
+        }
+
+        let mut res = Writer::default();
+        res.0.extend(MAGIC);
+        res.u16(VERSION);
+        res.u32(crc32(&payload.0));
+        res.0.extend(payload.0);
+        Ok(res.0)
+    }
+
+    /// Load a program from the bytes written by `encode`. The version and the
+    /// checksum are checked before anything else is read.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        ensure!(Self::is_bytecode(bytes), NotBytecodeSnafu);
+        let mut header = Reader::new(&bytes[MAGIC.len()..]);
+        let version = header.u16()?;
+        ensure!(
+            version == VERSION,
+            UnsupportedVersionSnafu {
+                found: version,
+                expected: VERSION,
+            }
+        );
+        let checksum = header.u32()?;
+        let payload = header.rest();
+        ensure!(crc32(payload) == checksum, ChecksumMismatchSnafu);
+
+        let mut reader = Reader::new(payload);
+        let memory = reader.memory()?;
+        let stream = reader.stream()?;
+        // Each instruction takes at least a byte.
+        let len = reader.len(1)?;
+        let instructions = (0..len)
+            .map(|_| reader.instruction())
+            .collect::<Result<Vec<_>>>()?;
+
+        let source_map = match reader.u8()? {
+            0 => None,
+            1 => {
+                let name = reader.str()?;
+                let spans = (0..len).map(|_| reader.span()).collect::<Result<_>>()?;
+                Some(SourceMap { name, spans })
+            }
+            _ => return InvalidSnafu.fail(),
+        };
+        ensure!(reader.remaining() == 0, InvalidSnafu);
+
+        let mut instructions = InstructionList::new(instructions);
+
+        if let Some(source_map) = &source_map {
+            instructions.spans = source_map.spans.clone();
+        }
+
+        // The processor relies on the jumps forming loops and `Halt` at the end.
+        ensure!(
+            instructions.unpaired_jumps().is_empty()
+                && instructions.instructions.last() == Some(&Instruction::Halt),
+            InvalidSnafu
+        );
+
+        Ok(Self {
+            instructions,
+            memory,
+            stream,
+            source_map,
+        })
+    }
+}
+
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum BytecodeError {
+    #[snafu(display("not a bytecode file"))]
+    NotBytecode,
+    #[snafu(display("the bytecode version {found} isn't supported, expected version {expected}"))]
+    UnsupportedVersion { found: u16, expected: u16 },
+    #[snafu(display("the bytecode is corrupted, whose checksum doesn't match"))]
+    ChecksumMismatch,
+    #[snafu(display("the bytecode ends unexpectedly"))]
+    Truncated,
+    #[snafu(display("the bytecode is invalid"))]
+    Invalid,
+    #[snafu(display("a program reading or writing a `Vec` stream can't be saved"))]
+    UnsupportedStream,
+}
+
+/// Compute the CRC-32 (IEEE 802.3) checksum.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Write the values in little-endian.
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, val: u8) {
+        self.0.push(val);
+    }
+
+    fn u16(&mut self, val: u16) {
+        self.0.extend(val.to_le_bytes());
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.0.extend(val.to_le_bytes());
+    }
+
+    fn i32(&mut self, val: i32) {
+        self.0.extend(val.to_le_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.0.extend(val.to_le_bytes());
+    }
+
+    fn i64(&mut self, val: i64) {
+        self.0.extend(val.to_le_bytes());
+    }
+
+    fn str(&mut self, val: &str) {
+        self.u64(val.len() as u64);
+        self.0.extend(val.as_bytes());
+    }
+
+    fn memory(&mut self, config: &MemoryConfig) {
+        self.u64(config.len as u64);
+        self.u8(match config.addr {
+            Addr::Unsigned => 0,
+            Addr::Signed => 1,
+        });
+        self.u8(match config.cell {
+            Cell::I8 => 0,
+            Cell::I32 => 1,
+        });
+        self.u8(match config.overflow {
+            Overflow::Error => 0,
+            Overflow::Wrap => 1,
+        });
+        self.u8(match config.eof {
+            Eof::Zero => 0,
+            Eof::Keep => 1,
+            Eof::Ignore => 2,
+        });
+    }
+
+    fn stream(&mut self, config: &StreamConfig) -> Result<()> {
+        self.u8(match config.input {
+            Input::Null => 0,
+            Input::Standard => 1,
+            Input::Vec(_) => return UnsupportedStreamSnafu.fail(),
+        });
+        self.u8(match config.output {
+            Output::Null => 0,
+            Output::CharStandard => 1,
+            Output::IntStandard => 2,
+            Output::Vec(_) => return UnsupportedStreamSnafu.fail(),
+        });
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::Add { offset, val } => {
+                self.u8(0);
+                self.i64(*offset as i64);
+                self.i32(*val);
+            }
+            Instruction::Seek { offset } => {
+                self.u8(1);
+                self.i64(*offset as i64);
+            }
+            Instruction::Clear => self.u8(2),
+            Instruction::AddUntilZero { target, step } => {
+                self.u8(3);
+                self.i32(*step);
+                self.u64(target.len() as u64);
+
+                for arg in target {
+                    self.i64(arg.offset as i64);
+                    self.i32(arg.times);
+                    self.u8(arg.clear as u8);
+                }
+            }
+            Instruction::Scan { stride } => {
+                self.u8(4);
+                self.i64(*stride as i64);
+            }
+            Instruction::Set { offset, val } => {
+                self.u8(5);
+                self.i64(*offset as i64);
+                self.i32(*val);
+            }
+            Instruction::Input { offset } => {
+                self.u8(6);
+                self.i64(*offset as i64);
+            }
+            Instruction::Output { offset } => {
+                self.u8(7);
+                self.i64(*offset as i64);
+            }
+            Instruction::OutputBytes { bytes } => {
+                self.u8(8);
+                self.u64(bytes.len() as u64);
+
+                for byte in bytes {
+                    self.i32(*byte);
+                }
+            }
+            Instruction::OutputRepeat { offset, count } => {
+                self.u8(9);
+                self.i64(*offset as i64);
+                self.u64(*count as u64);
+            }
+            Instruction::OutputSeq { items } => {
+                self.u8(10);
+                self.u64(items.len() as u64);
+
+                for item in items {
+                    match item {
+                        OutputItem::Cell { offset } => {
+                            self.u8(0);
+                            self.i64(*offset as i64);
+                        }
+                        OutputItem::Byte { val } => {
+                            self.u8(1);
+                            self.i32(*val);
+                        }
+                    }
+                }
+            }
+            Instruction::Jump { target } => {
+                self.u8(11);
+                self.u64(*target as u64);
+            }
+            Instruction::JumpIfZero { target } => {
+                self.u8(12);
+                self.u64(*target as u64);
+            }
+            Instruction::Halt => self.u8(13),
+        }
+    }
+
+    fn span(&mut self, span: Span) {
+        for position in [span.start, span.end] {
+            self.u64(position.offset as u64);
+            self.u64(position.line as u64);
+            self.u64(position.column as u64);
+        }
+    }
+}
+
+/// Read the values written by `Writer`.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn rest(self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        ensure!(self.bytes.len() >= N, TruncatedSnafu);
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| BytecodeError::Invalid)
+    }
+
+    /// Read an offset or a stride, which is limited to `i32` like the assembly, so
+    /// moving the pointer by one never overflows.
+    fn isize(&mut self) -> Result<isize> {
+        let offset =
+            i32::try_from(i64::from_le_bytes(self.take()?)).map_err(|_| BytecodeError::Invalid)?;
+        Ok(offset as isize)
+    }
+
+    /// Read the length of a list, whose items take at least `size` bytes each.
+    fn len(&mut self, size: usize) -> Result<usize> {
+        let len = self.usize()?;
+        ensure!(len <= self.remaining() / size, TruncatedSnafu);
+        Ok(len)
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.len(1)?;
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        String::from_utf8(head.to_vec()).map_err(|_| BytecodeError::Invalid)
+    }
+
+    fn memory(&mut self) -> Result<MemoryConfig> {
+        let len = self.usize()?;
+        let addr = match self.u8()? {
+            0 => Addr::Unsigned,
+            1 => Addr::Signed,
+            _ => return InvalidSnafu.fail(),
+        };
+        let cell = match self.u8()? {
+            0 => Cell::I8,
+            1 => Cell::I32,
+            _ => return InvalidSnafu.fail(),
+        };
+        let overflow = match self.u8()? {
+            0 => Overflow::Error,
+            1 => Overflow::Wrap,
+            _ => return InvalidSnafu.fail(),
+        };
+        let eof = match self.u8()? {
+            0 => Eof::Zero,
+            1 => Eof::Keep,
+            2 => Eof::Ignore,
+            _ => return InvalidSnafu.fail(),
+        };
+
+        Ok(MemoryConfig {
+            len,
+            addr,
+            cell,
+            overflow,
+            eof,
+        })
+    }
+
+    fn stream(&mut self) -> Result<StreamConfig> {
+        let input = match self.u8()? {
+            0 => Input::Null,
+            1 => Input::Standard,
+            _ => return InvalidSnafu.fail(),
+        };
+        let output = match self.u8()? {
+            0 => Output::Null,
+            1 => Output::CharStandard,
+            2 => Output::IntStandard,
+            _ => return InvalidSnafu.fail(),
+        };
+        Ok(StreamConfig { input, output })
+    }
+
+    /// Read an instruction, whose operands must be what the processor can run.
+    fn instruction(&mut self) -> Result<Instruction> {
+        let instruction = match self.u8()? {
+            0 => Instruction::Add {
+                offset: self.isize()?,
+                val: self.i32()?,
+            },
+            1 => Instruction::Seek {
+                offset: self.isize()?,
+            },
+            2 => Instruction::Clear,
+            3 => {
+                let step = self.i32()?;
+                ensure!(step != 0, InvalidSnafu);
+                let len = self.len(13)?;
+                let target = (0..len)
+                    .map(|_| {
+                        let (offset, times) = (self.isize()?, self.i32()?);
+
+                        match self.u8()? {
+                            0 => Ok(AddUntilZeroArg::new(offset, times)),
+                            1 => Ok(AddUntilZeroArg::cleared(offset, times)),
+                            _ => InvalidSnafu.fail(),
+                        }
+                    })
+                    .collect::<Result<_>>()?;
+                Instruction::AddUntilZero { target, step }
+            }
+            4 => {
+                let stride = self.isize()?;
+                ensure!(stride != 0, InvalidSnafu);
+                Instruction::Scan { stride }
+            }
+            5 => Instruction::Set {
+                offset: self.isize()?,
+                val: self.i32()?,
+            },
+            6 => Instruction::Input {
+                offset: self.isize()?,
+            },
+            7 => Instruction::Output {
+                offset: self.isize()?,
+            },
+            8 => {
+                let len = self.len(4)?;
+                let bytes = (0..len).map(|_| self.i32()).collect::<Result<_>>()?;
+                Instruction::OutputBytes { bytes }
+            }
+            9 => {
+                let offset = self.isize()?;
+                let count = self.usize()?;
+                ensure!(count <= MAX_REPEAT_COUNT, InvalidSnafu);
+                Instruction::OutputRepeat { offset, count }
+            }
+            10 => {
+                let len = self.len(5)?;
+                let items = (0..len)
+                    .map(|_| match self.u8()? {
+                        0 => Ok(OutputItem::Cell {
+                            offset: self.isize()?,
+                        }),
+                        1 => Ok(OutputItem::Byte { val: self.i32()? }),
+                        _ => InvalidSnafu.fail(),
+                    })
+                    .collect::<Result<_>>()?;
+                Instruction::OutputSeq { items }
+            }
+            11 => Instruction::Jump {
+                target: self.usize()?,
+            },
+            12 => Instruction::JumpIfZero {
+                target: self.usize()?,
+            },
+            13 => Instruction::Halt,
+            _ => return InvalidSnafu.fail(),
+        };
+
+        Ok(instruction)
+    }
+
+    fn span(&mut self) -> Result<Span> {
+        let mut position = || -> Result<Position> {
+            Ok(Position::new(self.usize()?, self.usize()?, self.usize()?))
+        };
+        let start = position()?;
+        let end = position()?;
+        Ok(Span::new(start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn bytecode(source_map: bool) -> Bytecode {
+        let compiler = Compiler::new();
+        let code = include_str!("../../../../../examples/hanoi.bf");
+        let instructions = compiler.compile(code).unwrap();
+        let source_map = source_map.then(|| SourceMap {
+            name: "hanoi.bf".to_string(),
+            spans: instructions.spans.clone(),
+        });
+
+        Bytecode {
+            instructions,
+            memory: MemoryConfig {
+                addr: Addr::Signed,
+                overflow: Overflow::Wrap,
+                ..Default::default()
+            },
+            stream: StreamConfig {
+                input: Input::Null,
+                output: Output::IntStandard,
+            },
+            source_map,
+        }
+    }
+
+    #[test]
+    fn crc32_checksum() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        for source_map in [false, true] {
+            let expected = bytecode(source_map);
+            let bytes = expected.encode().unwrap();
+            assert!(Bytecode::is_bytecode(&bytes));
+
+            let decoded = Bytecode::decode(&bytes).unwrap();
+            assert_eq!(
+                decoded.instructions.instructions,
+                expected.instructions.instructions
+            );
+            assert_eq!(decoded.source_map, expected.source_map);
+            assert!(matches!(decoded.memory.addr, Addr::Signed));
+            assert!(matches!(decoded.memory.overflow, Overflow::Wrap));
+            assert!(matches!(decoded.stream.output, Output::IntStandard));
+
+            if source_map {
+                assert_eq!(decoded.instructions.spans, expected.instructions.spans);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_bytecode() {
+        let bytes = bytecode(false).encode().unwrap();
+
+        assert_eq!(
+            Bytecode::decode(b"+[>+<-]").err(),
+            Some(BytecodeError::NotBytecode)
+        );
+
+        let mut other_version = bytes.clone();
+        other_version[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(
+            Bytecode::decode(&other_version).err(),
+            Some(BytecodeError::UnsupportedVersion {
+                found: 2,
+                expected: VERSION,
+            })
+        );
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            Bytecode::decode(&corrupted).err(),
+            Some(BytecodeError::ChecksumMismatch)
+        );
+
+        assert_eq!(
+            Bytecode::decode(&bytes[..8]).err(),
+            Some(BytecodeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn invalid_operands() {
+        let instructions = [
+            Instruction::Scan { stride: 0 },
+            Instruction::AddUntilZero {
+                target: vec![AddUntilZeroArg::new(1, 1)],
+                step: 0,
+            },
+            Instruction::OutputRepeat {
+                offset: 0,
+                count: MAX_REPEAT_COUNT + 1,
+            },
+            Instruction::Add {
+                offset: i32::MAX as isize + 1,
+                val: 1,
+            },
+            Instruction::AddUntilZero {
+                target: vec![AddUntilZeroArg::new(isize::MIN, 1)],
+                step: -1,
+            },
+        ];
+
+        for instruction in instructions {
+            let bytecode = Bytecode {
+                instructions: InstructionList::new(vec![instruction, Instruction::Halt]),
+                ..bytecode(false)
+            };
+            let bytes = bytecode.encode().unwrap();
+            assert_eq!(Bytecode::decode(&bytes).err(), Some(BytecodeError::Invalid));
+        }
+    }
+}
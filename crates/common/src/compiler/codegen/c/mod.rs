@@ -0,0 +1,305 @@
+use std::fmt::Write;
+
+use super::{check_loops, check_stream, empty_error, hang_mask, text, Result};
+use crate::compiler::instruction::{Instruction, InstructionList};
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem};
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Eof, Overflow};
+use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+
+/// The helpers the generated code calls, which behave like `Memory` and the
+/// streams, configured by the macros written before it.
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// Translate programs into self-contained C programs, which run on the memory
+/// and the streams they are built for, and fail with the same errors as the
+/// interpreter.
+pub struct CBackend {
+    memory: MemoryConfig,
+    stream: StreamConfig,
+}
+
+impl CBackend {
+    pub fn new(memory: MemoryConfig, stream: StreamConfig) -> Self {
+        Self { memory, stream }
+    }
+
+    pub fn generate(&self, list: &InstructionList) -> Result<String> {
+        check_stream(&self.stream)?;
+        check_loops(list)?;
+
+        let mut res = String::new();
+        self.write_config(&mut res);
+        res.push_str(RUNTIME);
+        res.push_str("\nint main(void) {\n");
+
+        if let Some(error) = empty_error(list) {
+            writeln!(res, "    fputs({error:?}, stderr);\n    return 1;\n}}").unwrap();
+            return Ok(res);
+        }
+
+        let mut indent = 1;
+
+        for (pc, instruction) in list.instructions.iter().enumerate() {
+            let span = list.span(pc);
+            let at = if span.is_empty() {
+                format!("\"instruction {pc}\"")
+            } else {
+                format!("\"instruction {pc} ({})\"", span.start)
+            };
+
+            if let Instruction::Jump { .. } = instruction {
+                indent -= 1;
+            }
+
+            let code = self.instruction(instruction, &at);
+
+            for line in code.lines() {
+                writeln!(res, "{:1$}{line}", "", indent * 4).unwrap();
+            }
+
+            if let Instruction::JumpIfZero { .. } = instruction {
+                indent += 1;
+            }
+        }
+
+        res.push_str("    fflush(stdout);\n    return 0;\n}\n");
+        Ok(res)
+    }
+
+    fn write_config(&self, res: &mut String) {
+        let range = self.memory.range();
+        let cell_bits = match self.memory.cell {
+            Cell::I8 => 8,
+            Cell::I32 => 32,
+        };
+        let wrap = match self.memory.overflow {
+            Overflow::Error => 0,
+            Overflow::Wrap => 1,
+        };
+        let eof = match self.memory.eof {
+            Eof::Zero => "EOF_ZERO",
+            Eof::Keep => "EOF_KEEP",
+            Eof::Ignore => "EOF_IGNORE",
+        };
+        let input_null = match self.stream.input {
+            Input::Null => 1,
+            _ => 0,
+        };
+        let output = match self.stream.output {
+            Output::CharStandard => "OUTPUT_CHAR",
+            Output::IntStandard => "OUTPUT_INT",
+            _ => "OUTPUT_NULL",
+        };
+
+        writeln!(res, "/* Generated by bf-exec. */").unwrap();
+        writeln!(res, "#define LEFT INT64_C({})", range.left).unwrap();
+        writeln!(res, "#define RIGHT INT64_C({})", range.right).unwrap();
+        writeln!(res, "#define CELL_BITS {cell_bits}").unwrap();
+        writeln!(res, "#define WRAP {wrap}").unwrap();
+        writeln!(res, "#define EOF_STRATEGY {eof}").unwrap();
+        writeln!(res, "#define INPUT_NULL {input_null}").unwrap();
+        writeln!(res, "#define OUTPUT {output}").unwrap();
+        res.push('\n');
+    }
+
+    /// Return the statements of an instruction. `at` is the C string describing
+    /// where the instruction is, which is reported when it fails.
+    fn instruction(&self, instruction: &Instruction, at: &str) -> String {
+        match instruction {
+            Instruction::Add { offset, val } => format!("add({offset}, {}, {at});", int(*val)),
+            Instruction::Seek { offset } => format!("seek({offset}, {at});"),
+            Instruction::Clear => "CURRENT = 0;".to_string(),
+            Instruction::AddUntilZero { target, step } => {
+                let mut res = "if (CURRENT != 0) {\n".to_string();
+
+                if let Some(mask) = hang_mask(&self.memory, *step) {
+                    writeln!(res, "    if (((uint32_t)CURRENT & {mask}u) != 0) {{").unwrap();
+
+                    for arg in target {
+                        writeln!(res, "        cell_at({}, {at});", arg.offset).unwrap();
+                    }
+
+                    res.push_str("        hang();\n    }\n");
+                }
+
+                if target.iter().all(|arg| arg.clear) {
+                    writeln!(res, "    count_until_zero(CURRENT, {step}, {at});").unwrap();
+                } else {
+                    writeln!(
+                        res,
+                        "    uint64_t count = count_until_zero(CURRENT, {step}, {at});"
+                    )
+                    .unwrap();
+                }
+
+                res.push_str("    CURRENT = 0;\n");
+
+                for AddUntilZeroArg {
+                    offset,
+                    times,
+                    clear,
+                } in target
+                {
+                    if *clear {
+                        writeln!(res, "    set({offset}, {}, {at});", int(*times)).unwrap();
+                    } else {
+                        writeln!(
+                            res,
+                            "    add_repeatedly_at({offset}, {}, count, {at});",
+                            int(*times)
+                        )
+                        .unwrap();
+                    }
+                }
+
+                res.push('}');
+                res
+            }
+            Instruction::Scan { stride } => format!("scan({stride}, {at});"),
+            Instruction::Set { offset, val } => format!("set({offset}, {}, {at});", int(*val)),
+            Instruction::Input { offset } => format!("input({offset}, {at});"),
+            Instruction::Output { offset } => format!("output_at({offset}, {at});"),
+            Instruction::OutputBytes { bytes } => match text(&self.stream.output, bytes) {
+                Some(text) => format!("print({}, {});", string(&text), text.len()),
+                None => String::new(),
+            },
+            Instruction::OutputRepeat { offset, count } => {
+                format!("output_repeat({offset}, {count}, {at});")
+            }
+            // The items before a cell out of the memory are written.
+            Instruction::OutputSeq { items } => items
+                .iter()
+                .map(|item| match item {
+                    OutputItem::Cell { offset } => format!("output_at({offset}, {at});"),
+                    OutputItem::Byte { val } => format!("output({});", int(*val)),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Instruction::JumpIfZero { .. } => "while (CURRENT != 0) {".to_string(),
+            Instruction::Jump { .. } => "}".to_string(),
+            Instruction::Halt => String::new(),
+        }
+    }
+}
+
+/// Write an `int32_t` as a C expression, where `-2147483648` isn't a constant.
+fn int(val: i32) -> String {
+    if val == i32::MIN {
+        "INT32_MIN".to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+/// Write the bytes as a C string literal.
+fn string(bytes: &[u8]) -> String {
+    let mut res = "\"".to_string();
+
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(res, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => res.push(byte as char),
+            _ => write!(res, "\\{byte:03o}").unwrap(),
+        }
+    }
+
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use super::*;
+    use crate::compiler::codegen::tests::{
+        compile, examples, interpret, run, strategies, workspace,
+    };
+    use crate::compiler::codegen::CodegenError;
+    use crate::compiler::Compiler;
+
+    /// Build the C program of `code` in `dir`, and check that it does the same as
+    /// the interpreter.
+    fn assert_same(dir: &Path, code: &str, memory: MemoryConfig, input: &[u8]) {
+        let list = compile(code, &memory);
+        let stream = StreamConfig {
+            input: Input::Standard,
+            output: Output::IntStandard,
+        };
+        let source = dir.join("main.c");
+        let binary = dir.join("main");
+        let c = CBackend::new(memory.clone(), stream)
+            .generate(&list)
+            .unwrap();
+        std::fs::write(&source, c).unwrap();
+
+        let status = Command::new("cc")
+            .args(["-std=c99", "-O2", "-Wall", "-Werror", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            run(&binary, &[], input),
+            interpret(code, memory, input),
+            "{code}"
+        );
+    }
+
+    #[test]
+    fn examples_run_the_same() {
+        let Some(dir) = workspace("cc", "examples") else {
+            return;
+        };
+
+        for (code, memory, input) in examples() {
+            assert_same(&dir, code, memory, &input);
+        }
+    }
+
+    #[test]
+    fn memory_strategies() {
+        let Some(dir) = workspace("cc", "strategies") else {
+            return;
+        };
+
+        for (code, memory, input) in strategies() {
+            assert_same(&dir, code, memory, input.as_bytes());
+        }
+    }
+
+    #[test]
+    fn empty_program() {
+        let Some(dir) = workspace("cc", "empty") else {
+            return;
+        };
+
+        assert_same(&dir, "---+++", Default::default(), b"");
+    }
+
+    #[test]
+    fn unsupported_programs() {
+        let list = Compiler::new().compile("+[-]").unwrap();
+        let stream = StreamConfig {
+            input: Input::Vec(Default::default()),
+            output: Output::Null,
+        };
+        let backend = CBackend::new(Default::default(), stream);
+        assert_eq!(
+            backend.generate(&list),
+            Err(CodegenError::UnsupportedStream)
+        );
+
+        let list = InstructionList::new(vec![
+            Instruction::JumpIfZero { target: 2 },
+            Instruction::Halt,
+        ]);
+        let backend = CBackend::new(Default::default(), Default::default());
+        assert_eq!(
+            backend.generate(&list),
+            Err(CodegenError::UnpairedJump { addr: 0 })
+        );
+    }
+}
@@ -0,0 +1,236 @@
+#include <inttypes.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define OUTPUT_NULL 0
+#define OUTPUT_CHAR 1
+#define OUTPUT_INT 2
+
+#define EOF_ZERO 0
+#define EOF_KEEP 1
+#define EOF_IGNORE 2
+
+#define CELL_MIN (-(INT64_C(1) << (CELL_BITS - 1)))
+#define CELL_MAX ((INT64_C(1) << (CELL_BITS - 1)) - 1)
+#define CELL_MASK (UINT64_MAX >> (64 - CELL_BITS))
+
+#define CURRENT memory[ptr - LEFT]
+
+static int32_t memory[RIGHT - LEFT + 1];
+static int64_t ptr = 0;
+
+/* Report a failed memory operation like the interpreter and exit. */
+static void fail(const char *at, const char *format, ...) {
+    va_list args;
+
+    fflush(stdout);
+    fprintf(stderr, "error: invalid memory operation occurred at %s: ", at);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static inline int32_t wrap(int64_t val) {
+    uint64_t bits = (uint64_t)val & CELL_MASK;
+
+    if (bits > (uint64_t)CELL_MAX) {
+        return (int32_t)((int64_t)bits - (int64_t)CELL_MASK - 1);
+    }
+
+    return (int32_t)bits;
+}
+
+static inline int32_t *cell_at(int64_t offset, const char *at) {
+    int64_t addr = ptr + offset;
+
+    if (addr < LEFT || addr > RIGHT) {
+        fail(at, "try to access cell at %" PRId64 ", which is out of [%" PRId64 ", %" PRId64 "]",
+             addr, (int64_t)LEFT, (int64_t)RIGHT);
+    }
+
+    return &memory[addr - LEFT];
+}
+
+static inline void seek(int64_t offset, const char *at) {
+    int64_t target = ptr + offset;
+
+    if (target < LEFT || target > RIGHT) {
+        fail(at, "try to seek pointer from %" PRId64 " to %" PRId64 ", which is out of [%" PRId64
+             ", %" PRId64 "]", ptr, target, (int64_t)LEFT, (int64_t)RIGHT);
+    }
+
+    ptr = target;
+}
+
+/* Move the pointer by `stride` until it points to a zero cell. */
+static inline void scan(int64_t stride, const char *at) {
+    while (CURRENT != 0) {
+        seek(stride, at);
+    }
+}
+
+static inline void add(int64_t offset, int32_t val, const char *at) {
+    int32_t *cell = cell_at(offset, at);
+    int64_t res = (int64_t)*cell + val;
+
+    if (res < CELL_MIN || res > CELL_MAX) {
+        if (!WRAP) {
+            fail(at, "%" PRId32 " + %" PRId32 " will overflow", *cell, val);
+        }
+
+        res = wrap(res);
+    }
+
+    *cell = (int32_t)res;
+}
+
+static inline int32_t checked_set(int32_t val, const char *at) {
+    if (val < CELL_MIN || val > CELL_MAX) {
+        if (!WRAP) {
+            fail(at, "%" PRId32 " will overflow", val);
+        }
+
+        return wrap(val);
+    }
+
+    return val;
+}
+
+static inline void set(int64_t offset, int32_t val, const char *at) {
+    int32_t *cell = cell_at(offset, at);
+    *cell = checked_set(val, at);
+}
+
+/* Add `val` to the cell `count` times, which fails as adding them one by one. */
+static inline int32_t add_repeatedly(int32_t before, int32_t val, uint64_t count, const char *at) {
+    int64_t abs_val = val > 0 ? (int64_t)val : -(int64_t)val;
+    int64_t room;
+
+    if (WRAP) {
+        return wrap((int64_t)((uint64_t)(int64_t)before + (uint64_t)(int64_t)val * count));
+    }
+
+    if (val == 0) {
+        return before;
+    }
+
+    room = val > 0 ? CELL_MAX - before : before - CELL_MIN;
+
+    if (count > (uint64_t)(room / abs_val)) {
+        fail(at, "%" PRId32 " + %" PRId32 " will overflow",
+             (int32_t)(before + room / abs_val * val), val);
+    }
+
+    return (int32_t)(before + (int64_t)count * val);
+}
+
+static inline void add_repeatedly_at(int64_t offset, int32_t val, uint64_t count, const char *at) {
+    int32_t *cell = cell_at(offset, at);
+    *cell = add_repeatedly(*cell, val, count, at);
+}
+
+/* Run forever like a loop whose counter never reaches zero. */
+static inline void hang(void) {
+    fflush(stdout);
+
+    for (;;) {
+    }
+}
+
+/* Return how many times `step` should be added to `val` until it's zero. If the
+ * cells wrap, `val` must be a multiple of the power of 2 in `step`, or the
+ * counter never reaches zero. */
+static inline uint64_t count_until_zero(int32_t val, int32_t step, const char *at) {
+    int64_t v = val, s = step;
+    uint64_t odd = (uint64_t)s & CELL_MASK, inverse;
+    int shift = 0, i;
+
+    if (WRAP) {
+        while ((odd & 1) == 0) {
+            odd >>= 1;
+            shift++;
+        }
+
+        inverse = odd;
+
+        for (i = 0; i < 5; i++) {
+            inverse *= 2 - odd * inverse;
+        }
+
+        return ((0 - (uint64_t)v) >> shift) * inverse & (CELL_MASK >> shift);
+    }
+
+    if (v % s == 0 && (v > 0) != (s > 0)) {
+        return (uint64_t)(v / s > 0 ? v / s : -(v / s));
+    }
+
+    /* The counter never reaches zero, so it overflows at last. */
+    return (uint64_t)add_repeatedly(val, step, UINT64_MAX, at);
+}
+
+static inline void input(int64_t offset, const char *at) {
+    int c = INPUT_NULL ? EOF : getchar();
+    int32_t *cell = cell_at(offset, at);
+    int32_t val = c == EOF ? -1 : c;
+
+    if (val == -1 && EOF_STRATEGY == EOF_ZERO) {
+        val = 0;
+    } else if (val == -1 && EOF_STRATEGY == EOF_IGNORE) {
+        return;
+    }
+
+    *cell = checked_set(val, at);
+}
+
+static inline void output(int32_t val) {
+    uint32_t c = (uint32_t)val;
+
+    if (OUTPUT == OUTPUT_INT) {
+        printf("%" PRId32 " ", val);
+        return;
+    } else if (OUTPUT == OUTPUT_NULL) {
+        return;
+    }
+
+    /* Write the character as UTF-8, or U+FFFD if it isn't a character. */
+    if (c > 0x10FFFF || (c >= 0xD800 && c <= 0xDFFF)) {
+        c = 0xFFFD;
+    }
+
+    if (c < 0x80) {
+        putchar((int)c);
+    } else if (c < 0x800) {
+        putchar((int)(0xC0 | c >> 6));
+        putchar((int)(0x80 | (c & 0x3F)));
+    } else if (c < 0x10000) {
+        putchar((int)(0xE0 | c >> 12));
+        putchar((int)(0x80 | (c >> 6 & 0x3F)));
+        putchar((int)(0x80 | (c & 0x3F)));
+    } else {
+        putchar((int)(0xF0 | c >> 18));
+        putchar((int)(0x80 | (c >> 12 & 0x3F)));
+        putchar((int)(0x80 | (c >> 6 & 0x3F)));
+        putchar((int)(0x80 | (c & 0x3F)));
+    }
+}
+
+static inline void output_at(int64_t offset, const char *at) {
+    output(*cell_at(offset, at));
+}
+
+static inline void output_repeat(int64_t offset, uint64_t count, const char *at) {
+    int32_t val = *cell_at(offset, at);
+    uint64_t i;
+
+    for (i = 0; i < count; i++) {
+        output(val);
+    }
+}
+
+static inline void print(const char *text, size_t len) {
+    fwrite(text, 1, len, stdout);
+}
@@ -0,0 +1,1090 @@
+use std::collections::HashMap;
+
+use super::{check_stream, empty_error, hang_mask, text, Result, UnsupportedMemorySnafu};
+use crate::compiler::instruction::{Instruction, InstructionList};
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem};
+use crate::execution::jit::assembler::{Assembler, Cond, Label, Mem, Reg};
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Eof, Overflow};
+use crate::execution::memory::strategy::split_step;
+use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+
+/// Where the program is loaded.
+const BASE: u64 = 0x400000;
+const PAGE: usize = 0x1000;
+/// The sizes of the ELF header and the two program headers before the code.
+const HEADERS: usize = 64 + 2 * 56;
+
+/// The layout of the memory out of the file, which is zeroed when the program
+/// starts: the count of the bytes in the output buffer, the buffer, the position
+/// and the count of the bytes in the input buffer, the buffer, and the cells.
+const OUTPUT_LEN: usize = 0;
+const OUTPUT_BUF: usize = 8;
+const INPUT_POS: usize = OUTPUT_BUF + BUF_SIZE;
+const INPUT_BUF: usize = INPUT_POS + 16;
+const CELLS: usize = INPUT_BUF + BUF_SIZE;
+const BUF_SIZE: usize = 4096;
+
+/// The registers kept through the code.
+const TAPE: Reg = Reg::R13;
+const INDEX: Reg = Reg::R12;
+
+const SYS_READ: u32 = 0;
+const SYS_WRITE: u32 = 1;
+const SYS_EXIT_GROUP: u32 = 231;
+
+/// Why an instruction fails.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Failure {
+    Seek,
+    Access,
+    Overflow,
+}
+
+/// The routines shared by the instructions. See `Translator::routines`.
+struct Routines {
+    exit: Label,
+    fail: Label,
+    hang: Label,
+    flush: Label,
+    put_byte: Label,
+    put_bytes: Label,
+    write_char: Label,
+    write_int: Label,
+    read: Label,
+}
+
+/// Translate programs into static executables for Linux on x86-64, which need
+/// nothing but the kernel. The streams are read and written by the `read` and
+/// `write` system calls with buffers, and the cells are kept out of the file.
+///
+/// The programs fail as the interpreter does, but only report where and why they
+/// fail, without the values.
+pub struct ElfBackend {
+    memory: MemoryConfig,
+    stream: StreamConfig,
+}
+
+impl ElfBackend {
+    pub fn new(memory: MemoryConfig, stream: StreamConfig) -> Self {
+        Self { memory, stream }
+    }
+
+    pub fn generate(&self, list: &InstructionList) -> Result<Vec<u8>> {
+        check_stream(&self.stream)?;
+        let len = self.memory.range().len();
+        ensure_memory(i32::try_from(len).is_ok(), len)?;
+
+        let mut translator = Translator::new(&self.memory, &self.stream, list);
+        translator.translate();
+        let (code, bss) = translator.finish();
+        Ok(Self::link(&code, bss))
+    }
+
+    /// Put the code after the headers, with the memory of `bss` bytes after it.
+    fn link(code: &[u8], bss: usize) -> Vec<u8> {
+        let file = (HEADERS + code.len()) as u64;
+        let mut res = vec![];
+
+        // The ELF header.
+        res.extend_from_slice(b"\x7fELF");
+        res.extend_from_slice(&[2, 1, 1, 0]);
+        res.extend_from_slice(&[0; 8]);
+        res.extend_from_slice(&2u16.to_le_bytes());
+        res.extend_from_slice(&0x3eu16.to_le_bytes());
+        res.extend_from_slice(&1u32.to_le_bytes());
+        res.extend_from_slice(&(BASE + HEADERS as u64).to_le_bytes());
+        res.extend_from_slice(&64u64.to_le_bytes());
+        res.extend_from_slice(&0u64.to_le_bytes());
+        res.extend_from_slice(&0u32.to_le_bytes());
+
+        for size in [64u16, 56, 2, 64, 0, 0] {
+            res.extend_from_slice(&size.to_le_bytes());
+        }
+
+        // The code, which is readable and executable, and the memory, which is
+        // readable and writable.
+        let segments = [
+            (5u32, 0u64, BASE, file, file),
+            (6, 0, Self::bss(code.len()) as u64 + BASE, 0, bss as u64),
+        ];
+
+        for (flags, offset, addr, file_size, memory_size) in segments {
+            res.extend_from_slice(&1u32.to_le_bytes());
+            res.extend_from_slice(&flags.to_le_bytes());
+
+            for field in [offset, addr, addr, file_size, memory_size, PAGE as u64] {
+                res.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+
+        debug_assert_eq!(res.len(), HEADERS);
+        res.extend_from_slice(code);
+        res
+    }
+
+    /// Return where the memory starts from `BASE`, at the page after the code.
+    fn bss(code: usize) -> usize {
+        (HEADERS + code).div_ceil(PAGE) * PAGE
+    }
+}
+
+fn ensure_memory(fits: bool, len: usize) -> Result<()> {
+    if fits {
+        Ok(())
+    } else {
+        UnsupportedMemorySnafu { len }.fail()
+    }
+}
+
+struct Translator<'a> {
+    asm: Assembler,
+    memory: &'a MemoryConfig,
+    stream: &'a StreamConfig,
+    list: &'a InstructionList,
+    routines: Routines,
+    /// The labels of the memory out of the file.
+    output_len: Label,
+    output_buf: Label,
+    input_pos: Label,
+    input_buf: Label,
+    cells: Label,
+    /// The labels of the instructions jumped to.
+    targets: Vec<Option<Label>>,
+    /// The code reporting each failure, with the message it writes and the
+    /// length of the message.
+    failures: HashMap<(usize, Failure), Label>,
+    stubs: Vec<(Label, Label, usize)>,
+    /// The bytes after the code.
+    data: Vec<(Label, Vec<u8>)>,
+}
+
+impl<'a> Translator<'a> {
+    fn new(memory: &'a MemoryConfig, stream: &'a StreamConfig, list: &'a InstructionList) -> Self {
+        let mut asm = Assembler::new();
+        let routines = Routines {
+            exit: asm.new_label(),
+            fail: asm.new_label(),
+            hang: asm.new_label(),
+            flush: asm.new_label(),
+            put_byte: asm.new_label(),
+            put_bytes: asm.new_label(),
+            write_char: asm.new_label(),
+            write_int: asm.new_label(),
+            read: asm.new_label(),
+        };
+
+        Self {
+            output_len: asm.new_label(),
+            output_buf: asm.new_label(),
+            input_pos: asm.new_label(),
+            input_buf: asm.new_label(),
+            cells: asm.new_label(),
+            asm,
+            memory,
+            stream,
+            list,
+            routines,
+            targets: vec![],
+            failures: HashMap::new(),
+            stubs: vec![],
+            data: vec![],
+        }
+    }
+
+    fn translate(&mut self) {
+        self.targets = vec![None; self.list.len()];
+
+        for instruction in &self.list.instructions {
+            if let Instruction::Jump { target } | Instruction::JumpIfZero { target } = instruction {
+                self.targets[*target] = Some(self.asm.new_label());
+            }
+        }
+
+        self.asm.lea_label(TAPE, self.cells);
+        self.asm
+            .mov_imm(INDEX, -(self.memory.range().left as i64) as u64);
+
+        if let Some(message) = empty_error(self.list) {
+            let stub = self.stub(message);
+            self.asm.jmp(stub);
+        }
+
+        for (pc, instruction) in self.list.instructions.iter().enumerate() {
+            if let Some(label) = self.targets[pc] {
+                self.asm.bind(label);
+            }
+
+            self.instruction(instruction, pc);
+        }
+
+        self.asm.jmp(self.routines.exit);
+        self.routines();
+
+        for (label, bytes) in std::mem::take(&mut self.data) {
+            self.asm.bind(label);
+            self.asm.data(&bytes);
+        }
+    }
+
+    /// Return the code and the size of the memory out of the file.
+    fn finish(mut self) -> (Vec<u8>, usize) {
+        let bss = ElfBackend::bss(self.asm.len()) - HEADERS;
+        let labels = [
+            (self.output_len, OUTPUT_LEN),
+            (self.output_buf, OUTPUT_BUF),
+            (self.input_pos, INPUT_POS),
+            (self.input_buf, INPUT_BUF),
+            (self.cells, CELLS),
+        ];
+
+        for (label, offset) in labels {
+            self.asm.bind_at(label, bss + offset);
+        }
+
+        (self.asm.finish(), CELLS + self.memory.range().len() * 4)
+    }
+
+    /// Return the code reporting the failure of the instruction at `pc`.
+    fn failure(&mut self, pc: usize, failure: Failure) -> Label {
+        if let Some(&label) = self.failures.get(&(pc, failure)) {
+            return label;
+        }
+
+        let range = self.memory.range();
+        let span = self.list.spans[pc];
+        let at = if span.is_empty() {
+            format!("instruction {pc}")
+        } else {
+            format!("instruction {pc} ({})", span.start)
+        };
+        let reason = match failure {
+            Failure::Seek => format!(
+                "try to seek pointer out of [{}, {}]",
+                range.left, range.right
+            ),
+            Failure::Access => format!(
+                "try to access a cell out of [{}, {}]",
+                range.left, range.right
+            ),
+            Failure::Overflow => "a cell will overflow".to_string(),
+        };
+        let message = format!("error: invalid memory operation occurred at {at}: {reason}\n");
+        let stub = self.stub(message);
+        self.failures.insert((pc, failure), stub);
+        stub
+    }
+
+    /// Return the code writing `message` to the standard error, which exits with 1.
+    fn stub(&mut self, message: String) -> Label {
+        let stub = self.asm.new_label();
+        let label = self.asm.new_label();
+        self.stubs.push((stub, label, message.len()));
+        self.data.push((label, message.into_bytes()));
+        stub
+    }
+
+    fn current() -> Mem {
+        Mem {
+            base: TAPE,
+            index: Some(INDEX),
+            disp: 0,
+        }
+    }
+
+    /// Return the cell at `offset` after checking that it's in the memory, whose
+    /// index is computed into `rdi` unless `offset` is 0.
+    fn cell(&mut self, offset: isize, pc: usize) -> Mem {
+        if offset == 0 {
+            return Self::current();
+        }
+
+        let fail = self.failure(pc, Failure::Access);
+
+        match i32::try_from(offset) {
+            Ok(disp) => {
+                let index = Mem {
+                    base: INDEX,
+                    index: None,
+                    disp,
+                };
+                self.asm.lea(Reg::Rdi, index);
+                self.asm.cmp_imm(Reg::Rdi, self.memory.range().len() as i32);
+                self.asm.jcc(Cond::AboveOrEqual, fail);
+            }
+            // The memory is smaller than the offset.
+            Err(_) => self.asm.jmp(fail),
+        }
+
+        Mem {
+            base: TAPE,
+            index: Some(Reg::Rdi),
+            disp: 0,
+        }
+    }
+
+    fn seek(&mut self, offset: isize, pc: usize) {
+        let fail = self.failure(pc, Failure::Seek);
+
+        match i32::try_from(offset) {
+            Ok(disp) => {
+                let index = Mem {
+                    base: INDEX,
+                    index: None,
+                    disp,
+                };
+                self.asm.lea(Reg::Rax, index);
+                self.asm.cmp_imm(Reg::Rax, self.memory.range().len() as i32);
+                self.asm.jcc(Cond::AboveOrEqual, fail);
+                self.asm.mov(INDEX, Reg::Rax);
+            }
+            Err(_) => self.asm.jmp(fail),
+        }
+    }
+
+    fn bounds(&self) -> (i64, i64) {
+        match self.memory.cell {
+            Cell::I8 => (i8::MIN as i64, i8::MAX as i64),
+            Cell::I32 => (i32::MIN as i64, i32::MAX as i64),
+        }
+    }
+
+    /// Return the value stored when `val` is set, or `None` if it overflows.
+    fn checked_set(&self, val: i32) -> Option<i32> {
+        let (min, max) = self.bounds();
+
+        if (min..=max).contains(&(val as i64)) {
+            Some(val)
+        } else {
+            match self.memory.overflow {
+                Overflow::Error => None,
+                Overflow::Wrap => Some(val as i8 as i32),
+            }
+        }
+    }
+
+    /// Store `eax` into `cell` as it's set, which may be out of the range of the
+    /// cells.
+    fn store(&mut self, cell: Mem, pc: usize) {
+        if let Cell::I8 = self.memory.cell {
+            self.asm.movsx32_byte(Reg::Rcx, Reg::Rax);
+
+            if let Overflow::Error = self.memory.overflow {
+                let fail = self.failure(pc, Failure::Overflow);
+                self.asm.cmp32(Reg::Rcx, Reg::Rax);
+                self.asm.jcc(Cond::NotEqual, fail);
+            }
+
+            self.asm.mov32_store(cell, Reg::Rcx);
+        } else {
+            self.asm.mov32_store(cell, Reg::Rax);
+        }
+    }
+
+    /// Write `eax` to the output stream.
+    fn write(&mut self) {
+        match self.stream.output {
+            Output::CharStandard => self.asm.call_label(self.routines.write_char),
+            Output::IntStandard => self.asm.call_label(self.routines.write_int),
+            _ => {}
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, pc: usize) {
+        match instruction {
+            Instruction::Add { offset, val } => {
+                let cell = self.cell(*offset, pc);
+                self.add(cell, *val, pc);
+            }
+            Instruction::Seek { offset } => self.seek(*offset, pc),
+            Instruction::Clear => self.asm.mov32_store_imm(Self::current(), 0),
+            Instruction::AddUntilZero { target, step } => self.add_until_zero(target, *step, pc),
+            Instruction::Scan { stride } => {
+                let start = self.asm.new_label();
+                let end = self.asm.new_label();
+                self.asm.bind(start);
+                self.asm.cmp32_mem_imm(Self::current(), 0);
+                self.asm.jcc(Cond::Equal, end);
+                self.seek(*stride, pc);
+                self.asm.jmp(start);
+                self.asm.bind(end);
+            }
+            Instruction::Set { offset, val } => {
+                let cell = self.cell(*offset, pc);
+
+                match self.checked_set(*val) {
+                    Some(val) => self.asm.mov32_store_imm(cell, val),
+                    None => {
+                        let fail = self.failure(pc, Failure::Overflow);
+                        self.asm.jmp(fail);
+                    }
+                }
+            }
+            // The input is read before the cell is checked.
+            Instruction::Input { offset } => {
+                match self.stream.input {
+                    Input::Standard => self.asm.call_label(self.routines.read),
+                    _ => self.asm.mov_imm(Reg::Rax, u64::MAX),
+                }
+
+                let cell = self.cell(*offset, pc);
+                let end = self.asm.new_label();
+
+                match self.memory.eof {
+                    Eof::Zero => {
+                        let read = self.asm.new_label();
+                        self.asm.cmp_imm(Reg::Rax, -1);
+                        self.asm.jcc(Cond::NotEqual, read);
+                        self.asm.xor32(Reg::Rax, Reg::Rax);
+                        self.asm.bind(read);
+                    }
+                    Eof::Ignore => {
+                        self.asm.cmp_imm(Reg::Rax, -1);
+                        self.asm.jcc(Cond::Equal, end);
+                    }
+                    Eof::Keep => {}
+                }
+
+                self.store(cell, pc);
+                self.asm.bind(end);
+            }
+            Instruction::Output { offset } => {
+                let cell = self.cell(*offset, pc);
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.write();
+            }
+            Instruction::OutputBytes { bytes } => {
+                if let Some(text) = text(&self.stream.output, bytes) {
+                    let label = self.asm.new_label();
+                    self.data.push((label, text.clone()));
+                    self.asm.lea_label(Reg::Rsi, label);
+                    self.asm.mov32_imm(Reg::Rdx, text.len() as u32);
+                    self.asm.call_label(self.routines.put_bytes);
+                }
+            }
+            Instruction::OutputRepeat { offset, count } => {
+                let cell = self.cell(*offset, pc);
+                let start = self.asm.new_label();
+                let end = self.asm.new_label();
+                self.asm.mov32_load(Reg::R14, cell);
+                self.asm.mov_imm(Reg::Rbx, *count as u64);
+                self.asm.bind(start);
+                self.asm.test(Reg::Rbx, Reg::Rbx);
+                self.asm.jcc(Cond::Equal, end);
+                self.asm.mov32(Reg::Rax, Reg::R14);
+                self.write();
+                self.asm.sub_imm(Reg::Rbx, 1);
+                self.asm.jmp(start);
+                self.asm.bind(end);
+            }
+            // The items before a cell out of the memory are written.
+            Instruction::OutputSeq { items } => {
+                for item in items {
+                    match item {
+                        OutputItem::Cell { offset } => {
+                            let cell = self.cell(*offset, pc);
+                            self.asm.mov32_load(Reg::Rax, cell);
+                        }
+                        OutputItem::Byte { val } => self.asm.mov32_imm(Reg::Rax, *val as u32),
+                    }
+
+                    self.write();
+                }
+            }
+            Instruction::JumpIfZero { target } => {
+                self.asm.cmp32_mem_imm(Self::current(), 0);
+                self.asm.jcc(Cond::Equal, self.targets[*target].unwrap());
+            }
+            Instruction::Jump { target } => self.asm.jmp(self.targets[*target].unwrap()),
+            Instruction::Halt => self.asm.jmp(self.routines.exit),
+        }
+    }
+
+    /// Add `val` to the cell at `cell`, failing if it overflows.
+    fn add(&mut self, cell: Mem, val: i32, pc: usize) {
+        match (&self.memory.overflow, &self.memory.cell) {
+            (Overflow::Wrap, Cell::I32) => self.asm.add32_store_imm(cell, val),
+            (Overflow::Wrap, Cell::I8) => {
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.asm.add32_imm(Reg::Rax, val);
+                self.asm.movsx32_byte(Reg::Rax, Reg::Rax);
+                self.asm.mov32_store(cell, Reg::Rax);
+            }
+            (Overflow::Error, Cell::I32) => {
+                let fail = self.failure(pc, Failure::Overflow);
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.asm.add32_imm(Reg::Rax, val);
+                self.asm.jcc(Cond::Overflow, fail);
+                self.asm.mov32_store(cell, Reg::Rax);
+            }
+            // Adding more than 255 always overflows, and the sum of the others
+            // fits in 32 bits.
+            (Overflow::Error, Cell::I8) if !(-255..=255).contains(&val) => {
+                let fail = self.failure(pc, Failure::Overflow);
+                self.asm.jmp(fail);
+            }
+            (Overflow::Error, Cell::I8) => {
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.asm.add32_imm(Reg::Rax, val);
+                self.store(cell, pc);
+            }
+        }
+    }
+
+    /// The targets are changed one by one like the interpreter, which doesn't
+    /// matter since the program exits when one fails.
+    fn add_until_zero(&mut self, target: &[AddUntilZeroArg], step: i32, pc: usize) {
+        let end = self.asm.new_label();
+        self.asm.movsxd_load(Reg::Rax, Self::current());
+        self.asm.test(Reg::Rax, Reg::Rax);
+        self.asm.jcc(Cond::Equal, end);
+
+        // Count into `rcx`.
+        match self.memory.overflow {
+            Overflow::Wrap => {
+                if let Some(mask) = hang_mask(self.memory, step) {
+                    let reaches = self.asm.new_label();
+                    self.asm.mov32(Reg::Rcx, Reg::Rax);
+                    self.asm.and32_imm(Reg::Rcx, mask as i32);
+                    self.asm.jcc(Cond::Equal, reaches);
+
+                    for arg in target {
+                        self.cell(arg.offset, pc);
+                    }
+
+                    self.asm.jmp(self.routines.hang);
+                    self.asm.bind(reaches);
+                }
+
+                let bits = match self.memory.cell {
+                    Cell::I8 => 8,
+                    Cell::I32 => 32,
+                };
+                // It's never reached if the step is a multiple of the modulus,
+                // since the counter isn't zero.
+                let (shift, inverse) = split_step(step, bits).unwrap_or((0, 0));
+
+                self.asm.neg32(Reg::Rax);
+
+                if shift > 0 {
+                    self.asm.shr32_imm(Reg::Rax, shift as u8);
+                }
+
+                self.asm
+                    .imul32_imm(Reg::Rax, Reg::Rax, inverse as u32 as i32);
+
+                match (&self.memory.cell, shift) {
+                    (Cell::I8, 0) => self.asm.movzx32_byte(Reg::Rcx, Reg::Rax),
+                    (Cell::I32, 0) => self.asm.mov32(Reg::Rcx, Reg::Rax),
+                    _ => {
+                        let mask = u32::MAX >> (32 - bits + shift);
+                        self.asm.and32_imm(Reg::Rax, mask as i32);
+                        self.asm.mov32(Reg::Rcx, Reg::Rax);
+                    }
+                }
+            }
+            // The counter reaches zero only if the cell and the step have the
+            // opposite signs, and the step divides the cell.
+            Overflow::Error => {
+                let fail = self.failure(pc, Failure::Overflow);
+                let sign = if step > 0 { Cond::NotSign } else { Cond::Sign };
+                self.asm.jcc(sign, fail);
+
+                match step {
+                    -1 => {}
+                    1 => self.asm.neg(Reg::Rax),
+                    _ => {
+                        self.asm
+                            .mov_imm(Reg::Rcx, (step as i64).wrapping_neg() as u64);
+                        self.asm.cqo();
+                        self.asm.idiv(Reg::Rcx);
+                        self.asm.test(Reg::Rdx, Reg::Rdx);
+                        self.asm.jcc(Cond::NotEqual, fail);
+                    }
+                }
+
+                self.asm.mov(Reg::Rcx, Reg::Rax);
+            }
+        }
+
+        self.asm.mov32_store_imm(Self::current(), 0);
+        let (min, max) = self.bounds();
+
+        for arg in target {
+            let cell = self.cell(arg.offset, pc);
+
+            if arg.clear {
+                match self.checked_set(arg.times) {
+                    Some(val) => self.asm.mov32_store_imm(cell, val),
+                    None => {
+                        let fail = self.failure(pc, Failure::Overflow);
+                        self.asm.jmp(fail);
+                    }
+                }
+
+                continue;
+            }
+
+            match self.memory.overflow {
+                Overflow::Wrap => {
+                    self.asm.imul32_imm(Reg::Rax, Reg::Rcx, arg.times);
+
+                    match self.memory.cell {
+                        Cell::I32 => self.asm.add32_store(cell, Reg::Rax),
+                        Cell::I8 => {
+                            self.asm.add32_load(Reg::Rax, cell);
+                            self.asm.movsx32_byte(Reg::Rax, Reg::Rax);
+                            self.asm.mov32_store(cell, Reg::Rax);
+                        }
+                    }
+                }
+                // The count is at most 2^31, so the sum fits in 64 bits.
+                Overflow::Error => {
+                    let fail = self.failure(pc, Failure::Overflow);
+                    self.asm.movsxd_load(Reg::Rax, cell);
+                    self.asm.imul_imm(Reg::Rdx, Reg::Rcx, arg.times);
+                    self.asm.add(Reg::Rax, Reg::Rdx);
+                    self.asm.cmp_imm(Reg::Rax, min as i32);
+                    self.asm.jcc(Cond::Less, fail);
+                    self.asm.cmp_imm(Reg::Rax, max as i32);
+                    self.asm.jcc(Cond::Greater, fail);
+                    self.asm.mov32_store(cell, Reg::Rax);
+                }
+            }
+        }
+
+        self.asm.bind(end);
+    }
+
+    /// Emit the routines and the code reporting the failures. The routines keep
+    /// `rbx` and `r12` to `r15`.
+    fn routines(&mut self) {
+        let Routines {
+            exit,
+            fail,
+            hang,
+            flush,
+            put_byte,
+            put_bytes,
+            write_char,
+            write_int,
+            read,
+        } = self.routines;
+        let at = |base| Mem {
+            base,
+            index: None,
+            disp: 0,
+        };
+
+        for (stub, message, len) in std::mem::take(&mut self.stubs) {
+            self.asm.bind(stub);
+            self.asm.lea_label(Reg::Rsi, message);
+            self.asm.mov32_imm(Reg::Rdx, len as u32);
+            self.asm.jmp(fail);
+        }
+
+        // Exit with 0 after flushing the output.
+        self.asm.bind(exit);
+        self.asm.call_label(flush);
+        self.asm.xor32(Reg::Rdi, Reg::Rdi);
+        self.asm.mov32_imm(Reg::Rax, SYS_EXIT_GROUP);
+        self.asm.syscall();
+
+        // Run forever after flushing the output, like a loop which never
+        // terminates.
+        let spin = self.asm.new_label();
+        self.asm.bind(hang);
+        self.asm.call_label(flush);
+        self.asm.bind(spin);
+        self.asm.jmp(spin);
+
+        // Write `rdx` bytes at `rsi` to the standard error, and exit with 1.
+        self.asm.bind(fail);
+        self.asm.push(Reg::Rsi);
+        self.asm.push(Reg::Rdx);
+        self.asm.call_label(flush);
+        self.asm.pop(Reg::Rdx);
+        self.asm.pop(Reg::Rsi);
+        self.asm.mov32_imm(Reg::Rdi, 2);
+        self.asm.mov32_imm(Reg::Rax, SYS_WRITE);
+        self.asm.syscall();
+        self.asm.mov32_imm(Reg::Rdi, 1);
+        self.asm.mov32_imm(Reg::Rax, SYS_EXIT_GROUP);
+        self.asm.syscall();
+
+        // Write the output buffer to the standard output.
+        let (start, done) = (self.asm.new_label(), self.asm.new_label());
+        self.asm.bind(flush);
+        self.asm.lea_label(Reg::Rsi, self.output_buf);
+        self.asm.lea_label(Reg::Rdi, self.output_len);
+        self.asm.mov_load(Reg::Rdx, at(Reg::Rdi));
+        self.asm.bind(start);
+        self.asm.test(Reg::Rdx, Reg::Rdx);
+        self.asm.jcc(Cond::Equal, done);
+        self.asm.mov32_imm(Reg::Rdi, 1);
+        self.asm.mov32_imm(Reg::Rax, SYS_WRITE);
+        self.asm.syscall();
+        self.asm.test(Reg::Rax, Reg::Rax);
+        self.asm.jcc(Cond::LessOrEqual, done);
+        self.asm.add(Reg::Rsi, Reg::Rax);
+        self.asm.sub(Reg::Rdx, Reg::Rax);
+        self.asm.jmp(start);
+        self.asm.bind(done);
+        self.asm.lea_label(Reg::Rdi, self.output_len);
+        self.asm.xor32(Reg::Rcx, Reg::Rcx);
+        self.asm.mov_store(at(Reg::Rdi), Reg::Rcx);
+        self.asm.ret();
+
+        // Put the byte in `al` into the output buffer, which keeps `rsi` and
+        // `rdx`.
+        let store = self.asm.new_label();
+        self.asm.bind(put_byte);
+        self.asm.lea_label(Reg::Rdi, self.output_len);
+        self.asm.mov_load(Reg::Rcx, at(Reg::Rdi));
+        self.asm.cmp_imm(Reg::Rcx, BUF_SIZE as i32);
+        self.asm.jcc(Cond::NotEqual, store);
+
+        for reg in [Reg::Rax, Reg::Rsi, Reg::Rdx] {
+            self.asm.push(reg);
+        }
+
+        self.asm.call_label(flush);
+
+        for reg in [Reg::Rdx, Reg::Rsi, Reg::Rax] {
+            self.asm.pop(reg);
+        }
+
+        self.asm.lea_label(Reg::Rdi, self.output_len);
+        self.asm.xor32(Reg::Rcx, Reg::Rcx);
+        self.asm.bind(store);
+        self.asm.add_imm(Reg::Rcx, 1);
+        self.asm.mov_store(at(Reg::Rdi), Reg::Rcx);
+        self.asm.add(Reg::Rdi, Reg::Rcx);
+        // `output_buf` is right after `output_len`.
+        self.asm.mov8_store(
+            Mem {
+                base: Reg::Rdi,
+                index: None,
+                disp: (OUTPUT_BUF - OUTPUT_LEN - 1) as i32,
+            },
+            Reg::Rax,
+        );
+        self.asm.ret();
+
+        // Put `rdx` bytes at `rsi` into the output buffer.
+        let (start, done) = (self.asm.new_label(), self.asm.new_label());
+        self.asm.bind(put_bytes);
+        self.asm.bind(start);
+        self.asm.test(Reg::Rdx, Reg::Rdx);
+        self.asm.jcc(Cond::Equal, done);
+        self.asm.movzx32_load_byte(Reg::Rax, at(Reg::Rsi));
+        self.asm.call_label(put_byte);
+        self.asm.add_imm(Reg::Rsi, 1);
+        self.asm.sub_imm(Reg::Rdx, 1);
+        self.asm.jmp(start);
+        self.asm.bind(done);
+        self.asm.ret();
+
+        self.write_char(write_char);
+        self.write_int(write_int);
+
+        // Read a byte into `rax`, or -1 at the end of the input.
+        let (take, eof) = (self.asm.new_label(), self.asm.new_label());
+        let input_len = Mem {
+            base: Reg::Rdi,
+            index: None,
+            disp: 8,
+        };
+        self.asm.bind(read);
+        self.asm.lea_label(Reg::Rdi, self.input_pos);
+        self.asm.mov_load(Reg::Rcx, at(Reg::Rdi));
+        self.asm.mov_load(Reg::Rdx, input_len);
+        self.asm.cmp(Reg::Rcx, Reg::Rdx);
+        self.asm.jcc(Cond::NotEqual, take);
+        // Show what's written before waiting for the input.
+        self.asm.call_label(flush);
+        self.asm.lea_label(Reg::Rsi, self.input_buf);
+        self.asm.mov32_imm(Reg::Rdx, BUF_SIZE as u32);
+        self.asm.xor32(Reg::Rdi, Reg::Rdi);
+        self.asm.mov32_imm(Reg::Rax, SYS_READ);
+        self.asm.syscall();
+        self.asm.test(Reg::Rax, Reg::Rax);
+        self.asm.jcc(Cond::LessOrEqual, eof);
+        self.asm.lea_label(Reg::Rdi, self.input_pos);
+        self.asm.mov_store(input_len, Reg::Rax);
+        self.asm.xor32(Reg::Rcx, Reg::Rcx);
+        self.asm.bind(take);
+        self.asm.lea_label(Reg::Rsi, self.input_buf);
+        self.asm.add(Reg::Rsi, Reg::Rcx);
+        self.asm.movzx32_load_byte(Reg::Rax, at(Reg::Rsi));
+        self.asm.add_imm(Reg::Rcx, 1);
+        self.asm.mov_store(at(Reg::Rdi), Reg::Rcx);
+        self.asm.ret();
+        self.asm.bind(eof);
+        self.asm.mov_imm(Reg::Rax, u64::MAX);
+        self.asm.ret();
+    }
+
+    /// Write `eax` as a character encoded in UTF-8, or U+FFFD if it isn't one.
+    fn write_char(&mut self, label: Label) {
+        let put_byte = self.routines.put_byte;
+        let [one, two, three, invalid] = [(); 4].map(|_| self.asm.new_label());
+        self.asm.bind(label);
+        self.asm.mov32(Reg::Rdx, Reg::Rax);
+        self.asm.cmp32_imm(Reg::Rdx, char::MAX as i32);
+        self.asm.jcc(Cond::Above, invalid);
+        // The surrogates from U+D800 to U+DFFF.
+        self.asm.mov32(Reg::Rcx, Reg::Rdx);
+        self.asm.and32_imm(Reg::Rcx, !0x7ff);
+        self.asm.cmp32_imm(Reg::Rcx, 0xd800);
+        self.asm.jcc(Cond::Equal, invalid);
+
+        for (bound, label) in [(0x80, one), (0x800, two), (0x10000, three)] {
+            self.asm.cmp32_imm(Reg::Rdx, bound);
+            self.asm.jcc(Cond::Below, label);
+        }
+
+        // Write the leading byte with `mark` and the continuation bytes.
+        let encode = |asm: &mut Assembler, mark: i32, len: u8| {
+            for i in (0..len).rev() {
+                asm.mov32(Reg::Rax, Reg::Rdx);
+
+                if i > 0 {
+                    asm.shr32_imm(Reg::Rax, 6 * i);
+                }
+
+                if i == len - 1 {
+                    asm.or32_imm(Reg::Rax, mark);
+                } else {
+                    asm.and32_imm(Reg::Rax, 0x3f);
+                    asm.or32_imm(Reg::Rax, 0x80);
+                }
+
+                asm.call_label(put_byte);
+            }
+
+            asm.ret();
+        };
+
+        encode(&mut self.asm, 0xf0, 4);
+        self.asm.bind(three);
+        encode(&mut self.asm, 0xe0, 3);
+        self.asm.bind(two);
+        encode(&mut self.asm, 0xc0, 2);
+        self.asm.bind(one);
+        encode(&mut self.asm, 0, 1);
+        self.asm.bind(invalid);
+        self.asm
+            .mov32_imm(Reg::Rdx, char::REPLACEMENT_CHARACTER as u32);
+        self.asm.jmp(three);
+    }
+
+    /// Write `eax` in decimal with a space after it.
+    fn write_int(&mut self, label: Label) {
+        let put_byte = self.routines.put_byte;
+        let [positive, digit, print] = [(); 3].map(|_| self.asm.new_label());
+        self.asm.bind(label);
+        self.asm.movsxd(Reg::Rdx, Reg::Rax);
+        self.asm.test(Reg::Rdx, Reg::Rdx);
+        self.asm.jcc(Cond::NotSign, positive);
+        self.asm.mov32_imm(Reg::Rax, b'-' as u32);
+        self.asm.call_label(put_byte);
+        self.asm.neg(Reg::Rdx);
+        self.asm.bind(positive);
+
+        // Push the digits from the lowest one, and count them in `rsi`.
+        self.asm.mov(Reg::Rax, Reg::Rdx);
+        self.asm.xor32(Reg::Rsi, Reg::Rsi);
+        self.asm.mov32_imm(Reg::R8, 10);
+        self.asm.bind(digit);
+        self.asm.xor32(Reg::Rdx, Reg::Rdx);
+        self.asm.div(Reg::R8);
+        self.asm.add_imm(Reg::Rdx, b'0' as i32);
+        self.asm.push(Reg::Rdx);
+        self.asm.add_imm(Reg::Rsi, 1);
+        self.asm.test(Reg::Rax, Reg::Rax);
+        self.asm.jcc(Cond::NotEqual, digit);
+
+        self.asm.bind(print);
+        self.asm.pop(Reg::Rax);
+        self.asm.call_label(put_byte);
+        self.asm.sub_imm(Reg::Rsi, 1);
+        self.asm.jcc(Cond::NotEqual, print);
+        self.asm.mov32_imm(Reg::Rax, b' ' as u32);
+        self.asm.call_label(put_byte);
+        self.asm.ret();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::compiler::codegen::CodegenError;
+    use crate::compiler::Compiler;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bf-codegen-elf-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    mod native {
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::Path;
+        use std::process::Command;
+
+        use super::*;
+        use crate::compiler::codegen::tests::{compile, examples, interpret, run, strategies};
+
+        /// Build an executable with the memory and the streams.
+        fn build(path: &Path, list: &InstructionList, memory: MemoryConfig, output: Output) {
+            let stream = StreamConfig {
+                input: Input::Standard,
+                output,
+            };
+            let binary = ElfBackend::new(memory, stream).generate(list).unwrap();
+            std::fs::write(path, binary).unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        /// Check that the executable does the same as the interpreter, where only
+        /// the locations of the errors are compared.
+        fn check(dir: &Path, programs: Vec<(&str, MemoryConfig, Vec<u8>)>) {
+            let location = |res: std::result::Result<Vec<i32>, String>| {
+                res.map_err(|e| match e.split_once("): ") {
+                    Some((location, _)) => location.to_string(),
+                    None => e,
+                })
+            };
+
+            for (i, (code, memory, input)) in programs.into_iter().enumerate() {
+                let list = compile(code, &memory);
+                let binary = dir.join(format!("program{i}"));
+                build(&binary, &list, memory.clone(), Output::IntStandard);
+                assert_eq!(
+                    location(run(&binary, &[], &input)),
+                    location(interpret(code, memory, &input)),
+                    "{code}"
+                );
+            }
+        }
+
+        #[test]
+        fn examples_run_the_same() {
+            check(&dir("examples"), examples());
+        }
+
+        #[test]
+        fn strategies_run_the_same() {
+            let programs = strategies()
+                .into_iter()
+                .map(|(code, memory, input)| (code, memory, input.as_bytes().to_vec()))
+                .collect();
+            check(&dir("strategies"), programs);
+        }
+
+        #[test]
+        fn empty_program() {
+            check(&dir("empty"), vec![("---+++", Default::default(), vec![])]);
+        }
+
+        #[test]
+        fn characters() {
+            let vals = [b'a' as i32, 0xe9, 0x4e2d, 0x1f600, 0xd800, 0x110000, -1];
+            let mut instructions = vec![];
+
+            for val in vals {
+                instructions.push(Instruction::Set { offset: 0, val });
+                instructions.push(Instruction::Output { offset: 0 });
+            }
+
+            instructions.push(Instruction::Halt);
+            let list = InstructionList::new(instructions);
+            let memory = MemoryConfig {
+                cell: Cell::I32,
+                ..Default::default()
+            };
+            let binary = dir("characters").join("program");
+            build(&binary, &list, memory, Output::CharStandard);
+
+            let output = Command::new(&binary).output().unwrap();
+            assert!(output.status.success());
+            assert_eq!(output.stdout, "aé中😀\u{fffd}\u{fffd}\u{fffd}".as_bytes());
+        }
+
+        #[test]
+        fn large_output() {
+            let list = InstructionList::new(vec![
+                Instruction::Set { offset: 0, val: 65 },
+                Instruction::OutputRepeat {
+                    offset: 0,
+                    count: 10000,
+                },
+                Instruction::OutputBytes {
+                    bytes: vec![66; 5000],
+                },
+                Instruction::Halt,
+            ]);
+            let binary = dir("large").join("program");
+            build(&binary, &list, Default::default(), Output::CharStandard);
+
+            let output = Command::new(&binary).output().unwrap();
+            assert!(output.status.success());
+            assert_eq!(
+                output.stdout,
+                ["A".repeat(10000), "B".repeat(5000)].concat().as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let list = Compiler::new().compile("+.").unwrap();
+        let binary = ElfBackend::new(Default::default(), Default::default())
+            .generate(&list)
+            .unwrap();
+        let u64_at = |pos: usize| u64::from_le_bytes(binary[pos..pos + 8].try_into().unwrap());
+
+        assert_eq!(&binary[..4], b"\x7fELF");
+        assert_eq!(u64_at(24), BASE + HEADERS as u64);
+
+        // The code is loaded from the start of the file, and the memory is at
+        // the page after it.
+        let code = 64;
+        let memory = 64 + 56;
+        assert_eq!(u64_at(code + 16), BASE);
+        assert_eq!(u64_at(code + 32), binary.len() as u64);
+        assert_eq!(u64_at(memory + 16), BASE + PAGE as u64);
+        assert_eq!(u64_at(memory + 32), 0);
+        assert_eq!(u64_at(memory + 40), (CELLS + 32768 * 4) as u64);
+    }
+
+    #[test]
+    fn unsupported() {
+        let list = Compiler::new().compile("+").unwrap();
+        let stream = StreamConfig {
+            input: Input::Vec(Default::default()),
+            output: Output::Null,
+        };
+        assert_eq!(
+            ElfBackend::new(Default::default(), stream)
+                .generate(&list)
+                .map(|_| ()),
+            Err(CodegenError::UnsupportedStream)
+        );
+
+        let memory = MemoryConfig {
+            len: 1 << 31,
+            ..Default::default()
+        };
+        assert_eq!(
+            ElfBackend::new(memory, Default::default())
+                .generate(&list)
+                .map(|_| ()),
+            Err(CodegenError::UnsupportedMemory { len: 1 << 31 })
+        );
+    }
+}
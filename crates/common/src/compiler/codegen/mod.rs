@@ -0,0 +1,279 @@
+mod c;
+mod elf;
+mod rust;
+mod wasm;
+
+pub use c::CBackend;
+pub use elf::ElfBackend;
+pub use rust::RustBackend;
+pub use wasm::WasmBackend;
+
+use snafu::prelude::*;
+
+use crate::compiler::instruction::InstructionList;
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Overflow};
+use crate::execution::memory::strategy::split_step;
+use crate::execution::processor::ProcessorError;
+use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+
+pub type Result<T> = std::result::Result<T, CodegenError>;
+
+#[derive(Snafu, Debug, PartialEq, Eq)]
+pub enum CodegenError {
+    #[snafu(display("a program reading or writing a `Vec` stream can't be translated"))]
+    UnsupportedStream,
+    #[snafu(display("the jump at {addr} doesn't form a loop with another one"))]
+    UnpairedJump { addr: usize },
+    #[snafu(display("the memory of {len} cells is too large to translate"))]
+    UnsupportedMemory { len: usize },
+}
+
+/// Check that the streams exist out of the interpreter.
+fn check_stream(stream: &StreamConfig) -> Result<()> {
+    if matches!(stream.input, Input::Vec(_)) || matches!(stream.output, Output::Vec(_)) {
+        UnsupportedStreamSnafu.fail()
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that the program can be translated to code with structured loops.
+fn check_loops(list: &InstructionList) -> Result<()> {
+    match list.unpaired_jumps().first() {
+        Some(&addr) => UnpairedJumpSnafu { addr }.fail(),
+        None => Ok(()),
+    }
+}
+
+/// Return what the translated program reports before it exits with 1 if it has
+/// nothing but the halt, which the interpreter refuses to run, or `None` if it
+/// has more.
+fn empty_error(list: &InstructionList) -> Option<String> {
+    (list.len() == 1).then(|| format!("error: {}\n", ProcessorError::Empty))
+}
+
+/// Return the mask of the lowest bits of the counter of `AddUntilZero` which must
+/// be zero for it to reach zero, or `None` if it always does. Otherwise the
+/// program runs forever like the loop, after checking the targets.
+fn hang_mask(memory: &MemoryConfig, step: i32) -> Option<u32> {
+    if let Overflow::Error = memory.overflow {
+        return None;
+    }
+
+    let bits = match memory.cell {
+        Cell::I8 => 8,
+        Cell::I32 => 32,
+    };
+
+    match split_step(step, bits) {
+        Some((0, _)) => None,
+        Some((shift, _)) => Some((1 << shift) - 1),
+        None => Some(u32::MAX >> (32 - bits)),
+    }
+}
+
+/// Return what the output stream writes for `bytes`, or `None` if it writes
+/// nothing.
+fn text(output: &Output, bytes: &[i32]) -> Option<Vec<u8>> {
+    match output {
+        Output::CharStandard => Some(
+            bytes
+                .iter()
+                .map(|&byte| char::from_u32(byte as u32).unwrap_or('�'))
+                .collect::<String>()
+                .into_bytes(),
+        ),
+        Output::IntStandard => Some(
+            bytes
+                .iter()
+                .map(|byte| format!("{byte} "))
+                .collect::<String>()
+                .into_bytes(),
+        ),
+        _ => None,
+    }
+}
+
+/// Helpers to run the generated programs and compare them with the interpreter.
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+    use std::rc::Rc;
+
+    use crate::compiler::{Compiler, Config, InstructionList, OptLevel};
+    use crate::execution::context::Context;
+    use crate::execution::memory::config::{Addr, Cell, Config as MemoryConfig, Eof, Overflow};
+    use crate::execution::processor::{Processor, ProcessorError};
+    use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+
+    /// The bundled examples with the memory and the input to run them.
+    pub(super) fn examples() -> Vec<(&'static str, MemoryConfig, Vec<u8>)> {
+        let wrap = MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+        let hello = include_str!("../../../../../examples/helloworld.bf");
+        let mut res: Vec<_> = [
+            hello,
+            include_str!("../../../../../examples/squares.bf"),
+            include_str!("../../../../../examples/hanoi.bf"),
+        ]
+        .into_iter()
+        .map(|code| (code, wrap.clone(), vec![]))
+        .collect();
+
+        res.push((
+            include_str!("../../../../../examples/self-interpreter.bf"),
+            MemoryConfig {
+                eof: Eof::Zero,
+                ..wrap
+            },
+            format!("{hello}!").into_bytes(),
+        ));
+        res
+    }
+
+    /// Small programs covering every strategy of the memory and every error.
+    pub(super) fn strategies() -> Vec<(&'static str, MemoryConfig, &'static str)> {
+        let wrap = MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+        let signed = MemoryConfig {
+            len: 10,
+            addr: Addr::Signed,
+            cell: Cell::I32,
+            ..Default::default()
+        };
+
+        vec![
+            (",[->+++<]>.-.", wrap.clone(), "a"),
+            (",[->++<]>.", Default::default(), "a"),
+            (",[->---<]>.", wrap.clone(), "b"),
+            (",[--->++<]>.", Default::default(), "c"),
+            (",[+++>+<]>.", Default::default(), "c"),
+            ("+[>-<+++]>.", wrap.clone(), ""),
+            ("+[>-<+++]>.", Default::default(), ""),
+            (">,<,>.<.,.", Default::default(), "\u{ff}"),
+            (",.,.,.", wrap.clone(), "\u{e9}"),
+            (
+                ",.,.",
+                MemoryConfig {
+                    eof: Eof::Keep,
+                    ..wrap.clone()
+                },
+                "a",
+            ),
+            (
+                ",.,.",
+                MemoryConfig {
+                    eof: Eof::Zero,
+                    ..wrap.clone()
+                },
+                "a",
+            ),
+            ("<<<<<+[<].", signed.clone(), ""),
+            ("+[>+].", signed.clone(), ""),
+            ("+>>>>>[-]<<<<<[->>>>>>+<<<<<<].", signed.clone(), ""),
+            ("-<<<<<.", signed, ""),
+            ("<.", Default::default(), ""),
+            ("+++.>.<<.", Default::default(), ""),
+            (",[-->+<]>.", wrap.clone(), "d"),
+            (",[++++>+<]>.", wrap.clone(), "d"),
+            (",[--<+>]", wrap.clone(), "e"),
+            (",[-->+<]>.", Default::default(), "d"),
+            (",[-->+<]>.", Default::default(), "e"),
+            (
+                ",[-->+<]>.",
+                MemoryConfig {
+                    cell: Cell::I32,
+                    ..wrap
+                },
+                "d",
+            ),
+        ]
+    }
+
+    /// Compile the code for the memory without folding the constants, so that the
+    /// translated instructions are the ones running.
+    pub(super) fn compile(code: &str, memory: &MemoryConfig) -> InstructionList {
+        let config = Config {
+            target: Some(memory.clone()),
+            opt_level: OptLevel::O2,
+            ..Default::default()
+        };
+        Compiler::with_config(config).compile(code).unwrap()
+    }
+
+    /// Return a directory for the generated programs, or `None` if `compiler`
+    /// isn't found to build them.
+    pub(super) fn workspace(compiler: &str, name: &str) -> Option<PathBuf> {
+        if Command::new(compiler).arg("--version").output().is_err() {
+            eprintln!("skipped: `{compiler}` isn't found");
+            return None;
+        }
+
+        let dir = std::env::temp_dir().join(format!(
+            "bf-codegen-{compiler}-{}-{name}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        Some(dir)
+    }
+
+    /// Run a generated program writing integers, and return what it writes, or
+    /// what it reports when it fails.
+    pub(super) fn run(binary: &Path, args: &[String], input: &[u8]) -> Result<Vec<i32>, String> {
+        let mut child = Command::new(binary)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        if output.status.success() {
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            Ok(stdout
+                .split_whitespace()
+                .map(|s| s.parse().unwrap())
+                .collect())
+        } else {
+            Err(String::from_utf8(output.stderr).unwrap())
+        }
+    }
+
+    /// Run the code with the interpreter, and return the same as `run`.
+    pub(super) fn interpret(
+        code: &str,
+        memory: MemoryConfig,
+        input: &[u8],
+    ) -> Result<Vec<i32>, String> {
+        let list = compile(code, &memory);
+        let input = input.iter().map(|&byte| byte as i32).collect();
+        let output = Rc::new(RefCell::new(VecDeque::new()));
+        let stream = StreamConfig {
+            input: Input::Vec(Rc::new(RefCell::new(input))),
+            output: Output::Vec(output.clone()),
+        };
+        let mut context = Context::new(memory, stream);
+
+        match Processor::new(list).run(&mut context) {
+            Ok(()) => Ok(output.take().into()),
+            Err(ProcessorError::Memory {
+                source, pc, span, ..
+            }) => Err(format!(
+                "error: invalid memory operation occurred at instruction {pc} ({}): {source}\n",
+                span.start
+            )),
+            Err(e @ ProcessorError::Empty) => Err(format!("error: {e}\n")),
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::fmt::Write;
+
+use super::{check_loops, empty_error, hang_mask, Result};
+use crate::compiler::instruction::{Instruction, InstructionList};
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem};
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Eof, Overflow};
+
+/// The streams, the errors and the memory of the generated module, configured by
+/// the constants written before it.
+const RUNTIME: &str = include_str!("runtime.rs");
+
+/// Translate programs into self-contained Rust modules, whose `run` function
+/// takes the input and the output streams, like
+///
+/// ```text
+/// pub fn run<I: InStream + ?Sized, O: OutStream + ?Sized>(
+///     input: &mut I,
+///     output: &mut O,
+/// ) -> Result<(), Error>
+/// ```
+///
+/// The module implements the streams for `VecDeque<i32>`, `&[u8]` and
+/// `Vec<i32>`, and provides `Bytes`, `Chars` and `Ints` to adapt readers and
+/// writers. The program runs on the memory it's built for, and fails with the
+/// same errors as the interpreter. Each loop is a function of its own, which
+/// keeps large programs quick to compile.
+pub struct RustBackend {
+    memory: MemoryConfig,
+}
+
+impl RustBackend {
+    pub fn new(memory: MemoryConfig) -> Self {
+        Self { memory }
+    }
+
+    pub fn generate(&self, list: &InstructionList) -> Result<String> {
+        check_loops(list)?;
+
+        let mut res = String::new();
+        self.write_config(&mut res);
+        Self::write_locations(&mut res, list);
+        res.push_str(RUNTIME);
+
+        // The bodies of the functions being written, from `run` to the innermost
+        // loop, and the written functions of the loops.
+        let mut open = vec![Self::header("pub fn run", false)];
+        let mut loops = vec![];
+
+        for (pc, instruction) in list.instructions.iter().enumerate() {
+            match instruction {
+                Instruction::JumpIfZero { .. } => {
+                    let body = open.last_mut().unwrap();
+                    writeln!(body, "    loop_{pc}(memory, input, output)?;").unwrap();
+                    let mut header = Self::header(&format!("fn loop_{pc}"), true);
+                    header.push_str("    while memory.get() != 0 {\n");
+                    open.push(header);
+                }
+                Instruction::Jump { target } => {
+                    let mut body = open.pop().unwrap();
+                    body.push_str("    }\n    Ok(())\n}\n");
+                    loops.push((*target, body));
+                }
+                _ => {
+                    let indent = if open.len() == 1 { 4 } else { 8 };
+                    let body = open.last_mut().unwrap();
+
+                    for line in self.instruction(instruction, pc).lines() {
+                        writeln!(body, "{:1$}{line}", "", indent).unwrap();
+                    }
+                }
+            }
+        }
+
+        let mut run = open.pop().unwrap();
+
+        if empty_error(list).is_some() {
+            run.push_str("    Err(Error::Empty)\n}\n");
+        } else {
+            run.push_str("    Ok(())\n}\n");
+        }
+        res.push('\n');
+        res.push_str(&run);
+        loops.sort_unstable_by_key(|(pc, _)| *pc);
+
+        for (_, body) in loops {
+            res.push('\n');
+            res.push_str(&body);
+        }
+
+        Ok(res)
+    }
+
+    /// Return the start of a function running the program on the streams. The
+    /// function of a loop takes the memory, while `run` creates it.
+    fn header(name: &str, takes_memory: bool) -> String {
+        let mut res = format!("{name}<I: InStream + ?Sized, O: OutStream + ?Sized>(\n");
+
+        if takes_memory {
+            res.push_str("    memory: &mut Memory,\n");
+        }
+
+        res.push_str("    input: &mut I,\n    output: &mut O,\n) -> Result<(), Error> {\n");
+
+        if !takes_memory {
+            res.push_str("    let memory = &mut Memory::new();\n");
+        }
+
+        res
+    }
+
+    fn write_config(&self, res: &mut String) {
+        let range = self.memory.range();
+        let cell_bits = match self.memory.cell {
+            Cell::I8 => 8,
+            Cell::I32 => 32,
+        };
+        let wrap = matches!(self.memory.overflow, Overflow::Wrap);
+        let eof = match self.memory.eof {
+            Eof::Zero => "Zero",
+            Eof::Keep => "Keep",
+            Eof::Ignore => "Ignore",
+        };
+
+        writeln!(res, "//! Generated by bf-exec.").unwrap();
+        writeln!(res).unwrap();
+        writeln!(res, "#![allow(dead_code, unused_variables, clippy::all)]").unwrap();
+        writeln!(res).unwrap();
+        writeln!(res, "const LEFT: isize = {};", range.left).unwrap();
+        writeln!(res, "const RIGHT: isize = {};", range.right).unwrap();
+        writeln!(res, "const CELL_BITS: u32 = {cell_bits};").unwrap();
+        writeln!(res, "const WRAP: bool = {wrap};").unwrap();
+        writeln!(res, "const EOF_STRATEGY: Eof = Eof::{eof};").unwrap();
+    }
+
+    /// Write the line and the column of each instruction, or `(0, 0)` if it's
+    /// unknown.
+    fn write_locations(res: &mut String, list: &InstructionList) {
+        writeln!(res, "const LOCATIONS: &[(usize, usize)] = &[").unwrap();
+
+        for pc in 0..list.len() {
+            let span = list.span(pc);
+            writeln!(res, "    ({}, {}),", span.start.line, span.start.column).unwrap();
+        }
+
+        writeln!(res, "];\n").unwrap();
+    }
+
+    /// Return the statements of the instruction at `pc`.
+    fn instruction(&self, instruction: &Instruction, pc: usize) -> String {
+        match instruction {
+            Instruction::Add { offset, val } => format!("memory.add({offset}, {val}, {pc})?;"),
+            Instruction::Seek { offset } => format!("memory.seek({offset}, {pc})?;"),
+            Instruction::Clear => "memory.clear();".to_string(),
+            Instruction::AddUntilZero { target, step } => {
+                let mut res = "if memory.get() != 0 {\n".to_string();
+
+                if let Some(mask) = hang_mask(&self.memory, *step) {
+                    writeln!(res, "    if memory.get() as u32 & {mask} != 0 {{").unwrap();
+
+                    for arg in target {
+                        writeln!(res, "        memory.get_at({}, {pc})?;", arg.offset).unwrap();
+                    }
+
+                    res.push_str("        hang();\n    }\n");
+                }
+
+                if target.iter().all(|arg| arg.clear) {
+                    writeln!(res, "    memory.count_until_zero({step}, {pc})?;").unwrap();
+                } else {
+                    writeln!(
+                        res,
+                        "    let count = memory.count_until_zero({step}, {pc})?;"
+                    )
+                    .unwrap();
+                }
+
+                res.push_str("    memory.clear();\n");
+
+                for AddUntilZeroArg {
+                    offset,
+                    times,
+                    clear,
+                } in target
+                {
+                    if *clear {
+                        writeln!(res, "    memory.set({offset}, {times}, {pc})?;").unwrap();
+                    } else {
+                        writeln!(
+                            res,
+                            "    memory.add_repeatedly_at({offset}, {times}, count, {pc})?;"
+                        )
+                        .unwrap();
+                    }
+                }
+
+                res.push('}');
+                res
+            }
+            Instruction::Scan { stride } => format!("memory.scan({stride}, {pc})?;"),
+            Instruction::Set { offset, val } => format!("memory.set({offset}, {val}, {pc})?;"),
+            Instruction::Input { offset } => {
+                format!("memory.input({offset}, input.read(), {pc})?;")
+            }
+            Instruction::Output { offset } => {
+                format!("output.write(memory.get_at({offset}, {pc})?);")
+            }
+            Instruction::OutputBytes { bytes } => {
+                let bytes: Vec<_> = bytes.iter().map(i32::to_string).collect();
+                format!("output.write_all(&[{}]);", bytes.join(", "))
+            }
+            Instruction::OutputRepeat { offset, count } => {
+                format!("output.write_all(&[memory.get_at({offset}, {pc})?; {count}]);")
+            }
+            // The items before a cell out of the memory are written.
+            Instruction::OutputSeq { items } => items
+                .iter()
+                .map(|item| match item {
+                    OutputItem::Cell { offset } => {
+                        format!("output.write(memory.get_at({offset}, {pc})?);")
+                    }
+                    OutputItem::Byte { val } => format!("output.write({val});"),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            // Loops are written by `generate`.
+            Instruction::JumpIfZero { .. } | Instruction::Jump { .. } | Instruction::Halt => {
+                String::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::*;
+    use crate::compiler::codegen::tests::{
+        compile, examples, interpret, run, strategies, workspace,
+    };
+    use crate::compiler::codegen::CodegenError;
+
+    /// Build a binary running the modules of `programs` with in-memory buffers,
+    /// which takes the index of the program to run.
+    fn build(dir: &Path, programs: &[(&str, MemoryConfig)]) -> PathBuf {
+        let mut main = String::new();
+        let mut calls = String::new();
+
+        for (i, (code, memory)) in programs.iter().enumerate() {
+            let list = compile(code, memory);
+            let module = RustBackend::new(memory.clone()).generate(&list).unwrap();
+            std::fs::write(dir.join(format!("program{i}.rs")), module).unwrap();
+            writeln!(main, "mod program{i};").unwrap();
+            writeln!(
+                calls,
+                "        \"{i}\" => program{i}::run(&mut input, &mut output).map_err(|e| e.to_string()),"
+            )
+            .unwrap();
+        }
+
+        main.push_str(concat!(
+            "\nuse std::collections::VecDeque;\n",
+            "use std::io::Read;\n\n",
+            "fn main() {\n",
+            "    let mut bytes = vec![];\n",
+            "    std::io::stdin().read_to_end(&mut bytes).unwrap();\n",
+            "    let mut input: VecDeque<i32> = bytes.into_iter().map(i32::from).collect();\n",
+            "    let mut output: Vec<i32> = vec![];\n",
+            "    let res = match std::env::args().nth(1).unwrap().as_str() {\n",
+        ));
+        main.push_str(&calls);
+        main.push_str(concat!(
+            "        _ => unreachable!(),\n",
+            "    };\n\n",
+            "    for content in output {\n",
+            "        print!(\"{content} \");\n",
+            "    }\n\n",
+            "    if let Err(e) = res {\n",
+            "        eprintln!(\"error: {e}\");\n",
+            "        std::process::exit(1);\n",
+            "    }\n",
+            "}\n",
+        ));
+
+        let source = dir.join("main.rs");
+        let binary = dir.join("main");
+        std::fs::write(&source, main).unwrap();
+
+        let status = Command::new("rustc")
+            .args(["--edition", "2021", "-D", "warnings", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        binary
+    }
+
+    #[test]
+    fn examples_run_the_same() {
+        let Some(dir) = workspace("rustc", "examples") else {
+            return;
+        };
+
+        let examples = examples();
+        let programs: Vec<_> = examples
+            .iter()
+            .map(|(code, memory, _)| (*code, memory.clone()))
+            .collect();
+        let binary = build(&dir, &programs);
+
+        for (i, (code, memory, input)) in examples.into_iter().enumerate() {
+            assert_eq!(
+                run(&binary, &[i.to_string()], &input),
+                interpret(code, memory, &input),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn memory_strategies() {
+        let Some(dir) = workspace("rustc", "strategies") else {
+            return;
+        };
+
+        let strategies = strategies();
+        let programs: Vec<_> = strategies
+            .iter()
+            .map(|(code, memory, _)| (*code, memory.clone()))
+            .collect();
+        let binary = build(&dir, &programs);
+
+        for (i, (code, memory, input)) in strategies.into_iter().enumerate() {
+            assert_eq!(
+                run(&binary, &[i.to_string()], input.as_bytes()),
+                interpret(code, memory, input.as_bytes()),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_program() {
+        let Some(dir) = workspace("rustc", "empty") else {
+            return;
+        };
+
+        let binary = build(&dir, &[("---+++", Default::default())]);
+        assert_eq!(
+            run(&binary, &["0".to_string()], b""),
+            interpret("---+++", Default::default(), b"")
+        );
+    }
+
+    #[test]
+    fn unpaired_jumps() {
+        let list = InstructionList::new(vec![Instruction::Jump { target: 0 }, Instruction::Halt]);
+        assert_eq!(
+            RustBackend::new(Default::default()).generate(&list),
+            Err(CodegenError::UnpairedJump { addr: 0 })
+        );
+    }
+}
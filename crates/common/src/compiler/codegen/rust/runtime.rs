@@ -0,0 +1,358 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+pub const EOF: i32 = -1;
+
+const CELL_MIN: i64 = -(1 << (CELL_BITS - 1));
+const CELL_MAX: i64 = (1 << (CELL_BITS - 1)) - 1;
+
+enum Eof {
+    Zero,
+    Keep,
+    Ignore,
+}
+
+pub trait InStream {
+    fn read(&mut self) -> i32;
+}
+
+pub trait OutStream {
+    fn write(&mut self, content: i32);
+
+    fn write_all(&mut self, contents: &[i32]) {
+        for content in contents {
+            self.write(*content);
+        }
+    }
+}
+
+impl InStream for VecDeque<i32> {
+    fn read(&mut self) -> i32 {
+        self.pop_front().unwrap_or(EOF)
+    }
+}
+
+impl InStream for &[u8] {
+    fn read(&mut self) -> i32 {
+        match self.split_first() {
+            Some((&byte, rest)) => {
+                *self = rest;
+                byte as i32
+            }
+            None => EOF,
+        }
+    }
+}
+
+impl OutStream for Vec<i32> {
+    fn write(&mut self, content: i32) {
+        self.push(content);
+    }
+
+    fn write_all(&mut self, contents: &[i32]) {
+        self.extend_from_slice(contents);
+    }
+}
+
+/// Read the input byte by byte from a reader, like `--input std`.
+pub struct Bytes<R: Read>(pub R);
+
+impl<R: Read> InStream for Bytes<R> {
+    fn read(&mut self) -> i32 {
+        let mut buf = [0u8; 1];
+
+        match self.0.read(&mut buf) {
+            Ok(0) | Err(_) => EOF,
+            _ => buf[0] as i32,
+        }
+    }
+}
+
+/// Write the output as characters encoded in UTF-8, like `--output char-std`.
+pub struct Chars<W: Write>(pub W);
+
+impl<W: Write> OutStream for Chars<W> {
+    fn write(&mut self, content: i32) {
+        let _ = write!(self.0, "{}", char::from_u32(content as u32).unwrap_or('�'));
+    }
+}
+
+/// Write the output as integers followed by spaces, like `--output int-std`.
+pub struct Ints<W: Write>(pub W);
+
+impl<W: Write> OutStream for Ints<W> {
+    fn write(&mut self, content: i32) {
+        let _ = write!(self.0, "{content} ");
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    SeekOutOfBounds { now_position: isize, offset: isize },
+    AccessOutOfBounds { addr: isize },
+    AddOverflow { before: i32, add: i32 },
+    SetOverflow { val: i32 },
+}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryError::SeekOutOfBounds {
+                now_position,
+                offset,
+            } => write!(
+                f,
+                "try to seek pointer from {now_position} to {}, which is out of [{LEFT}, {RIGHT}]",
+                now_position + offset
+            ),
+            MemoryError::AccessOutOfBounds { addr } => write!(
+                f,
+                "try to access cell at {addr}, which is out of [{LEFT}, {RIGHT}]"
+            ),
+            MemoryError::AddOverflow { before, add } => write!(f, "{before} + {add} will overflow"),
+            MemoryError::SetOverflow { val } => write!(f, "{val} will overflow"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// `pc` is the address of the failed instruction, and `line` and `column` are
+    /// where it comes from, which are 0 if they're unknown.
+    Memory {
+        source: MemoryError,
+        pc: usize,
+        line: usize,
+        column: usize,
+    },
+    /// The program has nothing to run, which the interpreter refuses too.
+    Empty,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Memory {
+                source,
+                pc,
+                line,
+                column,
+            } => {
+                write!(f, "invalid memory operation occurred at instruction {pc}")?;
+
+                if *line != 0 {
+                    write!(f, " ({line}:{column})")?;
+                }
+
+                write!(f, ": {source}")
+            }
+            Error::Empty => write!(f, "empty program loaded"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Run forever like a loop whose counter never reaches zero.
+fn hang() -> ! {
+    loop {
+        std::hint::spin_loop();
+    }
+}
+
+/// Build the error of the instruction at `pc`.
+fn fail(source: MemoryError, pc: usize) -> Error {
+    let (line, column) = LOCATIONS[pc];
+    Error::Memory {
+        source,
+        pc,
+        line,
+        column,
+    }
+}
+
+struct Memory {
+    cells: Vec<i32>,
+    cur: isize,
+}
+
+impl Memory {
+    fn new() -> Self {
+        Self {
+            cells: vec![0; (RIGHT - LEFT + 1) as usize],
+            cur: 0,
+        }
+    }
+
+    fn get(&self) -> i32 {
+        self.cells[(self.cur - LEFT) as usize]
+    }
+
+    fn clear(&mut self) {
+        self.cells[(self.cur - LEFT) as usize] = 0;
+    }
+
+    fn cell_at(&mut self, offset: isize, pc: usize) -> Result<&mut i32, Error> {
+        let addr = self.cur + offset;
+
+        if addr < LEFT || addr > RIGHT {
+            return Err(fail(MemoryError::AccessOutOfBounds { addr }, pc));
+        }
+
+        Ok(&mut self.cells[(addr - LEFT) as usize])
+    }
+
+    fn get_at(&mut self, offset: isize, pc: usize) -> Result<i32, Error> {
+        self.cell_at(offset, pc).map(|cell| *cell)
+    }
+
+    fn seek(&mut self, offset: isize, pc: usize) -> Result<(), Error> {
+        let target = self.cur + offset;
+
+        if target < LEFT || target > RIGHT {
+            let source = MemoryError::SeekOutOfBounds {
+                now_position: self.cur,
+                offset,
+            };
+            return Err(fail(source, pc));
+        }
+
+        self.cur = target;
+        Ok(())
+    }
+
+    /// Move the pointer by `stride` until it points to a zero cell.
+    fn scan(&mut self, stride: isize, pc: usize) -> Result<(), Error> {
+        while self.get() != 0 {
+            self.seek(stride, pc)?;
+        }
+
+        Ok(())
+    }
+
+    fn wrap(val: i64) -> i32 {
+        if CELL_BITS == 8 {
+            val as i8 as i32
+        } else {
+            val as i32
+        }
+    }
+
+    fn add(&mut self, offset: isize, add: i32, pc: usize) -> Result<(), Error> {
+        let cell = self.cell_at(offset, pc)?;
+        let res = *cell as i64 + add as i64;
+
+        *cell = if res < CELL_MIN || res > CELL_MAX {
+            if !WRAP {
+                return Err(fail(MemoryError::AddOverflow { before: *cell, add }, pc));
+            }
+
+            Self::wrap(res)
+        } else {
+            res as i32
+        };
+        Ok(())
+    }
+
+    fn checked_set(val: i32) -> Result<i32, MemoryError> {
+        if (val as i64) < CELL_MIN || (val as i64) > CELL_MAX {
+            if !WRAP {
+                return Err(MemoryError::SetOverflow { val });
+            }
+
+            return Ok(Self::wrap(val as i64));
+        }
+
+        Ok(val)
+    }
+
+    fn set(&mut self, offset: isize, val: i32, pc: usize) -> Result<(), Error> {
+        let cell = self.cell_at(offset, pc)?;
+        *cell = Self::checked_set(val).map_err(|source| fail(source, pc))?;
+        Ok(())
+    }
+
+    /// Add `add` to `before` for `count` times, which fails as adding them one by
+    /// one.
+    fn add_repeatedly(before: i32, add: i32, count: u64) -> Result<i32, MemoryError> {
+        if WRAP {
+            let res = (before as i64).wrapping_add((add as i64).wrapping_mul(count as i64));
+            return Ok(Self::wrap(res));
+        }
+
+        let res = before as i128 + add as i128 * count as i128;
+
+        if CELL_MIN as i128 <= res && res <= CELL_MAX as i128 {
+            return Ok(res as i32);
+        }
+
+        let room = if add > 0 {
+            CELL_MAX - before as i64
+        } else {
+            before as i64 - CELL_MIN
+        };
+        let before = before as i64 + room / (add as i64).abs() * add as i64;
+        Err(MemoryError::AddOverflow {
+            before: before as i32,
+            add,
+        })
+    }
+
+    fn add_repeatedly_at(
+        &mut self,
+        offset: isize,
+        add: i32,
+        count: u64,
+        pc: usize,
+    ) -> Result<(), Error> {
+        let cell = self.cell_at(offset, pc)?;
+        *cell = Self::add_repeatedly(*cell, add, count).map_err(|source| fail(source, pc))?;
+        Ok(())
+    }
+
+    /// Return how many times `step` should be added to the current cell until
+    /// it's zero. If the cells wrap, the cell must be a multiple of the power of 2
+    /// in `step`, or the counter never reaches zero.
+    fn count_until_zero(&self, step: i32, pc: usize) -> Result<u64, Error> {
+        let val = self.get();
+
+        if WRAP {
+            let mask = u64::MAX >> (64 - CELL_BITS);
+            let step = step as i64 as u64 & mask;
+            let shift = step.trailing_zeros();
+            let odd = step >> shift;
+            let mut inverse = odd;
+
+            for _ in 0..5 {
+                inverse = inverse.wrapping_mul(2u64.wrapping_sub(odd.wrapping_mul(inverse)));
+            }
+
+            let neg = (val as i64 as u64).wrapping_neg();
+            return Ok((neg >> shift).wrapping_mul(inverse) & (mask >> shift));
+        }
+
+        let (val, step) = (val as i64, step as i64);
+
+        if val % step == 0 && (val > 0) != (step > 0) {
+            Ok((val / step).unsigned_abs())
+        } else {
+            // The counter never reaches zero, so it overflows at last.
+            Self::add_repeatedly(val as i32, step as i32, u64::MAX)
+                .map(|_| unreachable!())
+                .map_err(|source| fail(source, pc))
+        }
+    }
+
+    fn input(&mut self, offset: isize, val: i32, pc: usize) -> Result<(), Error> {
+        let cell = self.cell_at(offset, pc)?;
+
+        let val = match (val, EOF_STRATEGY) {
+            (EOF, Eof::Zero) => 0,
+            (EOF, Eof::Ignore) => return Ok(()),
+            _ => val,
+        };
+        *cell = Self::checked_set(val).map_err(|source| fail(source, pc))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,1244 @@
+//! A small decoder, validator and interpreter of WebAssembly modules, which
+//! checks the generated modules without any runtime. It knows only the parts of
+//! the binary and the text formats the backend uses, and rejects everything else.
+
+use std::collections::{HashMap, VecDeque};
+
+type Result<T> = std::result::Result<T, String>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ValType {
+    I32,
+    I64,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct FuncType {
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Instr {
+    Unreachable,
+    /// The index of the matching `end`.
+    Block(usize),
+    Loop,
+    /// The index of the matching `end`.
+    If(usize),
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// A load or a store with its opcode and offset.
+    Memory(u8, u32),
+    I32Const(i32),
+    I64Const(i64),
+    /// A numeric instruction without immediates.
+    Numeric(u8),
+}
+
+#[derive(PartialEq, Eq)]
+struct Body {
+    locals: Vec<ValType>,
+    code: Vec<Instr>,
+}
+
+#[derive(PartialEq, Eq)]
+struct Global {
+    ty: ValType,
+    mutable: bool,
+    init: i64,
+}
+
+/// Two modules are equal if they are decoded from the same binary, or from a
+/// text and the binary it's assembled into.
+#[derive(PartialEq, Eq)]
+pub struct Module {
+    types: Vec<FuncType>,
+    /// The types of the imported functions and then the defined ones.
+    funcs: Vec<u32>,
+    imports: Vec<String>,
+    pages: u32,
+    globals: Vec<Global>,
+    exports: Vec<(String, u8, u32)>,
+    bodies: Vec<Body>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let res = *self.bytes.get(self.pos).ok_or("unexpected end")?;
+        self.pos += 1;
+        Ok(res)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let res = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or("unexpected end")?;
+        self.pos += len;
+        Ok(res)
+    }
+
+    fn leb(&mut self, bits: u32, signed: bool) -> Result<i128> {
+        let mut res = 0i128;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            res |= ((byte & 0x7f) as i128) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                if signed && shift < 128 && byte & 0x40 != 0 {
+                    res |= -1i128 << shift;
+                }
+
+                break;
+            }
+
+            if shift >= bits + 7 {
+                return Err("integer too long".into());
+            }
+        }
+
+        let (min, max) = if signed {
+            (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+        } else {
+            (0, (1i128 << bits) - 1)
+        };
+
+        if res < min || res > max {
+            Err("integer too large".into())
+        } else {
+            Ok(res)
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.leb(32, false).map(|n| n as u32)
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    fn val_type(&mut self) -> Result<ValType> {
+        match self.byte()? {
+            0x7f => Ok(ValType::I32),
+            0x7e => Ok(ValType::I64),
+            ty => Err(format!("unknown value type {ty:#x}")),
+        }
+    }
+
+    fn val_types(&mut self) -> Result<Vec<ValType>> {
+        (0..self.u32()?).map(|_| self.val_type()).collect()
+    }
+
+    fn finished(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+}
+
+impl Module {
+    /// Decode and validate a module.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.take(8)? != b"\0asm\x01\0\0\0" {
+            return Err("bad header".into());
+        }
+
+        let mut module = Module {
+            types: vec![],
+            funcs: vec![],
+            imports: vec![],
+            pages: 0,
+            globals: vec![],
+            exports: vec![],
+            bodies: vec![],
+        };
+        let mut last = 0;
+        let mut defined = 0;
+
+        while !reader.finished() {
+            let id = reader.byte()?;
+            let len = reader.u32()? as usize;
+            let mut section = Reader {
+                bytes: reader.take(len)?,
+                pos: 0,
+            };
+
+            if id <= last {
+                return Err(format!("section {id} out of order"));
+            }
+
+            last = id;
+
+            for _ in 0..section.u32()? {
+                match id {
+                    1 => {
+                        if section.byte()? != 0x60 {
+                            return Err("bad function type".into());
+                        }
+
+                        let params = section.val_types()?;
+                        let results = section.val_types()?;
+                        module.types.push(FuncType { params, results });
+                    }
+                    2 => {
+                        let name = format!("{}.{}", section.name()?, section.name()?);
+
+                        if section.byte()? != 0x00 {
+                            return Err(format!("import {name} isn't a function"));
+                        }
+
+                        if defined > 0 {
+                            return Err("imports after functions".into());
+                        }
+
+                        module.funcs.push(section.u32()?);
+                        module.imports.push(name);
+                    }
+                    3 => {
+                        module.funcs.push(section.u32()?);
+                        defined += 1;
+                    }
+                    5 => {
+                        if section.byte()? != 0x00 || module.pages != 0 {
+                            return Err("unsupported memory".into());
+                        }
+
+                        module.pages = section.u32()?.max(1);
+                    }
+                    6 => {
+                        let ty = section.val_type()?;
+                        let mutable = match section.byte()? {
+                            0 => false,
+                            1 => true,
+                            _ => return Err("bad mutability".into()),
+                        };
+                        let init = match (ty, section.byte()?) {
+                            (ValType::I32, 0x41) => section.leb(32, true)? as i64,
+                            (ValType::I64, 0x42) => section.leb(64, true)? as i64,
+                            _ => return Err("bad initializer".into()),
+                        };
+
+                        if section.byte()? != 0x0b {
+                            return Err("bad initializer".into());
+                        }
+
+                        module.globals.push(Global { ty, mutable, init });
+                    }
+                    7 => {
+                        let name = section.name()?;
+                        let kind = section.byte()?;
+                        let index = section.u32()?;
+                        module.exports.push((name, kind, index));
+                    }
+                    10 => {
+                        let len = section.u32()? as usize;
+                        let mut body = Reader {
+                            bytes: section.take(len)?,
+                            pos: 0,
+                        };
+                        module.bodies.push(Self::body(&mut body)?);
+                    }
+                    _ => return Err(format!("unsupported section {id}")),
+                }
+            }
+
+            if !section.finished() {
+                return Err(format!("section {id} has trailing bytes"));
+            }
+        }
+
+        // The order of the exports doesn't matter.
+        module.exports.sort();
+        module.validate()?;
+        Ok(module)
+    }
+
+    fn body(reader: &mut Reader) -> Result<Body> {
+        let mut locals = vec![];
+
+        for _ in 0..reader.u32()? {
+            let count = reader.u32()?;
+            let ty = reader.val_type()?;
+            locals.extend((0..count).map(|_| ty));
+        }
+
+        let mut code = vec![];
+        let mut blocks = vec![];
+
+        loop {
+            let opcode = reader.byte()?;
+            let instr = match opcode {
+                0x00 => Instr::Unreachable,
+                0x02..=0x04 => {
+                    if reader.byte()? != 0x40 {
+                        return Err("unsupported block type".into());
+                    }
+
+                    blocks.push(code.len());
+
+                    match opcode {
+                        0x02 => Instr::Block(0),
+                        0x03 => Instr::Loop,
+                        _ => Instr::If(0),
+                    }
+                }
+                0x0b => match blocks.pop() {
+                    Some(start) => {
+                        let end = code.len();
+
+                        match &mut code[start] {
+                            Instr::Block(target) | Instr::If(target) => *target = end,
+                            _ => {}
+                        }
+
+                        Instr::End
+                    }
+                    None if reader.finished() => {
+                        code.push(Instr::End);
+                        return Ok(Body { locals, code });
+                    }
+                    None => return Err("trailing bytes after the body".into()),
+                },
+                0x0c => Instr::Br(reader.u32()?),
+                0x0d => Instr::BrIf(reader.u32()?),
+                0x0f => Instr::Return,
+                0x10 => Instr::Call(reader.u32()?),
+                0x1a => Instr::Drop,
+                0x1b => Instr::Select,
+                0x20 => Instr::LocalGet(reader.u32()?),
+                0x21 => Instr::LocalSet(reader.u32()?),
+                0x22 => Instr::LocalTee(reader.u32()?),
+                0x23 => Instr::GlobalGet(reader.u32()?),
+                0x24 => Instr::GlobalSet(reader.u32()?),
+                0x28 | 0x2c | 0x36 | 0x3a => {
+                    let natural = match opcode {
+                        0x28 | 0x36 => 2,
+                        _ => 0,
+                    };
+
+                    if reader.u32()? > natural {
+                        return Err("alignment larger than natural".into());
+                    }
+
+                    Instr::Memory(opcode, reader.u32()?)
+                }
+                0x41 => Instr::I32Const(reader.leb(32, true)? as i32),
+                0x42 => Instr::I64Const(reader.leb(64, true)? as i64),
+                0x45..=0x5a
+                | 0x6a..=0x6c
+                | 0x71..=0x73
+                | 0x7c..=0x7f
+                | 0x81
+                | 0x83..=0x85
+                | 0xa7
+                | 0xac
+                | 0xad => Instr::Numeric(opcode),
+                _ => return Err(format!("unsupported opcode {opcode:#x}")),
+            };
+
+            code.push(instr);
+        }
+    }
+
+    fn func_type(&self, index: u32) -> Result<&FuncType> {
+        let ty = *self
+            .funcs
+            .get(index as usize)
+            .ok_or_else(|| format!("unknown function {index}"))?;
+        self.types
+            .get(ty as usize)
+            .ok_or_else(|| format!("unknown type {ty}"))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.bodies.len() + self.imports.len() != self.funcs.len() {
+            return Err("functions and bodies mismatch".into());
+        }
+
+        for (name, kind, index) in &self.exports {
+            match kind {
+                0x00 => {
+                    self.func_type(*index)?;
+                }
+                0x02 if *index == 0 && self.pages > 0 => {}
+                _ => return Err(format!("bad export {name}")),
+            }
+        }
+
+        for (i, body) in self.bodies.iter().enumerate() {
+            let index = (self.imports.len() + i) as u32;
+            self.validate_body(body)
+                .map_err(|e| format!("function {index}: {e}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the types of the operands with the algorithm in the appendix of the
+    /// specification, where `None` is an unknown type.
+    fn validate_body(&self, body: &Body) -> Result<()> {
+        use ValType::*;
+
+        struct Frame {
+            is_loop: bool,
+            height: usize,
+            results: Vec<ValType>,
+            unreachable: bool,
+        }
+
+        fn pop(stack: &mut Vec<Option<ValType>>, frames: &[Frame]) -> Result<Option<ValType>> {
+            let frame = frames.last().unwrap();
+
+            if stack.len() == frame.height {
+                if frame.unreachable {
+                    Ok(None)
+                } else {
+                    Err("stack underflow".into())
+                }
+            } else {
+                Ok(stack.pop().unwrap())
+            }
+        }
+
+        fn expect(stack: &mut Vec<Option<ValType>>, frames: &[Frame], ty: ValType) -> Result<()> {
+            match pop(stack, frames)? {
+                Some(actual) if actual != ty => Err(format!("expect {ty:?}, found {actual:?}")),
+                _ => Ok(()),
+            }
+        }
+
+        fn label(frames: &[Frame], depth: u32) -> Result<Vec<ValType>> {
+            let frame = frames
+                .len()
+                .checked_sub(depth as usize + 1)
+                .map(|i| &frames[i])
+                .ok_or("unknown label")?;
+
+            if frame.is_loop {
+                Ok(vec![])
+            } else {
+                Ok(frame.results.clone())
+            }
+        }
+
+        let ty = self.func_type((self.imports.len() + self.index_of(body)) as u32)?;
+        let locals: Vec<_> = ty.params.iter().chain(&body.locals).copied().collect();
+        let local = |index: u32| {
+            locals
+                .get(index as usize)
+                .copied()
+                .ok_or_else(|| format!("unknown local {index}"))
+        };
+        let global = |index: u32| {
+            self.globals
+                .get(index as usize)
+                .ok_or_else(|| format!("unknown global {index}"))
+        };
+
+        let mut stack: Vec<Option<ValType>> = vec![];
+        let mut frames = vec![Frame {
+            is_loop: false,
+            height: 0,
+            results: ty.results.clone(),
+            unreachable: false,
+        }];
+
+        for instr in &body.code {
+            if frames.is_empty() {
+                return Err("code after the end".into());
+            }
+
+            let (inputs, outputs): (&[ValType], &[ValType]) = match *instr {
+                Instr::Unreachable | Instr::Br(_) | Instr::Return => {
+                    let results = match *instr {
+                        Instr::Unreachable => vec![],
+                        Instr::Br(depth) => label(&frames, depth)?,
+                        _ => ty.results.clone(),
+                    };
+
+                    for ty in results.iter().rev() {
+                        expect(&mut stack, &frames, *ty)?;
+                    }
+
+                    let frame = frames.last_mut().unwrap();
+                    stack.truncate(frame.height);
+                    frame.unreachable = true;
+                    continue;
+                }
+                Instr::Block(_) | Instr::Loop | Instr::If(_) => {
+                    if let Instr::If(_) = instr {
+                        expect(&mut stack, &frames, I32)?;
+                    }
+
+                    frames.push(Frame {
+                        is_loop: *instr == Instr::Loop,
+                        height: stack.len(),
+                        results: vec![],
+                        unreachable: false,
+                    });
+                    continue;
+                }
+                Instr::End => {
+                    let frame = frames.last().unwrap();
+                    let results = frame.results.clone();
+
+                    for ty in results.iter().rev() {
+                        expect(&mut stack, &frames, *ty)?;
+                    }
+
+                    if stack.len() != frames.last().unwrap().height {
+                        return Err("values left at the end of a block".into());
+                    }
+
+                    frames.pop();
+                    stack.extend(results.into_iter().map(Some));
+                    continue;
+                }
+                Instr::BrIf(depth) => {
+                    expect(&mut stack, &frames, I32)?;
+                    let results = label(&frames, depth)?;
+
+                    for ty in results.iter().rev() {
+                        expect(&mut stack, &frames, *ty)?;
+                    }
+
+                    stack.extend(results.into_iter().map(Some));
+                    continue;
+                }
+                Instr::Call(index) => {
+                    let callee = self.func_type(index)?;
+
+                    for ty in callee.params.iter().rev() {
+                        expect(&mut stack, &frames, *ty)?;
+                    }
+
+                    stack.extend(callee.results.iter().copied().map(Some));
+                    continue;
+                }
+                Instr::Drop => {
+                    pop(&mut stack, &frames)?;
+                    continue;
+                }
+                Instr::Select => {
+                    expect(&mut stack, &frames, I32)?;
+                    let first = pop(&mut stack, &frames)?;
+                    let second = pop(&mut stack, &frames)?;
+
+                    match (first, second) {
+                        (Some(a), Some(b)) if a != b => {
+                            return Err("select with different types".into())
+                        }
+                        _ => stack.push(first.or(second)),
+                    }
+
+                    continue;
+                }
+                Instr::LocalGet(index) => {
+                    stack.push(Some(local(index)?));
+                    continue;
+                }
+                Instr::LocalSet(index) | Instr::LocalTee(index) => {
+                    let ty = local(index)?;
+                    expect(&mut stack, &frames, ty)?;
+
+                    if let Instr::LocalTee(_) = instr {
+                        stack.push(Some(ty));
+                    }
+
+                    continue;
+                }
+                Instr::GlobalGet(index) => {
+                    stack.push(Some(global(index)?.ty));
+                    continue;
+                }
+                Instr::GlobalSet(index) => {
+                    let global = global(index)?;
+
+                    if !global.mutable {
+                        return Err(format!("global {index} is immutable"));
+                    }
+
+                    expect(&mut stack, &frames, global.ty)?;
+                    continue;
+                }
+                Instr::Memory(opcode, _) => {
+                    if self.pages == 0 {
+                        return Err("no memory".into());
+                    }
+
+                    match opcode {
+                        0x28 | 0x2c => (&[I32], &[I32]),
+                        _ => (&[I32, I32], &[]),
+                    }
+                }
+                Instr::I32Const(_) => (&[], &[I32]),
+                Instr::I64Const(_) => (&[], &[I64]),
+                Instr::Numeric(opcode) => match opcode {
+                    0x45 => (&[I32], &[I32]),
+                    0x46..=0x4f => (&[I32, I32], &[I32]),
+                    0x50 => (&[I64], &[I32]),
+                    0x51..=0x5a => (&[I64, I64], &[I32]),
+                    0x6a..=0x78 => (&[I32, I32], &[I32]),
+                    0x7c..=0x8a => (&[I64, I64], &[I64]),
+                    0xa7 => (&[I64], &[I32]),
+                    _ => (&[I32], &[I64]),
+                },
+            };
+
+            for ty in inputs.iter().rev() {
+                expect(&mut stack, &frames, *ty)?;
+            }
+
+            stack.extend(outputs.iter().copied().map(Some));
+        }
+
+        if !frames.is_empty() {
+            return Err("missing end".into());
+        }
+
+        // The declared locals must be read, or they are useless.
+        for index in ty.params.len()..locals.len() {
+            if !body.code.contains(&Instr::LocalGet(index as u32)) {
+                return Err(format!("local {index} is never read"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn index_of(&self, body: &Body) -> usize {
+        self.bodies
+            .iter()
+            .position(|other| std::ptr::eq(other, body))
+            .unwrap()
+    }
+}
+
+/// An S-expression of the text format.
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn parse(text: &str) -> Result<Self> {
+        let mut tokens = vec![];
+        let mut chars = text.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '(' | ')' => tokens.push(ch.to_string()),
+                ';' if chars.peek() == Some(&';') => {
+                    chars.by_ref().take_while(|&ch| ch != '\n').for_each(drop);
+                }
+                '"' => {
+                    let string: String = chars.by_ref().take_while(|&ch| ch != '"').collect();
+                    tokens.push(format!("\"{string}\""));
+                }
+                _ if ch.is_whitespace() => {}
+                _ => {
+                    let mut atom = ch.to_string();
+
+                    while let Some(&ch) = chars.peek() {
+                        if ch == '(' || ch == ')' || ch.is_whitespace() {
+                            break;
+                        }
+
+                        atom.push(ch);
+                        chars.next();
+                    }
+
+                    tokens.push(atom);
+                }
+            }
+        }
+
+        let mut tokens = tokens.into_iter();
+
+        if tokens.next().as_deref() != Some("(") {
+            return Err("expected `(`".into());
+        }
+
+        let res = Self::list(&mut tokens)?;
+
+        match tokens.next() {
+            None => Ok(res),
+            Some(token) => Err(format!("unexpected `{token}` after the module")),
+        }
+    }
+
+    /// Read the items of a list whose `(` is read.
+    fn list(tokens: &mut impl Iterator<Item = String>) -> Result<Self> {
+        let mut items = vec![];
+
+        loop {
+            match tokens.next().as_deref() {
+                Some(")") => return Ok(Sexp::List(items)),
+                Some("(") => items.push(Self::list(tokens)?),
+                Some(atom) => items.push(Sexp::Atom(atom.to_string())),
+                None => return Err("missing `)`".into()),
+            }
+        }
+    }
+
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom) => Some(atom),
+            Sexp::List(_) => None,
+        }
+    }
+
+    /// Return the items of a list starting with the keyword `head`.
+    fn field(&self, head: &str) -> Option<&[Sexp]> {
+        match self {
+            Sexp::List(items) if items.first().and_then(Sexp::atom) == Some(head) => {
+                Some(&items[1..])
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The numeric instructions without immediates in the text format.
+const NUMERIC: [(&str, u8); 39] = [
+    ("i32.eqz", 0x45),
+    ("i32.eq", 0x46),
+    ("i32.ne", 0x47),
+    ("i32.lt_s", 0x48),
+    ("i32.lt_u", 0x49),
+    ("i32.gt_s", 0x4a),
+    ("i32.gt_u", 0x4b),
+    ("i32.le_s", 0x4c),
+    ("i32.le_u", 0x4d),
+    ("i32.ge_s", 0x4e),
+    ("i32.ge_u", 0x4f),
+    ("i64.eqz", 0x50),
+    ("i64.eq", 0x51),
+    ("i64.ne", 0x52),
+    ("i64.lt_s", 0x53),
+    ("i64.lt_u", 0x54),
+    ("i64.gt_s", 0x55),
+    ("i64.gt_u", 0x56),
+    ("i64.le_s", 0x57),
+    ("i64.le_u", 0x58),
+    ("i64.ge_s", 0x59),
+    ("i64.ge_u", 0x5a),
+    ("i32.add", 0x6a),
+    ("i32.sub", 0x6b),
+    ("i32.mul", 0x6c),
+    ("i32.and", 0x71),
+    ("i32.or", 0x72),
+    ("i32.xor", 0x73),
+    ("i64.add", 0x7c),
+    ("i64.sub", 0x7d),
+    ("i64.mul", 0x7e),
+    ("i64.div_s", 0x7f),
+    ("i64.rem_s", 0x81),
+    ("i64.and", 0x83),
+    ("i64.or", 0x84),
+    ("i64.xor", 0x85),
+    ("i32.wrap_i64", 0xa7),
+    ("i64.extend_i32_s", 0xac),
+    ("i64.extend_i32_u", 0xad),
+];
+
+impl Module {
+    /// Assemble and validate a module in the text format, where every function
+    /// has a type of its own like the binaries of the backend.
+    pub fn parse_text(text: &str) -> Result<Self> {
+        let module = Sexp::parse(text)?;
+        let fields = module.field("module").ok_or("expected a module")?;
+
+        let mut module = Module {
+            types: vec![],
+            funcs: vec![],
+            imports: vec![],
+            pages: 0,
+            globals: vec![],
+            exports: vec![],
+            bodies: vec![],
+        };
+        // The names of the functions and the globals are resolved after all of
+        // them are defined.
+        let mut funcs = HashMap::new();
+        let mut globals = HashMap::new();
+
+        for field in fields {
+            if let Some([_, _, func]) = field.field("import") {
+                let name = func.field("func").and_then(|func| func.first()?.atom());
+                funcs.insert(name.ok_or("bad import")?, funcs.len() as u32);
+            } else if let Some(func) = field.field("func") {
+                let name = func.first().and_then(Sexp::atom).ok_or("bad function")?;
+                funcs.insert(name, funcs.len() as u32);
+            } else if let Some(global) = field.field("global") {
+                let name = global.first().and_then(Sexp::atom).ok_or("bad global")?;
+                globals.insert(name, globals.len() as u32);
+            }
+        }
+
+        for field in fields {
+            if let Some([env, name, func]) = field.field("import") {
+                let (Some(env), Some(name), Some(func)) =
+                    (env.atom(), name.atom(), func.field("func"))
+                else {
+                    return Err("bad import".into());
+                };
+
+                module
+                    .imports
+                    .push(format!("{}.{}", unquote(env)?, unquote(name)?));
+                module.funcs.push(module.types.len() as u32);
+                module.types.push(Self::func_type_text(&func[1..])?.0);
+            } else if let Some([export, pages]) = field.field("memory") {
+                let name = export.field("export").ok_or("bad memory")?;
+                module.exports.push((unquote_field(name)?, 0x02, 0));
+                let pages = pages.atom().ok_or("bad memory")?;
+                module.pages = pages.parse::<u32>().map_err(|e| e.to_string())?.max(1);
+            } else if let Some([_, ty, init]) = field.field("global") {
+                let ty = ty.field("mut").ok_or("immutable global")?;
+                let ty = match ty {
+                    [ty] => val_type_text(ty.atom().unwrap_or_default())?,
+                    _ => return Err("bad global".into()),
+                };
+                let init = match init {
+                    Sexp::List(items) => Self::instrs(items, &funcs, &globals)?,
+                    _ => return Err("bad initializer".into()),
+                };
+                let init = match (ty, init.as_slice()) {
+                    (ValType::I32, [Instr::I32Const(n)]) => *n as i64,
+                    (ValType::I64, [Instr::I64Const(n)]) => *n,
+                    _ => return Err("bad initializer".into()),
+                };
+                module.globals.push(Global {
+                    ty,
+                    mutable: true,
+                    init,
+                });
+            } else if let Some(func) = field.field("func") {
+                let index = module.funcs.len() as u32;
+                let mut rest = &func[1..];
+
+                while let Some(name) = rest.first().and_then(|field| field.field("export")) {
+                    module.exports.push((unquote_field(name)?, 0x00, index));
+                    rest = &rest[1..];
+                }
+
+                let (ty, rest) = Self::func_type_text(rest)?;
+                let (locals, rest) = match rest.first().and_then(|field| field.field("local")) {
+                    Some(locals) => (val_types_text(locals)?, &rest[1..]),
+                    None => (vec![], rest),
+                };
+                let mut code = Self::instrs(rest, &funcs, &globals)?;
+                let mut blocks = vec![];
+
+                for end in 0..code.len() {
+                    match code[end] {
+                        Instr::Block(_) | Instr::Loop | Instr::If(_) => blocks.push(end),
+                        Instr::End => match &mut code[blocks.pop().ok_or("unpaired end")?] {
+                            Instr::Block(target) | Instr::If(target) => *target = end,
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+                }
+
+                if !blocks.is_empty() {
+                    return Err("missing end".into());
+                }
+
+                code.push(Instr::End);
+                module.funcs.push(module.types.len() as u32);
+                module.types.push(ty);
+                module.bodies.push(Body { locals, code });
+            } else {
+                return Err("unsupported field".into());
+            }
+        }
+
+        module.exports.sort();
+        module.validate()?;
+        Ok(module)
+    }
+
+    /// Read the parameters and the results at the start of `fields`, and return
+    /// the rest.
+    fn func_type_text(fields: &[Sexp]) -> Result<(FuncType, &[Sexp])> {
+        let mut ty = FuncType {
+            params: vec![],
+            results: vec![],
+        };
+        let mut rest = fields;
+
+        if let Some(params) = rest.first().and_then(|field| field.field("param")) {
+            ty.params = val_types_text(params)?;
+            rest = &rest[1..];
+        }
+
+        if let Some(results) = rest.first().and_then(|field| field.field("result")) {
+            ty.results = val_types_text(results)?;
+            rest = &rest[1..];
+        }
+
+        Ok((ty, rest))
+    }
+
+    /// Read the flat instructions, whose blocks aren't paired yet.
+    fn instrs(
+        items: &[Sexp],
+        funcs: &HashMap<&str, u32>,
+        globals: &HashMap<&str, u32>,
+    ) -> Result<Vec<Instr>> {
+        let mut items = items.iter();
+        let mut code = vec![];
+
+        while let Some(item) = items.next() {
+            let name = item.atom().ok_or("unexpected list")?;
+            let mut immediate = || {
+                items
+                    .next()
+                    .and_then(Sexp::atom)
+                    .ok_or_else(|| format!("`{name}` needs an immediate"))
+            };
+            let index = |atom: &str, names: &HashMap<&str, u32>| match names.get(atom) {
+                Some(&index) => Ok(index),
+                None => atom.parse().map_err(|_| format!("unknown `{atom}`")),
+            };
+            let number = |atom: &str| atom.parse::<u32>().map_err(|e| e.to_string());
+
+            let instr = match name {
+                "unreachable" => Instr::Unreachable,
+                "block" => Instr::Block(0),
+                "loop" => Instr::Loop,
+                "if" => Instr::If(0),
+                "end" => Instr::End,
+                "br" => Instr::Br(number(immediate()?)?),
+                "br_if" => Instr::BrIf(number(immediate()?)?),
+                "return" => Instr::Return,
+                "call" => Instr::Call(index(immediate()?, funcs)?),
+                "drop" => Instr::Drop,
+                "select" => Instr::Select,
+                "local.get" => Instr::LocalGet(number(immediate()?)?),
+                "local.set" => Instr::LocalSet(number(immediate()?)?),
+                "local.tee" => Instr::LocalTee(number(immediate()?)?),
+                "global.get" => Instr::GlobalGet(index(immediate()?, globals)?),
+                "global.set" => Instr::GlobalSet(index(immediate()?, globals)?),
+                "i32.load" => Instr::Memory(0x28, 0),
+                "i32.load8_s" => Instr::Memory(0x2c, 0),
+                "i32.store" => Instr::Memory(0x36, 0),
+                "i32.store8" => Instr::Memory(0x3a, 0),
+                "i32.const" => Instr::I32Const(immediate()?.parse().map_err(|e| format!("{e}"))?),
+                "i64.const" => Instr::I64Const(immediate()?.parse().map_err(|e| format!("{e}"))?),
+                _ => match NUMERIC.iter().find(|(numeric, _)| *numeric == name) {
+                    Some(&(_, opcode)) => Instr::Numeric(opcode),
+                    None => return Err(format!("unsupported instruction `{name}`")),
+                },
+            };
+
+            code.push(instr);
+        }
+
+        Ok(code)
+    }
+}
+
+fn unquote(string: &str) -> Result<String> {
+    string
+        .strip_prefix('"')
+        .and_then(|string| string.strip_suffix('"'))
+        .map(str::to_string)
+        .ok_or_else(|| format!("expected a string, found `{string}`"))
+}
+
+/// Read the name of `(export "name")`.
+fn unquote_field(fields: &[Sexp]) -> Result<String> {
+    match fields {
+        [name] => unquote(name.atom().unwrap_or_default()),
+        _ => Err("bad export".into()),
+    }
+}
+
+fn val_type_text(name: &str) -> Result<ValType> {
+    match name {
+        "i32" => Ok(ValType::I32),
+        "i64" => Ok(ValType::I64),
+        _ => Err(format!("unknown value type `{name}`")),
+    }
+}
+
+fn val_types_text(items: &[Sexp]) -> Result<Vec<ValType>> {
+    items
+        .iter()
+        .map(|item| val_type_text(item.atom().unwrap_or_default()))
+        .collect()
+}
+
+/// What happens when a module runs.
+pub struct Outcome {
+    pub output: Vec<i32>,
+    /// The arguments of `fail` if it's called.
+    pub failure: Option<[i32; 4]>,
+    /// The message if the module traps.
+    pub trap: Option<String>,
+    pub memory: Vec<u8>,
+}
+
+struct Instance<'a> {
+    module: &'a Module,
+    memory: Vec<u8>,
+    globals: Vec<i64>,
+    input: VecDeque<i32>,
+    output: Vec<i32>,
+    failure: Option<[i32; 4]>,
+}
+
+impl Module {
+    /// Run the exported function `run` with `env.read`, `env.write` and
+    /// `env.fail` reading from `input`.
+    pub fn run(&self, input: &[u8]) -> Outcome {
+        let mut instance = Instance {
+            module: self,
+            memory: vec![0; self.pages as usize * 65536],
+            globals: self.globals.iter().map(|global| global.init).collect(),
+            input: input.iter().map(|&byte| byte as i32).collect(),
+            output: vec![],
+            failure: None,
+        };
+        let run = self
+            .exports
+            .iter()
+            .find(|(name, kind, _)| name == "run" && *kind == 0x00)
+            .expect("`run` isn't exported");
+        let trap = instance.call(run.2, vec![]).err();
+
+        Outcome {
+            output: instance.output,
+            failure: instance.failure,
+            trap,
+            memory: instance.memory,
+        }
+    }
+}
+
+impl Instance<'_> {
+    fn call(&mut self, index: u32, args: Vec<i64>) -> Result<Vec<i64>> {
+        if let Some(import) = self.module.imports.get(index as usize) {
+            return match import.as_str() {
+                "env.read" => Ok(vec![self.input.pop_front().unwrap_or(-1) as i64]),
+                "env.write" => {
+                    self.output.push(args[0] as i32);
+                    Ok(vec![])
+                }
+                "env.fail" => {
+                    self.failure = Some([0, 1, 2, 3].map(|i| args[i] as i32));
+                    Ok(vec![])
+                }
+                _ => Err(format!("unknown import {import}")),
+            };
+        }
+
+        let module = self.module;
+        let body = &module.bodies[index as usize - module.imports.len()];
+        let results = module.func_type(index)?.results.len();
+        let mut locals = args;
+        locals.extend(body.locals.iter().map(|_| 0));
+
+        let mut stack: Vec<i64> = vec![];
+        // The labels with their targets and heights of the stack.
+        let mut labels: Vec<(usize, usize, bool)> = vec![];
+        let mut pc = 0;
+
+        macro_rules! pop {
+            () => {
+                stack.pop().unwrap()
+            };
+        }
+
+        while pc < body.code.len() {
+            let mut next = pc + 1;
+
+            match body.code[pc] {
+                Instr::Unreachable => return Err("unreachable".into()),
+                Instr::Block(end) => labels.push((end, stack.len(), false)),
+                Instr::Loop => labels.push((pc, stack.len(), true)),
+                Instr::If(end) => {
+                    if pop!() as i32 != 0 {
+                        labels.push((end, stack.len(), false));
+                    } else {
+                        next = end + 1;
+                    }
+                }
+                Instr::End => {
+                    labels.pop();
+                }
+                Instr::Br(depth) | Instr::BrIf(depth) => {
+                    let taken = match body.code[pc] {
+                        Instr::BrIf(_) => pop!() as i32 != 0,
+                        _ => true,
+                    };
+
+                    if taken {
+                        if depth as usize == labels.len() {
+                            break;
+                        }
+
+                        let i = labels.len() - 1 - depth as usize;
+                        let (target, height, is_loop) = labels[i];
+                        stack.truncate(height);
+                        labels.truncate(if is_loop { i + 1 } else { i });
+                        next = target + 1;
+                    }
+                }
+                Instr::Return => break,
+                Instr::Call(callee) => {
+                    let params = module.func_type(callee)?.params.len();
+                    let args = stack.split_off(stack.len() - params);
+                    stack.extend(self.call(callee, args)?);
+                }
+                Instr::Drop => {
+                    pop!();
+                }
+                Instr::Select => {
+                    let cond = pop!() as i32;
+                    let second = pop!();
+                    let first = pop!();
+                    stack.push(if cond != 0 { first } else { second });
+                }
+                Instr::LocalGet(index) => stack.push(locals[index as usize]),
+                Instr::LocalSet(index) => locals[index as usize] = pop!(),
+                Instr::LocalTee(index) => locals[index as usize] = *stack.last().unwrap(),
+                Instr::GlobalGet(index) => stack.push(self.globals[index as usize]),
+                Instr::GlobalSet(index) => self.globals[index as usize] = pop!(),
+                Instr::Memory(opcode, offset) => {
+                    let val = match opcode {
+                        0x36 | 0x3a => Some(pop!() as i32),
+                        _ => None,
+                    };
+                    let addr = pop!() as i32 as u32 as usize + offset as usize;
+                    let size = match opcode {
+                        0x28 | 0x36 => 4,
+                        _ => 1,
+                    };
+                    let bytes = self
+                        .memory
+                        .get_mut(addr..addr + size)
+                        .ok_or("out of bounds memory access")?;
+
+                    match (opcode, val) {
+                        (0x28, _) => {
+                            stack.push(i32::from_le_bytes(bytes.try_into().unwrap()) as i64)
+                        }
+                        (0x2c, _) => stack.push(bytes[0] as i8 as i64),
+                        (0x36, Some(val)) => bytes.copy_from_slice(&val.to_le_bytes()),
+                        (_, Some(val)) => bytes[0] = val as u8,
+                        _ => unreachable!(),
+                    }
+                }
+                Instr::I32Const(val) => stack.push(val as i64),
+                Instr::I64Const(val) => stack.push(val),
+                Instr::Numeric(opcode) => {
+                    let res = match opcode {
+                        0x45 => (pop!() as i32 == 0) as i64,
+                        0x50 => (pop!() == 0) as i64,
+                        0xa7 => pop!() as i32 as i64,
+                        0xac => pop!() as i32 as i64,
+                        0xad => pop!() as i32 as u32 as i64,
+                        0x46..=0x4f | 0x6a..=0x78 => {
+                            let b = pop!() as i32;
+                            let a = pop!() as i32;
+                            Self::i32(opcode, a, b)? as i64
+                        }
+                        _ => {
+                            let b = pop!();
+                            let a = pop!();
+                            Self::i64(opcode, a, b)?
+                        }
+                    };
+                    stack.push(res);
+                }
+            }
+
+            pc = next;
+        }
+
+        Ok(stack.split_off(stack.len() - results))
+    }
+
+    fn i32(opcode: u8, a: i32, b: i32) -> Result<i32> {
+        let (ua, ub) = (a as u32, b as u32);
+
+        Ok(match opcode {
+            0x46 => (a == b) as i32,
+            0x47 => (a != b) as i32,
+            0x48 => (a < b) as i32,
+            0x49 => (ua < ub) as i32,
+            0x4a => (a > b) as i32,
+            0x4b => (ua > ub) as i32,
+            0x4c => (a <= b) as i32,
+            0x4d => (ua <= ub) as i32,
+            0x4e => (a >= b) as i32,
+            0x4f => (ua >= ub) as i32,
+            0x6a => a.wrapping_add(b),
+            0x6b => a.wrapping_sub(b),
+            0x6c => a.wrapping_mul(b),
+            0x71 => a & b,
+            0x72 => a | b,
+            0x73 => a ^ b,
+            _ => return Err(format!("unsupported opcode {opcode:#x}")),
+        })
+    }
+
+    fn i64(opcode: u8, a: i64, b: i64) -> Result<i64> {
+        let (ua, ub) = (a as u64, b as u64);
+
+        Ok(match opcode {
+            0x51 => (a == b) as i64,
+            0x52 => (a != b) as i64,
+            0x53 => (a < b) as i64,
+            0x54 => (ua < ub) as i64,
+            0x55 => (a > b) as i64,
+            0x56 => (ua > ub) as i64,
+            0x57 => (a <= b) as i64,
+            0x58 => (ua <= ub) as i64,
+            0x59 => (a >= b) as i64,
+            0x5a => (ua >= ub) as i64,
+            0x7c => a.wrapping_add(b),
+            0x7d => a.wrapping_sub(b),
+            0x7e => a.wrapping_mul(b),
+            0x7f | 0x81 => {
+                if b == 0 {
+                    return Err("integer divide by zero".into());
+                }
+
+                match opcode {
+                    0x7f => a.checked_div(b).ok_or("integer overflow")?,
+                    _ => a.wrapping_rem(b),
+                }
+            }
+            0x83 => a & b,
+            0x84 => a | b,
+            0x85 => a ^ b,
+            _ => return Err(format!("unsupported opcode {opcode:#x}")),
+        })
+    }
+}
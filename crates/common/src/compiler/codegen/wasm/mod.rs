@@ -0,0 +1,1309 @@
+#[cfg(test)]
+mod machine;
+
+use std::fmt::Write;
+
+use super::{check_loops, hang_mask, Result, UnsupportedMemorySnafu};
+use crate::compiler::instruction::{Instruction, InstructionList};
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem};
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Eof, Overflow};
+use crate::execution::memory::strategy::split_step;
+use crate::execution::memory::AddrRange;
+
+/// The size of a page of the linear memory.
+const PAGE: u64 = 1 << 16;
+
+/// The kinds of the errors passed to `fail`.
+pub(crate) const FAIL_SEEK: i32 = 0;
+pub(crate) const FAIL_ACCESS: i32 = 1;
+pub(crate) const FAIL_ADD: i32 = 2;
+pub(crate) const FAIL_SET: i32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ValType {
+    I32,
+    I64,
+}
+
+impl ValType {
+    fn name(self) -> &'static str {
+        match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        }
+    }
+}
+
+/// The instructions the generated modules use. The blocks have no parameters or
+/// results, and the loads and the stores have no offset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Op {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load,
+    I32Load8S,
+    I32Store,
+    I32Store8,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32GtS,
+    I32GeU,
+    I64Eqz,
+    I64LtS,
+    I64GtS,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32And,
+    I32Or,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64RemS,
+    I64And,
+    I32WrapI64,
+    I64ExtendI32S,
+}
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Unreachable => "unreachable",
+            Op::Block => "block",
+            Op::Loop => "loop",
+            Op::If => "if",
+            Op::End => "end",
+            Op::Br(_) => "br",
+            Op::BrIf(_) => "br_if",
+            Op::Return => "return",
+            Op::Call(_) => "call",
+            Op::Drop => "drop",
+            Op::Select => "select",
+            Op::LocalGet(_) => "local.get",
+            Op::LocalSet(_) => "local.set",
+            Op::LocalTee(_) => "local.tee",
+            Op::GlobalGet(_) => "global.get",
+            Op::GlobalSet(_) => "global.set",
+            Op::I32Load => "i32.load",
+            Op::I32Load8S => "i32.load8_s",
+            Op::I32Store => "i32.store",
+            Op::I32Store8 => "i32.store8",
+            Op::I32Const(_) => "i32.const",
+            Op::I64Const(_) => "i64.const",
+            Op::I32Eqz => "i32.eqz",
+            Op::I32Eq => "i32.eq",
+            Op::I32Ne => "i32.ne",
+            Op::I32GtS => "i32.gt_s",
+            Op::I32GeU => "i32.ge_u",
+            Op::I64Eqz => "i64.eqz",
+            Op::I64LtS => "i64.lt_s",
+            Op::I64GtS => "i64.gt_s",
+            Op::I32Add => "i32.add",
+            Op::I32Sub => "i32.sub",
+            Op::I32Mul => "i32.mul",
+            Op::I32And => "i32.and",
+            Op::I32Or => "i32.or",
+            Op::I64Add => "i64.add",
+            Op::I64Sub => "i64.sub",
+            Op::I64Mul => "i64.mul",
+            Op::I64DivS => "i64.div_s",
+            Op::I64RemS => "i64.rem_s",
+            Op::I64And => "i64.and",
+            Op::I32WrapI64 => "i32.wrap_i64",
+            Op::I64ExtendI32S => "i64.extend_i32_s",
+        }
+    }
+
+    fn opcode(self) -> u8 {
+        match self {
+            Op::Unreachable => 0x00,
+            Op::Block => 0x02,
+            Op::Loop => 0x03,
+            Op::If => 0x04,
+            Op::End => 0x0b,
+            Op::Br(_) => 0x0c,
+            Op::BrIf(_) => 0x0d,
+            Op::Return => 0x0f,
+            Op::Call(_) => 0x10,
+            Op::Drop => 0x1a,
+            Op::Select => 0x1b,
+            Op::LocalGet(_) => 0x20,
+            Op::LocalSet(_) => 0x21,
+            Op::LocalTee(_) => 0x22,
+            Op::GlobalGet(_) => 0x23,
+            Op::GlobalSet(_) => 0x24,
+            Op::I32Load => 0x28,
+            Op::I32Load8S => 0x2c,
+            Op::I32Store => 0x36,
+            Op::I32Store8 => 0x3a,
+            Op::I32Const(_) => 0x41,
+            Op::I64Const(_) => 0x42,
+            Op::I32Eqz => 0x45,
+            Op::I32Eq => 0x46,
+            Op::I32Ne => 0x47,
+            Op::I32GtS => 0x4a,
+            Op::I32GeU => 0x4f,
+            Op::I64Eqz => 0x50,
+            Op::I64LtS => 0x53,
+            Op::I64GtS => 0x55,
+            Op::I32Add => 0x6a,
+            Op::I32Sub => 0x6b,
+            Op::I32Mul => 0x6c,
+            Op::I32And => 0x71,
+            Op::I32Or => 0x72,
+            Op::I64Add => 0x7c,
+            Op::I64Sub => 0x7d,
+            Op::I64Mul => 0x7e,
+            Op::I64DivS => 0x7f,
+            Op::I64RemS => 0x81,
+            Op::I64And => 0x83,
+            Op::I32WrapI64 => 0xa7,
+            Op::I64ExtendI32S => 0xac,
+        }
+    }
+}
+
+/// The functions of the module, in the order of their indices.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Func {
+    Read,
+    Write,
+    Fail,
+    Cur,
+    At,
+    Load,
+    Store,
+    Seek,
+    Add,
+    Set,
+    Scan,
+    Overflow,
+    Count,
+    AddRepeatedly,
+    Input,
+    Output,
+    Run,
+}
+
+impl Func {
+    const IMPORTED: [Func; 3] = [Func::Read, Func::Write, Func::Fail];
+    const DEFINED: [Func; 14] = [
+        Func::Cur,
+        Func::At,
+        Func::Load,
+        Func::Store,
+        Func::Seek,
+        Func::Add,
+        Func::Set,
+        Func::Scan,
+        Func::Overflow,
+        Func::Count,
+        Func::AddRepeatedly,
+        Func::Input,
+        Func::Output,
+        Func::Run,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Func::Read => "read",
+            Func::Write => "write",
+            Func::Fail => "fail",
+            Func::Cur => "cur",
+            Func::At => "at",
+            Func::Load => "load",
+            Func::Store => "store",
+            Func::Seek => "seek",
+            Func::Add => "add",
+            Func::Set => "set",
+            Func::Scan => "scan",
+            Func::Overflow => "overflow",
+            Func::Count => "count",
+            Func::AddRepeatedly => "add_repeatedly",
+            Func::Input => "input",
+            Func::Output => "output",
+            Func::Run => "run",
+        }
+    }
+
+    /// Return the parameters and the results.
+    fn signature(self) -> (Vec<ValType>, Vec<ValType>) {
+        use ValType::*;
+
+        match self {
+            Func::Read => (vec![], vec![I32]),
+            Func::Write => (vec![I32], vec![]),
+            Func::Fail => (vec![I32, I32, I32, I32], vec![]),
+            Func::Cur => (vec![], vec![I32]),
+            Func::At => (vec![I32, I32], vec![I32]),
+            Func::Load => (vec![I32], vec![I32]),
+            Func::Store => (vec![I32, I32], vec![]),
+            Func::Seek | Func::Scan | Func::Input | Func::Output => (vec![I32, I32], vec![]),
+            Func::Add | Func::Set | Func::Overflow => (vec![I32, I32, I32], vec![]),
+            Func::Count => (vec![I32, I32], vec![I64]),
+            Func::AddRepeatedly => (vec![I32, I32, I32, I64], vec![]),
+            Func::Run => (vec![], vec![]),
+        }
+    }
+
+    fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+struct Body {
+    locals: Vec<ValType>,
+    ops: Vec<Op>,
+}
+
+/// Translate programs into WebAssembly modules, in the text or the binary
+/// format. A module imports
+///
+/// ```text
+/// (import "env" "read" (func (result i32)))
+/// (import "env" "write" (func (param i32)))
+/// (import "env" "fail" (func (param i32 i32 i32 i32)))
+/// ```
+///
+/// where `read` and `write` do the same as `InStream::read` and
+/// `OutStream::write`, and `fail` takes the address of the failed instruction,
+/// the kind of the error and two values before the module traps. They are
+///
+/// - 0: `SeekOutOfBounds` with `now_position` and `offset`
+/// - 1: `AccessOutOfBounds` with `addr` and 0
+/// - 2: `AddOverflow` with `before` and `add`
+/// - 3: `SetOverflow` with `val` and 0
+///
+/// The module exports the function `run`, which runs the program, and its
+/// `memory`, where the cell at `addr` is the `addr - left`th 8-bit or 32-bit
+/// integer.
+pub struct WasmBackend {
+    memory: MemoryConfig,
+}
+
+impl WasmBackend {
+    pub fn new(memory: MemoryConfig) -> Self {
+        Self { memory }
+    }
+
+    pub fn generate_text(&self, list: &InstructionList) -> Result<String> {
+        let bodies = self.bodies(list)?;
+        let mut res = String::new();
+
+        writeln!(res, ";; Generated by bf-exec.").unwrap();
+        writeln!(res, "(module").unwrap();
+
+        for func in Func::IMPORTED {
+            writeln!(
+                res,
+                "  (import \"env\" \"{0}\" (func ${0}{1}))",
+                func.name(),
+                Self::signature_text(func)
+            )
+            .unwrap();
+        }
+
+        writeln!(res, "  (memory (export \"memory\") {})", self.pages()).unwrap();
+        writeln!(
+            res,
+            "  (global $p (mut i32) (i32.const {}))",
+            self.initial_index()
+        )
+        .unwrap();
+
+        for (func, body) in Func::DEFINED.into_iter().zip(bodies) {
+            let export = match func {
+                Func::Run => " (export \"run\")",
+                _ => "",
+            };
+            write!(
+                res,
+                "\n  (func ${}{export}{}",
+                func.name(),
+                Self::signature_text(func)
+            )
+            .unwrap();
+
+            if !body.locals.is_empty() {
+                let locals: Vec<_> = body.locals.iter().map(|ty| ty.name()).collect();
+                write!(res, " (local {})", locals.join(" ")).unwrap();
+            }
+
+            res.push('\n');
+            let mut indent = 2;
+
+            for op in body.ops {
+                if let Op::End = op {
+                    indent -= 1;
+                }
+
+                write!(res, "{:1$}{2}", "", indent * 2, op.name()).unwrap();
+
+                match op {
+                    Op::Br(n)
+                    | Op::BrIf(n)
+                    | Op::LocalGet(n)
+                    | Op::LocalSet(n)
+                    | Op::LocalTee(n) => write!(res, " {n}").unwrap(),
+                    Op::Call(n) => write!(res, " ${}", Self::func(n).name()).unwrap(),
+                    Op::GlobalGet(_) | Op::GlobalSet(_) => res.push_str(" $p"),
+                    Op::I32Const(n) => write!(res, " {n}").unwrap(),
+                    Op::I64Const(n) => write!(res, " {n}").unwrap(),
+                    _ => {}
+                }
+
+                res.push('\n');
+
+                if let Op::Block | Op::Loop | Op::If = op {
+                    indent += 1;
+                }
+            }
+
+            writeln!(res, "  )").unwrap();
+        }
+
+        writeln!(res, ")").unwrap();
+        Ok(res)
+    }
+
+    pub fn generate_binary(&self, list: &InstructionList) -> Result<Vec<u8>> {
+        let bodies = self.bodies(list)?;
+        let mut res = b"\0asm".to_vec();
+        res.extend_from_slice(&1u32.to_le_bytes());
+
+        // Every function has a type of its own.
+        let funcs: Vec<_> = Func::IMPORTED.into_iter().chain(Func::DEFINED).collect();
+        let mut types = vec![];
+        unsigned(&mut types, funcs.len() as u64);
+
+        for func in &funcs {
+            let (params, results) = func.signature();
+            types.push(0x60);
+
+            for list in [params, results] {
+                unsigned(&mut types, list.len() as u64);
+                types.extend(list.iter().map(|ty| ty.code()));
+            }
+        }
+
+        section(&mut res, 1, &types);
+
+        let mut imports = vec![];
+        unsigned(&mut imports, Func::IMPORTED.len() as u64);
+
+        for func in Func::IMPORTED {
+            name(&mut imports, "env");
+            name(&mut imports, func.name());
+            imports.push(0x00);
+            unsigned(&mut imports, func.index() as u64);
+        }
+
+        section(&mut res, 2, &imports);
+
+        let mut functions = vec![];
+        unsigned(&mut functions, Func::DEFINED.len() as u64);
+
+        for func in Func::DEFINED {
+            unsigned(&mut functions, func.index() as u64);
+        }
+
+        section(&mut res, 3, &functions);
+
+        let mut memories = vec![1, 0x00];
+        unsigned(&mut memories, self.pages());
+        section(&mut res, 5, &memories);
+
+        let mut globals = vec![1, ValType::I32.code(), 0x01, Op::I32Const(0).opcode()];
+        signed(&mut globals, self.initial_index());
+        globals.push(Op::End.opcode());
+        section(&mut res, 6, &globals);
+
+        let mut exports = vec![2];
+        name(&mut exports, "run");
+        exports.push(0x00);
+        unsigned(&mut exports, Func::Run.index() as u64);
+        name(&mut exports, "memory");
+        exports.extend_from_slice(&[0x02, 0x00]);
+        section(&mut res, 7, &exports);
+
+        let mut code = vec![];
+        unsigned(&mut code, bodies.len() as u64);
+
+        for body in bodies {
+            let mut func = vec![];
+            unsigned(&mut func, body.locals.len() as u64);
+
+            for ty in body.locals {
+                func.extend_from_slice(&[1, ty.code()]);
+            }
+
+            for op in body.ops {
+                Self::encode(&mut func, op);
+            }
+
+            func.push(Op::End.opcode());
+            unsigned(&mut code, func.len() as u64);
+            code.extend(func);
+        }
+
+        section(&mut res, 10, &code);
+        Ok(res)
+    }
+
+    fn encode(res: &mut Vec<u8>, op: Op) {
+        res.push(op.opcode());
+
+        match op {
+            Op::Block | Op::Loop | Op::If => res.push(0x40),
+            Op::Br(n)
+            | Op::BrIf(n)
+            | Op::Call(n)
+            | Op::LocalGet(n)
+            | Op::LocalSet(n)
+            | Op::LocalTee(n)
+            | Op::GlobalGet(n)
+            | Op::GlobalSet(n) => unsigned(res, n as u64),
+            // The alignment and the offset.
+            Op::I32Load | Op::I32Store => res.extend_from_slice(&[2, 0]),
+            Op::I32Load8S | Op::I32Store8 => res.extend_from_slice(&[0, 0]),
+            Op::I32Const(n) => signed(res, n as i64),
+            Op::I64Const(n) => signed(res, n),
+            _ => {}
+        }
+    }
+
+    fn func(index: u32) -> Func {
+        Func::IMPORTED
+            .into_iter()
+            .chain(Func::DEFINED)
+            .find(|func| func.index() == index)
+            .unwrap()
+    }
+
+    fn signature_text(func: Func) -> String {
+        let (params, results) = func.signature();
+        let mut res = String::new();
+
+        for (kind, list) in [("param", params), ("result", results)] {
+            if !list.is_empty() {
+                let types: Vec<_> = list.iter().map(|ty| ty.name()).collect();
+                write!(res, " ({kind} {})", types.join(" ")).unwrap();
+            }
+        }
+
+        res
+    }
+
+    fn range(&self) -> AddrRange {
+        self.memory.range()
+    }
+
+    fn cell_size(&self) -> u64 {
+        match self.memory.cell {
+            Cell::I8 => 1,
+            Cell::I32 => 4,
+        }
+    }
+
+    fn pages(&self) -> u64 {
+        (self.range().len() as u64 * self.cell_size())
+            .div_ceil(PAGE)
+            .max(1)
+    }
+
+    fn initial_index(&self) -> i64 {
+        -(self.range().left as i64)
+    }
+
+    fn bounds(&self) -> (i64, i64) {
+        match self.memory.cell {
+            Cell::I8 => (i8::MIN as i64, i8::MAX as i64),
+            Cell::I32 => (i32::MIN as i64, i32::MAX as i64),
+        }
+    }
+
+    fn wrap(&self) -> bool {
+        matches!(self.memory.overflow, Overflow::Wrap)
+    }
+
+    fn bodies(&self, list: &InstructionList) -> Result<Vec<Body>> {
+        check_loops(list)?;
+        let len = self.range().len();
+        // The bytes of the memory, which should be addressed by 32 bits.
+        ensure_memory(len as u64 * self.cell_size() <= u32::MAX as u64 + 1, len)?;
+
+        Ok(Func::DEFINED
+            .into_iter()
+            .map(|func| match func {
+                Func::Run => self.run(list),
+                func => self.helper(func),
+            })
+            .collect())
+    }
+
+    /// Push the ops failing if the `I64` on the stack is out of the range of the
+    /// cells, which pass the four values of `args` to `fail`.
+    fn check_overflow(&self, ops: &mut Vec<Op>, val: u32, args: [Op; 4]) {
+        use Op::*;
+
+        let (min, max) = self.bounds();
+        ops.extend([
+            LocalGet(val),
+            I64Const(min),
+            I64LtS,
+            LocalGet(val),
+            I64Const(max),
+            I64GtS,
+            I32Or,
+            If,
+        ]);
+        ops.extend(args);
+        ops.extend([Call(Func::Fail.index()), Unreachable, End]);
+    }
+
+    /// Return the body of a function shared by the instructions, which does the
+    /// same as the method of `Memory` with the same name. The most take the
+    /// address of the instruction first, and the offset of the cell next.
+    fn helper(&self, func: Func) -> Body {
+        use Op::*;
+        use ValType::*;
+
+        let len = self.range().len() as i32;
+        let left = self.range().left as i32;
+        let size = self.cell_size() as i32;
+        let (load, store) = match self.memory.cell {
+            Cell::I8 => (I32Load8S, I32Store8),
+            Cell::I32 => (I32Load, I32Store),
+        };
+        let fail = Call(Func::Fail.index());
+
+        let (locals, ops) = match func {
+            // The address in the memory of the current cell.
+            Func::Cur => (vec![], vec![GlobalGet(0), I32Const(size), I32Mul]),
+            // The address in the memory of the cell at the offset.
+            Func::At => (
+                vec![I32],
+                vec![
+                    GlobalGet(0),
+                    LocalGet(1),
+                    I32Add,
+                    LocalTee(2),
+                    I32Const(len),
+                    I32GeU,
+                    If,
+                    LocalGet(0),
+                    I32Const(FAIL_ACCESS),
+                    LocalGet(2),
+                    I32Const(left),
+                    I32Add,
+                    I32Const(0),
+                    fail,
+                    Unreachable,
+                    End,
+                    LocalGet(2),
+                    I32Const(size),
+                    I32Mul,
+                ],
+            ),
+            Func::Load => (vec![], vec![LocalGet(0), load]),
+            Func::Store => (vec![], vec![LocalGet(0), LocalGet(1), store]),
+            Func::Seek => (
+                vec![I32],
+                vec![
+                    GlobalGet(0),
+                    LocalGet(1),
+                    I32Add,
+                    LocalTee(2),
+                    I32Const(len),
+                    I32GeU,
+                    If,
+                    LocalGet(0),
+                    I32Const(FAIL_SEEK),
+                    GlobalGet(0),
+                    I32Const(left),
+                    I32Add,
+                    LocalGet(1),
+                    fail,
+                    Unreachable,
+                    End,
+                    LocalGet(2),
+                    GlobalSet(0),
+                ],
+            ),
+            // Takes the value to add third.
+            Func::Add if self.wrap() => (
+                vec![I32],
+                vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    LocalTee(3),
+                    LocalGet(3),
+                    Call(Func::Load.index()),
+                    LocalGet(2),
+                    I32Add,
+                    Call(Func::Store.index()),
+                ],
+            ),
+            Func::Add => {
+                let mut ops = vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    LocalTee(3),
+                    Call(Func::Load.index()),
+                    LocalTee(4),
+                    I64ExtendI32S,
+                    LocalGet(2),
+                    I64ExtendI32S,
+                    I64Add,
+                    LocalSet(5),
+                ];
+                let args = [LocalGet(0), I32Const(FAIL_ADD), LocalGet(4), LocalGet(2)];
+                self.check_overflow(&mut ops, 5, args);
+                ops.extend([
+                    LocalGet(3),
+                    LocalGet(5),
+                    I32WrapI64,
+                    Call(Func::Store.index()),
+                ]);
+                (vec![I32, I32, I64], ops)
+            }
+            // Takes the value to set third.
+            Func::Set if self.wrap() => (
+                vec![],
+                vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    LocalGet(2),
+                    Call(Func::Store.index()),
+                ],
+            ),
+            Func::Set => {
+                let mut ops = vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    LocalSet(3),
+                    LocalGet(2),
+                    I64ExtendI32S,
+                    LocalSet(4),
+                ];
+                let args = [LocalGet(0), I32Const(FAIL_SET), LocalGet(2), I32Const(0)];
+                self.check_overflow(&mut ops, 4, args);
+                ops.extend([LocalGet(3), LocalGet(2), Call(Func::Store.index())]);
+                (vec![I32, I64], ops)
+            }
+            // Takes the stride second.
+            Func::Scan => (
+                vec![],
+                vec![
+                    Block,
+                    Loop,
+                    Call(Func::Cur.index()),
+                    Call(Func::Load.index()),
+                    I32Eqz,
+                    BrIf(1),
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::Seek.index()),
+                    Br(0),
+                    End,
+                    End,
+                ],
+            ),
+            // Fail as adding the value second to the value third until it
+            // overflows, where the value before the failed adding is
+            // `before + room / |add| * add`.
+            Func::Overflow => (
+                vec![I64, I64, I64],
+                vec![
+                    LocalGet(1),
+                    I64ExtendI32S,
+                    LocalSet(3),
+                    LocalGet(2),
+                    I64ExtendI32S,
+                    LocalSet(4),
+                    // The room left for adding.
+                    I64Const(self.bounds().1),
+                    LocalGet(3),
+                    I64Sub,
+                    LocalGet(3),
+                    I64Const(self.bounds().0),
+                    I64Sub,
+                    LocalGet(2),
+                    I32Const(0),
+                    I32GtS,
+                    Select,
+                    // The absolute value of `add`.
+                    LocalGet(4),
+                    I64Const(0),
+                    LocalGet(4),
+                    I64Sub,
+                    LocalGet(2),
+                    I32Const(0),
+                    I32GtS,
+                    Select,
+                    I64DivS,
+                    LocalGet(4),
+                    I64Mul,
+                    LocalGet(3),
+                    I64Add,
+                    LocalSet(5),
+                    LocalGet(0),
+                    I32Const(FAIL_ADD),
+                    LocalGet(5),
+                    I32WrapI64,
+                    LocalGet(2),
+                    fail,
+                    Unreachable,
+                ],
+            ),
+            // Count how many times the step second should be added to the
+            // current cell until it's zero, when the cells don't wrap.
+            Func::Count => (
+                vec![I32],
+                vec![
+                    Call(Func::Cur.index()),
+                    Call(Func::Load.index()),
+                    LocalTee(2),
+                    I64ExtendI32S,
+                    LocalGet(1),
+                    I64ExtendI32S,
+                    I64RemS,
+                    I64Eqz,
+                    LocalGet(2),
+                    I32Const(0),
+                    I32GtS,
+                    LocalGet(1),
+                    I32Const(0),
+                    I32GtS,
+                    I32Ne,
+                    I32And,
+                    If,
+                    I64Const(0),
+                    LocalGet(2),
+                    I64ExtendI32S,
+                    LocalGet(1),
+                    I64ExtendI32S,
+                    I64DivS,
+                    I64Sub,
+                    Return,
+                    End,
+                    // The counter never reaches zero, so it overflows at last.
+                    LocalGet(0),
+                    LocalGet(2),
+                    LocalGet(1),
+                    Call(Func::Overflow.index()),
+                    Unreachable,
+                ],
+            ),
+            // Takes the value to add third and the count fourth.
+            Func::AddRepeatedly if self.wrap() => (
+                vec![I32],
+                vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    LocalTee(4),
+                    LocalGet(4),
+                    Call(Func::Load.index()),
+                    LocalGet(2),
+                    LocalGet(3),
+                    I32WrapI64,
+                    I32Mul,
+                    I32Add,
+                    Call(Func::Store.index()),
+                ],
+            ),
+            Func::AddRepeatedly => {
+                let (min, max) = self.bounds();
+                let ops = vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    LocalTee(4),
+                    Call(Func::Load.index()),
+                    LocalTee(5),
+                    I64ExtendI32S,
+                    LocalGet(2),
+                    I64ExtendI32S,
+                    LocalGet(3),
+                    I64Mul,
+                    I64Add,
+                    LocalSet(6),
+                    LocalGet(6),
+                    I64Const(min),
+                    I64LtS,
+                    LocalGet(6),
+                    I64Const(max),
+                    I64GtS,
+                    I32Or,
+                    If,
+                    LocalGet(0),
+                    LocalGet(5),
+                    LocalGet(2),
+                    Call(Func::Overflow.index()),
+                    End,
+                    LocalGet(4),
+                    LocalGet(6),
+                    I32WrapI64,
+                    Call(Func::Store.index()),
+                ];
+                (vec![I32, I32, I64], ops)
+            }
+            // The input is read before the bounds are checked.
+            Func::Input => {
+                let mut ops = vec![
+                    Call(Func::Read.index()),
+                    LocalSet(2),
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    Drop,
+                ];
+
+                match self.memory.eof {
+                    Eof::Zero => ops.extend([
+                        LocalGet(2),
+                        I32Const(-1),
+                        I32Eq,
+                        If,
+                        I32Const(0),
+                        LocalSet(2),
+                        End,
+                    ]),
+                    Eof::Ignore => ops.extend([LocalGet(2), I32Const(-1), I32Eq, If, Return, End]),
+                    Eof::Keep => {}
+                }
+
+                ops.extend([
+                    LocalGet(0),
+                    LocalGet(1),
+                    LocalGet(2),
+                    Call(Func::Set.index()),
+                ]);
+                (vec![I32], ops)
+            }
+            Func::Output => (
+                vec![],
+                vec![
+                    LocalGet(0),
+                    LocalGet(1),
+                    Call(Func::At.index()),
+                    Call(Func::Load.index()),
+                    Call(Func::Write.index()),
+                ],
+            ),
+            Func::Read | Func::Write | Func::Fail | Func::Run => unreachable!(),
+        };
+
+        Body { locals, ops }
+    }
+
+    /// Return the body of `run`, whose locals are the count of `AddUntilZero`,
+    /// the value of a cell and the count of `OutputRepeat`, each of which is
+    /// declared only if the program reads it.
+    fn run(&self, list: &InstructionList) -> Body {
+        use Op::*;
+
+        let counted = list.instructions.iter().any(|instruction| {
+            matches!(instruction, Instruction::AddUntilZero { target, .. }
+                if target.iter().any(|arg| !arg.clear))
+        });
+        let checked = self.wrap()
+            && list
+                .instructions
+                .iter()
+                .any(|instruction| matches!(instruction, Instruction::AddUntilZero { .. }));
+        let repeated = list
+            .instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::OutputRepeat { .. }));
+
+        let mut locals = vec![];
+        let mut local = |ty, used: bool| {
+            used.then(|| {
+                locals.push(ty);
+                locals.len() as u32 - 1
+            })
+        };
+        let count = local(ValType::I64, counted);
+        let val = local(ValType::I32, checked || repeated);
+        let times = local(ValType::I32, repeated);
+        let mut ops = vec![];
+        let call = |func: Func| Call(func.index());
+        // The loops load the current cell without calls.
+        let size = self.cell_size() as i32;
+        let load = match self.memory.cell {
+            Cell::I8 => I32Load8S,
+            Cell::I32 => I32Load,
+        };
+
+        for (pc, instruction) in list.instructions.iter().enumerate() {
+            let pc = I32Const(pc as i32);
+
+            match instruction {
+                Instruction::Add { offset, val } => ops.extend([
+                    pc,
+                    I32Const(*offset as i32),
+                    I32Const(*val),
+                    call(Func::Add),
+                ]),
+                Instruction::Seek { offset } => {
+                    ops.extend([pc, I32Const(*offset as i32), call(Func::Seek)])
+                }
+                Instruction::Clear => ops.extend([call(Func::Cur), I32Const(0), call(Func::Store)]),
+                Instruction::AddUntilZero { target, step } => {
+                    ops.extend([Block, GlobalGet(0), I32Const(size), I32Mul, load]);
+
+                    if self.wrap() {
+                        ops.push(LocalTee(val.unwrap()));
+                    }
+
+                    ops.extend([I32Eqz, BrIf(0)]);
+                    let counted = target.iter().any(|arg| !arg.clear);
+
+                    if self.wrap() {
+                        let val = val.unwrap();
+
+                        if let Some(mask) = hang_mask(&self.memory, *step) {
+                            ops.extend([LocalGet(val), I32Const(mask as i32), I32And, If]);
+
+                            for arg in target {
+                                ops.extend([pc, I32Const(arg.offset as i32), call(Func::At), Drop]);
+                            }
+
+                            ops.extend([Loop, Br(0), End, End]);
+                        }
+
+                        let bits = match self.memory.cell {
+                            Cell::I8 => 8,
+                            Cell::I32 => 32,
+                        };
+                        // It's never reached if the step is a multiple of the
+                        // modulus, since the counter isn't zero.
+                        let (shift, inverse) = split_step(*step, bits).unwrap_or((0, 0));
+
+                        if counted {
+                            // The counter is a multiple of `2^shift`, so the
+                            // division is exact.
+                            ops.extend([
+                                I64Const(0),
+                                LocalGet(val),
+                                I64ExtendI32S,
+                                I64Sub,
+                                I64Const(1 << shift),
+                                I64DivS,
+                                I64Const(inverse as i64),
+                                I64Mul,
+                                I64Const((u64::MAX >> (64 - bits + shift)) as i64),
+                                I64And,
+                                LocalSet(count.unwrap()),
+                            ]);
+                        }
+                    } else {
+                        // The count is still needed to check the overflow.
+                        ops.extend([pc, I32Const(*step), call(Func::Count)]);
+                        ops.push(if counted {
+                            LocalSet(count.unwrap())
+                        } else {
+                            Drop
+                        });
+                    }
+
+                    ops.extend([call(Func::Cur), I32Const(0), call(Func::Store)]);
+
+                    for AddUntilZeroArg {
+                        offset,
+                        times,
+                        clear,
+                    } in target
+                    {
+                        ops.extend([pc, I32Const(*offset as i32), I32Const(*times)]);
+
+                        if *clear {
+                            ops.push(call(Func::Set));
+                        } else {
+                            ops.extend([LocalGet(count.unwrap()), call(Func::AddRepeatedly)]);
+                        }
+                    }
+
+                    ops.push(End);
+                }
+                Instruction::Scan { stride } => {
+                    ops.extend([pc, I32Const(*stride as i32), call(Func::Scan)])
+                }
+                Instruction::Set { offset, val } => ops.extend([
+                    pc,
+                    I32Const(*offset as i32),
+                    I32Const(*val),
+                    call(Func::Set),
+                ]),
+                Instruction::Input { offset } => {
+                    ops.extend([pc, I32Const(*offset as i32), call(Func::Input)])
+                }
+                Instruction::Output { offset } => {
+                    ops.extend([pc, I32Const(*offset as i32), call(Func::Output)])
+                }
+                Instruction::OutputBytes { bytes } => {
+                    for byte in bytes {
+                        ops.extend([I32Const(*byte), call(Func::Write)]);
+                    }
+                }
+                Instruction::OutputRepeat { offset, count } => ops.extend([
+                    pc,
+                    I32Const(*offset as i32),
+                    call(Func::At),
+                    call(Func::Load),
+                    LocalSet(val.unwrap()),
+                    I32Const(*count as i32),
+                    LocalSet(times.unwrap()),
+                    Block,
+                    Loop,
+                    LocalGet(times.unwrap()),
+                    I32Eqz,
+                    BrIf(1),
+                    LocalGet(val.unwrap()),
+                    call(Func::Write),
+                    LocalGet(times.unwrap()),
+                    I32Const(1),
+                    I32Sub,
+                    LocalSet(times.unwrap()),
+                    Br(0),
+                    End,
+                    End,
+                ]),
+                // The items before a cell out of the memory are written.
+                Instruction::OutputSeq { items } => {
+                    for item in items {
+                        match item {
+                            OutputItem::Cell { offset } => {
+                                ops.extend([pc, I32Const(*offset as i32), call(Func::Output)])
+                            }
+                            OutputItem::Byte { val } => {
+                                ops.extend([I32Const(*val), call(Func::Write)])
+                            }
+                        }
+                    }
+                }
+                Instruction::JumpIfZero { .. } => ops.extend([
+                    Block,
+                    Loop,
+                    GlobalGet(0),
+                    I32Const(size),
+                    I32Mul,
+                    load,
+                    I32Eqz,
+                    BrIf(1),
+                ]),
+                Instruction::Jump { .. } => ops.extend([Br(0), End, End]),
+                Instruction::Halt => {}
+            }
+        }
+
+        Body { locals, ops }
+    }
+}
+
+fn ensure_memory(fits: bool, len: usize) -> Result<()> {
+    if fits {
+        Ok(())
+    } else {
+        UnsupportedMemorySnafu { len }.fail()
+    }
+}
+
+/// Write an unsigned LEB128 integer.
+fn unsigned(res: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+
+        if val == 0 {
+            res.push(byte);
+            return;
+        }
+
+        res.push(byte | 0x80);
+    }
+}
+
+/// Write a signed LEB128 integer.
+fn signed(res: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            res.push(byte);
+            return;
+        }
+
+        res.push(byte | 0x80);
+    }
+}
+
+fn name(res: &mut Vec<u8>, name: &str) {
+    unsigned(res, name.len() as u64);
+    res.extend_from_slice(name.as_bytes());
+}
+
+fn section(res: &mut Vec<u8>, id: u8, content: &[u8]) {
+    res.push(id);
+    unsigned(res, content.len() as u64);
+    res.extend_from_slice(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::machine::Module;
+    use super::*;
+    use crate::compiler::codegen::tests::{compile, examples, interpret, strategies};
+    use crate::compiler::codegen::CodegenError;
+    use crate::compiler::Compiler;
+    use crate::execution::memory::config::Addr;
+    use crate::execution::memory::MemoryError;
+
+    /// Run the code translated to a module, and return the same as `interpret`.
+    fn execute(
+        code: &str,
+        memory: MemoryConfig,
+        input: &[u8],
+    ) -> std::result::Result<Vec<i32>, String> {
+        let list = compile(code, &memory);
+        let range = memory.range();
+        let backend = WasmBackend::new(memory);
+        let module = Module::parse(&backend.generate_binary(&list).unwrap()).unwrap();
+        let text = backend.generate_text(&list).unwrap();
+        assert!(Module::parse_text(&text).unwrap() == module, "{code}");
+        let outcome = module.run(input);
+
+        let Some([pc, kind, a, b]) = outcome.failure else {
+            assert_eq!(outcome.trap, None);
+            return Ok(outcome.output);
+        };
+
+        assert_eq!(outcome.trap.as_deref(), Some("unreachable"));
+        let source = match kind {
+            FAIL_SEEK => MemoryError::SeekOutOfBounds {
+                now_position: a as isize,
+                offset: b as isize,
+                range,
+            },
+            FAIL_ACCESS => MemoryError::AccessOutOfBounds {
+                addr: a as isize,
+                range,
+            },
+            FAIL_ADD => MemoryError::AddOverflow { before: a, add: b },
+            FAIL_SET => MemoryError::SetOverflow { val: a },
+            _ => panic!("unknown kind {kind}"),
+        };
+        Err(format!(
+            "error: invalid memory operation occurred at instruction {pc} ({}): {source}\n",
+            list.spans[pc as usize].start
+        ))
+    }
+
+    #[test]
+    fn examples_run_the_same() {
+        for (code, memory, input) in examples() {
+            assert_eq!(
+                execute(code, memory.clone(), &input),
+                interpret(code, memory, &input)
+            );
+        }
+    }
+
+    #[test]
+    fn strategies_run_the_same() {
+        for (code, memory, input) in strategies() {
+            assert_eq!(
+                execute(code, memory.clone(), input.as_bytes()),
+                interpret(code, memory, input.as_bytes()),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn memory_layout() {
+        let list = Compiler::new().compile(">+++<<-").unwrap();
+        let memory = MemoryConfig {
+            len: 10,
+            addr: Addr::Signed,
+            cell: Cell::I32,
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+        let binary = WasmBackend::new(memory).generate_binary(&list).unwrap();
+        let outcome = Module::parse(&binary).unwrap().run(&[]);
+        let cells: Vec<_> = outcome.memory[..40]
+            .chunks(4)
+            .map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(cells, [0, 0, 0, 0, -1, 0, 3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn text_format() {
+        let list = Compiler::new().compile(",[.,]").unwrap();
+        let text = WasmBackend::new(Default::default())
+            .generate_text(&list)
+            .unwrap();
+
+        assert!(text.contains("(import \"env\" \"read\" (func $read (result i32)))"));
+        assert!(text.contains("(import \"env\" \"write\" (func $write (param i32)))"));
+        assert!(text.contains("(memory (export \"memory\") 1)"));
+        assert!(text.contains("(func $run (export \"run\")\n"));
+        assert!(text.contains("    call $input\n    block\n      loop\n"));
+        assert_eq!(text.matches('(').count(), text.matches(')').count());
+    }
+
+    #[test]
+    fn unsupported_programs() {
+        let list = InstructionList::new(vec![Instruction::Jump { target: 0 }]);
+        let backend = WasmBackend::new(Default::default());
+        assert_eq!(
+            backend.generate_binary(&list).map(|_| ()),
+            Err(CodegenError::UnpairedJump { addr: 0 })
+        );
+
+        let list = Compiler::new().compile("+").unwrap();
+        let backend = WasmBackend::new(MemoryConfig {
+            len: 1 << 31,
+            cell: Cell::I32,
+            ..Default::default()
+        });
+        assert_eq!(
+            backend.generate_text(&list).map(|_| ()),
+            Err(CodegenError::UnsupportedMemory { len: 1 << 31 })
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use crate::execution::memory::config::Config as MemoryConfig;
+
+#[derive(Clone)]
+pub struct Config {
+    pub opt_level: OptLevel,
+    /// The names of the rules to run regardless of `opt_level`.
+    pub enable: Vec<String>,
+    /// The names of the rules not to run, which takes precedence over `enable`.
+    pub disable: Vec<String>,
+    /// The memory the code will run on. The rules relying on its behaviors are
+    /// skipped if it's unknown.
+    pub target: Option<MemoryConfig>,
+    /// Whether the code runs alone on a fresh memory, which isn't looked at after
+    /// the code halts. It's false when the memory is kept between pieces of code,
+    /// like in a REPL.
+    pub standalone: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::default(),
+            enable: vec![],
+            disable: vec![],
+            target: None,
+            standalone: true,
+        }
+    }
+}
+
+/// How hard the compiler tries to optimize the code. Each level runs the rules
+/// of the levels below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// No optimization, so that every instruction maps to a run of the same
+    /// commands in the code.
+    O0,
+    /// Turn the simplest loops into single instructions.
+    O1,
+    /// Rewrite multiplication loops and straight-line code, coalesce outputs and
+    /// remove dead code.
+    O2,
+    /// Also fuse the writes to the same cell, and run the code before reading the
+    /// input at compile time.
+    #[default]
+    O3,
+}
@@ -0,0 +1,206 @@
+use std::fmt::{Display, Formatter, Write};
+
+use crate::compiler::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Extra information attached to a `Diagnostic`, optionally pointing to another
+/// piece of the source code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+/// A message about the source code, which can be rendered with an excerpt of
+/// the code and carets under the offending part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new(level: Level, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            level,
+            message: message.into(),
+            span,
+            label: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Level::Error, message, span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self::new(Level::Warning, message, span)
+    }
+
+    /// Set the text shown right after the carets.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn note(mut self, message: impl Into<String>) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            span: None,
+        });
+        self
+    }
+
+    pub fn note_at(mut self, message: impl Into<String>, span: Span) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            span: Some(span),
+        });
+        self
+    }
+
+    /// Render the diagnostic for the source code `code` read from `name`, such as:
+    ///
+    /// ```text
+    /// error: unclosed loop, expected another `]`
+    ///  --> hello.bf:1:2
+    ///   |
+    /// 1 | +[>+
+    ///   |  ^ this `[` is never closed
+    /// ```
+    pub fn render(&self, name: &str, code: &str) -> String {
+        let width = self
+            .notes
+            .iter()
+            .filter_map(|note| note.span)
+            .chain([self.span])
+            .map(|span| span.start.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let mut res = String::new();
+
+        writeln!(res, "{}: {}", self.level, self.message).unwrap();
+        excerpt(
+            &mut res,
+            name,
+            code,
+            self.span,
+            self.label.as_deref(),
+            width,
+        );
+
+        for note in &self.notes {
+            match note.span {
+                Some(span) => {
+                    writeln!(res, "note: {}", note.message).unwrap();
+                    excerpt(&mut res, name, code, span, None, width);
+                }
+                None => writeln!(res, "{:width$} = note: {}", "", note.message).unwrap(),
+            }
+        }
+
+        res
+    }
+}
+
+/// Write the location and the line of `span` with carets under it. Only the
+/// location is written for a generated (empty) span.
+fn excerpt(
+    res: &mut String,
+    name: &str,
+    code: &str,
+    span: Span,
+    label: Option<&str>,
+    width: usize,
+) {
+    if span.is_empty() || span.start.offset > code.len() {
+        writeln!(res, "{:width$}--> {name}", "").unwrap();
+        return;
+    }
+
+    writeln!(res, "{:width$}--> {name}:{}", "", span.start).unwrap();
+
+    let line_start = code[..span.start.offset]
+        .rfind('\n')
+        .map_or(0, |index| index + 1);
+    let line_end = code[span.start.offset..]
+        .find('\n')
+        .map_or(code.len(), |index| span.start.offset + index);
+    let line = code[line_start..line_end].trim_end_matches('\r');
+
+    // Keep the tabs so that the carets are aligned with the code.
+    let indent: String = code[line_start..span.start.offset]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let end = span.end.offset.min(line_end).max(span.start.offset);
+    let carets = code[span.start.offset..end].chars().count().max(1);
+
+    writeln!(res, "{:width$} |", "").unwrap();
+    writeln!(res, "{:>width$} | {line}", span.start.line).unwrap();
+    write!(res, "{:width$} | {indent}{}", "", "^".repeat(carets)).unwrap();
+
+    if let Some(label) = label {
+        write!(res, " {label}").unwrap();
+    }
+
+    res.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::span::Position;
+
+    #[test]
+    fn render() {
+        let code = "+\n+[>-\n]]";
+        let diagnostic =
+            Diagnostic::error("unexpected `]`", Span::of_char(Position::new(8, 3, 2), ']'))
+                .label("this `]` doesn't close any loop")
+                .note_at(
+                    "loop opened here",
+                    Span::of_char(Position::new(3, 2, 2), '['),
+                )
+                .note("remove it");
+        let expected = "\
+error: unexpected `]`
+ --> test.bf:3:2
+  |
+3 | ]]
+  |  ^ this `]` doesn't close any loop
+note: loop opened here
+ --> test.bf:2:2
+  |
+2 | +[>-
+  |  ^
+  = note: remove it
+";
+        assert_eq!(diagnostic.render("test.bf", code), expected);
+    }
+
+    #[test]
+    fn render_generated_code() {
+        let diagnostic = Diagnostic::warning("something happened", Span::default());
+        let expected = "warning: something happened\n --> test.bf\n";
+        assert_eq!(diagnostic.render("test.bf", ""), expected);
+    }
+}
@@ -1,113 +1,402 @@
-use crate::compiler::parser::{AddUntilZeroArg, SyntaxTree};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
 
-#[derive(Debug, PartialEq, Eq)]
+use crate::compiler::parser::{AddUntilZeroArg, OutputItem, SyntaxTree};
+use crate::compiler::span::{self, Span};
+
+/// The largest `count` of `OutputRepeat`, whose values are written at once.
+pub(crate) const MAX_REPEAT_COUNT: usize = 1 << 20;
+
+/// `offset` in `Add`, `Set`, `Input` and `Output` is the position of the cell
+/// they operate, relative to the pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
-    Add { val: i32 },
-    Seek { offset: isize },
+    Add {
+        offset: isize,
+        val: i32,
+    },
+    Seek {
+        offset: isize,
+    },
     Clear,
-    AddUntilZero { target: Vec<AddUntilZeroArg> },
-    Input,
-    Output,
-    Jump { target: usize },
-    JumpIfZero { target: usize },
+    AddUntilZero {
+        target: Vec<AddUntilZeroArg>,
+        step: i32,
+    },
+    Scan {
+        stride: isize,
+    },
+    Set {
+        offset: isize,
+        val: i32,
+    },
+    Input {
+        offset: isize,
+    },
+    Output {
+        offset: isize,
+    },
+    OutputBytes {
+        bytes: Vec<i32>,
+    },
+    OutputRepeat {
+        offset: isize,
+        count: usize,
+    },
+    OutputSeq {
+        items: Vec<OutputItem>,
+    },
+    Jump {
+        target: usize,
+    },
+    JumpIfZero {
+        target: usize,
+    },
     Halt,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct InstructionList(pub Vec<Instruction>);
+impl Instruction {
+    /// Convert a node without a block to the instruction, or return `None` for
+    /// `Root` and `Loop`.
+    pub(crate) fn from_leaf(node: SyntaxTree) -> Option<(Instruction, Span)> {
+        let res = match node {
+            SyntaxTree::Add { offset, val, span } => (Instruction::Add { offset, val }, span),
+            SyntaxTree::Seek { offset, span } => (
+                Instruction::Seek {
+                    offset: offset as isize,
+                },
+                span,
+            ),
+            SyntaxTree::Clear { span } => (Instruction::Clear, span),
+            SyntaxTree::AddUntilZero { target, step, span } => {
+                (Instruction::AddUntilZero { target, step }, span)
+            }
+            SyntaxTree::Scan { stride, span } => (
+                Instruction::Scan {
+                    stride: stride as isize,
+                },
+                span,
+            ),
+            SyntaxTree::Set { offset, val, span } => (Instruction::Set { offset, val }, span),
+            SyntaxTree::Input { offset, span } => (Instruction::Input { offset }, span),
+            SyntaxTree::Output { offset, span } => (Instruction::Output { offset }, span),
+            SyntaxTree::OutputBytes { bytes, span } => (Instruction::OutputBytes { bytes }, span),
+            SyntaxTree::OutputRepeat {
+                offset,
+                count,
+                span,
+            } => (Instruction::OutputRepeat { offset, count }, span),
+            SyntaxTree::OutputSeq { items, span } => (Instruction::OutputSeq { items }, span),
+            SyntaxTree::Root { .. } | SyntaxTree::Loop { .. } => return None,
+        };
+
+        Some(res)
+    }
+}
+
+impl Display for Instruction {
+    /// Write the instruction in the IR text format, where the targets of jumps
+    /// are addresses.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::Add { offset, val } => write!(f, "add {offset}, {val}"),
+            Instruction::Seek { offset } => write!(f, "seek {offset}"),
+            Instruction::Clear => write!(f, "clear"),
+            Instruction::AddUntilZero { target, step } => {
+                write!(f, "add_until_zero {step}, [{}]", join(target))
+            }
+            Instruction::Scan { stride } => write!(f, "scan {stride}"),
+            Instruction::Set { offset, val } => write!(f, "set {offset}, {val}"),
+            Instruction::Input { offset } => write!(f, "input {offset}"),
+            Instruction::Output { offset } => write!(f, "output {offset}"),
+            Instruction::OutputBytes { bytes } => write!(f, "output_bytes [{}]", join(bytes)),
+            Instruction::OutputRepeat { offset, count } => {
+                write!(f, "output_repeat {offset}, {count}")
+            }
+            Instruction::OutputSeq { items } => write!(f, "output_seq [{}]", join(items)),
+            Instruction::Jump { target } => write!(f, "jmp {target}"),
+            Instruction::JumpIfZero { target } => write!(f, "jz {target}"),
+            Instruction::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+fn join<T: Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The compiled program. `spans[i]` is the source code which `instructions[i]`
+/// comes from, or an empty span when it's generated (e.g. `Instruction::Halt`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionList {
+    pub instructions: Vec<Instruction>,
+    pub spans: Vec<Span>,
+}
 
 impl InstructionList {
+    /// Build an `InstructionList` without source code information.
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        let spans = vec![Span::default(); instructions.len()];
+        Self {
+            instructions,
+            spans,
+        }
+    }
+
     pub fn compile(syntax_tree: SyntaxTree) -> InstructionList {
         let root = match syntax_tree {
             SyntaxTree::Root { block: v } => v,
             _ => unreachable!(),
         };
 
-        let mut ins = vec![];
-        InstructionList::compile_impl(&mut ins, root);
-        ins.push(Instruction::Halt);
-        InstructionList(ins)
+        let mut res = InstructionList {
+            instructions: vec![],
+            spans: vec![],
+        };
+        res.compile_impl(root);
+        res.push(Instruction::Halt, Span::default());
+        res
+    }
+
+    fn push(&mut self, instruction: Instruction, span: Span) {
+        self.instructions.push(instruction);
+        self.spans.push(span);
     }
 
-    fn compile_impl(ins: &mut Vec<Instruction>, syntax_tree: Vec<SyntaxTree>) {
+    fn compile_impl(&mut self, syntax_tree: Vec<SyntaxTree>) {
         for node in syntax_tree {
             match node {
-                SyntaxTree::Add { val } => ins.push(Instruction::Add { val }),
-                SyntaxTree::Seek { offset } => ins.push(Instruction::Seek {
-                    offset: offset as isize,
-                }),
-                SyntaxTree::Clear => ins.push(Instruction::Clear),
-                SyntaxTree::AddUntilZero { target } => {
-                    ins.push(Instruction::AddUntilZero { target })
-                }
-                SyntaxTree::Input => ins.push(Instruction::Input),
-                SyntaxTree::Output => ins.push(Instruction::Output),
-                SyntaxTree::Loop { block } => {
-                    let loop_start_addr = ins.len();
-                    ins.push(Instruction::JumpIfZero { target: 0 }); // 0 as a placeholder
-                    InstructionList::compile_impl(ins, block);
-                    let loop_end_addr = ins.len();
-                    ins.push(Instruction::Jump {
-                        target: loop_start_addr,
-                    });
-                    ins[loop_start_addr] = Instruction::JumpIfZero {
+                SyntaxTree::Loop { block, span } => {
+                    let loop_start_addr = self.len();
+                    // 0 as a placeholder
+                    self.push(Instruction::JumpIfZero { target: 0 }, span.head());
+                    self.compile_impl(block);
+                    let loop_end_addr = self.len();
+                    self.push(
+                        Instruction::Jump {
+                            target: loop_start_addr,
+                        },
+                        span.tail(),
+                    );
+                    self.instructions[loop_start_addr] = Instruction::JumpIfZero {
                         target: loop_end_addr + 1,
                     };
                 }
                 SyntaxTree::Root { block: _ } => unreachable!(),
+                leaf => match Instruction::from_leaf(leaf) {
+                    Some((instruction, span)) => self.push(instruction, span),
+                    None => unreachable!(),
+                },
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Return the addresses of the jumps which don't form loops like what
+    /// `compile` generates, where `JumpIfZero` jumps to right after the `Jump`
+    /// jumping back to it.
+    pub(crate) fn unpaired_jumps(&self) -> Vec<usize> {
+        let mut res = vec![];
+        // The addresses of `JumpIfZero` in the enclosing loops.
+        let mut loops = vec![];
+
+        for (addr, instruction) in self.instructions.iter().enumerate() {
+            match instruction {
+                Instruction::JumpIfZero { .. } => loops.push(addr),
+                Instruction::Jump { target } => match loops.pop() {
+                    Some(start)
+                        if start == *target
+                            && self.instructions[start]
+                                == (Instruction::JumpIfZero { target: addr + 1 }) => {}
+                    Some(start) => res.extend([start, addr]),
+                    None => res.push(addr),
+                },
+                _ => {}
             }
         }
+
+        res.extend(loops);
+        res.sort_unstable();
+        res
+    }
+
+    /// Get the span of the instruction at `addr`.
+    pub fn span(&self, addr: usize) -> Span {
+        self.spans.get(addr).copied().unwrap_or_default()
+    }
+}
+
+impl Display for InstructionList {
+    /// Write the program in the IR text format, an instruction per line. The
+    /// targets of jumps are written as labels `L0`, `L1`, ..., which are numbered
+    /// in the order of their addresses and written before the instructions they
+    /// point to. In the alternate form, non-generated spans are written as
+    /// comments. See `assembly::assemble` for the format.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let targets: BTreeSet<_> = self
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Jump { target } | Instruction::JumpIfZero { target } => Some(*target),
+                _ => None,
+            })
+            .collect();
+        let labels: HashMap<_, _> = targets
+            .into_iter()
+            .enumerate()
+            .map(|(label, addr)| (addr, label))
+            .collect();
+
+        for addr in 0..=self.len() {
+            if let Some(label) = labels.get(&addr) {
+                writeln!(f, "L{label}:")?;
+            }
+
+            let line = match self.instructions.get(addr) {
+                Some(Instruction::Jump { target }) => format!("jmp L{}", labels[target]),
+                Some(Instruction::JumpIfZero { target }) => format!("jz L{}", labels[target]),
+                Some(instruction) => instruction.to_string(),
+                None => break,
+            };
+            span::write_line(f, 1, line, self.span(addr))?;
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compiler::span::Position;
 
     #[test]
     fn compile() {
+        let span = Span::default();
         let syntax_tree = SyntaxTree::Root {
             block: vec![
-                SyntaxTree::Input,
-                SyntaxTree::Add { val: 1 },
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
                 SyntaxTree::Loop {
                     block: vec![
-                        SyntaxTree::Seek { offset: -1 },
-                        SyntaxTree::Add { val: 1 },
-                        SyntaxTree::Seek { offset: 1 },
+                        SyntaxTree::Seek { offset: -1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
                         SyntaxTree::Loop {
-                            block: vec![SyntaxTree::Output],
+                            block: vec![SyntaxTree::Output { offset: 0, span }],
+                            span,
                         },
                     ],
+                    span,
                 },
-                SyntaxTree::Output,
+                SyntaxTree::Output { offset: 0, span },
             ],
         };
 
         let ins = InstructionList::compile(syntax_tree);
 
-        let expected = InstructionList(vec![
-            Instruction::Input,
-            Instruction::Add { val: 1 },
+        let expected = InstructionList::new(vec![
+            Instruction::Input { offset: 0 },
+            Instruction::Add { offset: 0, val: 1 },
             Instruction::JumpIfZero { target: 10 },
             Instruction::Seek { offset: -1 },
-            Instruction::Add { val: 1 },
+            Instruction::Add { offset: 0, val: 1 },
             Instruction::Seek { offset: 1 },
             Instruction::JumpIfZero { target: 9 },
-            Instruction::Output,
+            Instruction::Output { offset: 0 },
             Instruction::Jump { target: 6 },
             Instruction::Jump { target: 2 },
-            Instruction::Output,
+            Instruction::Output { offset: 0 },
             Instruction::Halt,
         ]);
 
         assert_eq!(ins, expected);
     }
 
+    #[test]
+    fn compile_loop_span() {
+        let position = |offset, line, column| Position::new(offset, line, column);
+        let syntax_tree = SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: vec![SyntaxTree::Output {
+                    offset: 0,
+                    span: Span::new(position(1, 1, 2), position(2, 1, 3)),
+                }],
+                span: Span::new(position(0, 1, 1), position(3, 1, 4)),
+            }],
+        };
+
+        let ins = InstructionList::compile(syntax_tree);
+
+        let expected = vec![
+            Span::new(position(0, 1, 1), position(1, 1, 2)),
+            Span::new(position(1, 1, 2), position(2, 1, 3)),
+            Span::new(position(2, 1, 3), position(3, 1, 4)),
+            Span::default(),
+        ];
+
+        assert_eq!(ins.spans, expected);
+    }
+
     #[test]
     fn compile_from_empty_syntax_tree() {
         let ins = InstructionList::compile(SyntaxTree::Root { block: vec![] });
-        let expected = InstructionList(vec![Instruction::Halt]);
+        let expected = InstructionList::new(vec![Instruction::Halt]);
         assert_eq!(ins, expected);
     }
+
+    #[test]
+    fn display_instruction_list() {
+        let ins = InstructionList::new(vec![
+            Instruction::Input { offset: 0 },
+            Instruction::JumpIfZero { target: 5 },
+            Instruction::AddUntilZero {
+                target: vec![AddUntilZeroArg::new(1, 2), AddUntilZeroArg::cleared(-1, 3)],
+                step: -1,
+            },
+            Instruction::Scan { stride: -2 },
+            Instruction::Jump { target: 1 },
+            Instruction::OutputSeq {
+                items: vec![OutputItem::Cell { offset: 1 }, OutputItem::Byte { val: 10 }],
+            },
+            Instruction::OutputBytes {
+                bytes: vec![72, 105],
+            },
+            Instruction::Halt,
+        ]);
+
+        let expected = concat!(
+            "    input 0\n",
+            "L0:\n",
+            "    jz L1\n",
+            "    add_until_zero -1, [1: 2, -1: =3]\n",
+            "    scan -2\n",
+            "    jmp L0\n",
+            "L1:\n",
+            "    output_seq [@1, 10]\n",
+            "    output_bytes [72, 105]\n",
+            "    halt\n",
+        );
+        assert_eq!(ins.to_string(), expected);
+    }
 }
@@ -1,3 +1,7 @@
+use std::fmt::{Display, Formatter};
+
+use crate::compiler::span::{self, Position, Span};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SingleToken {
     GreaterThan,
@@ -10,17 +14,22 @@ pub enum SingleToken {
     RightBracket,
 }
 
-type SingleTokenList = Vec<SingleToken>;
+type SingleTokenList = Vec<(SingleToken, Span)>;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Token {
     pub token: SingleToken,
     pub count: i32,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(token: SingleToken, count: i32) -> Self {
-        Self { token, count }
+        Self::with_span(token, count, Span::default())
+    }
+
+    pub fn with_span(token: SingleToken, count: i32, span: Span) -> Self {
+        Self { token, count, span }
     }
 }
 
@@ -35,19 +44,21 @@ impl TokenList {
         let mut last = None::<SingleToken>;
         let mut now = None::<Token>;
 
-        for token in tokens {
+        for (token, span) in tokens {
             if let Some(last) = last {
                 if last == token
                     && token != SingleToken::LeftBracket
                     && token != SingleToken::RightBracket
                 {
-                    now.as_mut().unwrap().count += 1;
+                    let now = now.as_mut().unwrap();
+                    now.count += 1;
+                    now.span = now.span.merge(span);
                 } else {
                     res.push(now.take().unwrap());
-                    now = Some(Token::new(token, 1));
+                    now = Some(Token::with_span(token, 1, span));
                 }
             } else {
-                now = Some(Token::new(token, 1));
+                now = Some(Token::with_span(token, 1, span));
             }
 
             last = Some(token);
@@ -66,20 +77,17 @@ impl TokenList {
         let mut res = vec![];
         let mut now = None::<Token>;
 
-        for Token { token, count } in self.0 {
-            if let SingleToken::Add = token {
-                if now.is_none() {
-                    now = Some(Token::new(SingleToken::Add, 0));
-                }
+        for Token { token, count, span } in self.0 {
+            if let SingleToken::Add | SingleToken::Sub = token {
+                let now = now.get_or_insert(Token::with_span(SingleToken::Add, 0, span));
+                now.span = now.span.merge(span);
 
-                now.as_mut().unwrap().count += count;
-                continue;
-            } else if let SingleToken::Sub = token {
-                if now.is_none() {
-                    now = Some(Token::new(SingleToken::Add, 0));
+                if let SingleToken::Add = token {
+                    now.count += count;
+                } else {
+                    now.count -= count;
                 }
 
-                now.as_mut().unwrap().count -= count;
                 continue;
             }
 
@@ -89,7 +97,7 @@ impl TokenList {
                 }
             }
 
-            res.push(Token::new(token, count));
+            res.push(Token::with_span(token, count, span));
         }
 
         if let Some(now) = now.take() {
@@ -107,20 +115,17 @@ impl TokenList {
         let mut res = vec![];
         let mut now = None::<Token>;
 
-        for Token { token, count } in self.0 {
-            if let SingleToken::LessThan = token {
-                if now.is_none() {
-                    now = Some(Token::new(SingleToken::GreaterThan, 0));
-                }
+        for Token { token, count, span } in self.0 {
+            if let SingleToken::LessThan | SingleToken::GreaterThan = token {
+                let now = now.get_or_insert(Token::with_span(SingleToken::GreaterThan, 0, span));
+                now.span = now.span.merge(span);
 
-                now.as_mut().unwrap().count -= count;
-                continue;
-            } else if let SingleToken::GreaterThan = token {
-                if now.is_none() {
-                    now = Some(Token::new(SingleToken::GreaterThan, 0));
+                if let SingleToken::GreaterThan = token {
+                    now.count += count;
+                } else {
+                    now.count -= count;
                 }
 
-                now.as_mut().unwrap().count += count;
                 continue;
             }
 
@@ -130,7 +135,7 @@ impl TokenList {
                 }
             }
 
-            res.push(Token::new(token, count));
+            res.push(Token::with_span(token, count, span));
         }
 
         if let Some(now) = now.take() {
@@ -143,6 +148,38 @@ impl TokenList {
     }
 }
 
+impl Display for Token {
+    /// Write the character of the token and its count, such as `> -2` for `<<`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ch = match self.token {
+            SingleToken::GreaterThan => '>',
+            SingleToken::LessThan => '<',
+            SingleToken::Add => '+',
+            SingleToken::Sub => '-',
+            SingleToken::Dot => '.',
+            SingleToken::Comma => ',',
+            SingleToken::LeftBracket => '[',
+            SingleToken::RightBracket => ']',
+        };
+
+        match self.token {
+            SingleToken::LeftBracket | SingleToken::RightBracket => write!(f, "{ch}"),
+            _ => write!(f, "{ch} {}", self.count),
+        }
+    }
+}
+
+impl Display for TokenList {
+    /// Write a token per line, and their spans in the alternate form.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for token in &self.0 {
+            span::write_line(f, 0, token, token.span)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl From<SingleTokenList> for TokenList {
     /// Combine the similar and adjacent tokens, such as `[Token::Add, Token::Add,
     /// Token::Sub]` to `[(Token::Add, 1)]`.
@@ -154,15 +191,20 @@ impl From<SingleTokenList> for TokenList {
 }
 
 /// Split the program to some tokens and ignore what a brainfuck program doesn't
-/// contain.
-fn split(code: &str) -> Vec<char> {
-    code.chars().fold(Vec::new(), |mut v, c| match c {
-        c @ ('>' | '<' | '+' | '-' | '.' | ',' | '[' | ']') => {
-            v.push(c);
-            v
+/// contain. Every character kept is paired with its location in the code.
+fn split(code: &str) -> Vec<(char, Span)> {
+    let mut res = Vec::new();
+    let mut position = Position::new(0, 1, 1);
+
+    for c in code.chars() {
+        if let '>' | '<' | '+' | '-' | '.' | ',' | '[' | ']' = c {
+            res.push((c, Span::of_char(position, c)));
         }
-        _ => v,
-    })
+
+        position = position.advance(c);
+    }
+
+    res
 }
 
 fn token(ch: char) -> SingleToken {
@@ -180,7 +222,10 @@ fn token(ch: char) -> SingleToken {
 }
 
 fn build_single_token_list(code: &str) -> SingleTokenList {
-    split(code).into_iter().map(token).collect()
+    split(code)
+        .into_iter()
+        .map(|(ch, span)| (token(ch), span))
+        .collect()
 }
 
 /// Build a `TokenList` from a brainfuck program.
@@ -196,7 +241,8 @@ mod tests {
     fn split_code() {
         let code = "+ [>a+]>d.>-,.";
         let expected = vec!['+', '[', '>', '+', ']', '>', '.', '>', '-', ',', '.'];
-        assert_eq!(split(code), expected);
+        let chars: Vec<_> = split(code).into_iter().map(|(ch, _)| ch).collect();
+        assert_eq!(chars, expected);
     }
 
     #[test]
@@ -229,6 +275,7 @@ mod tests {
             SingleToken::RightBracket,
             SingleToken::RightBracket,
         ];
+        let list: SingleTokenList = list.into_iter().map(|t| (t, Span::default())).collect();
         let simplifed = TokenList::from(list);
         let expected = TokenList(vec![
             Token::new(SingleToken::Add, -1),
@@ -241,9 +288,45 @@ mod tests {
         assert_eq!(simplifed, expected);
     }
 
+    #[test]
+    fn token_span() {
+        let code = "+ -+\n>>[";
+        let position = |offset, line, column| Position::new(offset, line, column);
+        let expected = TokenList(vec![
+            Token::with_span(
+                SingleToken::Add,
+                1,
+                Span::new(position(0, 1, 1), position(4, 1, 5)),
+            ),
+            Token::with_span(
+                SingleToken::GreaterThan,
+                2,
+                Span::new(position(5, 2, 1), position(7, 2, 3)),
+            ),
+            Token::with_span(
+                SingleToken::LeftBracket,
+                1,
+                Span::new(position(7, 2, 3), position(8, 2, 4)),
+            ),
+        ]);
+        assert_eq!(build_token_list(code), expected);
+    }
+
     #[test]
     fn empty_token_list() {
         let list: SingleTokenList = vec![];
         assert!(TokenList::from(list).0.is_empty());
     }
+
+    #[test]
+    fn display_token_list() {
+        let expected = concat!(
+            "+ 1                              ; 1:1..1:4\n",
+            "[                                ; 1:4..1:5\n",
+            "> -2                             ; 1:5..1:7\n",
+            "]                                ; 1:7..1:8\n",
+            ". 1                              ; 2:1..2:2\n",
+        );
+        assert_eq!(format!("{:#}", build_token_list("++-[<<]\n.")), expected);
+    }
 }
@@ -1,26 +1,87 @@
+mod analysis;
+mod assembly;
+mod bytecode;
+mod codegen;
+mod config;
+mod diagnostic;
 mod instruction;
 mod lexer;
 mod parser;
+mod span;
 
+pub use analysis::{Hang, Interval, LoopHang, PointerBounds, Reach, Termination};
+pub use assembly::AssemblyError;
+pub use bytecode::{Bytecode, BytecodeError, SourceMap};
+pub use codegen::{CBackend, CodegenError, ElfBackend, RustBackend, WasmBackend};
+pub use config::{Config, OptLevel};
+pub use diagnostic::{Diagnostic, Level, Note};
 pub use instruction::{Instruction, InstructionList};
 use lexer::build_token_list;
+pub use lexer::{SingleToken, Token, TokenList};
 use parser::Parser;
-pub use parser::{AddUntilZeroArg, ParseError};
+pub use parser::{
+    AddUntilZeroArg, AddUntilZeroRule, ClearRule, OffsetRule, Optimizer, OptimizerBuilder,
+    OptimizerStats, OutputItem, ParseError, PassStats, Rule, ScanRule, SetRule, SyntaxError,
+    SyntaxTree,
+};
+pub use span::{Position, Span};
 
 pub type Result<T> = std::result::Result<T, ParseError>;
 
-pub struct Compiler;
+pub struct Compiler {
+    optimizer: OptimizerBuilder,
+}
 
 impl Compiler {
     pub fn new() -> Self {
-        Self
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            optimizer: OptimizerBuilder::with_config(config),
+        }
+    }
+
+    /// Register a custom rule, which runs to a fixpoint together with the
+    /// built-in ones, after them in each iteration.
+    pub fn add_rule(&mut self, rule: impl Rule + 'static) {
+        self.optimizer = self.optimizer.clone().rule(rule);
     }
 
     pub fn compile(&self, code: &str) -> Result<InstructionList> {
-        let token_list = build_token_list(code);
-        let parser = Parser::new();
-        let syntax_tree = parser.parse(token_list)?;
-        let instruction_list = InstructionList::compile(syntax_tree);
+        let (instruction_list, _) = self.compile_with_stats(code)?;
         Ok(instruction_list)
     }
+
+    /// Compile the code like `compile`, and also report what the optimizer did.
+    pub fn compile_with_stats(&self, code: &str) -> Result<(InstructionList, OptimizerStats)> {
+        let (syntax_tree, stats) = self.parse_with_stats(code)?;
+        let instruction_list = InstructionList::compile(syntax_tree);
+        Ok((instruction_list, stats))
+    }
+
+    /// Build the program from its IR text form, which isn't optimized. See
+    /// `InstructionList`'s `Display` for the format.
+    pub fn assemble(&self, code: &str) -> Result<InstructionList> {
+        Ok(assembly::assemble(code)?)
+    }
+
+    /// Split the code into tokens, which is the first stage of `compile`.
+    pub fn tokenize(&self, code: &str) -> TokenList {
+        build_token_list(code)
+    }
+
+    /// Build the optimized syntax tree, which is the second stage of `compile`.
+    pub fn parse(&self, code: &str) -> Result<SyntaxTree> {
+        let (syntax_tree, _) = self.parse_with_stats(code)?;
+        Ok(syntax_tree)
+    }
+
+    fn parse_with_stats(&self, code: &str) -> Result<(SyntaxTree, OptimizerStats)> {
+        let optimizer = self.optimizer.clone().build()?;
+        let token_list = self.tokenize(code);
+        let parser = Parser::new(&optimizer);
+        parser.parse(token_list)
+    }
 }
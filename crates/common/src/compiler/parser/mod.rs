@@ -1,37 +1,108 @@
 mod optimizer;
 mod syntax;
 
+use crate::compiler::assembly::AssemblyError;
+use crate::compiler::diagnostic::Diagnostic;
 use crate::compiler::lexer::TokenList;
-use optimizer::Optimizer;
+use crate::compiler::span::Span;
+pub use optimizer::{
+    AddUntilZeroRule, Builder as OptimizerBuilder, ClearRule, OffsetRule, Optimizer, PassStats,
+    Rule, ScanRule, SetRule, Stats as OptimizerStats,
+};
 use snafu::prelude::*;
-pub use syntax::{AddUntilZeroArg, SyntaxError, SyntaxTree};
+pub use syntax::{AddUntilZeroArg, OutputItem, SyntaxError, SyntaxTree};
 
 type Result<T> = std::result::Result<T, ParseError>;
 
-pub struct Parser;
+pub struct Parser<'a> {
+    optimizer: &'a Optimizer,
+}
 
-impl Parser {
-    pub fn new() -> Self {
-        Self
+impl<'a> Parser<'a> {
+    pub fn new(optimizer: &'a Optimizer) -> Self {
+        Self { optimizer }
     }
 
-    pub fn parse(&self, token_list: TokenList) -> Result<SyntaxTree> {
-        let mut optimizer = Optimizer::new();
-        optimizer.load_rules();
+    pub fn parse(&self, token_list: TokenList) -> Result<(SyntaxTree, OptimizerStats)> {
         let tree = SyntaxTree::build(token_list)?;
-        let tree = optimizer.optimize(tree);
-        Ok(tree)
+        Ok(self.optimizer.optimize_with_stats(tree))
     }
 }
 
 #[derive(Debug, Snafu, PartialEq, Eq)]
 pub enum ParseError {
+    /// `source` is the first syntax error, and `others` are the rest of them.
     #[snafu(display("error occurred when parsing code"))]
-    Syntax { source: SyntaxError },
+    Syntax {
+        source: Box<SyntaxError>,
+        others: Vec<SyntaxError>,
+    },
+    #[snafu(display("unknown optimization rule `{name}`"))]
+    UnknownRule { name: String },
+    /// `source` is the first error in the IR text, and `others` are the rest of
+    /// them.
+    #[snafu(display("error occurred when assembling code"))]
+    Assembly {
+        source: Box<AssemblyError>,
+        others: Vec<AssemblyError>,
+    },
+}
+
+impl ParseError {
+    /// Return all errors found in the code, sorted by their locations.
+    pub fn errors(&self) -> Vec<&SyntaxError> {
+        match self {
+            ParseError::Syntax { source, others } => {
+                [source.as_ref()].into_iter().chain(others).collect()
+            }
+            ParseError::UnknownRule { .. } | ParseError::Assembly { .. } => vec![],
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            ParseError::Syntax { .. } => {
+                self.errors().into_iter().map(|e| e.diagnostic()).collect()
+            }
+            ParseError::UnknownRule { .. } => {
+                vec![Diagnostic::error(self.to_string(), Span::default())]
+            }
+            ParseError::Assembly { source, others } => [source.as_ref()]
+                .into_iter()
+                .chain(others)
+                .map(|e| e.diagnostic())
+                .collect(),
+        }
+    }
 }
 
 impl From<SyntaxError> for ParseError {
     fn from(e: SyntaxError) -> Self {
-        Self::Syntax { source: e }
+        Self::Syntax {
+            source: Box::new(e),
+            others: vec![],
+        }
+    }
+}
+
+impl From<Vec<SyntaxError>> for ParseError {
+    /// The errors shouldn't be empty.
+    fn from(mut errors: Vec<SyntaxError>) -> Self {
+        let others = errors.split_off(1);
+        Self::Syntax {
+            source: Box::new(errors.pop().unwrap()),
+            others,
+        }
+    }
+}
+
+impl From<Vec<AssemblyError>> for ParseError {
+    /// The errors shouldn't be empty.
+    fn from(mut errors: Vec<AssemblyError>) -> Self {
+        let others = errors.split_off(1);
+        Self::Assembly {
+            source: Box::new(errors.pop().unwrap()),
+            others,
+        }
     }
 }
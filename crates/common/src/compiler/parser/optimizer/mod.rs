@@ -1,45 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::rc::Rc;
+
+use crate::compiler::config::{Config, OptLevel};
+use crate::compiler::instruction::MAX_REPEAT_COUNT;
 use crate::compiler::parser::syntax::AddUntilZeroArg;
-use crate::compiler::parser::syntax::SyntaxTree;
+use crate::compiler::parser::syntax::{OutputItem, SyntaxTree};
+use crate::compiler::parser::ParseError;
+use crate::compiler::span::Span;
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Eof, Overflow};
+use crate::execution::memory::strategy::split_step;
+use crate::execution::memory::{dispatch, Builder as MemoryBuilder, DynMemory};
+use crate::execution::processor::Processor;
 
+/// A rewriting of the syntax tree. The optimizer calls `apply` on each `Root`
+/// and `Loop` node, and any other node in them, after their children have been
+/// optimized, so a rule only needs to look at one level of the tree.
+///
+/// A rule must keep the behavior of the program, and shouldn't undo what the
+/// other rules do, or the optimizer can't reach a fixpoint.
 pub trait Rule {
+    /// The name used to enable or disable the rule.
+    fn name(&self) -> &'static str;
+
     fn apply(&self, block: SyntaxTree) -> SyntaxTree;
 }
 
+const DEFAULT_MAX_ITERATIONS: usize = 16;
+
 pub struct Optimizer {
-    rules: Vec<Box<dyn Rule>>,
+    rules: Vec<Rc<dyn Rule>>,
+    max_iterations: usize,
 }
 
 impl Optimizer {
-    pub fn new() -> Self {
-        Self { rules: vec![] }
+    fn new() -> Self {
+        Self {
+            rules: vec![],
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+        }
     }
 
-    pub fn optimize(&self, mut tree: SyntaxTree) -> SyntaxTree {
-        for rule in &self.rules {
-            tree = rule.apply(tree);
+    /// Run all the rules repeatedly until the tree doesn't change any more, or
+    /// `max_iterations` is reached.
+    pub fn optimize(&self, tree: SyntaxTree) -> SyntaxTree {
+        self.optimize_with_stats(tree).0
+    }
+
+    /// Optimize the tree like `optimize`, and also report what each rule did.
+    pub fn optimize_with_stats(&self, mut tree: SyntaxTree) -> (SyntaxTree, Stats) {
+        let mut stats = Stats {
+            iterations: 0,
+            before: tree.instruction_count(),
+            after: 0,
+            passes: self
+                .rules
+                .iter()
+                .map(|rule| PassStats::new(rule.name()))
+                .collect(),
+        };
+
+        while stats.iterations < self.max_iterations {
+            stats.iterations += 1;
+            let optimized = self.optimize_once(tree.clone(), &mut stats.passes);
+
+            if optimized == tree {
+                break;
+            }
+
+            tree = optimized;
         }
 
-        match tree {
+        stats.after = tree.instruction_count();
+        (tree, stats)
+    }
+
+    /// Optimize the tree from bottom to top, so that a rule always sees the
+    /// optimized children of a node.
+    fn optimize_once(&self, tree: SyntaxTree, passes: &mut [PassStats]) -> SyntaxTree {
+        let mut tree = match tree {
             SyntaxTree::Root { block } => SyntaxTree::Root {
-                block: block.into_iter().map(|tree| self.optimize(tree)).collect(),
+                block: block
+                    .into_iter()
+                    .map(|tree| self.optimize_once(tree, passes))
+                    .collect(),
             },
-            SyntaxTree::Loop { block } => SyntaxTree::Loop {
-                block: block.into_iter().map(|tree| self.optimize(tree)).collect(),
+            SyntaxTree::Loop { block, span } => SyntaxTree::Loop {
+                block: block
+                    .into_iter()
+                    .map(|tree| self.optimize_once(tree, passes))
+                    .collect(),
+                span,
             },
             otherwise => otherwise,
+        };
+
+        for (rule, pass) in self.rules.iter().zip(passes.iter_mut()) {
+            let before = tree.clone();
+            tree = rule.apply(tree);
+
+            if tree != before {
+                pass.rewrites += 1;
+                pass.before += before.instruction_count();
+                pass.after += tree.instruction_count();
+            }
         }
-    }
 
-    pub fn load_rules(&mut self) {
-        self.add_rule(Box::new(ClearRule::new()));
-        self.add_rule(Box::new(AddUntilZeroRule::new()));
+        tree
     }
 
-    fn add_rule(&mut self, rule: Box<dyn Rule>) {
+    fn add_rule(&mut self, rule: Rc<dyn Rule>) {
         self.rules.push(rule);
     }
 }
 
+/// What the optimizer did to a tree. `before` and `after` are the numbers of
+/// instructions the tree is compiled into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of times the rules were run over the whole tree, including the
+    /// last one which changed nothing.
+    pub iterations: usize,
+    pub before: usize,
+    pub after: usize,
+    /// The statistics of each rule, in the order they run.
+    pub passes: Vec<PassStats>,
+}
+
+/// What a rule did in all the iterations. `before` and `after` are the numbers
+/// of instructions of the nodes it rewrote, before and after the rewriting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassStats {
+    pub name: &'static str,
+    pub rewrites: usize,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl PassStats {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            rewrites: 0,
+            before: 0,
+            after: 0,
+        }
+    }
+}
+
+/// Select the built-in rules with a `Config`, and append custom rules after them.
+#[derive(Clone)]
+pub struct Builder {
+    config: Config,
+    rules: Vec<Rc<dyn Rule>>,
+    max_iterations: usize,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            config,
+            rules: vec![],
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+        }
+    }
+
+    /// Add a custom rule, which runs at every optimization level unless it's
+    /// disabled by its name.
+    pub fn rule(mut self, rule: impl Rule + 'static) -> Self {
+        self.rules.push(Rc::new(rule));
+        self
+    }
+
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Build the optimizer with the rules selected by the config, keeping their
+    /// order. Fail if the config names an unknown rule.
+    pub fn build(self) -> Result<Optimizer, ParseError> {
+        let config = &self.config;
+        let input_overwrites = matches!(
+            config.target.as_ref().map(|target| &target.eof),
+            Some(Eof::Zero | Eof::Keep)
+        );
+        let wrapping = matches!(
+            config.target.as_ref().map(|target| &target.overflow),
+            Some(Overflow::Wrap)
+        );
+        let built_in: Vec<(OptLevel, Rc<dyn Rule>)> = vec![
+            (OptLevel::O1, Rc::new(ClearRule::new())),
+            (
+                OptLevel::O2,
+                Rc::new(AddUntilZeroRule::new(config.target.clone())),
+            ),
+            (OptLevel::O1, Rc::new(ScanRule::new())),
+            // They must be the last ones, for the rules above only recognize the loops
+            // before the rewriting.
+            (OptLevel::O2, Rc::new(OffsetRule::new())),
+            (
+                OptLevel::O3,
+                Rc::new(SetRule::new(input_overwrites, wrapping)),
+            ),
+            (OptLevel::O2, Rc::new(OutputRule::new())),
+            (
+                OptLevel::O3,
+                Rc::new(ConstantRule::new(
+                    config.target.clone().filter(|_| config.standalone),
+                )),
+            ),
+            (
+                OptLevel::O2,
+                Rc::new(DeadCodeRule::new(config.standalone, wrapping)),
+            ),
+        ];
+        let rules: Vec<(OptLevel, Rc<dyn Rule>)> = built_in
+            .into_iter()
+            .chain(self.rules.into_iter().map(|rule| (OptLevel::O0, rule)))
+            .collect();
+
+        if let Some(name) = config
+            .enable
+            .iter()
+            .chain(&config.disable)
+            .find(|name| rules.iter().all(|(_, rule)| rule.name() != name.as_str()))
+        {
+            return Err(ParseError::UnknownRule { name: name.clone() });
+        }
+
+        let mut optimizer = Optimizer::new();
+        optimizer.max_iterations = self.max_iterations;
+
+        for (level, rule) in rules {
+            let name = rule.name();
+            let enabled = level <= config.opt_level || config.enable.iter().any(|n| n == name);
+            let disabled = config.disable.iter().any(|n| n == name);
+
+            if enabled && !disabled {
+                optimizer.add_rule(rule);
+            }
+        }
+
+        Ok(optimizer)
+    }
+}
+
 pub struct ClearRule;
 
 impl ClearRule {
@@ -49,13 +259,20 @@ impl ClearRule {
 }
 
 impl Rule for ClearRule {
+    fn name(&self) -> &'static str {
+        "clear"
+    }
+
     fn apply(&self, block: SyntaxTree) -> SyntaxTree {
         match block {
-            SyntaxTree::Loop { block } => {
-                if block.len() == 1 && block[0] == (SyntaxTree::Add { val: -1 }) {
-                    SyntaxTree::Clear
+            SyntaxTree::Loop { block, span } => {
+                if let [SyntaxTree::Add {
+                    offset: 0, val: -1, ..
+                }] = block[..]
+                {
+                    SyntaxTree::Clear { span }
                 } else {
-                    SyntaxTree::Loop { block }
+                    SyntaxTree::Loop { block, span }
                 }
             }
             otherwise => otherwise,
@@ -63,168 +280,2196 @@ impl Rule for ClearRule {
     }
 }
 
-pub struct AddUntilZeroRule;
+/// Rewrite a balanced loop whose counter changes by a constant into
+/// `AddUntilZero`. `target` is the memory the program runs on if it's known,
+/// which tells whether an even step is exact.
+pub struct AddUntilZeroRule {
+    target: Option<MemoryConfig>,
+}
 
 impl AddUntilZeroRule {
-    pub fn new() -> Self {
-        Self
+    pub fn new(target: Option<MemoryConfig>) -> Self {
+        Self { target }
+    }
+
+    /// Whether `AddUntilZero` counts the iterations of a loop exactly, where the
+    /// counter changes by `step` in each one. An odd step always does. Otherwise
+    /// the counter either reaches zero or overflows if the cells don't wrap, and
+    /// if they do, the count is solved unless the step is a multiple of the
+    /// modulus, while it runs forever like the loop if there is no solution.
+    fn is_exact(&self, step: i32) -> bool {
+        match &self.target {
+            _ if step % 2 != 0 => true,
+            None => false,
+            Some(target) => match target.overflow {
+                Overflow::Error => step != 0,
+                Overflow::Wrap => {
+                    let bits = match target.cell {
+                        Cell::I8 => 8,
+                        Cell::I32 => 32,
+                    };
+                    split_step(step, bits).is_some()
+                }
+            },
+        }
+    }
+
+    /// Sum up the changes of each cell in one iteration of the loop, in the order
+    /// they are first changed. Return `None` if the loop isn't balanced or contains
+    /// anything other than changing cells.
+    fn collect(block: &[SyntaxTree]) -> Option<Vec<AddUntilZeroArg>> {
+        let mut current_offset = 0;
+        let mut cells: Vec<AddUntilZeroArg> = Vec::with_capacity(block.len() / 2);
+
+        for statement in block {
+            let (offset, val, clear) = match statement {
+                SyntaxTree::Add { offset, val, .. } => (current_offset + offset, *val, false),
+                SyntaxTree::Set { offset, val, .. } => (current_offset + offset, *val, true),
+                SyntaxTree::Clear { .. } => (current_offset, 0, true),
+                SyntaxTree::Seek { offset, .. } => {
+                    current_offset += *offset as isize;
+                    continue;
+                }
+                _ => return None,
+            };
+
+            match cells.iter_mut().find(|cell| cell.offset == offset) {
+                Some(cell) if clear => *cell = AddUntilZeroArg::cleared(offset, val),
+                Some(cell) => cell.times = cell.times.checked_add(val)?,
+                None if clear => cells.push(AddUntilZeroArg::cleared(offset, val)),
+                None => cells.push(AddUntilZeroArg::new(offset, val)),
+            }
+        }
+
+        // Ensure the last behavior is moving the pointer back to the place
+        // where it stayed when the loop started.
+        if current_offset != 0 {
+            None
+        } else {
+            Some(cells)
+        }
     }
 }
 
 impl Rule for AddUntilZeroRule {
+    fn name(&self) -> &'static str {
+        "add-until-zero"
+    }
+
     fn apply(&self, block: SyntaxTree) -> SyntaxTree {
-        let block = match block {
-            SyntaxTree::Loop { block } => block,
+        let (block, span) = match block {
+            SyntaxTree::Loop { block, span } => (block, span),
             otherwise => return otherwise,
         };
 
-        // Check whether the first character in code is `-`.
-        match block.get(0) {
-            Some(SyntaxTree::Add { val: -1 }) => (),
-            _ => return SyntaxTree::Loop { block },
+        let mut target = match Self::collect(&block) {
+            Some(target) => target,
+            None => return SyntaxTree::Loop { block, span },
+        };
+
+        // The counter must change by a constant in each iteration, whose count is
+        // exact. It's not the case when the program clears the counter inside the
+        // loop.
+        let step = match target.iter().position(|cell| cell.offset == 0) {
+            Some(index) if !target[index].clear && self.is_exact(target[index].times) => {
+                target.remove(index).times
+            }
+            _ => return SyntaxTree::Loop { block, span },
+        };
+
+        target.retain(|cell| cell.clear || cell.times != 0);
+        SyntaxTree::AddUntilZero { target, step, span }
+    }
+}
+
+pub struct ScanRule;
+
+impl ScanRule {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Rule for ScanRule {
+    fn name(&self) -> &'static str {
+        "scan"
+    }
+
+    fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+        match block {
+            SyntaxTree::Loop { block, span } => {
+                if let [SyntaxTree::Seek { offset, .. }] = block[..] {
+                    SyntaxTree::Scan {
+                        stride: offset,
+                        span,
+                    }
+                } else {
+                    SyntaxTree::Loop { block, span }
+                }
+            }
+            otherwise => otherwise,
         }
+    }
+}
+
+/// Rewrite the straight-line code in a block, so that cells are accessed by their
+/// offsets and the pointer only moves once before the next loop or the end of the
+/// block. For example, `>+>+<<-` becomes `Add(1, 1)`, `Add(2, 1)` and `Add(0, -1)`
+/// without any `Seek`.
+pub struct OffsetRule;
+
+impl OffsetRule {
+    pub fn new() -> Self {
+        Self
+    }
 
+    fn rewrite(block: Vec<SyntaxTree>) -> Vec<SyntaxTree> {
+        let mut res = Vec::with_capacity(block.len());
         let mut current_offset = 0;
-        let mut target = Vec::with_capacity(block.len() / 2);
-
-        for statement in block.iter().skip(1) {
-            match statement {
-                SyntaxTree::Add { val } => {
-                    // Optimization fails if the program tries to change the
-                    // counter inside a loop.
-                    if current_offset == 0 {
-                        return SyntaxTree::Loop { block };
-                    }
+        // The moves of the pointer belong to the next instruction, so that it's
+        // reported when accessing the cell fails.
+        let mut seek_span = Span::default();
+        // All moves of the pointer, which belong to the final `Seek`.
+        let mut moved_span = Span::default();
 
-                    target.push(AddUntilZeroArg::new(current_offset, *val))
+        for tree in block {
+            match tree {
+                SyntaxTree::Seek { offset, span } => {
+                    current_offset += offset as isize;
+                    seek_span = seek_span.merge(span);
+                    moved_span = moved_span.merge(span);
+                }
+                SyntaxTree::Add { offset, val, span } => res.push(SyntaxTree::Add {
+                    offset: current_offset + offset,
+                    val,
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                SyntaxTree::Set { offset, val, span } => res.push(SyntaxTree::Set {
+                    offset: current_offset + offset,
+                    val,
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                SyntaxTree::Clear { span } => res.push(SyntaxTree::Set {
+                    offset: current_offset,
+                    val: 0,
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                SyntaxTree::Input { offset, span } => res.push(SyntaxTree::Input {
+                    offset: current_offset + offset,
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                SyntaxTree::Output { offset, span } => res.push(SyntaxTree::Output {
+                    offset: current_offset + offset,
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                SyntaxTree::OutputBytes { bytes, span } => {
+                    res.push(SyntaxTree::OutputBytes { bytes, span })
+                }
+                SyntaxTree::OutputRepeat {
+                    offset,
+                    count,
+                    span,
+                } => res.push(SyntaxTree::OutputRepeat {
+                    offset: current_offset + offset,
+                    count,
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                SyntaxTree::OutputSeq { items, span } => res.push(SyntaxTree::OutputSeq {
+                    items: items
+                        .into_iter()
+                        .map(|item| match item {
+                            OutputItem::Cell { offset } => OutputItem::Cell {
+                                offset: current_offset + offset,
+                            },
+                            byte => byte,
+                        })
+                        .collect(),
+                    span: mem::take(&mut seek_span).merge(span),
+                }),
+                // Loops depend on where the pointer is.
+                otherwise => {
+                    seek_span = Span::default();
+                    Self::flush(&mut res, &mut current_offset, &mut moved_span);
+                    res.push(otherwise);
                 }
-                SyntaxTree::Seek { offset } => current_offset += *offset as isize,
-                _ => return SyntaxTree::Loop { block },
             }
         }
 
-        // Ensure the last behavior is moving the pointer back to the place
-        // where it stayed when the loop started.
-        if current_offset != 0 {
-            SyntaxTree::Loop { block }
-        } else {
-            SyntaxTree::AddUntilZero { target }
+        Self::flush(&mut res, &mut current_offset, &mut moved_span);
+        res
+    }
+
+    /// Move the pointer to where it should be.
+    fn flush(res: &mut Vec<SyntaxTree>, current_offset: &mut isize, moved_span: &mut Span) {
+        let span = mem::take(moved_span);
+
+        if *current_offset != 0 {
+            res.push(SyntaxTree::Seek {
+                offset: *current_offset as i32,
+                span,
+            });
+            *current_offset = 0;
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::compiler::parser::syntax::AddUntilZeroArg;
+impl Rule for OffsetRule {
+    fn name(&self) -> &'static str {
+        "offset"
+    }
 
-    use super::*;
+    fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+        match block {
+            SyntaxTree::Root { block } => SyntaxTree::Root {
+                block: Self::rewrite(block),
+            },
+            SyntaxTree::Loop { block, span } => SyntaxTree::Loop {
+                block: Self::rewrite(block),
+                span,
+            },
+            otherwise => otherwise,
+        }
+    }
+}
 
-    #[test]
-    fn clear_rule() {
-        let mut optimizer = Optimizer::new();
-        optimizer.add_rule(Box::new(ClearRule::new()));
+/// Fuse the writes to the same cell in the straight-line code rewritten by
+/// `OffsetRule`, such as turning `[-]+++` into `Set(0, 3)`, and remove the writes
+/// overwritten before being read, unless they may overflow.
+pub struct SetRule {
+    /// Whether `Input` always overwrites the cell, which is false when `EOF` is
+    /// ignored.
+    input_overwrites: bool,
+    /// Whether writing to a cell never overflows, for the target wraps the value.
+    wrapping: bool,
+}
 
-        let tree = SyntaxTree::Root {
-            block: vec![
-                SyntaxTree::Input,
-                SyntaxTree::Loop {
-                    block: vec![SyntaxTree::Add { val: -1 }],
-                },
-            ],
+impl SetRule {
+    pub fn new(input_overwrites: bool, wrapping: bool) -> Self {
+        Self {
+            input_overwrites,
+            wrapping,
+        }
+    }
+
+    /// Remove the write at `index` if it never raises an error, for its cell is
+    /// known to be in the memory and it never overflows.
+    fn remove(&self, res: &mut [Option<SyntaxTree>], (index, in_bounds): (usize, bool)) {
+        let removable = match &res[index] {
+            Some(SyntaxTree::Set { val, .. }) => self.wrapping || i8::try_from(*val).is_ok(),
+            _ => self.wrapping,
         };
 
-        let tree = optimizer.optimize(tree);
+        if removable && in_bounds {
+            res[index] = None;
+        }
+    }
 
-        let expected = SyntaxTree::Root {
-            block: vec![SyntaxTree::Input, SyntaxTree::Clear],
-        };
+    fn rewrite(&self, block: Vec<SyntaxTree>) -> Vec<SyntaxTree> {
+        let mut res: Vec<Option<SyntaxTree>> = Vec::with_capacity(block.len());
+        // The index of the last write to each cell in `res`, which hasn't been read,
+        // and whether the cell is known to be in the memory when it's written.
+        let mut writes: HashMap<isize, (usize, bool)> = HashMap::new();
+        // The cells accessed so far, which are in the memory if the code gets here.
+        let mut accessed: HashSet<isize> = HashSet::from([0]);
 
-        assert_eq!(tree, expected);
+        for tree in block {
+            match tree {
+                SyntaxTree::Add { offset, val, span } => {
+                    if let Some(&(index, _)) = writes.get(&offset) {
+                        // Nothing may fail or be written between them, if the sum
+                        // may overflow at the place of the `Add`.
+                        let adjacent = res[index + 1..].iter().all(Option::is_none);
+
+                        if let Some(SyntaxTree::Set {
+                            val: before,
+                            span: before_span,
+                            ..
+                        }) = &mut res[index]
+                        {
+                            match before.checked_add(val) {
+                                Some(sum)
+                                    if self.wrapping || adjacent || i8::try_from(sum).is_ok() =>
+                                {
+                                    *before = sum;
+                                    *before_span = before_span.merge(span);
+                                    continue;
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+
+                    writes.insert(offset, (res.len(), !accessed.insert(offset)));
+                    res.push(Some(SyntaxTree::Add { offset, val, span }));
+                }
+                SyntaxTree::Set { offset, val, span } => {
+                    let write = (res.len(), !accessed.insert(offset));
+
+                    if let Some(write) = writes.insert(offset, write) {
+                        self.remove(&mut res, write);
+                    }
+
+                    res.push(Some(SyntaxTree::Set { offset, val, span }));
+                }
+                SyntaxTree::Input { offset, span } => {
+                    if let Some(write) = writes.remove(&offset) {
+                        if self.input_overwrites {
+                            self.remove(&mut res, write);
+                        }
+                    }
+
+                    accessed.insert(offset);
+                    res.push(Some(SyntaxTree::Input { offset, span }));
+                }
+                SyntaxTree::Output { offset, span } => {
+                    writes.remove(&offset);
+                    accessed.insert(offset);
+                    res.push(Some(SyntaxTree::Output { offset, span }));
+                }
+                SyntaxTree::OutputBytes { .. } => res.push(Some(tree)),
+                SyntaxTree::OutputRepeat { offset, .. } => {
+                    writes.remove(&offset);
+                    accessed.insert(offset);
+                    res.push(Some(tree));
+                }
+                SyntaxTree::OutputSeq { ref items, .. } => {
+                    for item in items {
+                        if let OutputItem::Cell { offset } = item {
+                            writes.remove(offset);
+                            accessed.insert(*offset);
+                        }
+                    }
+
+                    res.push(Some(tree));
+                }
+                // The pointer may be moved, so the offsets are no longer the same.
+                otherwise => {
+                    writes.clear();
+                    accessed = HashSet::from([0]);
+                    res.push(Some(otherwise));
+                }
+            }
+        }
+
+        res.into_iter().flatten().collect()
     }
+}
 
-    #[test]
-    fn add_until_zero_rule() {
-        let mut optimizer = Optimizer::new();
-        optimizer.add_rule(Box::new(AddUntilZeroRule::new()));
+impl Rule for SetRule {
+    fn name(&self) -> &'static str {
+        "set"
+    }
 
-        let tree = SyntaxTree::Root {
-            block: vec![
-                SyntaxTree::Loop {
-                    block: vec![
-                        SyntaxTree::Add { val: -1 },
-                        SyntaxTree::Seek { offset: 2 },
-                        SyntaxTree::Add { val: -2 },
-                        SyntaxTree::Seek { offset: -3 },
-                        SyntaxTree::Add { val: 1 },
-                        SyntaxTree::Seek { offset: 1 },
-                    ],
-                },
-                SyntaxTree::Loop {
-                    block: vec![
-                        SyntaxTree::Add { val: -1 },
-                        SyntaxTree::Seek { offset: 1 },
-                        SyntaxTree::Output,
-                        SyntaxTree::Add { val: 1 },
-                        SyntaxTree::Seek { offset: -1 },
-                    ],
-                },
-            ],
-        };
+    fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+        match block {
+            SyntaxTree::Root { block } => SyntaxTree::Root {
+                block: self.rewrite(block),
+            },
+            SyntaxTree::Loop { block, span } => SyntaxTree::Loop {
+                block: self.rewrite(block),
+                span,
+            },
+            otherwise => otherwise,
+        }
+    }
+}
 
-        let tree = optimizer.optimize(tree);
+/// Coalesce the adjacent outputs into one instruction, such as turning `...` into
+/// `OutputRepeat(0, 3)`. The cells set to constants before are written as the
+/// constants, so that `Set(0, 72)`, `Output(0)`, `Set(0, 105)` and `Output(0)`
+/// write `OutputBytes([72, 105])` instead.
+pub struct OutputRule;
 
-        let expected = SyntaxTree::Root {
-            block: vec![
-                SyntaxTree::AddUntilZero {
-                    target: vec![AddUntilZeroArg::new(2, -2), AddUntilZeroArg::new(-1, 1)],
-                },
-                SyntaxTree::Loop {
-                    block: vec![
-                        SyntaxTree::Add { val: -1 },
-                        SyntaxTree::Seek { offset: 1 },
-                        SyntaxTree::Output,
-                        SyntaxTree::Add { val: 1 },
-                        SyntaxTree::Seek { offset: -1 },
-                    ],
-                },
-            ],
+impl OutputRule {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn rewrite(block: Vec<SyntaxTree>) -> Vec<SyntaxTree> {
+        let mut res = Vec::with_capacity(block.len());
+        // The values of the cells set in the straight-line code. They are kept only
+        // if they fit in any cell, so that no overflow can occur.
+        let mut known: HashMap<isize, i32> = HashMap::new();
+        // The outputs which haven't been pushed to `res`.
+        let mut items = vec![];
+        let mut span = Span::default();
+        let item = |known: &HashMap<isize, i32>, offset| match known.get(&offset) {
+            Some(&val) => OutputItem::Byte { val },
+            None => OutputItem::Cell { offset },
         };
 
-        assert_eq!(tree, expected);
+        for tree in block {
+            match tree {
+                SyntaxTree::Output {
+                    offset,
+                    span: output_span,
+                } => {
+                    items.push(item(&known, offset));
+                    span = span.merge(output_span);
+                }
+                SyntaxTree::OutputBytes {
+                    bytes,
+                    span: output_span,
+                } => {
+                    items.extend(bytes.into_iter().map(|val| OutputItem::Byte { val }));
+                    span = span.merge(output_span);
+                }
+                SyntaxTree::OutputRepeat {
+                    offset,
+                    count,
+                    span: output_span,
+                } => {
+                    items.extend(std::iter::repeat_n(item(&known, offset), count));
+                    span = span.merge(output_span);
+                }
+                SyntaxTree::OutputSeq {
+                    items: seq,
+                    span: output_span,
+                } => {
+                    items.extend(seq.into_iter().map(|seq_item| match seq_item {
+                        OutputItem::Cell { offset } => item(&known, offset),
+                        byte => byte,
+                    }));
+                    span = span.merge(output_span);
+                }
+                otherwise => {
+                    Self::flush(&mut res, &mut items, &mut span);
+
+                    match &otherwise {
+                        SyntaxTree::Set { offset, val, .. } if i8::try_from(*val).is_ok() => {
+                            known.insert(*offset, *val);
+                        }
+                        SyntaxTree::Add { offset, .. }
+                        | SyntaxTree::Set { offset, .. }
+                        | SyntaxTree::Input { offset, .. } => {
+                            known.remove(offset);
+                        }
+                        _ => known.clear(),
+                    }
+
+                    res.push(otherwise);
+                }
+            }
+        }
+
+        Self::flush(&mut res, &mut items, &mut span);
+        res
     }
 
-    #[test]
-    fn add_while_zero_rule_with_changing_the_counter_incorrectly() {
-        let mut optimizer = Optimizer::new();
-        optimizer.add_rule(Box::new(AddUntilZeroRule::new()));
+    /// Push the outputs as one instruction.
+    fn flush(res: &mut Vec<SyntaxTree>, items: &mut Vec<OutputItem>, span: &mut Span) {
+        let items = mem::take(items);
+        let span = mem::take(span);
 
-        let tree = SyntaxTree::Root {
-            block: vec![SyntaxTree::Loop {
-                block: vec![
-                    SyntaxTree::Add { val: -1 },
-                    SyntaxTree::Seek { offset: 1 },
-                    SyntaxTree::Add { val: 1 },
-                    // Move the pointer to the counter and change it apart from
-                    // the decrement in the front of the loop.
-                    SyntaxTree::Seek { offset: -1 },
-                    SyntaxTree::Add { val: -1 },
-                ],
-            }],
+        let tree = match items[..] {
+            [] => return,
+            [OutputItem::Cell { offset }] => SyntaxTree::Output { offset, span },
+            [OutputItem::Cell { offset }, ..]
+                if items.len() <= MAX_REPEAT_COUNT
+                    && items
+                        .iter()
+                        .all(|item| *item == OutputItem::Cell { offset }) =>
+            {
+                SyntaxTree::OutputRepeat {
+                    offset,
+                    count: items.len(),
+                    span,
+                }
+            }
+            _ if items
+                .iter()
+                .all(|item| matches!(item, OutputItem::Byte { .. })) =>
+            {
+                SyntaxTree::OutputBytes {
+                    bytes: items
+                        .iter()
+                        .map(|item| match item {
+                            OutputItem::Byte { val } => *val,
+                            OutputItem::Cell { .. } => unreachable!(),
+                        })
+                        .collect(),
+                    span,
+                }
+            }
+            _ => SyntaxTree::OutputSeq { items, span },
         };
 
-        let tree = optimizer.optimize(tree);
+        res.push(tree);
+    }
+}
 
-        // Failed to optimize the code and nothing changed
-        let expected = SyntaxTree::Root {
-            block: vec![SyntaxTree::Loop {
-                block: vec![
-                    SyntaxTree::Add { val: -1 },
-                    SyntaxTree::Seek { offset: 1 },
-                    SyntaxTree::Add { val: 1 },
-                    SyntaxTree::Seek { offset: -1 },
-                    SyntaxTree::Add { val: -1 },
-                ],
-            }],
+impl Rule for OutputRule {
+    fn name(&self) -> &'static str {
+        "output"
+    }
+
+    fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+        match block {
+            SyntaxTree::Root { block } => SyntaxTree::Root {
+                block: Self::rewrite(block),
+            },
+            SyntaxTree::Loop { block, span } => SyntaxTree::Loop {
+                block: Self::rewrite(block),
+                span,
+            },
+            otherwise => otherwise,
+        }
+    }
+}
+
+const MAX_EVALUATED_STEPS: u64 = 1 << 20;
+
+/// Run the beginning of the program at compile time until it reads the input,
+/// and replace it with `OutputBytes` and the `Set`s and `Seek` which make the
+/// same memory. Loops are run as long as their counters are known, so they are
+/// folded into `Set`s as well. Overflows are handled as the target memory does,
+/// and the code raising an error is left to run.
+pub struct ConstantRule {
+    /// The memory the program runs on, which must be fresh at the beginning.
+    target: Option<MemoryConfig>,
+}
+
+impl ConstantRule {
+    pub fn new(target: Option<MemoryConfig>) -> Self {
+        Self { target }
+    }
+
+    /// Run the trees from the beginning of the block on `memory`, and return how
+    /// many of them are finished with the number of steps taken.
+    fn evaluate_prefix(
+        block: &[SyntaxTree],
+        memory: &mut DynMemory,
+        output: &mut Vec<i32>,
+    ) -> (usize, u64) {
+        let mut steps = 0;
+
+        for (index, tree) in block.iter().enumerate() {
+            if Self::evaluate(tree, memory, output, &mut steps).is_none() {
+                return (index, steps);
+            }
+        }
+
+        (block.len(), steps)
+    }
+
+    /// Run the tree, or return `None` if it reads the input, fails or takes too
+    /// many steps. `memory` and `output` are left changed in that case.
+    fn evaluate(
+        tree: &SyntaxTree,
+        memory: &mut DynMemory,
+        output: &mut Vec<i32>,
+        steps: &mut u64,
+    ) -> Option<()> {
+        *steps += 1;
+
+        if *steps > MAX_EVALUATED_STEPS {
+            return None;
+        }
+
+        let position = memory.position();
+
+        match tree {
+            SyntaxTree::Add { offset, val, .. } => memory.add_at(position + offset, *val).ok(),
+            SyntaxTree::Seek { offset, .. } => memory.seek(*offset as isize).ok(),
+            SyntaxTree::Clear { .. } => memory.set(0).ok(),
+            SyntaxTree::AddUntilZero { target, step, .. } => {
+                // The loop never terminates if the counter never reaches zero.
+                memory.count_until_zero(*step).ok()??;
+                dispatch!(memory, memory => Processor::add_until_zero(target, *step, memory)).ok()
+            }
+            SyntaxTree::Scan { stride, .. } => memory.scan(*stride as isize).ok(),
+            SyntaxTree::Set { offset, val, .. } => memory.set_at(position + offset, *val).ok(),
+            SyntaxTree::Input { .. } => None,
+            SyntaxTree::Output { offset, .. } => {
+                output.push(memory.get_at(position + offset).ok()?);
+                Some(())
+            }
+            SyntaxTree::OutputBytes { bytes, .. } => {
+                output.extend(bytes);
+                Some(())
+            }
+            SyntaxTree::OutputRepeat { offset, count, .. } => {
+                let val = memory.get_at(position + offset).ok()?;
+                output.extend(std::iter::repeat_n(val, *count));
+                Some(())
+            }
+            SyntaxTree::OutputSeq { items, .. } => {
+                dispatch!(memory, memory => Processor::output_seq(items, memory, output)).ok()
+            }
+            SyntaxTree::Loop { block, .. } => {
+                while memory.get() != 0 {
+                    for tree in block {
+                        Self::evaluate(tree, memory, output, steps)?;
+                    }
+
+                    *steps += 1;
+
+                    if *steps > MAX_EVALUATED_STEPS {
+                        return None;
+                    }
+                }
+
+                Some(())
+            }
+            SyntaxTree::Root { .. } => None,
+        }
+    }
+
+    fn rewrite(target: &MemoryConfig, block: Vec<SyntaxTree>) -> Vec<SyntaxTree> {
+        let mut memory = MemoryBuilder::with_config(target.clone()).build();
+        let (count, _) = Self::evaluate_prefix(&block, &mut memory, &mut vec![]);
+
+        if count == 0 {
+            return block;
+        }
+
+        // Run the finished part again on a fresh memory, for the unfinished tree
+        // may have changed it.
+        let mut memory = MemoryBuilder::with_config(target.clone()).build();
+        let initial = memory.position();
+        let mut output = vec![];
+        let (_, steps) = Self::evaluate_prefix(&block[..count], &mut memory, &mut output);
+
+        let span = block[..count]
+            .iter()
+            .fold(Span::default(), |span, tree| span.merge(tree.span()));
+        let mut prefix = vec![];
+
+        if !output.is_empty() {
+            prefix.push(SyntaxTree::OutputBytes {
+                bytes: output,
+                span,
+            });
+        }
+
+        prefix.extend(
+            memory
+                .cells()
+                .filter(|&(_, val)| val != 0)
+                .map(|(addr, val)| SyntaxTree::Set {
+                    offset: addr - initial,
+                    val,
+                    span: Span::default(),
+                }),
+        );
+
+        if memory.position() != initial {
+            prefix.push(SyntaxTree::Seek {
+                offset: (memory.position() - initial) as i32,
+                span: Span::default(),
+            });
+        }
+
+        // It's not worth it when the memory is filled by a few steps, and an empty
+        // program is an error to run.
+        if prefix.len() as u64 > steps || (prefix.is_empty() && count == block.len()) {
+            return block;
+        }
+
+        prefix.extend(block.into_iter().skip(count));
+        prefix
+    }
+}
+
+impl Rule for ConstantRule {
+    fn name(&self) -> &'static str {
+        "constant"
+    }
+
+    fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+        match (block, &self.target) {
+            (SyntaxTree::Root { block }, Some(target)) => SyntaxTree::Root {
+                block: Self::rewrite(target, block),
+            },
+            (otherwise, _) => otherwise,
+        }
+    }
+}
+
+/// Remove the code which never runs or has no effect, with the cells known to be
+/// zero at each point of a block. It removes the loops at the beginning of the
+/// program, where all cells are zero, and the loops right after another one,
+/// which is the idiom of comments. It also removes the changes to the memory at
+/// the end of the program which never fail. A program is never made empty, for
+/// an empty program is an error to run.
+pub struct DeadCodeRule {
+    /// Whether all cells are zero at the beginning, and the memory isn't looked at
+    /// after the program halts.
+    standalone: bool,
+    /// Whether adding to a cell never fails, for the target wraps the value when
+    /// an overflow occurs.
+    wrapping: bool,
+}
+
+impl DeadCodeRule {
+    pub fn new(standalone: bool, wrapping: bool) -> Self {
+        Self {
+            standalone,
+            wrapping,
+        }
+    }
+
+    fn rewrite(block: Vec<SyntaxTree>, mut zeros: ZeroCells) -> Vec<SyntaxTree> {
+        let mut res = Vec::with_capacity(block.len());
+        // Whether the pointer has moved since the current cell was last accessed.
+        // Removing a loop there would merge the `Seek`s around it, which hides the
+        // error of the first one moving the pointer out of the memory.
+        let mut moved = false;
+
+        for tree in block {
+            match &tree {
+                // They all do nothing when the current cell is zero.
+                SyntaxTree::Loop { .. }
+                | SyntaxTree::AddUntilZero { .. }
+                | SyntaxTree::Scan { .. }
+                    if zeros.contains(0) =>
+                {
+                    if moved {
+                        moved = false;
+                        res.push(tree);
+                    }
+
+                    continue;
+                }
+                SyntaxTree::Add { offset, val, .. } => {
+                    if *val != 0 {
+                        zeros.remove(*offset);
+                    }
+                }
+                SyntaxTree::Set { offset, val, .. } => {
+                    if *val == 0 {
+                        zeros.insert(*offset);
+                    } else {
+                        zeros.remove(*offset);
+                    }
+                }
+                SyntaxTree::Clear { .. } => zeros.insert(0),
+                SyntaxTree::Seek { offset, .. } => {
+                    moved = true;
+                    zeros.seek(*offset as isize);
+                }
+                SyntaxTree::Input { offset, .. } => zeros.remove(*offset),
+                SyntaxTree::Output { .. }
+                | SyntaxTree::OutputBytes { .. }
+                | SyntaxTree::OutputRepeat { .. }
+                | SyntaxTree::OutputSeq { .. } => {}
+                SyntaxTree::AddUntilZero { target, .. } => {
+                    for cell in target {
+                        zeros.remove(cell.offset);
+                    }
+
+                    zeros.insert(0);
+                }
+                // Only the current cell is known after the loop ends.
+                SyntaxTree::Scan { .. } | SyntaxTree::Loop { .. } | SyntaxTree::Root { .. } => {
+                    zeros = ZeroCells::Only(HashSet::from([0]));
+                }
+            }
+
+            // They fail there if the pointer is out of the memory.
+            if let SyntaxTree::Add { offset: 0, .. }
+            | SyntaxTree::Set { offset: 0, .. }
+            | SyntaxTree::Input { offset: 0, .. }
+            | SyntaxTree::Output { offset: 0, .. }
+            | SyntaxTree::Clear { .. }
+            | SyntaxTree::AddUntilZero { .. }
+            | SyntaxTree::Scan { .. }
+            | SyntaxTree::Loop { .. } = tree
+            {
+                moved = false;
+            }
+
+            res.push(tree);
+        }
+
+        res
+    }
+
+    /// Remove the changes to the memory at the end of the program which never
+    /// fail. Moving the pointer or touching other cells may fail, for the bounds
+    /// of the memory aren't known here.
+    fn remove_trailing(&self, block: &mut Vec<SyntaxTree>) {
+        while let Some(tree) = block.last() {
+            match tree {
+                SyntaxTree::Clear { .. } => {}
+                SyntaxTree::Add { offset: 0, .. } | SyntaxTree::Set { offset: 0, .. }
+                    if self.wrapping => {}
+                _ => break,
+            }
+
+            block.pop();
+        }
+    }
+}
+
+impl Rule for DeadCodeRule {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+        match block {
+            SyntaxTree::Root { block } if self.standalone => {
+                let mut res = Self::rewrite(block.clone(), ZeroCells::AllBut(HashSet::new()));
+                self.remove_trailing(&mut res);
+
+                if res.is_empty() {
+                    SyntaxTree::Root { block }
+                } else {
+                    SyntaxTree::Root { block: res }
+                }
+            }
+            SyntaxTree::Root { block } => SyntaxTree::Root {
+                block: Self::rewrite(block, ZeroCells::Only(HashSet::new())),
+            },
+            SyntaxTree::Loop { block, span } => SyntaxTree::Loop {
+                block: Self::rewrite(block, ZeroCells::Only(HashSet::new())),
+                span,
+            },
+            otherwise => otherwise,
+        }
+    }
+}
+
+/// The cells known to be zero, whose offsets are relative to the pointer.
+enum ZeroCells {
+    /// All cells are zero except these ones.
+    AllBut(HashSet<isize>),
+    /// Only these cells are zero.
+    Only(HashSet<isize>),
+}
+
+impl ZeroCells {
+    fn contains(&self, offset: isize) -> bool {
+        match self {
+            ZeroCells::AllBut(cells) => !cells.contains(&offset),
+            ZeroCells::Only(cells) => cells.contains(&offset),
+        }
+    }
+
+    fn insert(&mut self, offset: isize) {
+        match self {
+            ZeroCells::AllBut(cells) => cells.remove(&offset),
+            ZeroCells::Only(cells) => cells.insert(offset),
+        };
+    }
+
+    fn remove(&mut self, offset: isize) {
+        match self {
+            ZeroCells::AllBut(cells) => cells.insert(offset),
+            ZeroCells::Only(cells) => cells.remove(&offset),
+        };
+    }
+
+    /// Move the pointer by `offset`.
+    fn seek(&mut self, offset: isize) {
+        let (ZeroCells::AllBut(cells) | ZeroCells::Only(cells)) = self;
+        *cells = cells.iter().map(|cell| cell - offset).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::config::{Config, OptLevel};
+    use crate::compiler::parser::syntax::AddUntilZeroArg;
+    use crate::compiler::span::Position;
+
+    use super::*;
+
+    #[test]
+    fn clear_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(ClearRule::new()));
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Loop {
+                    block: vec![SyntaxTree::Add {
+                        offset: 0,
+                        val: -1,
+                        span,
+                    }],
+                    span,
+                },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Clear { span },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn add_until_zero_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(AddUntilZeroRule::new(None)));
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 2, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -2,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -3, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                    ],
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Output { offset: 0, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -1, span },
+                    ],
+                    span,
+                },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::AddUntilZero {
+                    target: vec![AddUntilZeroArg::new(2, -2), AddUntilZeroArg::new(-1, 1)],
+                    step: -1,
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Output { offset: 0, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -1, span },
+                    ],
+                    span,
+                },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn add_while_zero_rule_with_changing_the_counter_incorrectly() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(AddUntilZeroRule::new(None)));
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: vec![
+                    SyntaxTree::Add {
+                        offset: 0,
+                        val: -1,
+                        span,
+                    },
+                    SyntaxTree::Seek { offset: 1, span },
+                    SyntaxTree::Add {
+                        offset: 0,
+                        val: 1,
+                        span,
+                    },
+                    // Move the pointer to the counter and change it apart from
+                    // the decrement in the front of the loop.
+                    SyntaxTree::Seek { offset: -1, span },
+                    SyntaxTree::Add {
+                        offset: 0,
+                        val: -1,
+                        span,
+                    },
+                ],
+                span,
+            }],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        // Failed to optimize the code and nothing changed
+        let expected = SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: vec![
+                    SyntaxTree::Add {
+                        offset: 0,
+                        val: -1,
+                        span,
+                    },
+                    SyntaxTree::Seek { offset: 1, span },
+                    SyntaxTree::Add {
+                        offset: 0,
+                        val: 1,
+                        span,
+                    },
+                    SyntaxTree::Seek { offset: -1, span },
+                    SyntaxTree::Add {
+                        offset: 0,
+                        val: -1,
+                        span,
+                    },
+                ],
+                span,
+            }],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn scan_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(ScanRule::new()));
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Loop {
+                    block: vec![SyntaxTree::Seek { offset: -2, span }],
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Seek { offset: 1, span },
+                    ],
+                    span,
+                },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Scan { stride: -2, span },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Seek { offset: 1, span },
+                    ],
+                    span,
+                },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn offset_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(OffsetRule::new()));
+        let span = |offset| Span::of_char(Position::new(offset, 1, offset + 1), '+');
+
+        // `>+>.<<-[>,]<`
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Seek {
+                    offset: 1,
+                    span: span(0),
+                },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span: span(1),
+                },
+                SyntaxTree::Seek {
+                    offset: 1,
+                    span: span(2),
+                },
+                SyntaxTree::Output {
+                    offset: 0,
+                    span: span(3),
+                },
+                SyntaxTree::Seek {
+                    offset: -2,
+                    span: span(4).merge(span(5)),
+                },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: -1,
+                    span: span(6),
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Seek {
+                            offset: 1,
+                            span: span(8),
+                        },
+                        SyntaxTree::Input {
+                            offset: 0,
+                            span: span(9),
+                        },
+                    ],
+                    span: span(7).merge(span(10)),
+                },
+                SyntaxTree::Seek {
+                    offset: -1,
+                    span: span(11),
+                },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 1,
+                    val: 1,
+                    span: span(0).merge(span(1)),
+                },
+                SyntaxTree::Output {
+                    offset: 2,
+                    span: span(2).merge(span(3)),
+                },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: -1,
+                    span: span(4).merge(span(6)),
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Input {
+                            offset: 1,
+                            span: span(8).merge(span(9)),
+                        },
+                        SyntaxTree::Seek {
+                            offset: 1,
+                            span: span(8),
+                        },
+                    ],
+                    span: span(7).merge(span(10)),
+                },
+                SyntaxTree::Seek {
+                    offset: -1,
+                    span: span(11),
+                },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn add_until_zero_rule_with_general_counter() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(AddUntilZeroRule::new(None)));
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                // `[>+<---]`
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -3,
+                            span,
+                        },
+                    ],
+                    span,
+                },
+                // `[>+>[-]++>+<<<+>-<]`
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Clear { span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 2,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -3, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -1, span },
+                    ],
+                    span,
+                },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::AddUntilZero {
+                    target: vec![AddUntilZeroArg::new(1, 1)],
+                    step: -3,
+                    span,
+                },
+                SyntaxTree::AddUntilZero {
+                    target: vec![AddUntilZeroArg::cleared(2, 2), AddUntilZeroArg::new(3, 1)],
+                    step: 1,
+                    span,
+                },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn add_until_zero_rule_with_even_step() {
+        use crate::execution::memory::config::Cell;
+
+        let span = Span::default();
+        // A loop adding `step` to the counter and 1 to the next cell.
+        let tree = |step| SyntaxTree::Loop {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: step,
+                    span,
+                },
+                SyntaxTree::Add {
+                    offset: 1,
+                    val: 1,
+                    span,
+                },
+            ],
+            span,
+        };
+        let optimize = |target, step| {
+            let mut optimizer = Optimizer::new();
+            optimizer.add_rule(Rc::new(AddUntilZeroRule::new(target)));
+            optimizer.optimize(SyntaxTree::Root {
+                block: vec![tree(step)],
+            })
+        };
+        let folded = |step| SyntaxTree::Root {
+            block: vec![SyntaxTree::AddUntilZero {
+                target: vec![AddUntilZeroArg::new(1, 1)],
+                step,
+                span,
+            }],
+        };
+        let kept = |step| SyntaxTree::Root {
+            block: vec![tree(step)],
+        };
+        let wrap = MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+
+        // The count is exact when the counter overflows with an error.
+        assert_eq!(optimize(Some(Default::default()), -2), folded(-2));
+        assert_eq!(optimize(Some(Default::default()), 256), folded(256));
+        assert_eq!(optimize(Some(Default::default()), 0), kept(0));
+        // It's solved when the counter wraps, unless it never changes.
+        assert_eq!(optimize(Some(wrap.clone()), 4), folded(4));
+        assert_eq!(optimize(Some(wrap.clone()), 256), kept(256));
+        let wide = MemoryConfig {
+            cell: Cell::I32,
+            ..wrap
+        };
+        assert_eq!(optimize(Some(wide), 256), folded(256));
+        // An unknown memory only allows odd steps.
+        assert_eq!(optimize(None, -2), kept(-2));
+        assert_eq!(optimize(None, 3), folded(3));
+    }
+
+    #[test]
+    fn add_until_zero_rule_with_clearing_the_counter() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(AddUntilZeroRule::new(None)));
+        let span = Span::default();
+
+        // `[>+<[-]]` runs at most once.
+        let block = || {
+            vec![
+                SyntaxTree::Seek { offset: 1, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Seek { offset: -1, span },
+                SyntaxTree::Clear { span },
+            ]
+        };
+
+        let tree = SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: block(),
+                span,
+            }],
+        };
+
+        let expected = SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: block(),
+                span,
+            }],
+        };
+
+        assert_eq!(optimizer.optimize(tree), expected);
+    }
+
+    #[test]
+    fn set_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(SetRule::new(false, true)));
+        let span = |offset| Span::of_char(Position::new(offset, 1, offset + 1), '+');
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                // The cell 2 is read first, so it's known to be in the memory.
+                SyntaxTree::Output {
+                    offset: 2,
+                    span: span(9),
+                },
+                SyntaxTree::Set {
+                    offset: 0,
+                    val: 0,
+                    span: span(0),
+                },
+                SyntaxTree::Set {
+                    offset: 1,
+                    val: 0,
+                    span: span(1),
+                },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 3,
+                    span: span(2),
+                },
+                SyntaxTree::Output {
+                    offset: 1,
+                    span: span(3),
+                },
+                SyntaxTree::Add {
+                    offset: 1,
+                    val: 2,
+                    span: span(4),
+                },
+                SyntaxTree::Add {
+                    offset: 2,
+                    val: 1,
+                    span: span(5),
+                },
+                SyntaxTree::Set {
+                    offset: 2,
+                    val: 4,
+                    span: span(6),
+                },
+                SyntaxTree::Add {
+                    offset: 3,
+                    val: 1,
+                    span: span(7),
+                },
+                SyntaxTree::Input {
+                    offset: 3,
+                    span: span(8),
+                },
+            ],
+        };
+
+        let res = optimizer.optimize(tree.clone());
+
+        let mut expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Output {
+                    offset: 2,
+                    span: span(9),
+                },
+                SyntaxTree::Set {
+                    offset: 0,
+                    val: 3,
+                    span: span(0).merge(span(2)),
+                },
+                SyntaxTree::Set {
+                    offset: 1,
+                    val: 0,
+                    span: span(1),
+                },
+                SyntaxTree::Output {
+                    offset: 1,
+                    span: span(3),
+                },
+                SyntaxTree::Add {
+                    offset: 1,
+                    val: 2,
+                    span: span(4),
+                },
+                SyntaxTree::Set {
+                    offset: 2,
+                    val: 4,
+                    span: span(6),
+                },
+                // `EOF` may be ignored, so the cell may be unchanged.
+                SyntaxTree::Add {
+                    offset: 3,
+                    val: 1,
+                    span: span(7),
+                },
+                SyntaxTree::Input {
+                    offset: 3,
+                    span: span(8),
+                },
+            ],
+        };
+
+        assert_eq!(res, expected);
+
+        // The `Add` may overflow, so it's kept when the target doesn't wrap.
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(SetRule::new(false, false)));
+        let SyntaxTree::Root { block } = &mut expected else {
+            unreachable!()
+        };
+        block.insert(
+            5,
+            SyntaxTree::Add {
+                offset: 2,
+                val: 1,
+                span: span(5),
+            },
+        );
+        assert_eq!(optimizer.optimize(tree), expected);
+    }
+
+    #[test]
+    fn set_rule_keeps_failing_writes() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(SetRule::new(false, false)));
+        let span = |offset| Span::of_char(Position::new(offset, 1, offset + 1), '+');
+
+        // The second `Add` overflows after the output, so it isn't folded.
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Set {
+                    offset: 0,
+                    val: 100,
+                    span: span(0),
+                },
+                SyntaxTree::Output {
+                    offset: 1,
+                    span: span(1),
+                },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 100,
+                    span: span(2),
+                },
+            ],
+        };
+
+        assert_eq!(optimizer.optimize(tree.clone()), tree);
+
+        // The cell -1 may be out of the memory, so the first write fails before the
+        // output, and it's kept until the cell is accessed.
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Set {
+                    offset: -1,
+                    val: 5,
+                    span: span(0),
+                },
+                SyntaxTree::Output {
+                    offset: 0,
+                    span: span(1),
+                },
+                SyntaxTree::Set {
+                    offset: -1,
+                    val: 7,
+                    span: span(2),
+                },
+                SyntaxTree::Set {
+                    offset: -1,
+                    val: 9,
+                    span: span(3),
+                },
+            ],
+        };
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Set {
+                    offset: -1,
+                    val: 5,
+                    span: span(0),
+                },
+                SyntaxTree::Output {
+                    offset: 0,
+                    span: span(1),
+                },
+                SyntaxTree::Set {
+                    offset: -1,
+                    val: 9,
+                    span: span(3),
+                },
+            ],
+        };
+
+        assert_eq!(optimizer.optimize(tree), expected);
+    }
+
+    #[test]
+    fn set_rule_with_input_overwriting() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(SetRule::new(true, true)));
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Set {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    fn rule_names(config: Config) -> Result<Vec<&'static str>, ParseError> {
+        let optimizer = Builder::with_config(config).build()?;
+        Ok(optimizer.rules.iter().map(|rule| rule.name()).collect())
+    }
+
+    #[test]
+    fn select_rules_by_level() {
+        let config = |opt_level| Config {
+            opt_level,
+            ..Default::default()
+        };
+
+        assert_eq!(rule_names(config(OptLevel::O0)), Ok(vec![]));
+        assert_eq!(rule_names(config(OptLevel::O1)), Ok(vec!["clear", "scan"]));
+        assert_eq!(
+            rule_names(config(OptLevel::O3)),
+            Ok(vec![
+                "clear",
+                "add-until-zero",
+                "scan",
+                "offset",
+                "set",
+                "output",
+                "constant",
+                "dead-code"
+            ])
+        );
+    }
+
+    #[test]
+    fn select_rules_by_name() {
+        let config = Config {
+            opt_level: OptLevel::O1,
+            enable: vec!["offset".to_string(), "set".to_string()],
+            disable: vec!["scan".to_string(), "set".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(rule_names(config), Ok(vec!["clear", "offset"]));
+
+        let config = Config {
+            disable: vec!["nothing".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            rule_names(config),
+            Err(ParseError::UnknownRule {
+                name: "nothing".to_string()
+            })
+        );
+    }
+
+    /// Remove all outputs, which may leave a loop to `ScanRule` in the next
+    /// iteration.
+    struct NoOutputRule;
+
+    impl Rule for NoOutputRule {
+        fn name(&self) -> &'static str {
+            "no-output"
+        }
+
+        fn apply(&self, block: SyntaxTree) -> SyntaxTree {
+            let remove = |block: Vec<SyntaxTree>| {
+                block
+                    .into_iter()
+                    .filter(|tree| !matches!(tree, SyntaxTree::Output { .. }))
+                    .collect()
+            };
+
+            match block {
+                SyntaxTree::Root { block } => SyntaxTree::Root {
+                    block: remove(block),
+                },
+                SyntaxTree::Loop { block, span } => SyntaxTree::Loop {
+                    block: remove(block),
+                    span,
+                },
+                otherwise => otherwise,
+            }
+        }
+    }
+
+    #[test]
+    fn custom_rule_to_fixpoint() {
+        let span = Span::default();
+        let tree = || SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: vec![
+                    SyntaxTree::Output { offset: 0, span },
+                    SyntaxTree::Seek { offset: 1, span },
+                ],
+                span,
+            }],
+        };
+
+        // Keep the loop at the beginning.
+        let config = Config {
+            standalone: false,
+            ..Default::default()
+        };
+
+        let optimizer = Builder::with_config(config.clone())
+            .rule(NoOutputRule)
+            .build()
+            .unwrap();
+        let expected = SyntaxTree::Root {
+            block: vec![SyntaxTree::Scan { stride: 1, span }],
+        };
+        assert_eq!(optimizer.optimize(tree()), expected);
+
+        let optimizer = Builder::with_config(config)
+            .rule(NoOutputRule)
+            .max_iterations(1)
+            .build()
+            .unwrap();
+        let expected = SyntaxTree::Root {
+            block: vec![SyntaxTree::Loop {
+                block: vec![SyntaxTree::Seek { offset: 1, span }],
+                span,
+            }],
+        };
+        assert_eq!(optimizer.optimize(tree()), expected);
+    }
+
+    #[test]
+    fn disable_custom_rule() {
+        let config = Config {
+            opt_level: OptLevel::O0,
+            disable: vec!["no-output".to_string()],
+            ..Default::default()
+        };
+        let optimizer = Builder::with_config(config)
+            .rule(NoOutputRule)
+            .build()
+            .unwrap();
+        assert!(optimizer.rules.is_empty());
+    }
+
+    #[test]
+    fn stats() {
+        let optimizer = Builder::new().build().unwrap();
+        let span = Span::default();
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Loop {
+                    block: vec![SyntaxTree::Add {
+                        offset: 0,
+                        val: -1,
+                        span,
+                    }],
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: 1, span },
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: 1,
+                            span,
+                        },
+                        SyntaxTree::Seek { offset: -1, span },
+                    ],
+                    span,
+                },
+            ],
+        };
+
+        let (_, stats) = optimizer.optimize_with_stats(tree);
+
+        let pass = |name, rewrites, before, after| PassStats {
+            name,
+            rewrites,
+            before,
+            after,
+        };
+        // The multiplication loop is removed at last, for the cell is cleared before.
+        let expected = Stats {
+            iterations: 2,
+            before: 10,
+            after: 2,
+            passes: vec![
+                pass("clear", 1, 3, 1),
+                pass("add-until-zero", 1, 6, 1),
+                pass("scan", 0, 0, 0),
+                pass("offset", 1, 3, 3),
+                pass("set", 0, 0, 0),
+                pass("output", 0, 0, 0),
+                pass("constant", 0, 0, 0),
+                pass("dead-code", 1, 3, 2),
+            ],
+        };
+
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn dead_code_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(DeadCodeRule::new(true, true)));
+        let span = Span::default();
+        let comment = || SyntaxTree::Loop {
+            block: vec![SyntaxTree::Output { offset: 0, span }],
+            span,
+        };
+        let transfer = || SyntaxTree::Loop {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: -1,
+                    span,
+                },
+                SyntaxTree::Seek { offset: 1, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Seek { offset: -1, span },
+            ],
+            span,
+        };
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                comment(),
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                transfer(),
+                comment(),
+                SyntaxTree::Output { offset: 0, span },
+                SyntaxTree::Seek { offset: 1, span },
+                comment(),
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Clear { span },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                transfer(),
+                SyntaxTree::Output { offset: 0, span },
+                SyntaxTree::Seek { offset: 1, span },
+                comment(),
+            ],
+        };
+
+        assert_eq!(tree, expected);
+
+        // Moving the pointer may fail, and a program is never made empty.
+        for block in [
+            vec![SyntaxTree::Seek { offset: -1, span }],
+            vec![comment()],
+            vec![SyntaxTree::Add {
+                offset: 0,
+                val: 3,
+                span,
+            }],
+        ] {
+            let tree = SyntaxTree::Root { block };
+            assert_eq!(optimizer.optimize(tree.clone()), tree);
+        }
+    }
+
+    #[test]
+    fn dead_code_rule_with_unknown_memory() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(DeadCodeRule::new(false, false)));
+        let span = Span::default();
+        let comment = || SyntaxTree::Loop {
+            block: vec![SyntaxTree::Output { offset: 0, span }],
+            span,
+        };
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                comment(),
+                comment(),
+                SyntaxTree::Set {
+                    offset: 1,
+                    val: 0,
+                    span,
+                },
+                SyntaxTree::Seek { offset: 1, span },
+                comment(),
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Clear { span },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                comment(),
+                SyntaxTree::Set {
+                    offset: 1,
+                    val: 0,
+                    span,
+                },
+                SyntaxTree::Seek { offset: 1, span },
+                // Removing it would merge the `Seek`s around it.
+                comment(),
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Clear { span },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+
+        // The `Add` may overflow, so only what's after it is removed.
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(DeadCodeRule::new(true, false)));
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Output { offset: 0, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Clear { span },
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Output { offset: 0, span },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+            ],
+        };
+
+        assert_eq!(tree, expected);
+    }
+
+    #[test]
+    fn constant_rule() {
+        use crate::execution::memory::config::{Addr, Cell};
+
+        let span = Span::default();
+        let tree = || SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 8,
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![
+                        SyntaxTree::Add {
+                            offset: 0,
+                            val: -1,
+                            span,
+                        },
+                        SyntaxTree::Add {
+                            offset: 1,
+                            val: 16,
+                            span,
+                        },
+                    ],
+                    span,
+                },
+                SyntaxTree::Output { offset: 1, span },
+                SyntaxTree::Seek { offset: 1, span },
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Output { offset: 0, span },
+            ],
+        };
+        let optimize = |cell, overflow| {
+            let target = MemoryConfig {
+                len: 16,
+                addr: Addr::Unsigned,
+                cell,
+                overflow,
+                eof: Eof::Ignore,
+            };
+            let mut optimizer = Optimizer::new();
+            optimizer.add_rule(Rc::new(ConstantRule::new(Some(target))));
+            optimizer.optimize(tree())
+        };
+        let folded = |val| SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::OutputBytes {
+                    bytes: vec![val],
+                    span,
+                },
+                SyntaxTree::Set {
+                    offset: 1,
+                    val,
+                    span,
+                },
+                SyntaxTree::Seek { offset: 1, span },
+                SyntaxTree::Input { offset: 0, span },
+                SyntaxTree::Output { offset: 0, span },
+            ],
+        };
+
+        assert_eq!(optimize(Cell::I8, Overflow::Wrap), folded(-128));
+        assert_eq!(optimize(Cell::I32, Overflow::Error), folded(128));
+
+        // The loop overflows, so it's left to report the error.
+        let mut expected = tree();
+        let SyntaxTree::Root { block } = &mut expected else {
+            unreachable!()
+        };
+        block[0] = SyntaxTree::Set {
+            offset: 0,
+            val: 8,
+            span,
+        };
+        assert_eq!(optimize(Cell::I8, Overflow::Error), expected);
+
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(ConstantRule::new(None)));
+        assert_eq!(optimizer.optimize(tree()), tree());
+
+        // The program does nothing, but it isn't made empty.
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(ConstantRule::new(Some(Default::default()))));
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Clear { span },
+            ],
+        };
+        assert_eq!(optimizer.optimize(tree.clone()), tree);
+
+        // The loop never ends, so it's left to run.
+        let tree = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![],
+                    span,
+                },
+            ],
+        };
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::Set {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Loop {
+                    block: vec![],
+                    span,
+                },
+            ],
+        };
+        assert_eq!(optimizer.optimize(tree), expected);
+    }
+
+    #[test]
+    fn output_rule() {
+        let mut optimizer = Optimizer::new();
+        optimizer.add_rule(Rc::new(OutputRule::new()));
+        let span = Span::default();
+        let set = |offset, val| SyntaxTree::Set { offset, val, span };
+        let output = |offset| SyntaxTree::Output { offset, span };
+
+        let tree = SyntaxTree::Root {
+            block: vec![
+                output(0),
+                output(0),
+                output(0),
+                set(1, 72),
+                output(1),
+                output(0),
+                set(1, 105),
+                output(1),
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                output(1),
+                output(1),
+                set(2, 300),
+                output(2),
+            ],
+        };
+
+        let tree = optimizer.optimize(tree);
+
+        let expected = SyntaxTree::Root {
+            block: vec![
+                SyntaxTree::OutputRepeat {
+                    offset: 0,
+                    count: 3,
+                    span,
+                },
+                set(1, 72),
+                SyntaxTree::OutputSeq {
+                    items: vec![OutputItem::Byte { val: 72 }, OutputItem::Cell { offset: 0 }],
+                    span,
+                },
+                set(1, 105),
+                SyntaxTree::OutputBytes {
+                    bytes: vec![105],
+                    span,
+                },
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::OutputBytes {
+                    bytes: vec![105, 105],
+                    span,
+                },
+                // It may not fit in the cell.
+                set(2, 300),
+                output(2),
+            ],
         };
 
         assert_eq!(tree, expected);
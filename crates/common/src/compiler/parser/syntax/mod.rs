@@ -1,101 +1,344 @@
+use std::fmt::{Display, Formatter};
+
+use crate::compiler::diagnostic::Diagnostic;
+use crate::compiler::instruction::Instruction;
 use crate::compiler::lexer::{SingleToken, Token, TokenList};
+use crate::compiler::span::{self, Span};
 use snafu::prelude::*;
 
-pub type Result<T> = std::result::Result<T, SyntaxError>;
+pub type Result<T> = std::result::Result<T, Vec<SyntaxError>>;
 
-#[derive(Debug, PartialEq, Eq)]
+/// A cell changed by `AddUntilZero`. `times` is added to the cell in each
+/// iteration. If `clear` is true, the cell is cleared in each iteration before
+/// adding, so it's set to `times` after the loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AddUntilZeroArg {
     pub offset: isize,
     pub times: i32,
+    pub clear: bool,
 }
 
 impl AddUntilZeroArg {
     pub fn new(offset: isize, times: i32) -> Self {
-        Self { offset, times }
+        Self {
+            offset,
+            times,
+            clear: false,
+        }
+    }
+
+    pub fn cleared(offset: isize, times: i32) -> Self {
+        Self {
+            offset,
+            times,
+            clear: true,
+        }
+    }
+}
+
+impl Display for AddUntilZeroArg {
+    /// Write `offset: times`, or `offset: =times` if the cell is cleared.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.clear {
+            write!(f, "{}: ={}", self.offset, self.times)
+        } else {
+            write!(f, "{}: {}", self.offset, self.times)
+        }
+    }
+}
+
+/// What `OutputSeq` writes, which is either a cell at `offset` or a constant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputItem {
+    Cell { offset: isize },
+    Byte { val: i32 },
+}
+
+impl Display for OutputItem {
+    /// Write a cell as `@offset`, and a constant as it is.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputItem::Cell { offset } => write!(f, "@{offset}"),
+            OutputItem::Byte { val } => write!(f, "{val}"),
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// `offset` in `Add`, `Set`, `Input` and `Output` is the position of the cell
+/// they operate, relative to the pointer.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SyntaxTree {
-    Add { val: i32 },
-    Seek { offset: i32 },
-    Clear,
-    AddUntilZero { target: Vec<AddUntilZeroArg> },
-    Input,
-    Output,
-    Root { block: Vec<SyntaxTree> },
-    Loop { block: Vec<SyntaxTree> },
+    Add {
+        offset: isize,
+        val: i32,
+        span: Span,
+    },
+    Seek {
+        offset: i32,
+        span: Span,
+    },
+    Clear {
+        span: Span,
+    },
+    /// A loop which changes the counter by `step` in each iteration until it's
+    /// zero, and changes other cells in `target` linearly.
+    AddUntilZero {
+        target: Vec<AddUntilZeroArg>,
+        step: i32,
+        span: Span,
+    },
+    /// Move the pointer by `stride` until it points to a zero cell.
+    Scan {
+        stride: i32,
+        span: Span,
+    },
+    Set {
+        offset: isize,
+        val: i32,
+        span: Span,
+    },
+    Input {
+        offset: isize,
+        span: Span,
+    },
+    Output {
+        offset: isize,
+        span: Span,
+    },
+    /// Write the values in `bytes` to the output, which are computed at compile
+    /// time.
+    OutputBytes {
+        bytes: Vec<i32>,
+        span: Span,
+    },
+    /// Write the cell at `offset` to the output for `count` times.
+    OutputRepeat {
+        offset: isize,
+        count: usize,
+        span: Span,
+    },
+    /// Write what `items` refer to in order.
+    OutputSeq {
+        items: Vec<OutputItem>,
+        span: Span,
+    },
+    Root {
+        block: Vec<SyntaxTree>,
+    },
+    Loop {
+        block: Vec<SyntaxTree>,
+        span: Span,
+    },
 }
 
 impl SyntaxTree {
+    /// Build the syntax tree. Unpaired brackets don't stop the building, so all of
+    /// them are reported at once, sorted by their locations.
     pub fn build(token_list: TokenList) -> Result<SyntaxTree> {
         let mut current = token_list.0.into_iter();
-        let mut left_bracket_count = 0;
-        let block = SyntaxTree::build_impl(&mut current, &mut left_bracket_count)?;
-        Ok(SyntaxTree::Root { block })
+        let mut errors = vec![];
+        let (block, _) = SyntaxTree::build_impl(&mut current, None, &mut errors);
+
+        if errors.is_empty() {
+            Ok(SyntaxTree::Root { block })
+        } else {
+            errors.sort_by_key(|e| e.span().start.offset);
+            Err(errors)
+        }
     }
 
-    fn build_impl<I>(current: &mut I, left_bracket_count: &mut i32) -> Result<Vec<SyntaxTree>>
+    /// Build the block until the `]` paired with the `[` at `left_bracket`, which
+    /// is `None` for the outermost block. Return the block and the span of the `]`.
+    fn build_impl<I>(
+        current: &mut I,
+        left_bracket: Option<Span>,
+        errors: &mut Vec<SyntaxError>,
+    ) -> (Vec<SyntaxTree>, Option<Span>)
     where
         I: Iterator<Item = Token>,
     {
         let mut res: Vec<SyntaxTree> = vec![];
 
         loop {
-            if let Some(Token { token, count }) = current.next() {
+            if let Some(Token { token, count, span }) = current.next() {
                 match token {
-                    SingleToken::Add => res.push(SyntaxTree::Add { val: count }),
-                    SingleToken::GreaterThan => res.push(SyntaxTree::Seek { offset: count }),
+                    SingleToken::Add => res.push(SyntaxTree::Add {
+                        offset: 0,
+                        val: count,
+                        span,
+                    }),
+                    SingleToken::GreaterThan => res.push(SyntaxTree::Seek {
+                        offset: count,
+                        span,
+                    }),
                     SingleToken::Comma => {
                         for _ in 0..count {
-                            res.push(SyntaxTree::Input)
+                            res.push(SyntaxTree::Input { offset: 0, span })
                         }
                     }
                     SingleToken::Dot => {
                         for _ in 0..count {
-                            res.push(SyntaxTree::Output)
+                            res.push(SyntaxTree::Output { offset: 0, span })
                         }
                     }
                     SingleToken::LeftBracket => {
-                        *left_bracket_count += 1;
-                        let block = SyntaxTree::build_impl(current, left_bracket_count)?;
-                        res.push(SyntaxTree::Loop { block })
+                        let (block, right_bracket) =
+                            SyntaxTree::build_impl(current, Some(span), errors);
+                        // An unpaired `[` has been reported, so the span is only
+                        // used for recovering.
+                        let span = span.merge(right_bracket.unwrap_or_default());
+                        res.push(SyntaxTree::Loop { block, span })
+                    }
+                    SingleToken::RightBracket if left_bracket.is_some() => {
+                        return (res, Some(span));
                     }
                     SingleToken::RightBracket => {
-                        *left_bracket_count -= 1;
-                        ensure!(*left_bracket_count >= 0, UnpairedRightBracketSnafu);
-                        break;
+                        let last_loop = res.iter().rev().find_map(|tree| match tree {
+                            SyntaxTree::Loop { span, .. } => Some(*span),
+                            _ => None,
+                        });
+                        errors.push(SyntaxError::UnpairedRightBracket { span, last_loop });
                     }
                     // Both `SingleToken::Sub` and `SingleToken::LessThan` have been
                     // converted to `SingleToken::Add` and `SingleToken::GreaterThan`.
                     SingleToken::Sub | SingleToken::LessThan => {}
                 }
             } else {
-                if *left_bracket_count == 0 {
-                    break;
-                } else if *left_bracket_count > 0 {
-                    return Err(SyntaxError::UnpairedLeftBracket);
+                if let Some(span) = left_bracket {
+                    errors.push(SyntaxError::UnpairedLeftBracket { span });
+                }
+
+                return (res, None);
+            }
+        }
+    }
+
+    /// Return the span of the source code this node comes from. `Root` covers
+    /// all of its children.
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxTree::Add { span, .. }
+            | SyntaxTree::Seek { span, .. }
+            | SyntaxTree::Clear { span }
+            | SyntaxTree::AddUntilZero { span, .. }
+            | SyntaxTree::Scan { span, .. }
+            | SyntaxTree::Set { span, .. }
+            | SyntaxTree::Input { span, .. }
+            | SyntaxTree::Output { span, .. }
+            | SyntaxTree::OutputBytes { span, .. }
+            | SyntaxTree::OutputRepeat { span, .. }
+            | SyntaxTree::OutputSeq { span, .. }
+            | SyntaxTree::Loop { span, .. } => *span,
+            SyntaxTree::Root { block } => block
+                .iter()
+                .fold(Span::default(), |span, tree| span.merge(tree.span())),
+        }
+    }
+
+    /// Return the number of instructions the node is compiled into, including
+    /// the jumps of a `Loop` and the `Halt` at the end of `Root`.
+    pub fn instruction_count(&self) -> usize {
+        match self {
+            SyntaxTree::Root { block } => {
+                block
+                    .iter()
+                    .map(|tree| tree.instruction_count())
+                    .sum::<usize>()
+                    + 1
+            }
+            SyntaxTree::Loop { block, .. } => {
+                block
+                    .iter()
+                    .map(|tree| tree.instruction_count())
+                    .sum::<usize>()
+                    + 2
+            }
+            _ => 1,
+        }
+    }
+
+    fn write(&self, f: &mut Formatter<'_>, indent: usize) -> std::fmt::Result {
+        match self {
+            SyntaxTree::Root { block } => {
+                for tree in block {
+                    tree.write(f, indent)?;
                 }
-                // It's impossible to reach where `left_bracket_count < 0`, for it has
-                // been already checked above.
+
+                Ok(())
+            }
+            SyntaxTree::Loop { block, span } => {
+                span::write_line(f, indent, "loop", *span)?;
+
+                for tree in block {
+                    tree.write(f, indent + 1)?;
+                }
+
+                span::write_line(f, indent, "end", Span::default())
             }
+            // The other nodes are written in the same way as their instructions.
+            leaf => match Instruction::from_leaf(leaf.clone()) {
+                Some((instruction, span)) => span::write_line(f, indent, instruction, span),
+                None => unreachable!(),
+            },
         }
+    }
+}
 
-        Ok(res)
+impl Display for SyntaxTree {
+    /// Write a node per line, with the nodes in a loop indented between `loop`
+    /// and `end`. Their spans are written in the alternate form.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write(f, 0)
     }
 }
 
 #[derive(Snafu, Debug, PartialEq, Eq)]
 pub enum SyntaxError {
-    #[snafu(display("found an unpaired `[`, expected another `]`"))]
-    UnpairedLeftBracket,
-    #[snafu(display("found an unpaired `]`, expected another `[`"))]
-    UnpairedRightBracket,
+    #[snafu(display("found an unpaired `[` at {span}, expected another `]`"))]
+    UnpairedLeftBracket { span: Span },
+    /// `last_loop` is the last loop closed before the unpaired `]` in the same
+    /// block, which is usually where an extra `]` was written.
+    #[snafu(display("found an unpaired `]` at {span}, expected another `[`"))]
+    UnpairedRightBracket { span: Span, last_loop: Option<Span> },
+}
+
+impl SyntaxError {
+    /// Return the span of the unpaired bracket.
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxError::UnpairedLeftBracket { span } => *span,
+            SyntaxError::UnpairedRightBracket { span, .. } => *span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            SyntaxError::UnpairedLeftBracket { span } => {
+                Diagnostic::error("unclosed loop, expected another `]`", *span)
+                    .label("this `[` is never closed")
+            }
+            SyntaxError::UnpairedRightBracket { span, last_loop } => {
+                let diagnostic =
+                    Diagnostic::error("unexpected `]`, expected another `[` before it", *span)
+                        .label("this `]` doesn't close any loop");
+
+                match last_loop {
+                    Some(last_loop) => diagnostic.note_at(
+                        "loop opened here is already closed by the previous `]`",
+                        last_loop.head(),
+                    ),
+                    None => diagnostic,
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compiler::lexer::build_token_list;
+    use crate::compiler::span::Position;
 
     #[test]
     fn to_syntax_tree() {
@@ -109,16 +352,22 @@ mod tests {
             Token::new(SingleToken::RightBracket, 1),
         ]);
 
+        let span = Span::default();
         let expected = Ok(SyntaxTree::Root {
             block: vec![
-                SyntaxTree::Add { val: 1 },
-                SyntaxTree::Output,
+                SyntaxTree::Add {
+                    offset: 0,
+                    val: 1,
+                    span,
+                },
+                SyntaxTree::Output { offset: 0, span },
                 SyntaxTree::Loop {
                     block: vec![
-                        SyntaxTree::Seek { offset: -2 },
-                        SyntaxTree::Input,
-                        SyntaxTree::Seek { offset: 1 },
+                        SyntaxTree::Seek { offset: -2, span },
+                        SyntaxTree::Input { offset: 0, span },
+                        SyntaxTree::Seek { offset: 1, span },
                     ],
+                    span,
                 },
             ],
         });
@@ -126,6 +375,17 @@ mod tests {
         assert_eq!(SyntaxTree::build(tokens), expected);
     }
 
+    #[test]
+    fn loop_span() {
+        let tree = SyntaxTree::build(build_token_list("+\n [ - ]")).unwrap();
+        let block = match tree {
+            SyntaxTree::Root { block } => block,
+            _ => unreachable!(),
+        };
+        let expected = Span::new(Position::new(3, 2, 2), Position::new(8, 2, 7));
+        assert_eq!(block[1].span(), expected);
+    }
+
     #[test]
     fn unpaired_left_bracket() {
         let tokens = TokenList(vec![
@@ -134,7 +394,9 @@ mod tests {
             Token::new(SingleToken::LessThan, 2),
         ]);
 
-        let expected = Err(SyntaxError::UnpairedLeftBracket);
+        let expected = Err(vec![SyntaxError::UnpairedLeftBracket {
+            span: Span::default(),
+        }]);
         assert_eq!(SyntaxTree::build(tokens), expected);
     }
 
@@ -148,7 +410,60 @@ mod tests {
             Token::new(SingleToken::LessThan, 2),
         ]);
 
-        let expected = Err(SyntaxError::UnpairedRightBracket);
+        let expected = Err(vec![SyntaxError::UnpairedRightBracket {
+            span: Span::default(),
+            last_loop: Some(Span::default()),
+        }]);
         assert_eq!(SyntaxTree::build(tokens), expected);
     }
+
+    #[test]
+    fn unpaired_bracket_span() {
+        let span = |offset, column| {
+            Span::new(
+                Position::new(offset, 1, column),
+                Position::new(offset + 1, 1, column + 1),
+            )
+        };
+        let expected = Err(vec![SyntaxError::UnpairedLeftBracket { span: span(2, 3) }]);
+        assert_eq!(SyntaxTree::build(build_token_list("[][")), expected);
+    }
+
+    #[test]
+    fn all_unpaired_brackets() {
+        let span = |offset, column| {
+            Span::new(
+                Position::new(offset, 1, column),
+                Position::new(offset + 1, 1, column + 1),
+            )
+        };
+        let expected = Err(vec![
+            SyntaxError::UnpairedRightBracket {
+                span: span(0, 1),
+                last_loop: None,
+            },
+            SyntaxError::UnpairedRightBracket {
+                span: span(6, 7),
+                last_loop: Some(Span::new(Position::new(4, 1, 5), Position::new(6, 1, 7))),
+            },
+            SyntaxError::UnpairedLeftBracket { span: span(7, 8) },
+            SyntaxError::UnpairedLeftBracket { span: span(8, 9) },
+        ]);
+        assert_eq!(SyntaxTree::build(build_token_list("]+[][]][[")), expected);
+    }
+
+    #[test]
+    fn display_syntax_tree() {
+        let tree = SyntaxTree::build(build_token_list(",[>+<-]")).unwrap();
+        let expected = concat!(
+            "input 0                          ; 1:1..1:2\n",
+            "loop                             ; 1:2..1:8\n",
+            "    seek 1                       ; 1:3..1:4\n",
+            "    add 0, 1                     ; 1:4..1:5\n",
+            "    seek -1                      ; 1:5..1:6\n",
+            "    add 0, -1                    ; 1:6..1:7\n",
+            "end\n",
+        );
+        assert_eq!(format!("{tree:#}"), expected);
+    }
 }
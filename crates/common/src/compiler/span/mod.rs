@@ -0,0 +1,152 @@
+use std::fmt::{self, Display, Formatter};
+
+/// A location in the source code. `line` and `column` start from 1, while
+/// `offset` is the byte offset from the beginning of the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(offset: usize, line: usize, column: usize) -> Self {
+        Self {
+            offset,
+            line,
+            column,
+        }
+    }
+
+    /// The position right after `ch`, which is located at `self`.
+    pub fn advance(self, ch: char) -> Self {
+        if ch == '\n' {
+            Self::new(self.offset + ch.len_utf8(), self.line + 1, 1)
+        } else {
+            Self::new(self.offset + ch.len_utf8(), self.line, self.column + 1)
+        }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A range `[start, end)` of the source code. A default `Span` doesn't point to
+/// any source code and is used for generated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// Build the span of a single character `ch` located at `start`.
+    pub fn of_char(start: Position, ch: char) -> Self {
+        Self::new(start, start.advance(ch))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.offset == self.end.offset
+    }
+
+    /// Return the smallest span containing both `self` and `other`. A generated
+    /// (empty) span is absorbed by the other one.
+    #[must_use]
+    pub fn merge(self, other: Span) -> Span {
+        if self.is_empty() {
+            return other;
+        } else if other.is_empty() {
+            return self;
+        }
+
+        let start = if self.start.offset <= other.start.offset {
+            self.start
+        } else {
+            other.start
+        };
+        let end = if self.end.offset >= other.end.offset {
+            self.end
+        } else {
+            other.end
+        };
+        Span::new(start, end)
+    }
+
+    /// The span of the first character. It's only meaningful when the first
+    /// character is an ASCII one, such as `[`.
+    #[must_use]
+    pub fn head(self) -> Span {
+        if self.is_empty() {
+            self
+        } else {
+            Span::of_char(self.start, ' ')
+        }
+    }
+
+    /// The span of the last character. It's only meaningful when the last
+    /// character is an ASCII one, such as `]`.
+    #[must_use]
+    pub fn tail(self) -> Span {
+        if self.is_empty() {
+            return self;
+        }
+
+        let start = Position::new(self.end.offset - 1, self.end.line, self.end.column - 1);
+        Span::new(start, self.end)
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
+/// Write a line in the text form of a compiler stage. In the alternate form
+/// (`{:#}`), it's followed by `span` as a comment unless it's generated.
+pub(crate) fn write_line(
+    f: &mut Formatter<'_>,
+    indent: usize,
+    line: impl Display,
+    span: Span,
+) -> fmt::Result {
+    let line = format!("{:indent$}{line}", "", indent = indent * 4);
+
+    if span.is_empty() || !f.alternate() {
+        writeln!(f, "{line}")
+    } else {
+        writeln!(f, "{line:<32} ; {}..{}", span.start, span.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_position() {
+        let p = Position::new(0, 1, 1);
+        assert_eq!(p.advance('+'), Position::new(1, 1, 2));
+        assert_eq!(p.advance('\n'), Position::new(1, 2, 1));
+        assert_eq!(p.advance('é'), Position::new(2, 1, 2));
+    }
+
+    #[test]
+    fn merge_span() {
+        let a = Span::of_char(Position::new(0, 1, 1), '[');
+        let b = Span::of_char(Position::new(4, 1, 5), ']');
+        let expected = Span::new(Position::new(0, 1, 1), Position::new(5, 1, 6));
+        assert_eq!(a.merge(b), expected);
+        assert_eq!(b.merge(a), expected);
+        assert_eq!(a.merge(Span::default()), a);
+        assert_eq!(expected.head(), a);
+        assert_eq!(expected.tail(), b);
+    }
+}
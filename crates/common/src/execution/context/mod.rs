@@ -1,10 +1,12 @@
-use crate::execution::memory::{config::Config as MemoryConfig, Builder as MemoryBuilder, Memory};
+use crate::execution::memory::{
+    config::Config as MemoryConfig, Builder as MemoryBuilder, DynMemory,
+};
 use crate::execution::stream::{
     config::Config as StreamConfig, Builder as StreamBuilder, InStream, OutStream,
 };
 
 pub struct Context {
-    pub memory: Memory,
+    pub memory: DynMemory,
     pub in_stream: Box<dyn InStream>,
     pub out_stream: Box<dyn OutStream>,
 }
@@ -0,0 +1,507 @@
+/// The general purpose registers of x86-64, numbered as in the encoding.
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    fn low(self) -> u8 {
+        self as u8 & 7
+    }
+
+    fn high(self) -> u8 {
+        self as u8 >> 3
+    }
+}
+
+/// The memory operand `[base + index * 4 + disp]`, which addresses the cells.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mem {
+    pub base: Reg,
+    pub index: Option<Reg>,
+    pub disp: i32,
+}
+
+/// The conditions of the conditional jumps.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cond {
+    Overflow = 0x0,
+    Below = 0x2,
+    AboveOrEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    Above = 0x7,
+    Sign = 0x8,
+    NotSign = 0x9,
+    Less = 0xc,
+    LessOrEqual = 0xe,
+    Greater = 0xf,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Label(usize);
+
+/// Encode the few instructions the JIT and `ElfBackend` need. The methods are named after the
+/// instructions and take the destination first, like the Intel syntax. Those
+/// ending with `32` work on the low 32 bits, which clears the high 32 bits of the
+/// destination register, and the others work on 64 bits.
+pub struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// The positions of the 32-bit displacements jumping to the labels.
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            code: vec![],
+            labels: vec![],
+            fixups: vec![],
+        }
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Place the label at the next instruction.
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none());
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    /// Place the label at `pos` from the start of the code, which may be after
+    /// the end of it, like the memory out of the code.
+    pub fn bind_at(&mut self, label: Label, pos: usize) {
+        debug_assert!(self.labels[label.0].is_none());
+        self.labels[label.0] = Some(pos);
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Return the code after filling in the jumps. Every label jumped to must be
+    /// bound.
+    pub fn finish(mut self) -> Vec<u8> {
+        for (pos, Label(label)) in self.fixups {
+            let target = self.labels[label].expect("the label isn't bound");
+            let rel = target as i64 - (pos + 4) as i64;
+            self.code[pos..pos + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+
+        self.code
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, index: u8, base: u8) {
+        let rex = 0x40 | (w as u8) << 3 | reg << 2 | index << 1 | base;
+
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+    }
+
+    fn imm32(&mut self, imm: i32) {
+        self.code.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    /// Emit an instruction whose `r/m` operand is a register.
+    fn op_rr(&mut self, w: bool, opcode: &[u8], reg: u8, rm: Reg) {
+        self.rex(w, reg >> 3, 0, rm.high());
+        self.code.extend_from_slice(opcode);
+        self.code.push(0xc0 | (reg & 7) << 3 | rm.low());
+    }
+
+    /// Emit an instruction whose `r/m` operand is in the memory.
+    fn op_rm(&mut self, w: bool, opcode: &[u8], reg: u8, mem: Mem) {
+        let index = mem.index.map_or(0, Reg::high);
+        self.rex(w, reg >> 3, index, mem.base.high());
+        self.code.extend_from_slice(opcode);
+
+        // `[rbp]` and `[r13]` can't go without a displacement, so there is always
+        // one.
+        let short = i8::try_from(mem.disp).is_ok();
+        let mode = if short { 0x40 } else { 0x80 };
+
+        match mem.index {
+            Some(index) => {
+                debug_assert!(index != Reg::Rsp);
+                self.code.push(mode | (reg & 7) << 3 | 0b100);
+                self.code
+                    .push(0b10 << 6 | index.low() << 3 | mem.base.low());
+            }
+            None if mem.base.low() == 0b100 => {
+                self.code.push(mode | (reg & 7) << 3 | 0b100);
+                self.code.push(0x24);
+            }
+            None => self.code.push(mode | (reg & 7) << 3 | mem.base.low()),
+        }
+
+        if short {
+            self.code.push(mem.disp as u8);
+        } else {
+            self.imm32(mem.disp);
+        }
+    }
+
+    /// Emit an arithmetic instruction with an immediate, where `ext` selects the
+    /// operation.
+    fn arith_ri(&mut self, w: bool, ext: u8, dst: Reg, imm: i32) {
+        if let Ok(imm) = i8::try_from(imm) {
+            self.op_rr(w, &[0x83], ext, dst);
+            self.code.push(imm as u8);
+        } else {
+            self.op_rr(w, &[0x81], ext, dst);
+            self.imm32(imm);
+        }
+    }
+
+    fn arith_mi32(&mut self, ext: u8, dst: Mem, imm: i32) {
+        if let Ok(imm) = i8::try_from(imm) {
+            self.op_rm(false, &[0x83], ext, dst);
+            self.code.push(imm as u8);
+        } else {
+            self.op_rm(false, &[0x81], ext, dst);
+            self.imm32(imm);
+        }
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        self.rex(false, 0, 0, reg.high());
+        self.code.push(0x50 + reg.low());
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        self.rex(false, 0, 0, reg.high());
+        self.code.push(0x58 + reg.low());
+    }
+
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(true, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov32(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(false, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        self.rex(true, 0, 0, dst.high());
+        self.code.push(0xb8 + dst.low());
+        self.code.extend_from_slice(&imm.to_le_bytes());
+    }
+
+    pub fn mov32_imm(&mut self, dst: Reg, imm: u32) {
+        self.rex(false, 0, 0, dst.high());
+        self.code.push(0xb8 + dst.low());
+        self.imm32(imm as i32);
+    }
+
+    pub fn mov32_load(&mut self, dst: Reg, src: Mem) {
+        self.op_rm(false, &[0x8b], dst as u8, src);
+    }
+
+    pub fn mov32_store(&mut self, dst: Mem, src: Reg) {
+        self.op_rm(false, &[0x89], src as u8, dst);
+    }
+
+    pub fn mov32_store_imm(&mut self, dst: Mem, imm: i32) {
+        self.op_rm(false, &[0xc7], 0, dst);
+        self.imm32(imm);
+    }
+
+    pub fn mov_load(&mut self, dst: Reg, src: Mem) {
+        self.op_rm(true, &[0x8b], dst as u8, src);
+    }
+
+    pub fn mov_store(&mut self, dst: Mem, src: Reg) {
+        self.op_rm(true, &[0x89], src as u8, dst);
+    }
+
+    /// Load a 32-bit value with the sign extended.
+    pub fn movsxd_load(&mut self, dst: Reg, src: Mem) {
+        self.op_rm(true, &[0x63], dst as u8, src);
+    }
+
+    /// Store the low byte of `src`, which must be one of `rax` to `rbx`.
+    pub fn mov8_store(&mut self, dst: Mem, src: Reg) {
+        debug_assert!((src as u8) < 4);
+        self.op_rm(false, &[0x88], src as u8, dst);
+    }
+
+    pub fn movsxd(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(true, &[0x63], dst as u8, src);
+    }
+
+    pub fn movzx32_load_byte(&mut self, dst: Reg, src: Mem) {
+        self.op_rm(false, &[0x0f, 0xb6], dst as u8, src);
+    }
+
+    /// Sign-extend the low byte of `src`, which must be one of `rax` to `rbx`.
+    pub fn movsx32_byte(&mut self, dst: Reg, src: Reg) {
+        debug_assert!((src as u8) < 4);
+        self.op_rr(false, &[0x0f, 0xbe], dst as u8, src);
+    }
+
+    /// Zero-extend the low byte of `src`, which must be one of `rax` to `rbx`.
+    pub fn movzx32_byte(&mut self, dst: Reg, src: Reg) {
+        debug_assert!((src as u8) < 4);
+        self.op_rr(false, &[0x0f, 0xb6], dst as u8, src);
+    }
+
+    pub fn lea(&mut self, dst: Reg, src: Mem) {
+        self.op_rm(true, &[0x8d], dst as u8, src);
+    }
+
+    /// Load the address of the label.
+    pub fn lea_label(&mut self, dst: Reg, label: Label) {
+        self.rex(true, dst.high(), 0, 0);
+        self.code
+            .extend_from_slice(&[0x8d, (dst.low()) << 3 | 0b101]);
+        self.fixups.push((self.code.len(), label));
+        self.imm32(0);
+    }
+
+    pub fn add(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(true, &[0x01], src as u8, dst);
+    }
+
+    pub fn add_imm(&mut self, dst: Reg, imm: i32) {
+        self.arith_ri(true, 0, dst, imm);
+    }
+
+    pub fn add32_imm(&mut self, dst: Reg, imm: i32) {
+        self.arith_ri(false, 0, dst, imm);
+    }
+
+    pub fn add32_load(&mut self, dst: Reg, src: Mem) {
+        self.op_rm(false, &[0x03], dst as u8, src);
+    }
+
+    pub fn add32_store(&mut self, dst: Mem, src: Reg) {
+        self.op_rm(false, &[0x01], src as u8, dst);
+    }
+
+    pub fn add32_store_imm(&mut self, dst: Mem, imm: i32) {
+        self.arith_mi32(0, dst, imm);
+    }
+
+    pub fn sub(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(true, &[0x29], src as u8, dst);
+    }
+
+    pub fn sub_imm(&mut self, dst: Reg, imm: i32) {
+        self.arith_ri(true, 5, dst, imm);
+    }
+
+    pub fn cmp_imm(&mut self, lhs: Reg, imm: i32) {
+        self.arith_ri(true, 7, lhs, imm);
+    }
+
+    pub fn cmp(&mut self, lhs: Reg, rhs: Reg) {
+        self.op_rr(true, &[0x39], rhs as u8, lhs);
+    }
+
+    pub fn cmp32_imm(&mut self, lhs: Reg, imm: i32) {
+        self.arith_ri(false, 7, lhs, imm);
+    }
+
+    pub fn cmp32(&mut self, lhs: Reg, rhs: Reg) {
+        self.op_rr(false, &[0x39], rhs as u8, lhs);
+    }
+
+    pub fn cmp32_mem_imm(&mut self, lhs: Mem, imm: i32) {
+        self.arith_mi32(7, lhs, imm);
+    }
+
+    pub fn test(&mut self, lhs: Reg, rhs: Reg) {
+        self.op_rr(true, &[0x85], rhs as u8, lhs);
+    }
+
+    pub fn test32(&mut self, lhs: Reg, rhs: Reg) {
+        self.op_rr(false, &[0x85], rhs as u8, lhs);
+    }
+
+    pub fn and32_imm(&mut self, dst: Reg, imm: i32) {
+        self.arith_ri(false, 4, dst, imm);
+    }
+
+    pub fn or32_imm(&mut self, dst: Reg, imm: i32) {
+        self.arith_ri(false, 1, dst, imm);
+    }
+
+    pub fn shr32_imm(&mut self, dst: Reg, imm: u8) {
+        self.op_rr(false, &[0xc1], 5, dst);
+        self.code.push(imm);
+    }
+
+    pub fn xor32(&mut self, dst: Reg, src: Reg) {
+        self.op_rr(false, &[0x31], src as u8, dst);
+    }
+
+    pub fn neg(&mut self, dst: Reg) {
+        self.op_rr(true, &[0xf7], 3, dst);
+    }
+
+    pub fn neg32(&mut self, dst: Reg) {
+        self.op_rr(false, &[0xf7], 3, dst);
+    }
+
+    /// Sign-extend `rax` into `rdx`.
+    pub fn cqo(&mut self) {
+        self.code.extend_from_slice(&[0x48, 0x99]);
+    }
+
+    /// Divide `rdx:rax` by `src` as unsigned integers, into the quotient in `rax`
+    /// and the remainder in `rdx`.
+    pub fn div(&mut self, src: Reg) {
+        self.op_rr(true, &[0xf7], 6, src);
+    }
+
+    /// Divide `rdx:rax` by `src` as signed integers.
+    pub fn idiv(&mut self, src: Reg) {
+        self.op_rr(true, &[0xf7], 7, src);
+    }
+
+    /// `dst = src * imm`.
+    pub fn imul_imm(&mut self, dst: Reg, src: Reg, imm: i32) {
+        self.op_rr(true, &[0x69], dst as u8, src);
+        self.imm32(imm);
+    }
+
+    pub fn imul32_imm(&mut self, dst: Reg, src: Reg, imm: i32) {
+        self.op_rr(false, &[0x69], dst as u8, src);
+        self.imm32(imm);
+    }
+
+    pub fn call(&mut self, target: Reg) {
+        self.op_rr(false, &[0xff], 2, target);
+    }
+
+    pub fn call_label(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.fixups.push((self.code.len(), label));
+        self.imm32(0);
+    }
+
+    pub fn syscall(&mut self) {
+        self.code.extend_from_slice(&[0x0f, 0x05]);
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.fixups.push((self.code.len(), label));
+        self.imm32(0);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.code.extend_from_slice(&[0x0f, 0x80 + cond as u8]);
+        self.fixups.push((self.code.len(), label));
+        self.imm32(0);
+    }
+
+    /// Emit bytes which aren't instructions.
+    pub fn data(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(f: impl FnOnce(&mut Assembler)) -> Vec<u8> {
+        let mut asm = Assembler::new();
+        f(&mut asm);
+        asm.finish()
+    }
+
+    #[test]
+    fn registers() {
+        assert_eq!(assemble(|asm| asm.push(Reg::R15)), [0x41, 0x57]);
+        assert_eq!(assemble(|asm| asm.pop(Reg::Rbx)), [0x5b]);
+        assert_eq!(
+            assemble(|asm| asm.mov(Reg::R14, Reg::Rdi)),
+            [0x49, 0x89, 0xfe]
+        );
+        assert_eq!(assemble(|asm| asm.mov32(Reg::Rcx, Reg::Rax)), [0x89, 0xc1]);
+        assert_eq!(
+            assemble(|asm| asm.movsx32_byte(Reg::Rax, Reg::Rax)),
+            [0x0f, 0xbe, 0xc0]
+        );
+        assert_eq!(
+            assemble(|asm| asm.add_imm(Reg::R15, 1000)),
+            [0x49, 0x81, 0xc7, 0xe8, 0x03, 0x00, 0x00]
+        );
+        assert_eq!(
+            assemble(|asm| asm.imul_imm(Reg::Rdx, Reg::Rcx, -3)),
+            [0x48, 0x69, 0xd1, 0xfd, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn memory_operands() {
+        let cell = Mem {
+            base: Reg::R13,
+            index: Some(Reg::R12),
+            disp: 0,
+        };
+        assert_eq!(
+            assemble(|asm| asm.mov32_load(Reg::Rax, cell)),
+            [0x43, 0x8b, 0x44, 0xa5, 0x00]
+        );
+        assert_eq!(
+            assemble(|asm| asm.add32_store_imm(Mem { disp: -400, ..cell }, 1)),
+            [0x43, 0x83, 0x84, 0xa5, 0x70, 0xfe, 0xff, 0xff, 0x01]
+        );
+        assert_eq!(
+            assemble(|asm| asm.lea(
+                Reg::Rax,
+                Mem {
+                    base: Reg::R12,
+                    index: None,
+                    disp: -1
+                }
+            )),
+            [0x49, 0x8d, 0x44, 0x24, 0xff]
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        let code = assemble(|asm| {
+            let start = asm.new_label();
+            let end = asm.new_label();
+            asm.bind(start);
+            asm.jcc(Cond::Equal, end);
+            asm.jmp(start);
+            asm.bind(end);
+            asm.ret();
+        });
+        assert_eq!(
+            code,
+            [0x0f, 0x84, 0x05, 0x00, 0x00, 0x00, 0xe9, 0xf5, 0xff, 0xff, 0xff, 0xc3]
+        );
+    }
+}
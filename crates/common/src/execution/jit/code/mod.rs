@@ -0,0 +1,54 @@
+use std::io;
+use std::ptr;
+
+/// Machine code in memory mapped readable and executable, which is unmapped on
+/// drop.
+pub struct Code {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Code {
+    pub fn new(code: &[u8]) -> io::Result<Self> {
+        let len = code.len().max(1);
+
+        // SAFETY: The new mapping is written within its length, and it's made
+        // executable only after that.
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            let res = Self { ptr, len };
+            ptr::copy_nonoverlapping(code.as_ptr(), ptr.cast(), code.len());
+
+            if libc::mprotect(ptr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(res)
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.cast()
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        // SAFETY: The mapping is created by `new` and isn't used any more.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
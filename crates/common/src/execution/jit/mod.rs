@@ -0,0 +1,476 @@
+pub(crate) mod assembler;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod code;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod translator;
+
+use std::any::Any;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+
+use snafu::prelude::*;
+
+use crate::compiler::InstructionList;
+use crate::execution::context::Context;
+use crate::execution::memory::config::Config as MemoryConfig;
+use crate::execution::memory::MemoryError;
+use crate::execution::processor::{Processor, ProcessorError, Result as ProcessorResult};
+
+pub type Result<T> = std::result::Result<T, JitError>;
+
+#[derive(Snafu, Debug, PartialEq, Eq)]
+pub enum JitError {
+    #[snafu(display("the JIT only runs on Linux x86-64"))]
+    UnsupportedTarget,
+    #[snafu(display("the memory of {len} cells is too large for the JIT"))]
+    UnsupportedMemory { len: usize },
+    #[snafu(display("couldn't map the memory for the machine code (os error {errno})"))]
+    Map { errno: i32 },
+}
+
+/// What the machine code and the callback share while running.
+#[repr(C)]
+struct State<'a> {
+    /// The index of the cell the pointer points to, and the count of the executed
+    /// instructions, which are written when the code returns.
+    index: i64,
+    steps: u64,
+    context: &'a mut Context,
+    instructions: &'a InstructionList,
+    /// The address of the failed instruction and why it failed.
+    error: Option<(usize, MemoryError)>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// Run an instruction the machine code doesn't handle itself. See `Translator`.
+extern "C" fn callback(state: *mut c_void, index: i64, pc: u64) -> i64 {
+    // SAFETY: The code passes the state it's called with, which is borrowed by
+    // `Jit::run` until the code returns.
+    let state = unsafe { &mut *state.cast::<State>() };
+    let pc = pc as usize;
+
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        let memory = &mut state.context.memory;
+        let left = memory.range().left;
+        memory.seek(index as isize + left - memory.position())?;
+        Processor::execute(&state.instructions.instructions[pc], state.context)?;
+        Ok((state.context.memory.position() - left) as i64)
+    }));
+
+    match res {
+        Ok(Ok(index)) => index,
+        Ok(Err(e)) => {
+            state.error = Some((pc, e));
+            -1
+        }
+        Err(payload) => {
+            state.panic = Some(payload);
+            -1
+        }
+    }
+}
+
+/// Run programs as machine code compiled just in time, which does the same as
+/// `Processor`, including the errors. Only Linux on x86-64 is supported, where
+/// `supports` tells whether a memory can be used.
+pub struct Jit {
+    instructions: InstructionList,
+    memory: MemoryConfig,
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    code: code::Code,
+}
+
+impl Jit {
+    pub fn supports(memory: &MemoryConfig) -> bool {
+        Self::check(memory).is_ok()
+    }
+
+    fn check(memory: &MemoryConfig) -> Result<()> {
+        ensure!(
+            cfg!(all(target_os = "linux", target_arch = "x86_64")),
+            UnsupportedTargetSnafu
+        );
+        let len = memory.range().len();
+        ensure!(len <= i32::MAX as usize, UnsupportedMemorySnafu { len });
+        Ok(())
+    }
+
+    /// Compile the instructions for `memory`, which is the memory of the contexts
+    /// it runs on.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn compile(instructions: InstructionList, memory: &MemoryConfig) -> Result<Self> {
+        Self::check(memory)?;
+        let callback = callback as extern "C" fn(*mut c_void, i64, u64) -> i64;
+        let code =
+            translator::Translator::new(memory, callback as usize as u64).translate(&instructions);
+        let code = code::Code::new(&code).map_err(|e| JitError::Map {
+            errno: e.raw_os_error().unwrap_or(0),
+        })?;
+        Ok(Self {
+            instructions,
+            memory: memory.clone(),
+            code,
+        })
+    }
+
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    pub fn compile(_instructions: InstructionList, memory: &MemoryConfig) -> Result<Self> {
+        Self::check(memory)?;
+        unreachable!()
+    }
+
+    /// Run the program to the end, like `Processor::run`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the memory of `context` isn't the one the program is compiled
+    /// for, since the machine code only checks the bounds of that one.
+    pub fn run(self, context: &mut Context) -> ProcessorResult<()> {
+        assert!(
+            context.memory.is_built_for(&self.memory),
+            "the program is compiled for another memory"
+        );
+
+        // There is only one halt instruction
+        if self.instructions.len() == 1 {
+            return Err(ProcessorError::Empty);
+        }
+
+        let memory = &mut context.memory;
+        let left = memory.range().left;
+        let cells = memory.as_mut_ptr();
+        let index = (memory.position() - left) as i64;
+        let mut state = State {
+            index,
+            steps: 0,
+            context,
+            instructions: &self.instructions,
+            error: None,
+            panic: None,
+        };
+        self.enter(&mut state, cells, index);
+
+        if let Some(payload) = state.panic {
+            panic::resume_unwind(payload);
+        }
+
+        let memory = &mut state.context.memory;
+
+        match state.error {
+            Some((pc, source)) => Err(ProcessorError::Memory {
+                source,
+                pc,
+                span: self.instructions.span(pc),
+                pointer: memory.position(),
+                steps: state.steps,
+            }),
+            None => {
+                memory
+                    .seek(state.index as isize + left - memory.position())
+                    .unwrap();
+                Ok(())
+            }
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn enter(&self, state: &mut State, cells: *mut i32, index: i64) {
+        type Entry = unsafe extern "C" fn(*mut c_void, *mut i32, i64) -> u64;
+
+        // SAFETY: The code is translated to be called as `Entry`, and it only
+        // touches the cells in the range of the memory it's compiled for, which
+        // `run` checks is the memory of the context.
+        unsafe {
+            let entry: Entry = std::mem::transmute(self.code.as_ptr());
+            entry((state as *mut State).cast(), cells, index);
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    fn enter(&self, _state: &mut State, _cells: *mut i32, _index: i64) {
+        unreachable!()
+    }
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::compiler::{
+        AddUntilZeroArg, Compiler, Config as CompilerConfig, Instruction, OutputItem,
+    };
+    use crate::execution::memory::config::{Addr, Cell, Eof, Overflow};
+    use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    type Outcome = (ProcessorResult<()>, Vec<i32>, isize, Vec<(isize, i32)>);
+
+    /// Run the instructions with `Processor` or `Jit`, and return the result,
+    /// the output and the memory.
+    fn run(list: InstructionList, memory: &MemoryConfig, input: &[u8], jit: bool) -> Outcome {
+        let input = input.iter().map(|&byte| byte as i32).collect();
+        let output = Rc::new(RefCell::new(VecDeque::new()));
+        let stream = StreamConfig {
+            input: Input::Vec(Rc::new(RefCell::new(input))),
+            output: Output::Vec(output.clone()),
+        };
+        let mut context = Context::new(memory.clone(), stream);
+
+        let res = if jit {
+            Jit::compile(list, memory).unwrap().run(&mut context)
+        } else {
+            Processor::new(list).run(&mut context)
+        };
+
+        let cells = context.memory.cells().collect();
+        (res, output.take().into(), context.memory.position(), cells)
+    }
+
+    fn assert_same_list(list: InstructionList, memory: &MemoryConfig, input: &[u8]) {
+        let expected = run(list.clone(), memory, input, false);
+        assert_eq!(run(list, memory, input, true), expected, "{input:?}");
+    }
+
+    fn assert_same(code: &str, memory: &MemoryConfig, input: &[u8]) {
+        let config = CompilerConfig {
+            target: Some(memory.clone()),
+            ..Default::default()
+        };
+        let list = Compiler::with_config(config).compile(code).unwrap();
+        assert_same_list(list, memory, input);
+    }
+
+    fn wrap() -> MemoryConfig {
+        MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        }
+    }
+
+    /// `mandelbrot.bf` is left out, which takes minutes to interpret in debug
+    /// builds.
+    #[test]
+    fn examples_run_the_same() {
+        let hello = include_str!("../../../../../examples/helloworld.bf");
+        let examples = [
+            hello,
+            include_str!("../../../../../examples/squares.bf"),
+            include_str!("../../../../../examples/hanoi.bf"),
+        ];
+
+        for code in examples {
+            assert_same(code, &wrap(), b"");
+        }
+
+        let memory = MemoryConfig {
+            eof: Eof::Zero,
+            ..wrap()
+        };
+        let code = include_str!("../../../../../examples/self-interpreter.bf");
+        assert_same(code, &memory, format!("{hello}!").as_bytes());
+        // Without wrapping, hanoi fails with an overflow.
+        assert_same(examples[2], &Default::default(), b"");
+    }
+
+    #[test]
+    fn memory_strategies() {
+        let signed = MemoryConfig {
+            len: 10,
+            addr: Addr::Signed,
+            cell: Cell::I32,
+            ..Default::default()
+        };
+        let cases = [
+            (",[->+++<]>.-.", wrap(), "a"),
+            (",[->++<]>.", Default::default(), "a"),
+            (",[->--<]>.", Default::default(), "a"),
+            (",[-->+<]>.", wrap(), "d"),
+            (",[--<+>]", wrap(), "e"),
+            (",[-->+<]>.", Default::default(), "e"),
+            (",[->---<]>.", wrap(), "b"),
+            (",[-<+>>-<]<.>>.", Default::default(), "\u{7f}"),
+            (",[+>+<]>.", Default::default(), "\u{81}"),
+            ("-[+>+<]>.", Default::default(), ""),
+            ("+[>-<+++]>.", wrap(), ""),
+            ("+[>-<+++]>.", Default::default(), ""),
+            (">,<,>.<.,.", Default::default(), "\u{ff}"),
+            (",.,.,.", wrap(), "\u{e9}"),
+            (
+                ",.,.",
+                MemoryConfig {
+                    eof: Eof::Keep,
+                    ..wrap()
+                },
+                "a",
+            ),
+            (
+                ",.,.",
+                MemoryConfig {
+                    eof: Eof::Zero,
+                    ..wrap()
+                },
+                "a",
+            ),
+            ("<<<<<+[<].", signed.clone(), ""),
+            ("+[>+].", signed.clone(), ""),
+            ("+>>>>>[-]<<<<<[->>>>>>+<<<<<<].", signed.clone(), ""),
+            ("+[->>>>>>+<<<<<<].", signed.clone(), ""),
+            ("-<<<<<.", signed.clone(), ""),
+            ("+++.>.<<.", Default::default(), ""),
+            ("+>++>+++<<[>>>>>]+.", signed.clone(), ""),
+            ("++++++++[>++++++++<-]>[>++<-]>.", Default::default(), ""),
+            ("++++++++[>++++++++<-]>[>++<-]>.", wrap(), ""),
+            ("<.", Default::default(), ""),
+            (
+                ">>+<<,[>>.<<,]",
+                MemoryConfig {
+                    eof: Eof::Zero,
+                    ..signed
+                },
+                "abc",
+            ),
+        ];
+
+        for (code, memory, input) in cases {
+            assert_same(code, &memory, input.as_bytes());
+        }
+    }
+
+    /// Instructions the compiler doesn't generate for the loops above, which are
+    /// partly run by `Processor`.
+    #[test]
+    fn unusual_instructions() {
+        let i8_error = MemoryConfig {
+            len: 8,
+            ..Default::default()
+        };
+        let i8_wrap = MemoryConfig {
+            len: 8,
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+        let cases = vec![
+            vec![Instruction::Add {
+                offset: 1,
+                val: 300,
+            }],
+            vec![Instruction::Add {
+                offset: 1,
+                val: -128,
+            }],
+            vec![Instruction::Set {
+                offset: 2,
+                val: 128,
+            }],
+            vec![Instruction::Set { offset: 9, val: 1 }],
+            vec![Instruction::Seek { offset: -1 }],
+            vec![
+                Instruction::Set { offset: 0, val: 6 },
+                Instruction::AddUntilZero {
+                    target: vec![AddUntilZeroArg {
+                        offset: 1,
+                        times: 5,
+                        clear: false,
+                    }],
+                    step: -3,
+                },
+                Instruction::Output { offset: 1 },
+            ],
+            vec![
+                Instruction::Set { offset: 0, val: 7 },
+                Instruction::AddUntilZero {
+                    target: vec![AddUntilZeroArg {
+                        offset: 1,
+                        times: 5,
+                        clear: false,
+                    }],
+                    step: -3,
+                },
+            ],
+            vec![
+                Instruction::Set { offset: 0, val: 2 },
+                Instruction::AddUntilZero {
+                    target: vec![
+                        AddUntilZeroArg {
+                            offset: 0,
+                            times: 3,
+                            clear: false,
+                        },
+                        AddUntilZeroArg {
+                            offset: 2,
+                            times: 200,
+                            clear: true,
+                        },
+                    ],
+                    step: -1,
+                },
+                Instruction::OutputSeq {
+                    items: vec![
+                        OutputItem::Cell { offset: 0 },
+                        OutputItem::Byte { val: 33 },
+                        OutputItem::Cell { offset: 2 },
+                    ],
+                },
+            ],
+            vec![
+                Instruction::Set { offset: 0, val: 1 },
+                Instruction::OutputRepeat {
+                    offset: 0,
+                    count: 3,
+                },
+                Instruction::OutputBytes {
+                    bytes: vec![104, 105],
+                },
+                Instruction::Scan { stride: 3 },
+            ],
+        ];
+
+        for instructions in cases {
+            for memory in [&i8_error, &i8_wrap] {
+                let mut instructions = instructions.clone();
+                instructions.push(Instruction::Halt);
+                assert_same_list(InstructionList::new(instructions), memory, b"");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_program() {
+        let list = InstructionList::new(vec![Instruction::Halt]);
+        let mut context = Context::new(Default::default(), Default::default());
+        assert_eq!(
+            Jit::compile(list, &Default::default())
+                .unwrap()
+                .run(&mut context),
+            Err(ProcessorError::Empty)
+        );
+    }
+
+    #[test]
+    fn unsupported_memory() {
+        let memory = MemoryConfig {
+            len: 1 << 32,
+            ..Default::default()
+        };
+        assert!(!Jit::supports(&memory));
+        assert!(Jit::supports(&Default::default()));
+    }
+
+    #[test]
+    #[should_panic(expected = "the program is compiled for another memory")]
+    fn mismatched_memory() {
+        let code = format!("{}+", ">".repeat(1 << 20));
+        let compiled = MemoryConfig {
+            len: 1 << 21,
+            ..Default::default()
+        };
+        let list = Compiler::new().compile(&code).unwrap();
+        let memory = MemoryConfig {
+            len: 4,
+            ..Default::default()
+        };
+        let mut context = Context::new(memory, Default::default());
+        let _ = Jit::compile(list, &compiled).unwrap().run(&mut context);
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashSet;
+
+use super::assembler::{Assembler, Cond, Label, Mem, Reg};
+use crate::compiler::{AddUntilZeroArg, Instruction, InstructionList};
+use crate::execution::memory::config::{Cell, Config as MemoryConfig, Overflow};
+
+/// The registers kept through the code, which the callee saves.
+const STATE: Reg = Reg::R14;
+const CELLS: Reg = Reg::R13;
+const INDEX: Reg = Reg::R12;
+const STEPS: Reg = Reg::R15;
+
+/// The offsets of the fields of `State` the code writes before returning.
+const STATE_INDEX: i32 = 0;
+const STATE_STEPS: i32 = 8;
+
+/// The cold code after an instruction fails, which counts it in `STEPS`. When a
+/// check of the code fails, the stub lets the callback run the instruction to
+/// report the error, and when the callback fails, the stub returns.
+struct Stub {
+    label: Label,
+    resume: Label,
+    pc: usize,
+    steps: u32,
+    call: bool,
+}
+
+/// Translate the instructions into a function called as
+///
+/// ```text
+/// extern "C" fn(state: *mut State, cells: *mut i32, index: i64) -> u64
+/// ```
+///
+/// which runs the program on `cells` from the `index`th cell, and returns 0 when
+/// it halts, or 1 when `callback` fails. The pointer and the count of the
+/// executed instructions are written back to the state.
+///
+/// The pointer, the cells and the jumps are handled by the code, which checks the
+/// bounds and the overflow of the cells itself. The streams and the rare cases
+/// are left to `callback`, called as
+///
+/// ```text
+/// extern "C" fn(state: *mut State, index: i64, pc: u64) -> i64
+/// ```
+///
+/// which runs the instruction at `pc` from the `index`th cell, and returns where
+/// the pointer is moved to, or a negative number if it fails. When a check fails,
+/// the code calls `callback` as well, so that errors are only reported by it.
+pub struct Translator<'a> {
+    asm: Assembler,
+    memory: &'a MemoryConfig,
+    len: i32,
+    callback: u64,
+    /// The labels of the instructions jumped to.
+    targets: Vec<Option<Label>>,
+    /// Where the code returns, with the status in `eax`.
+    exit: Label,
+    failed: Label,
+    stubs: Vec<Stub>,
+    /// The count of the instructions executed since the last time `STEPS` was
+    /// updated.
+    pending: u32,
+}
+
+impl<'a> Translator<'a> {
+    /// `memory` must have no more than `i32::MAX` cells.
+    pub fn new(memory: &'a MemoryConfig, callback: u64) -> Self {
+        let mut asm = Assembler::new();
+        let exit = asm.new_label();
+        let failed = asm.new_label();
+
+        Self {
+            asm,
+            memory,
+            len: memory.range().len() as i32,
+            callback,
+            targets: vec![],
+            exit,
+            failed,
+            stubs: vec![],
+            pending: 0,
+        }
+    }
+
+    pub fn translate(mut self, list: &InstructionList) -> Vec<u8> {
+        self.targets = vec![None; list.len()];
+
+        for instruction in &list.instructions {
+            if let Instruction::Jump { target } | Instruction::JumpIfZero { target } = instruction {
+                self.targets[*target] = Some(self.asm.new_label());
+            }
+        }
+
+        self.prologue();
+
+        for (pc, instruction) in list.instructions.iter().enumerate() {
+            if let Some(label) = self.targets[pc] {
+                self.flush();
+                self.asm.bind(label);
+            }
+
+            self.instruction(instruction, pc);
+        }
+
+        self.epilogue();
+
+        for Stub {
+            label,
+            resume,
+            pc,
+            steps,
+            call,
+        } in std::mem::take(&mut self.stubs)
+        {
+            self.asm.bind(label);
+            self.asm.add_imm(STEPS, steps as i32);
+
+            if !call {
+                self.asm.jmp(self.failed);
+                continue;
+            }
+
+            self.call(pc);
+            self.asm.test(Reg::Rax, Reg::Rax);
+            self.asm.jcc(Cond::Sign, self.failed);
+            self.asm.mov(INDEX, Reg::Rax);
+            self.asm.sub_imm(STEPS, steps as i32);
+            self.asm.jmp(resume);
+        }
+
+        self.asm.finish()
+    }
+
+    fn prologue(&mut self) {
+        for reg in [Reg::Rbx, Reg::Rbp, Reg::R12, Reg::R13, Reg::R14, Reg::R15] {
+            self.asm.push(reg);
+        }
+
+        // Keep the stack aligned to 16 bytes at calls.
+        self.asm.sub_imm(Reg::Rsp, 8);
+        self.asm.mov(STATE, Reg::Rdi);
+        self.asm.mov(CELLS, Reg::Rsi);
+        self.asm.mov(INDEX, Reg::Rdx);
+        self.asm.xor32(STEPS, STEPS);
+    }
+
+    fn epilogue(&mut self) {
+        self.asm.bind(self.failed);
+        self.asm.mov32_imm(Reg::Rax, 1);
+        self.asm.bind(self.exit);
+        let state = |disp| Mem {
+            base: STATE,
+            index: None,
+            disp,
+        };
+        self.asm.mov_store(state(STATE_INDEX), INDEX);
+        self.asm.mov_store(state(STATE_STEPS), STEPS);
+        self.asm.add_imm(Reg::Rsp, 8);
+
+        for reg in [Reg::R15, Reg::R14, Reg::R13, Reg::R12, Reg::Rbp, Reg::Rbx] {
+            self.asm.pop(reg);
+        }
+
+        self.asm.ret();
+    }
+
+    /// Add the pending instructions to `STEPS`.
+    fn flush(&mut self) {
+        if self.pending != 0 {
+            self.asm.add_imm(STEPS, self.pending as i32);
+            self.pending = 0;
+        }
+    }
+
+    fn call(&mut self, pc: usize) {
+        self.asm.mov(Reg::Rdi, STATE);
+        self.asm.mov(Reg::Rsi, INDEX);
+        self.asm.mov32_imm(Reg::Rdx, pc as u32);
+        self.asm.mov_imm(Reg::Rax, self.callback);
+        self.asm.call(Reg::Rax);
+    }
+
+    fn cell(offset: isize) -> Mem {
+        Mem {
+            base: CELLS,
+            index: Some(INDEX),
+            disp: offset as i32 * 4,
+        }
+    }
+
+    fn bounds(&self) -> (i64, i64) {
+        match self.memory.cell {
+            Cell::I8 => (i8::MIN as i64, i8::MAX as i64),
+            Cell::I32 => (i32::MIN as i64, i32::MAX as i64),
+        }
+    }
+
+    fn wrap(&self, val: i32) -> i32 {
+        match self.memory.cell {
+            Cell::I8 => val as i8 as i32,
+            Cell::I32 => val,
+        }
+    }
+
+    /// Return the value stored when `val` is set, or `None` if it overflows.
+    fn checked_set(&self, val: i32) -> Option<i32> {
+        let (min, max) = self.bounds();
+
+        if (min..=max).contains(&(val as i64)) {
+            Some(val)
+        } else {
+            match self.memory.overflow {
+                Overflow::Error => None,
+                Overflow::Wrap => Some(self.wrap(val)),
+            }
+        }
+    }
+
+    /// Jump to `stub` if the cell at `offset` is out of the memory.
+    fn check_cell(&mut self, offset: isize, stub: Label) {
+        if offset != 0 {
+            self.check_index(offset, stub);
+        }
+    }
+
+    /// Compute the index of the cell at `offset` into `rax`, and jump to `stub`
+    /// if it's out of the memory.
+    fn check_index(&mut self, offset: isize, stub: Label) {
+        let addr = Mem {
+            base: INDEX,
+            index: None,
+            disp: offset as i32,
+        };
+        self.asm.lea(Reg::Rax, addr);
+        self.asm.cmp_imm(Reg::Rax, self.len);
+        self.asm.jcc(Cond::AboveOrEqual, stub);
+    }
+
+    /// Whether the code can address the cell at `offset`.
+    fn reachable(offset: isize) -> bool {
+        offset
+            .checked_mul(4)
+            .is_some_and(|disp| i32::try_from(disp).is_ok())
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, pc: usize) {
+        let resume = self.asm.new_label();
+        let mut stub = Stub {
+            label: self.asm.new_label(),
+            resume,
+            pc,
+            steps: self.pending + 1,
+            call: true,
+        };
+        let label = stub.label;
+        let mut used = true;
+
+        match instruction {
+            Instruction::Add { offset, val } if Self::reachable(*offset) => {
+                self.check_cell(*offset, label);
+                self.add(Self::cell(*offset), *val, label);
+            }
+            Instruction::Seek { offset } if Self::reachable(*offset) => {
+                if *offset != 0 {
+                    self.check_index(*offset, label);
+                    self.asm.mov(INDEX, Reg::Rax);
+                }
+            }
+            Instruction::Clear => {
+                self.asm.mov32_store_imm(Self::cell(0), 0);
+                used = false;
+            }
+            Instruction::Set { offset, val } if Self::reachable(*offset) => {
+                self.check_cell(*offset, label);
+
+                match self.checked_set(*val) {
+                    Some(val) => self.asm.mov32_store_imm(Self::cell(*offset), val),
+                    None => self.asm.jmp(label),
+                }
+            }
+            Instruction::Scan { stride } if *stride != 0 && Self::reachable(*stride) => {
+                let start = self.asm.new_label();
+                self.asm.bind(start);
+                self.asm.cmp32_mem_imm(Self::cell(0), 0);
+                self.asm.jcc(Cond::Equal, resume);
+                self.check_index(*stride, label);
+                self.asm.mov(INDEX, Reg::Rax);
+                self.asm.jmp(start);
+            }
+            Instruction::AddUntilZero { target, step } if self.native(target, *step) => {
+                self.add_until_zero(target, *step, label, resume);
+            }
+            Instruction::JumpIfZero { target } => {
+                self.pending += 1;
+                self.flush();
+                self.asm.cmp32_mem_imm(Self::cell(0), 0);
+                self.asm.jcc(Cond::Equal, self.targets[*target].unwrap());
+                used = false;
+            }
+            Instruction::Jump { target } => {
+                self.pending += 1;
+                self.flush();
+                self.asm.jmp(self.targets[*target].unwrap());
+                used = false;
+            }
+            Instruction::Halt => {
+                self.flush();
+                self.asm.xor32(Reg::Rax, Reg::Rax);
+                self.asm.jmp(self.exit);
+                used = false;
+            }
+            // The streams and the rest are run by the callback.
+            _ => {
+                self.call(pc);
+                self.asm.test(Reg::Rax, Reg::Rax);
+                self.asm.jcc(Cond::Sign, label);
+                self.asm.mov(INDEX, Reg::Rax);
+                stub.call = false;
+            }
+        }
+
+        if !matches!(
+            instruction,
+            Instruction::JumpIfZero { .. } | Instruction::Jump { .. } | Instruction::Halt
+        ) {
+            self.pending += 1;
+        }
+
+        self.asm.bind(resume);
+
+        if used {
+            self.stubs.push(stub);
+        }
+    }
+
+    /// Add `val` to the cell at `cell`, jumping to `stub` if it overflows.
+    fn add(&mut self, cell: Mem, val: i32, stub: Label) {
+        match (&self.memory.overflow, &self.memory.cell) {
+            (Overflow::Wrap, Cell::I32) => self.asm.add32_store_imm(cell, val),
+            (Overflow::Wrap, Cell::I8) => {
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.asm.add32_imm(Reg::Rax, val);
+                self.asm.movsx32_byte(Reg::Rax, Reg::Rax);
+                self.asm.mov32_store(cell, Reg::Rax);
+            }
+            (Overflow::Error, Cell::I32) => {
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.asm.add32_imm(Reg::Rax, val);
+                self.asm.jcc(Cond::Overflow, stub);
+                self.asm.mov32_store(cell, Reg::Rax);
+            }
+            // Adding more than 255 always overflows, and the sum of the others
+            // fits in 32 bits.
+            (Overflow::Error, Cell::I8) if !(-255..=255).contains(&val) => self.asm.jmp(stub),
+            (Overflow::Error, Cell::I8) => {
+                self.asm.mov32_load(Reg::Rax, cell);
+                self.asm.add32_imm(Reg::Rax, val);
+                self.asm.movsx32_byte(Reg::Rcx, Reg::Rax);
+                self.asm.cmp32(Reg::Rcx, Reg::Rax);
+                self.asm.jcc(Cond::NotEqual, stub);
+                self.asm.mov32_store(cell, Reg::Rax);
+            }
+        }
+    }
+
+    /// Whether the code handles `AddUntilZero` itself, which needs the targets
+    /// to be distinct cells other than the current one. When the cells wrap, only
+    /// the odd steps are handled, which always reach zero, and when they overflow
+    /// with an error, only the steps of 1 and -1 are.
+    fn native(&self, target: &[AddUntilZeroArg], step: i32) -> bool {
+        let mut offsets = HashSet::new();
+        let distinct = target.iter().all(|arg| {
+            arg.offset != 0 && Self::reachable(arg.offset) && offsets.insert(arg.offset)
+        });
+        let step = match self.memory.overflow {
+            Overflow::Wrap => step % 2 != 0,
+            Overflow::Error => step == 1 || step == -1,
+        };
+        distinct && step
+    }
+
+    /// All checks are done before the first cell is changed, so the callback
+    /// runs the instruction from the beginning when one fails.
+    fn add_until_zero(&mut self, target: &[AddUntilZeroArg], step: i32, stub: Label, end: Label) {
+        self.asm.mov32_load(Reg::Rax, Self::cell(0));
+        self.asm.test32(Reg::Rax, Reg::Rax);
+        self.asm.jcc(Cond::Equal, end);
+
+        // Count into `rcx`.
+        match self.memory.overflow {
+            Overflow::Wrap => {
+                let step = step as i64 as u64;
+                let mut inverse = step;
+
+                for _ in 0..5 {
+                    inverse = inverse.wrapping_mul(2u64.wrapping_sub(step.wrapping_mul(inverse)));
+                }
+
+                self.asm.neg32(Reg::Rax);
+                self.asm
+                    .imul32_imm(Reg::Rax, Reg::Rax, inverse as u32 as i32);
+
+                match self.memory.cell {
+                    Cell::I8 => self.asm.movzx32_byte(Reg::Rcx, Reg::Rax),
+                    Cell::I32 => self.asm.mov32(Reg::Rcx, Reg::Rax),
+                }
+            }
+            Overflow::Error => {
+                if step < 0 {
+                    self.asm.jcc(Cond::Sign, stub);
+                    self.asm.mov32(Reg::Rcx, Reg::Rax);
+                } else {
+                    self.asm.jcc(Cond::NotSign, stub);
+                    self.asm.mov32(Reg::Rcx, Reg::Rax);
+                    self.asm.neg32(Reg::Rcx);
+                }
+            }
+        }
+
+        let (min, max) = self.bounds();
+
+        for arg in target {
+            self.check_cell(arg.offset, stub);
+
+            if let Overflow::Error = self.memory.overflow {
+                if arg.clear {
+                    if self.checked_set(arg.times).is_none() {
+                        self.asm.jmp(stub);
+                    }
+                } else {
+                    // The count is at most 2^31, so the sum fits in 64 bits.
+                    self.asm.movsxd_load(Reg::Rax, Self::cell(arg.offset));
+                    self.asm.imul_imm(Reg::Rdx, Reg::Rcx, arg.times);
+                    self.asm.add(Reg::Rax, Reg::Rdx);
+                    self.asm.cmp_imm(Reg::Rax, min as i32);
+                    self.asm.jcc(Cond::Less, stub);
+                    self.asm.cmp_imm(Reg::Rax, max as i32);
+                    self.asm.jcc(Cond::Greater, stub);
+                }
+            }
+        }
+
+        self.asm.mov32_store_imm(Self::cell(0), 0);
+
+        for arg in target {
+            let cell = Self::cell(arg.offset);
+
+            if arg.clear {
+                // It's checked to fit if the cells can't wrap.
+                let val = self.checked_set(arg.times).unwrap_or(arg.times);
+                self.asm.mov32_store_imm(cell, val);
+                continue;
+            }
+
+            self.asm.imul32_imm(Reg::Rax, Reg::Rcx, arg.times);
+
+            match self.memory.cell {
+                Cell::I32 => self.asm.add32_store(cell, Reg::Rax),
+                Cell::I8 => {
+                    self.asm.add32_load(Reg::Rax, cell);
+                    self.asm.movsx32_byte(Reg::Rax, Reg::Rax);
+                    self.asm.mov32_store(cell, Reg::Rax);
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::DEFAULT_LEN;
+use super::{AddrRange, DEFAULT_LEN};
 
 #[derive(Clone)]
 pub struct Config {
@@ -9,6 +9,25 @@ pub struct Config {
     pub eof: Eof,
 }
 
+impl Config {
+    /// Return the address range of the memory, where the pointer starts at 0.
+    pub fn range(&self) -> AddrRange {
+        match self.addr {
+            Addr::Unsigned => AddrRange {
+                left: 0,
+                right: self.len as isize - 1,
+            },
+            Addr::Signed => {
+                let half_len = self.len.div_ceil(2) as isize;
+                AddrRange {
+                    left: -half_len,
+                    right: half_len - 1,
+                }
+            }
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -0,0 +1,279 @@
+use super::config::{Addr, Cell, Config, Eof, Overflow};
+use super::strategy::{
+    AddrStrategy, CellStrategy, EofStrategy, ErrorOverflowStrategy, I32CellStrategy,
+    I8CellStrategy, IgnoreEofStrategy, KeepEofStrategy, OverflowStrategy, SignedAddrStrategy,
+    UnsignedAddrStrategy, WrapOverflowStrategy, ZeroEofStrategy,
+};
+use super::{AddrRange, Builder, Memory, Result};
+
+/// The strategy named after the variant of the config choosing it.
+macro_rules! strategy {
+    (Unsigned) => {
+        UnsignedAddrStrategy
+    };
+    (Signed) => {
+        SignedAddrStrategy
+    };
+    (I8) => {
+        I8CellStrategy
+    };
+    (I32) => {
+        I32CellStrategy
+    };
+    (Error) => {
+        ErrorOverflowStrategy
+    };
+    (Wrap) => {
+        WrapOverflowStrategy
+    };
+    (Zero) => {
+        ZeroEofStrategy
+    };
+    (Keep) => {
+        KeepEofStrategy
+    };
+    (Ignore) => {
+        IgnoreEofStrategy
+    };
+}
+
+/// Define `DynMemory` with a variant for each combination of the strategies,
+/// and `dispatch!` to match on them. `$d` is `$`, which the inner macro needs
+/// for its own fragments.
+macro_rules! memories {
+    ($d:tt $($variant:ident: $addr:ident, $cell:ident, $overflow:ident, $eof:ident;)*) => {
+        /// The memory with the strategies chosen at runtime, which is what
+        /// `Builder` builds. Each operation matches on the variant before
+        /// running the inlined one of `Memory`, and a hot loop can match once
+        /// with `dispatch!` instead.
+        pub enum DynMemory {
+            $(
+                $variant(
+                    Memory<strategy!($addr), strategy!($cell), strategy!($overflow), strategy!($eof)>,
+                ),
+            )*
+        }
+
+        impl DynMemory {
+            pub fn new(config: Config) -> Self {
+                match (config.addr, config.cell, config.overflow, config.eof) {
+                    $(
+                        (Addr::$addr, Cell::$cell, Overflow::$overflow, Eof::$eof) => {
+                            Self::$variant(Memory::with_len(config.len))
+                        }
+                    )*
+                }
+            }
+
+            /// Check whether `new` builds a memory like this one for `config`,
+            /// whatever the cells hold.
+            pub fn is_built_for(&self, config: &Config) -> bool {
+                let strategies = match (self, &config.addr, &config.cell, &config.overflow, &config.eof) {
+                    $(
+                        (Self::$variant(_), Addr::$addr, Cell::$cell, Overflow::$overflow, Eof::$eof) => true,
+                    )*
+                    _ => false,
+                };
+                strategies && self.range() == config.range()
+            }
+
+            /// Call `visitor` with the strategies of the variant `config` chooses,
+            /// without building the memory.
+            pub(crate) fn visit<V: MemoryVisitor>(config: &Config, visitor: V) -> V::Output {
+                match (&config.addr, &config.cell, &config.overflow, &config.eof) {
+                    $(
+                        (Addr::$addr, Cell::$cell, Overflow::$overflow, Eof::$eof) => visitor
+                            .visit::<strategy!($addr), strategy!($cell), strategy!($overflow), strategy!($eof)>(),
+                    )*
+                }
+            }
+        }
+
+        /// Evaluate `body` with `inner` bound to the `Memory` in `memory`, which
+        /// is a (reference to) `DynMemory`. `body` is compiled for every variant.
+        macro_rules! dispatch {
+            ($d memory:expr, $d inner:ident => $d body:expr) => {
+                match $d memory {
+                    $($crate::execution::memory::DynMemory::$variant($d inner) => $d body,)*
+                }
+            };
+        }
+
+        pub(crate) use dispatch;
+    };
+}
+
+/// Something built for the `Memory` with the strategies chosen at runtime. See
+/// `DynMemory::visit`.
+pub(crate) trait MemoryVisitor {
+    type Output;
+
+    fn visit<A, C, O, E>(self) -> Self::Output
+    where
+        A: AddrStrategy + 'static,
+        C: CellStrategy + 'static,
+        O: OverflowStrategy + 'static,
+        E: EofStrategy + 'static;
+}
+
+memories! {
+    $
+    UnsignedI8ErrorZero: Unsigned, I8, Error, Zero;
+    UnsignedI8ErrorKeep: Unsigned, I8, Error, Keep;
+    UnsignedI8ErrorIgnore: Unsigned, I8, Error, Ignore;
+    UnsignedI8WrapZero: Unsigned, I8, Wrap, Zero;
+    UnsignedI8WrapKeep: Unsigned, I8, Wrap, Keep;
+    UnsignedI8WrapIgnore: Unsigned, I8, Wrap, Ignore;
+    UnsignedI32ErrorZero: Unsigned, I32, Error, Zero;
+    UnsignedI32ErrorKeep: Unsigned, I32, Error, Keep;
+    UnsignedI32ErrorIgnore: Unsigned, I32, Error, Ignore;
+    UnsignedI32WrapZero: Unsigned, I32, Wrap, Zero;
+    UnsignedI32WrapKeep: Unsigned, I32, Wrap, Keep;
+    UnsignedI32WrapIgnore: Unsigned, I32, Wrap, Ignore;
+    SignedI8ErrorZero: Signed, I8, Error, Zero;
+    SignedI8ErrorKeep: Signed, I8, Error, Keep;
+    SignedI8ErrorIgnore: Signed, I8, Error, Ignore;
+    SignedI8WrapZero: Signed, I8, Wrap, Zero;
+    SignedI8WrapKeep: Signed, I8, Wrap, Keep;
+    SignedI8WrapIgnore: Signed, I8, Wrap, Ignore;
+    SignedI32ErrorZero: Signed, I32, Error, Zero;
+    SignedI32ErrorKeep: Signed, I32, Error, Keep;
+    SignedI32ErrorIgnore: Signed, I32, Error, Ignore;
+    SignedI32WrapZero: Signed, I32, Wrap, Zero;
+    SignedI32WrapKeep: Signed, I32, Wrap, Keep;
+    SignedI32WrapIgnore: Signed, I32, Wrap, Ignore;
+}
+
+/// See `Memory` for what the operations do.
+impl DynMemory {
+    pub fn seek(&mut self, offset: isize) -> Result<()> {
+        dispatch!(self, memory => memory.seek(offset))
+    }
+
+    pub fn scan(&mut self, stride: isize) -> Result<()> {
+        dispatch!(self, memory => memory.scan(stride))
+    }
+
+    pub fn position(&self) -> isize {
+        dispatch!(self, memory => memory.position())
+    }
+
+    pub fn add(&mut self, add: i32) -> Result<()> {
+        dispatch!(self, memory => memory.add(add))
+    }
+
+    pub fn add_at(&mut self, addr: isize, add: i32) -> Result<()> {
+        dispatch!(self, memory => memory.add_at(addr, add))
+    }
+
+    pub fn add_repeatedly_at(&mut self, addr: isize, add: i32, count: u64) -> Result<()> {
+        dispatch!(self, memory => memory.add_repeatedly_at(addr, add, count))
+    }
+
+    pub fn count_until_zero(&self, step: i32) -> Result<Option<u64>> {
+        dispatch!(self, memory => memory.count_until_zero(step))
+    }
+
+    pub fn set(&mut self, val: i32) -> Result<()> {
+        dispatch!(self, memory => memory.set(val))
+    }
+
+    pub fn set_at(&mut self, addr: isize, val: i32) -> Result<()> {
+        dispatch!(self, memory => memory.set_at(addr, val))
+    }
+
+    pub fn input(&mut self, val: i32) -> Result<()> {
+        dispatch!(self, memory => memory.input(val))
+    }
+
+    pub fn input_at(&mut self, addr: isize, val: i32) -> Result<()> {
+        dispatch!(self, memory => memory.input_at(addr, val))
+    }
+
+    pub fn get(&self) -> i32 {
+        dispatch!(self, memory => memory.get())
+    }
+
+    pub fn get_at(&self, addr: isize) -> Result<i32> {
+        dispatch!(self, memory => memory.get_at(addr))
+    }
+
+    pub fn range(&self) -> AddrRange {
+        dispatch!(self, memory => memory.range())
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut i32 {
+        dispatch!(self, memory => memory.as_mut_ptr())
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (isize, i32)> + '_ {
+        let range = self.range();
+        (range.left..=range.right).map(|addr| (addr, self.get_at(addr).unwrap()))
+    }
+}
+
+impl Default for DynMemory {
+    fn default() -> Self {
+        Builder::new().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::memory::MemoryError;
+
+    #[test]
+    fn builder_picks_variant() {
+        let memory = Builder::new()
+            .len(5)
+            .addr(Addr::Signed)
+            .cell(Cell::I32)
+            .overflow(Overflow::Wrap)
+            .eof(Eof::Keep)
+            .build();
+        assert!(matches!(memory, DynMemory::SignedI32WrapKeep(_)));
+        assert_eq!(memory.range(), AddrRange { left: -3, right: 2 });
+        let config = Config {
+            len: 6,
+            addr: Addr::Signed,
+            cell: Cell::I32,
+            overflow: Overflow::Wrap,
+            eof: Eof::Keep,
+        };
+        assert!(memory.is_built_for(&config));
+        assert!(!memory.is_built_for(&Config {
+            len: 4,
+            ..config.clone()
+        }));
+        assert!(!memory.is_built_for(&Config {
+            cell: Cell::I8,
+            ..config
+        }));
+        assert!(matches!(
+            DynMemory::default(),
+            DynMemory::UnsignedI8ErrorIgnore(_)
+        ));
+    }
+
+    #[test]
+    fn dispatch_once() {
+        let mut memory = Builder::new().len(4).overflow(Overflow::Wrap).build();
+        let res = dispatch!(&mut memory, memory => {
+            memory.add_at(1, 200).unwrap();
+            memory.seek(1).unwrap();
+            memory.add(100).unwrap();
+            memory.seek(3)
+        });
+
+        assert_eq!(
+            res,
+            Err(MemoryError::SeekOutOfBounds {
+                now_position: 1,
+                offset: 3,
+                range: AddrRange { left: 0, right: 3 }
+            })
+        );
+        assert_eq!(memory.get(), 44);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod config;
-mod strategy;
+mod dynamic;
+pub mod strategy;
 
 use config::{Addr, Cell, Config, Eof, Overflow};
+pub(crate) use dynamic::dispatch;
+pub use dynamic::DynMemory;
+pub(crate) use dynamic::MemoryVisitor;
 use snafu::prelude::*;
 pub use strategy::AddrRange;
 use strategy::{AddrStrategy, CellStrategy, EofStrategy, OverflowStrategy};
@@ -26,22 +30,19 @@ pub enum MemoryError {
     SetOverflow { val: i32 },
 }
 
-pub struct Memory {
+/// The memory with the strategies as type parameters, so that they are inlined
+/// into each operation. See `DynMemory` for choosing them at runtime.
+pub struct Memory<A, C, O, E> {
     memory: Vec<i32>,
     cur: isize,
-    addr_strategy: Box<dyn AddrStrategy>,
-    cell_strategy: Box<dyn CellStrategy>,
-    eof_strategy: Box<dyn EofStrategy>,
-    overflow_strategy: Box<dyn OverflowStrategy>,
+    addr_strategy: A,
+    cell_strategy: C,
+    eof_strategy: E,
+    overflow_strategy: O,
 }
 
-impl Memory {
-    pub fn new(
-        addr_strategy: Box<dyn AddrStrategy>,
-        cell_strategy: Box<dyn CellStrategy>,
-        eof_strategy: Box<dyn EofStrategy>,
-        overflow_strategy: Box<dyn OverflowStrategy>,
-    ) -> Self {
+impl<A: AddrStrategy, C: CellStrategy, O: OverflowStrategy, E: EofStrategy> Memory<A, C, O, E> {
+    pub fn new(addr_strategy: A, cell_strategy: C, eof_strategy: E, overflow_strategy: O) -> Self {
         let memory = vec![0; addr_strategy.range().len()];
         let cur = addr_strategy.initial();
         Self {
@@ -54,11 +55,64 @@ impl Memory {
         }
     }
 
+    /// Create a memory of `len` cells, with the default of the other strategies.
+    pub fn with_len(len: usize) -> Self
+    where
+        C: Default,
+        O: Default,
+        E: Default,
+    {
+        Self::new(A::with_len(len), C::default(), E::default(), O::default())
+    }
+
+    /// Return the index of the cell at `addr`. Every address out of the range
+    /// is mapped to an index out of the cells, so checking the index is enough.
+    fn index(&self, addr: isize) -> Result<usize> {
+        let index = self.addr_strategy.calc(addr);
+        ensure!(
+            index < self.memory.len(),
+            AccessOutOfBoundsSnafu {
+                addr,
+                range: self.range()
+            }
+        );
+        Ok(index)
+    }
+
     pub fn seek(&mut self, offset: isize) -> Result<()> {
         self.cur = self.addr_strategy.seek(self.cur, offset)?;
         Ok(())
     }
 
+    /// Move the pointer by `stride` until it points to a zero cell, which does the
+    /// same as loops like `[>]` and `[<<]`. When it fails, the pointer stops at the
+    /// last cell the loop could reach, and the error is the same as what `seek`
+    /// reports there.
+    pub fn scan(&mut self, stride: isize) -> Result<()> {
+        let index = self.addr_strategy.calc(self.cur);
+        let step = stride.unsigned_abs();
+        let (found, visited) = if stride > 0 {
+            let cells = &self.memory[index..];
+            let found = cells.iter().step_by(step).position(|&cell| cell == 0);
+            (found, cells.len().div_ceil(step))
+        } else {
+            let cells = &self.memory[..=index];
+            let found = cells.iter().rev().step_by(step).position(|&cell| cell == 0);
+            (found, cells.len().div_ceil(step))
+        };
+
+        match found {
+            Some(count) => {
+                self.cur += count as isize * stride;
+                Ok(())
+            }
+            None => {
+                self.cur += (visited - 1) as isize * stride;
+                self.seek(stride)
+            }
+        }
+    }
+
     pub fn position(&self) -> isize {
         self.cur
     }
@@ -68,69 +122,78 @@ impl Memory {
     }
 
     pub fn add_at(&mut self, addr: isize, add: i32) -> Result<()> {
-        ensure!(
-            self.range().contains(addr),
-            AccessOutOfBoundsSnafu {
-                addr,
-                range: self.range()
-            }
-        );
-        let addr = self.addr_strategy.calc(addr);
-        let target = self.memory.get_mut(addr).unwrap();
-        let strategy = self.cell_strategy.as_ref();
-        let res = self.overflow_strategy.add(strategy, *target, add)?;
-        *target = res;
+        let index = self.index(addr)?;
+        let target = &mut self.memory[index];
+        *target = self
+            .overflow_strategy
+            .add(&self.cell_strategy, *target, add)?;
         Ok(())
     }
 
+    /// Add `add` to the cell at `addr` for `count` times.
+    pub fn add_repeatedly_at(&mut self, addr: isize, add: i32, count: u64) -> Result<()> {
+        let index = self.index(addr)?;
+        let target = &mut self.memory[index];
+        *target =
+            self.overflow_strategy
+                .add_repeatedly(&self.cell_strategy, *target, add, count)?;
+        Ok(())
+    }
+
+    /// Return how many times `step` should be added to the current cell until it's
+    /// zero, or `None` if it never is. `step` must not be zero.
+    pub fn count_until_zero(&self, step: i32) -> Result<Option<u64>> {
+        self.overflow_strategy
+            .count_until_zero(&self.cell_strategy, self.get(), step)
+    }
+
     pub fn set(&mut self, val: i32) -> Result<()> {
         self.set_at(self.cur, val)
     }
 
     pub fn set_at(&mut self, addr: isize, val: i32) -> Result<()> {
-        ensure!(
-            self.range().contains(addr),
-            AccessOutOfBoundsSnafu {
-                addr,
-                range: self.range()
-            }
-        );
-        let addr = self.addr_strategy.calc(addr);
-        let target = self.memory.get_mut(addr).unwrap();
+        let index = self.index(addr)?;
+        self.memory[index] = self.overflow_strategy.set(&self.cell_strategy, val)?;
+        Ok(())
+    }
 
-        if let Some(res) = self.eof_strategy.check(val) {
-            let strategy = self.cell_strategy.as_ref();
-            let res = self.overflow_strategy.set(strategy, res)?;
-            *target = res;
-        }
+    /// Store what is read from the input, handling `EOF` with the `EofStrategy`.
+    pub fn input(&mut self, val: i32) -> Result<()> {
+        self.input_at(self.cur, val)
+    }
 
-        Ok(())
+    pub fn input_at(&mut self, addr: isize, val: i32) -> Result<()> {
+        self.index(addr)?;
+
+        match self.eof_strategy.check(val) {
+            Some(val) => self.set_at(addr, val),
+            None => Ok(()),
+        }
     }
 
+    /// The pointer is always in the range, so the current cell always exists.
     pub fn get(&self) -> i32 {
-        self.get_at(self.cur).unwrap()
+        self.memory[self.addr_strategy.calc(self.cur)]
     }
 
     pub fn get_at(&self, addr: isize) -> Result<i32> {
-        ensure!(
-            self.range().contains(addr),
-            AccessOutOfBoundsSnafu {
-                addr,
-                range: self.range()
-            }
-        );
-        let addr = self.addr_strategy.calc(addr);
-        Ok(self.memory[addr])
+        Ok(self.memory[self.index(addr)?])
     }
 
     pub fn range(&self) -> AddrRange {
         self.addr_strategy.range()
     }
-}
 
-impl Default for Memory {
-    fn default() -> Self {
-        Builder::new().build()
+    /// Return the pointer to the leftmost cell, after which the cell at `addr` is
+    /// the `addr - range().left`th one.
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut i32 {
+        self.memory.as_mut_ptr()
+    }
+
+    /// Return the address and the value of every cell, from the leftmost one.
+    pub fn cells(&self) -> impl Iterator<Item = (isize, i32)> + '_ {
+        let range = self.range();
+        (range.left..=range.right).map(|addr| (addr, self.memory[self.addr_strategy.calc(addr)]))
     }
 }
 
@@ -199,29 +262,67 @@ impl Builder {
         self
     }
 
-    pub fn build(self) -> Memory {
-        let addr_strategy: Box<dyn AddrStrategy> = match self.addr {
-            Addr::Unsigned => Box::new(strategy::UnsignedAddrStrategy::new(self.len)),
-            Addr::Signed => Box::new(strategy::SignedAddrStrategy::new((self.len + 1) / 2)),
-        };
-        let cell_strategy: Box<dyn CellStrategy> = match self.cell {
-            Cell::I8 => Box::new(strategy::I8CellStrategy {}),
-            Cell::I32 => Box::new(strategy::I32CellStrategy {}),
-        };
-        let overflow_strategy: Box<dyn OverflowStrategy> = match self.overflow {
-            Overflow::Error => Box::new(strategy::ErrorOverflowStrategy {}),
-            Overflow::Wrap => Box::new(strategy::WrapOverflowStrategy {}),
-        };
-        let eof_strategy: Box<dyn EofStrategy> = match self.eof {
-            Eof::Zero => Box::new(strategy::ZeroEofStrategy {}),
-            Eof::Keep => Box::new(strategy::KeepEofStrategy {}),
-            Eof::Ignore => Box::new(strategy::IgnoreEofStrategy {}),
-        };
-        Memory::new(
-            addr_strategy,
-            cell_strategy,
-            eof_strategy,
-            overflow_strategy,
-        )
+    pub fn build(self) -> DynMemory {
+        DynMemory::new(Config {
+            len: self.len,
+            addr: self.addr,
+            cell: self.cell,
+            overflow: self.overflow,
+            eof: self.eof,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::stream::EOF;
+
+    #[test]
+    fn scan() {
+        let mut memory = Builder::new().len(10).build();
+
+        for addr in [0, 2, 4, 5, 6] {
+            memory.set_at(addr, 1).unwrap();
+        }
+
+        memory.scan(2).unwrap();
+        assert_eq!(memory.position(), 8);
+        memory.scan(-1).unwrap();
+        assert_eq!(memory.position(), 8);
+        memory.seek(-2).unwrap();
+        memory.scan(-1).unwrap();
+        assert_eq!(memory.position(), 3);
+        memory.scan(1).unwrap();
+        assert_eq!(memory.position(), 3);
+    }
+
+    #[test]
+    fn scan_out_of_bounds() {
+        let mut memory = Builder::new().len(10).addr(Addr::Signed).build();
+
+        for addr in -5..5 {
+            memory.set_at(addr, 1).unwrap();
+        }
+
+        assert_eq!(
+            memory.scan(-3),
+            Err(MemoryError::SeekOutOfBounds {
+                now_position: -3,
+                offset: -3,
+                range: AddrRange { left: -5, right: 4 }
+            })
+        );
+        assert_eq!(memory.position(), -3);
+    }
+
+    #[test]
+    fn input_and_set() {
+        let mut memory = Builder::new().eof(Eof::Ignore).build();
+        memory.input(1).unwrap();
+        memory.input(EOF).unwrap();
+        assert_eq!(memory.get(), 1);
+        memory.set(EOF).unwrap();
+        assert_eq!(memory.get(), EOF);
     }
 }
@@ -23,6 +23,9 @@ impl AddrRange {
 }
 
 pub trait AddrStrategy {
+    /// Create the strategy for a memory of `len` cells.
+    fn with_len(len: usize) -> Self;
+
     /// Return the initial value the pointer should contain.
     fn initial(&self) -> isize {
         0
@@ -49,6 +52,10 @@ impl UnsignedAddrStrategy {
 }
 
 impl AddrStrategy for UnsignedAddrStrategy {
+    fn with_len(len: usize) -> Self {
+        Self::new(len)
+    }
+
     fn seek(&self, addr: isize, offset: isize) -> Result<isize> {
         let target = addr + offset;
 
@@ -86,6 +93,10 @@ impl SignedAddrStrategy {
 }
 
 impl AddrStrategy for SignedAddrStrategy {
+    fn with_len(len: usize) -> Self {
+        Self::new(len.div_ceil(2))
+    }
+
     fn seek(&self, addr: isize, offset: isize) -> Result<isize> {
         let target = addr + offset;
 
@@ -101,7 +112,7 @@ impl AddrStrategy for SignedAddrStrategy {
     }
 
     fn calc(&self, addr: isize) -> usize {
-        addr as usize + self.half_len
+        (addr + self.half_len as isize) as usize
     }
 
     fn range(&self) -> AddrRange {
@@ -116,11 +127,27 @@ pub trait CellStrategy {
     fn is_overflowed(&self, num: i64) -> bool;
 
     fn wrap(&self, num: i64) -> i32;
+
+    /// Return the width of a cell in bits.
+    fn bits(&self) -> u32;
+
+    fn min(&self) -> i64 {
+        -(1 << (self.bits() - 1))
+    }
+
+    fn max(&self) -> i64 {
+        (1 << (self.bits() - 1)) - 1
+    }
 }
 
+#[derive(Default)]
 pub struct I8CellStrategy {}
 
 impl CellStrategy for I8CellStrategy {
+    fn bits(&self) -> u32 {
+        8
+    }
+
     fn is_overflowed(&self, num: i64) -> bool {
         num < i8::MIN as i64 || num > i8::MAX as i64
     }
@@ -130,9 +157,14 @@ impl CellStrategy for I8CellStrategy {
     }
 }
 
+#[derive(Default)]
 pub struct I32CellStrategy {}
 
 impl CellStrategy for I32CellStrategy {
+    fn bits(&self) -> u32 {
+        32
+    }
+
     fn is_overflowed(&self, num: i64) -> bool {
         num < i32::MIN as i64 || num > i32::MAX as i64
     }
@@ -144,15 +176,59 @@ impl CellStrategy for I32CellStrategy {
 
 pub trait OverflowStrategy {
     /// Calculate and check the value for the `add` operation.
-    fn add(&self, cell_strategy: &dyn CellStrategy, before: i32, add: i32) -> Result<i32>;
+    fn add<C: CellStrategy>(&self, cell_strategy: &C, before: i32, add: i32) -> Result<i32>;
+
+    fn set<C: CellStrategy>(&self, cell_strategy: &C, val: i32) -> Result<i32>;
+
+    /// Calculate the value after adding `add` to `before` for `count` times.
+    fn add_repeatedly<C: CellStrategy>(
+        &self,
+        cell_strategy: &C,
+        before: i32,
+        add: i32,
+        count: u64,
+    ) -> Result<i32>;
+
+    /// Calculate how many times `step` should be added to `val` until it's zero,
+    /// or return `None` if it never is. `step` must not be zero.
+    fn count_until_zero<C: CellStrategy>(
+        &self,
+        cell_strategy: &C,
+        val: i32,
+        step: i32,
+    ) -> Result<Option<u64>>;
+}
+
+/// Split `step` into `2^shift * odd` modulo `2^bits`, and return `shift` with the
+/// multiplicative inverse of `odd`, which is found by Newton's method. Return
+/// `None` if `step` is a multiple of `2^bits`.
+///
+/// Adding `step` to a wrapping cell reaches zero only if the lowest `shift` bits
+/// of the cell are zero, after `(-cell >> shift) * inverse` times modulo
+/// `2^(bits - shift)`.
+pub(crate) fn split_step(step: i32, bits: u32) -> Option<(u32, u64)> {
+    let step = step as i64 as u64 & (u64::MAX >> (64 - bits));
+
+    if step == 0 {
+        return None;
+    }
+
+    let shift = step.trailing_zeros();
+    let odd = step >> shift;
+    let mut inverse = odd;
 
-    fn set(&self, cell_strategy: &dyn CellStrategy, val: i32) -> Result<i32>;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(odd.wrapping_mul(inverse)));
+    }
+
+    Some((shift, inverse))
 }
 
+#[derive(Default)]
 pub struct ErrorOverflowStrategy {}
 
 impl OverflowStrategy for ErrorOverflowStrategy {
-    fn add(&self, cell_strategy: &dyn CellStrategy, before: i32, add: i32) -> Result<i32> {
+    fn add<C: CellStrategy>(&self, cell_strategy: &C, before: i32, add: i32) -> Result<i32> {
         let res = before as i64 + add as i64;
 
         if cell_strategy.is_overflowed(res) {
@@ -162,19 +238,64 @@ impl OverflowStrategy for ErrorOverflowStrategy {
         }
     }
 
-    fn set(&self, cell_strategy: &dyn CellStrategy, val: i32) -> Result<i32> {
+    fn set<C: CellStrategy>(&self, cell_strategy: &C, val: i32) -> Result<i32> {
         if cell_strategy.is_overflowed(val as i64) {
             Err(MemoryError::SetOverflow { val })
         } else {
             Ok(val)
         }
     }
+
+    fn add_repeatedly<C: CellStrategy>(
+        &self,
+        cell_strategy: &C,
+        before: i32,
+        add: i32,
+        count: u64,
+    ) -> Result<i32> {
+        let res = before as i128 + add as i128 * count as i128;
+
+        if !cell_strategy.is_overflowed(res as i64) && res == res as i64 as i128 {
+            return Ok(res as i32);
+        }
+
+        // Report the same error as adding them one by one.
+        let room = if add > 0 {
+            cell_strategy.max() - before as i64
+        } else {
+            before as i64 - cell_strategy.min()
+        };
+        let before = before as i64 + room / (add as i64).abs() * add as i64;
+        Err(MemoryError::AddOverflow {
+            before: before as i32,
+            add,
+        })
+    }
+
+    fn count_until_zero<C: CellStrategy>(
+        &self,
+        cell_strategy: &C,
+        val: i32,
+        step: i32,
+    ) -> Result<Option<u64>> {
+        // `i32::MIN / -1` overflows in `i32`.
+        let (wide_val, wide_step) = (val as i64, step as i64);
+
+        if wide_val % wide_step == 0 && (val > 0) != (step > 0) {
+            Ok(Some((wide_val / wide_step).unsigned_abs()))
+        } else {
+            // The counter never reaches zero, so it overflows at last.
+            self.add_repeatedly(cell_strategy, val, step, u64::MAX)
+                .map(|_| unreachable!())
+        }
+    }
 }
 
+#[derive(Default)]
 pub struct WrapOverflowStrategy {}
 
 impl OverflowStrategy for WrapOverflowStrategy {
-    fn add(&self, cell_strategy: &dyn CellStrategy, before: i32, add: i32) -> Result<i32> {
+    fn add<C: CellStrategy>(&self, cell_strategy: &C, before: i32, add: i32) -> Result<i32> {
         let res = before as i64 + add as i64;
 
         if cell_strategy.is_overflowed(res) {
@@ -184,20 +305,53 @@ impl OverflowStrategy for WrapOverflowStrategy {
         }
     }
 
-    fn set(&self, cell_strategy: &dyn CellStrategy, val: i32) -> Result<i32> {
+    fn set<C: CellStrategy>(&self, cell_strategy: &C, val: i32) -> Result<i32> {
         if cell_strategy.is_overflowed(val as i64) {
             Ok(cell_strategy.wrap(val as i64))
         } else {
             Ok(val)
         }
     }
+
+    fn add_repeatedly<C: CellStrategy>(
+        &self,
+        cell_strategy: &C,
+        before: i32,
+        add: i32,
+        count: u64,
+    ) -> Result<i32> {
+        let res = (before as i64).wrapping_add((add as i64).wrapping_mul(count as i64));
+        Ok(cell_strategy.wrap(res))
+    }
+
+    fn count_until_zero<C: CellStrategy>(
+        &self,
+        cell_strategy: &C,
+        val: i32,
+        step: i32,
+    ) -> Result<Option<u64>> {
+        // Solve `val + count * step = 0 (mod 2^bits)`.
+        let bits = cell_strategy.bits();
+        let (shift, inverse) = match split_step(step, bits) {
+            Some(split) => split,
+            None => return Ok((val == 0).then_some(0)),
+        };
+        let neg = (val as i64 as u64).wrapping_neg();
+
+        if neg.trailing_zeros() < shift {
+            return Ok(None);
+        }
+
+        let mask = u64::MAX >> (64 - bits + shift);
+        Ok(Some((neg >> shift).wrapping_mul(inverse) & mask))
+    }
 }
 
 pub trait EofStrategy {
     fn check(&self, input: i32) -> Option<i32>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ZeroEofStrategy {}
 
 /// Turn EOF to 0.
@@ -212,6 +366,7 @@ impl EofStrategy for ZeroEofStrategy {
 }
 
 /// Keep EOF.
+#[derive(Default)]
 pub struct KeepEofStrategy {}
 
 impl EofStrategy for KeepEofStrategy {
@@ -221,6 +376,7 @@ impl EofStrategy for KeepEofStrategy {
 }
 
 /// Ignore this input if it's EOF.
+#[derive(Default)]
 pub struct IgnoreEofStrategy {}
 
 impl EofStrategy for IgnoreEofStrategy {
@@ -316,4 +472,72 @@ mod tests {
         assert_eq!(o.add(&c, 0, 1), Ok(1));
         assert_eq!(o.add(&c, 127, 1), Ok(-128));
     }
+
+    #[test]
+    fn count_until_zero() {
+        let c = I8CellStrategy {};
+        let o = WrapOverflowStrategy {};
+        assert_eq!(o.count_until_zero(&c, 5, -1), Ok(Some(5)));
+        assert_eq!(o.count_until_zero(&c, -5, -1), Ok(Some(251)));
+        assert_eq!(o.count_until_zero(&c, 1, -3), Ok(Some(171)));
+        assert_eq!(o.count_until_zero(&c, 9, 3), Ok(Some(253)));
+        assert_eq!(
+            o.count_until_zero(&I32CellStrategy {}, 1, -3),
+            Ok(Some(2863311531))
+        );
+        assert_eq!(o.count_until_zero(&c, 6, -2), Ok(Some(3)));
+        assert_eq!(o.count_until_zero(&c, -4, -2), Ok(Some(126)));
+        assert_eq!(o.count_until_zero(&c, 12, 12), Ok(Some(63)));
+        assert_eq!(o.count_until_zero(&c, 5, -2), Ok(None));
+        assert_eq!(o.count_until_zero(&c, 6, 4), Ok(None));
+        assert_eq!(o.count_until_zero(&c, 1, 256), Ok(None));
+        assert_eq!(o.count_until_zero(&I32CellStrategy {}, 6, -2), Ok(Some(3)));
+
+        let o = ErrorOverflowStrategy {};
+        assert_eq!(o.count_until_zero(&c, 9, -3), Ok(Some(3)));
+        assert_eq!(o.count_until_zero(&c, -9, 3), Ok(Some(3)));
+        assert_eq!(o.count_until_zero(&c, 6, -2), Ok(Some(3)));
+        assert_eq!(
+            o.count_until_zero(&c, 10, -3),
+            Err(MemoryError::AddOverflow {
+                before: -128,
+                add: -3
+            })
+        );
+
+        let c = I32CellStrategy {};
+        assert_eq!(o.count_until_zero(&c, i32::MIN, 1), Ok(Some(1 << 31)));
+        assert_eq!(
+            o.count_until_zero(&c, i32::MIN, -1),
+            Err(MemoryError::AddOverflow {
+                before: i32::MIN,
+                add: -1
+            })
+        );
+    }
+
+    #[test]
+    fn add_repeatedly() {
+        let c = I8CellStrategy {};
+        let o = WrapOverflowStrategy {};
+        assert_eq!(o.add_repeatedly(&c, 1, 2, 3), Ok(7));
+        assert_eq!(o.add_repeatedly(&c, 0, 3, 171), Ok(1));
+
+        let o = ErrorOverflowStrategy {};
+        assert_eq!(o.add_repeatedly(&c, 1, 2, 3), Ok(7));
+        assert_eq!(
+            o.add_repeatedly(&c, 100, 10, 3),
+            Err(MemoryError::AddOverflow {
+                before: 120,
+                add: 10
+            })
+        );
+        assert_eq!(
+            o.add_repeatedly(&I32CellStrategy {}, 0, i32::MIN, u64::MAX),
+            Err(MemoryError::AddOverflow {
+                before: i32::MIN,
+                add: i32::MIN
+            })
+        );
+    }
 }
@@ -1,4 +1,6 @@
 pub mod context;
+pub mod jit;
 pub mod memory;
 pub mod processor;
 pub mod stream;
+pub mod threaded;
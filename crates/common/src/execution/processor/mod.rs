@@ -1,8 +1,14 @@
 use snafu::prelude::*;
 
-use crate::compiler::{AddUntilZeroArg, Instruction, InstructionList};
+use crate::compiler::{
+    AddUntilZeroArg, Diagnostic, Instruction, InstructionList, OutputItem, Span,
+};
 use crate::execution::context::Context;
-use crate::execution::memory::{Memory, MemoryError};
+use crate::execution::memory::strategy::{
+    AddrStrategy, CellStrategy, EofStrategy, OverflowStrategy,
+};
+use crate::execution::memory::{dispatch, Memory, MemoryError, Result as MemoryResult};
+use crate::execution::stream::{InStream, OutStream};
 
 pub type Result<T> = std::result::Result<T, ProcessorError>;
 
@@ -40,6 +46,7 @@ pub struct Processor {
     counter: Counter,
     instructions: InstructionList,
     state: ProcessorState,
+    steps: u64,
 }
 
 impl Processor {
@@ -48,6 +55,7 @@ impl Processor {
             counter: Counter::new(),
             instructions,
             state: ProcessorState::Ready,
+            steps: 0,
         }
     }
 
@@ -55,107 +63,216 @@ impl Processor {
         self.state = ProcessorState::Failed;
     }
 
+    /// Abort and build the error with where and when the program failed.
+    fn fail(&mut self, source: MemoryError, pointer: isize) -> ProcessorError {
+        self.abort();
+        let pc = self.counter.get();
+        ProcessorError::Memory {
+            source,
+            pc,
+            span: self.instructions.span(pc),
+            pointer,
+            steps: self.steps,
+        }
+    }
+
+    /// Return how many instructions have been executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
     fn tick(&mut self) {
         self.counter.tick();
         self.check_halted();
     }
 
     fn check_halted(&mut self) {
-        if self.instructions.0[self.counter.get()] == Instruction::Halt {
+        if self.instructions.instructions[self.counter.get()] == Instruction::Halt {
             self.state = ProcessorState::Halted;
         }
     }
 
-    fn step(&mut self, context: &mut Context) -> Result<()> {
-        let Context {
-            memory,
-            in_stream,
-            out_stream,
-        } = context;
-
+    fn step<A, C, O, E>(
+        &mut self,
+        memory: &mut Memory<A, C, O, E>,
+        in_stream: &mut dyn InStream,
+        out_stream: &mut dyn OutStream,
+    ) -> Result<()>
+    where
+        A: AddrStrategy,
+        C: CellStrategy,
+        O: OverflowStrategy,
+        E: EofStrategy,
+    {
         match self.state {
             ProcessorState::Halted => return Err(ProcessorError::AlreadyHalted),
             ProcessorState::Failed => return Err(ProcessorError::Failed),
             _ => {}
         }
 
-        match &self.instructions.0[self.counter.get()] {
-            Instruction::Add { val } => {
-                if let Err(e) = memory.add(*val) {
-                    self.abort();
-                    Err(e.into())
-                } else {
-                    self.tick();
-                    Ok(())
-                }
+        self.steps += 1;
+
+        match &self.instructions.instructions[self.counter.get()] {
+            Instruction::Jump { target } => {
+                self.counter.jump(*target);
+                self.check_halted();
+                Ok(())
             }
-            Instruction::Seek { offset } => {
-                if let Err(e) = memory.seek(*offset) {
-                    self.abort();
-                    Err(e.into())
+            Instruction::JumpIfZero { target } => {
+                if memory.get() == 0 {
+                    self.counter.jump(*target);
+                    self.check_halted();
                 } else {
                     self.tick();
-                    Ok(())
                 }
-            }
-            Instruction::Clear => {
-                memory.set(0).unwrap();
-                self.tick();
+
                 Ok(())
             }
-            Instruction::AddUntilZero { target } => {
-                if let Err(e) = self.add_while_zero(target, memory) {
-                    self.abort();
-                    Err(e)
+            Instruction::Halt => {
+                unreachable!()
+            }
+            instruction => {
+                if let Err(e) = Self::execute_on(instruction, memory, in_stream, out_stream) {
+                    Err(self.fail(e, memory.position()))
                 } else {
                     self.tick();
                     Ok(())
                 }
             }
-            Instruction::Input => {
-                memory.set(in_stream.read()).unwrap();
-                self.tick();
-                Ok(())
+        }
+    }
+
+    /// Execute an instruction other than the jumps and `Halt`, leaving the
+    /// pointer where it moves the pointer to.
+    pub(crate) fn execute(instruction: &Instruction, context: &mut Context) -> MemoryResult<()> {
+        let Context {
+            memory,
+            in_stream,
+            out_stream,
+        } = context;
+
+        dispatch!(memory, memory => {
+            Self::execute_on(instruction, memory, in_stream.as_mut(), out_stream.as_mut())
+        })
+    }
+
+    #[inline]
+    pub(crate) fn execute_on<A, C, O, E>(
+        instruction: &Instruction,
+        memory: &mut Memory<A, C, O, E>,
+        in_stream: &mut dyn InStream,
+        out_stream: &mut dyn OutStream,
+    ) -> MemoryResult<()>
+    where
+        A: AddrStrategy,
+        C: CellStrategy,
+        O: OverflowStrategy,
+        E: EofStrategy,
+    {
+        match instruction {
+            Instruction::Add { offset, val } => memory.add_at(memory.position() + offset, *val),
+            Instruction::Seek { offset } => memory.seek(*offset),
+            Instruction::Clear => memory.set(0),
+            Instruction::Set { offset, val } => memory.set_at(memory.position() + offset, *val),
+            Instruction::AddUntilZero { target, step } => {
+                Self::add_until_zero(target, *step, memory)
+            }
+            Instruction::Scan { stride } => memory.scan(*stride),
+            // The input may be out of the range of the cell.
+            Instruction::Input { offset } => {
+                memory.input_at(memory.position() + offset, in_stream.read())
             }
-            Instruction::Output => {
-                out_stream.write(memory.get());
-                self.tick();
+            Instruction::Output { offset } => {
+                out_stream.write(memory.get_at(memory.position() + offset)?);
                 Ok(())
             }
-            Instruction::Jump { target } => {
-                self.counter.jump(*target);
-                self.check_halted();
+            Instruction::OutputBytes { bytes } => {
+                out_stream.write_all(bytes);
                 Ok(())
             }
-            Instruction::JumpIfZero { target } => {
-                if memory.get() == 0 {
-                    self.counter.jump(*target);
-                    self.check_halted();
-                } else {
-                    self.tick();
-                }
-
+            Instruction::OutputRepeat { offset, count } => {
+                let val = memory.get_at(memory.position() + offset)?;
+                out_stream.write_all(&vec![val; *count]);
                 Ok(())
             }
-            Instruction::Halt => {
+            Instruction::OutputSeq { items } => {
+                let mut vals = Vec::with_capacity(items.len());
+                let res = Self::output_seq(items, memory, &mut vals);
+                out_stream.write_all(&vals);
+                res
+            }
+            Instruction::Jump { .. } | Instruction::JumpIfZero { .. } | Instruction::Halt => {
                 unreachable!()
             }
         }
     }
 
-    fn add_while_zero(&self, target: &Vec<AddUntilZeroArg>, memory: &mut Memory) -> Result<()> {
-        let val = memory.get();
+    /// Collect what `OutputSeq` writes into `vals`, which has the items before
+    /// the failed one if a cell is out of the memory.
+    pub(crate) fn output_seq<A, C, O, E>(
+        items: &[OutputItem],
+        memory: &Memory<A, C, O, E>,
+        vals: &mut Vec<i32>,
+    ) -> MemoryResult<()>
+    where
+        A: AddrStrategy,
+        C: CellStrategy,
+        O: OverflowStrategy,
+        E: EofStrategy,
+    {
+        for item in items {
+            vals.push(match item {
+                OutputItem::Cell { offset } => memory.get_at(memory.position() + offset)?,
+                OutputItem::Byte { val } => *val,
+            });
+        }
 
-        if val == 0 {
+        Ok(())
+    }
+
+    /// Run `AddUntilZero`. If the counter never reaches zero, it runs forever like
+    /// the loop does, after checking that the targets are in the memory.
+    pub(crate) fn add_until_zero<A, C, O, E>(
+        target: &Vec<AddUntilZeroArg>,
+        step: i32,
+        memory: &mut Memory<A, C, O, E>,
+    ) -> MemoryResult<()>
+    where
+        A: AddrStrategy,
+        C: CellStrategy,
+        O: OverflowStrategy,
+        E: EofStrategy,
+    {
+        if memory.get() == 0 {
             return Ok(());
         }
 
-        memory.set(0).unwrap();
+        let position = memory.position();
+        let count = match memory.count_until_zero(step)? {
+            Some(count) => count,
+            None => {
+                for arg in target {
+                    memory.get_at(position + arg.offset)?;
+                }
 
-        for AddUntilZeroArg { offset, times } in target {
-            memory.seek(*offset)?;
-            memory.add(val * *times)?;
-            memory.seek(-*offset)?;
+                loop {
+                    std::hint::spin_loop();
+                }
+            }
+        };
+        memory.set(0)?;
+
+        for AddUntilZeroArg {
+            offset,
+            times,
+            clear,
+        } in target
+        {
+            if *clear {
+                memory.set_at(position + offset, *times)?;
+            } else {
+                memory.add_repeatedly_at(position + offset, *times, count)?;
+            }
         }
 
         Ok(())
@@ -164,7 +281,7 @@ impl Processor {
     pub fn run(&mut self, context: &mut Context) -> Result<()> {
         match self.state {
             // There is only one halt instruction
-            ProcessorState::Ready if self.instructions.0.len() == 1 => {
+            ProcessorState::Ready if self.instructions.len() == 1 => {
                 return Err(ProcessorError::Empty)
             }
             ProcessorState::Halted => return Err(ProcessorError::AlreadyHalted),
@@ -172,18 +289,36 @@ impl Processor {
             _ => {}
         }
 
-        while self.state == ProcessorState::Ready || self.state == ProcessorState::Running {
-            self.step(context)?
-        }
+        let Context {
+            memory,
+            in_stream,
+            out_stream,
+        } = context;
 
-        Ok(())
+        // Match on the memory once, so that the whole loop is compiled for it.
+        dispatch!(memory, memory => {
+            while self.state == ProcessorState::Ready || self.state == ProcessorState::Running {
+                self.step(memory, in_stream.as_mut(), out_stream.as_mut())?
+            }
+
+            Ok(())
+        })
     }
 }
 
 #[derive(Snafu, Debug, PartialEq, Eq)]
 pub enum ProcessorError {
-    #[snafu(display("invalid memory operation occurred"))]
-    Memory { source: MemoryError },
+    /// `pc` is the address of the failed instruction and `span` is where it comes
+    /// from. `pointer` is the position of the pointer and `steps` is the count of
+    /// the executed instructions, including the failed one.
+    #[snafu(display("invalid memory operation occurred at instruction {pc} after {steps} steps"))]
+    Memory {
+        source: MemoryError,
+        pc: usize,
+        span: Span,
+        pointer: isize,
+        steps: u64,
+    },
     #[snafu(display("all instructions have already finished"))]
     AlreadyHalted,
     #[snafu(display("couldn't continue to run due to the previous error"))]
@@ -192,8 +327,120 @@ pub enum ProcessorError {
     Empty,
 }
 
-impl From<MemoryError> for ProcessorError {
-    fn from(e: MemoryError) -> Self {
-        Self::Memory { source: e }
+impl ProcessorError {
+    /// Build a diagnostic pointing to the source code of the failed instruction.
+    /// Return `None` if the error isn't caused by any instruction.
+    pub fn diagnostic(&self) -> Option<Diagnostic> {
+        match self {
+            ProcessorError::Memory {
+                source,
+                pc,
+                span,
+                pointer,
+                steps,
+            } => Some(
+                Diagnostic::error("invalid memory operation occurred", *span)
+                    .label(source.to_string())
+                    .note(format!(
+                        "the pointer was at {pointer} when instruction {pc} failed after {steps} steps"
+                    )),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{Compiler, Config, OptLevel, Position};
+    use crate::execution::memory::AddrRange;
+    use crate::execution::stream::VecOutStream;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    #[test]
+    fn memory_error_location() {
+        let instructions = Compiler::new().compile("+>+\n[<+]").unwrap();
+        let mut processor = Processor::new(instructions);
+        let mut context = Context::new(Default::default(), Default::default());
+
+        let expected = Err(ProcessorError::Memory {
+            source: MemoryError::AccessOutOfBounds {
+                addr: -1,
+                range: AddrRange {
+                    left: 0,
+                    right: 32767,
+                },
+            },
+            pc: 4,
+            span: Span::new(Position::new(5, 2, 2), Position::new(7, 2, 4)),
+            pointer: 0,
+            steps: 9,
+        });
+        assert_eq!(processor.run(&mut context), expected);
+        assert_eq!(processor.run(&mut context), Err(ProcessorError::Failed));
+    }
+
+    /// The items before the cell out of the memory are written.
+    #[test]
+    fn coalesced_output_out_of_bounds() {
+        for opt_level in [OptLevel::O0, OptLevel::O3] {
+            let config = Config {
+                opt_level,
+                target: Some(Default::default()),
+                ..Default::default()
+            };
+            let instructions = Compiler::with_config(config).compile("+++.>.<<.").unwrap();
+            let mut processor = Processor::new(instructions);
+            let mut context = Context::new(Default::default(), Default::default());
+            let output = Rc::new(RefCell::new(VecDeque::new()));
+            context.out_stream = Box::new(VecOutStream::new(output.clone()));
+
+            assert!(matches!(
+                processor.run(&mut context),
+                Err(ProcessorError::Memory { .. })
+            ));
+            assert_eq!(
+                output.borrow().iter().copied().collect::<Vec<_>>(),
+                vec![3, 0]
+            );
+        }
+    }
+
+    /// The never-running loop is kept, for the `Seek`s around it would be merged
+    /// into one which doesn't go out of the memory.
+    #[test]
+    fn dead_code_keeps_seek_errors() {
+        let code = ">>>>-<<<--<<<[>>]>>--->>.";
+        let instructions = Compiler::new().compile(code).unwrap();
+        let mut processor = Processor::new(instructions);
+        let mut context = Context::new(Default::default(), Default::default());
+
+        assert!(matches!(
+            processor.run(&mut context),
+            Err(ProcessorError::Memory {
+                source: MemoryError::SeekOutOfBounds { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn coalesced_output() {
+        let instructions = Compiler::new()
+            .compile("+++++[>+++++++++++++<-]>...<.>+.")
+            .unwrap();
+        let mut processor = Processor::new(instructions);
+        let mut context = Context::new(Default::default(), Default::default());
+        let output = Rc::new(RefCell::new(VecDeque::new()));
+        context.out_stream = Box::new(VecOutStream::new(output.clone()));
+
+        processor.run(&mut context).unwrap();
+        assert_eq!(
+            output.borrow().iter().copied().collect::<Vec<_>>(),
+            vec![65, 65, 65, 0, 66]
+        );
     }
 }
@@ -2,6 +2,7 @@ pub mod config;
 
 use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::fmt::Write;
 use std::io::{stdin, BufReader, Read, Stdin};
 use std::rc::Rc;
 
@@ -63,19 +64,43 @@ impl InStream for VecInStream {
 
 pub trait OutStream {
     fn write(&mut self, content: i32);
+
+    /// Write all of `contents` at once, which is faster than writing them one by
+    /// one for most streams.
+    fn write_all(&mut self, contents: &[i32]) {
+        for content in contents {
+            self.write(*content);
+        }
+    }
 }
 
 pub struct NullOutStream;
 
 impl OutStream for NullOutStream {
     fn write(&mut self, _content: i32) {}
+
+    fn write_all(&mut self, _contents: &[i32]) {}
 }
 
 pub struct CharStandardOutStream;
 
+impl CharStandardOutStream {
+    fn to_char(content: i32) -> char {
+        char::from_u32(content as u32).unwrap_or('�')
+    }
+}
+
 impl OutStream for CharStandardOutStream {
     fn write(&mut self, content: i32) {
-        print!("{}", char::from_u32(content as u32).unwrap_or('�'));
+        print!("{}", Self::to_char(content));
+    }
+
+    fn write_all(&mut self, contents: &[i32]) {
+        let s: String = contents
+            .iter()
+            .map(|&content| Self::to_char(content))
+            .collect();
+        print!("{s}");
     }
 }
 
@@ -85,6 +110,16 @@ impl OutStream for IntStandardOutStream {
     fn write(&mut self, content: i32) {
         print!("{content} ");
     }
+
+    fn write_all(&mut self, contents: &[i32]) {
+        let mut s = String::new();
+
+        for content in contents {
+            write!(s, "{content} ").unwrap();
+        }
+
+        print!("{s}");
+    }
 }
 
 pub struct VecOutStream {
@@ -101,6 +136,10 @@ impl OutStream for VecOutStream {
     fn write(&mut self, content: i32) {
         self.output.borrow_mut().push_back(content);
     }
+
+    fn write_all(&mut self, contents: &[i32]) {
+        self.output.borrow_mut().extend(contents);
+    }
 }
 
 pub struct Builder {
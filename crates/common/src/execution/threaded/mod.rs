@@ -0,0 +1,449 @@
+use std::any::Any;
+
+use crate::compiler::{Instruction, InstructionList};
+use crate::execution::context::Context;
+use crate::execution::memory::config::Config as MemoryConfig;
+use crate::execution::memory::strategy::{
+    AddrStrategy, CellStrategy, EofStrategy, OverflowStrategy,
+};
+use crate::execution::memory::{
+    dispatch, DynMemory, Memory, MemoryError, MemoryVisitor, Result as MemoryResult,
+};
+use crate::execution::processor::{Processor, ProcessorError, Result as ProcessorResult};
+use crate::execution::stream::{InStream, OutStream};
+
+/// What the closures share while running.
+struct Machine<'a, M> {
+    memory: &'a mut M,
+    in_stream: &'a mut dyn InStream,
+    out_stream: &'a mut dyn OutStream,
+    /// The count of the executed instructions.
+    steps: u64,
+    /// The address of the failed instruction and why it failed.
+    error: Option<(usize, MemoryError)>,
+}
+
+/// Run an instruction or a loop on the memory `M`, and return whether the
+/// program goes on, which is false when it halts or fails.
+type Block<M> = Box<dyn Fn(&mut Machine<M>) -> bool>;
+
+/// Run programs as closures compiled from the instructions, which does the same
+/// as `Processor`, including the errors. The closures are built once for the
+/// `Memory` the program runs on, and each loop runs as a loop over the closures
+/// of its body, so nothing but the closure of the current instruction is looked
+/// at on each step. Only programs whose jumps form loops are supported.
+pub struct Threaded {
+    instructions: InstructionList,
+    program: Box<dyn Program>,
+}
+
+impl Threaded {
+    pub fn supports(instructions: &InstructionList) -> bool {
+        instructions.unpaired_jumps().is_empty()
+    }
+
+    /// Compile the instructions for `memory`, which is the memory of the contexts
+    /// it runs on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the jumps don't form loops. See `supports`.
+    pub fn compile(instructions: InstructionList, memory: &MemoryConfig) -> Self {
+        assert!(Self::supports(&instructions), "the jumps must form loops");
+        let program = DynMemory::visit(memory, Compile(&instructions));
+
+        Self {
+            instructions,
+            program,
+        }
+    }
+
+    /// Run the program to the end, like `Processor::run`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the memory of the context isn't what the program is compiled
+    /// for.
+    pub fn run(&self, context: &mut Context) -> ProcessorResult<()> {
+        // There is only one halt instruction
+        if self.instructions.len() == 1 {
+            return Err(ProcessorError::Empty);
+        }
+
+        match self.program.run(context) {
+            (_, None) => Ok(()),
+            (steps, Some((pc, source))) => Err(ProcessorError::Memory {
+                source,
+                pc,
+                span: self.instructions.span(pc),
+                pointer: context.memory.position(),
+                steps,
+            }),
+        }
+    }
+}
+
+/// The closures built for a `Memory`, whose type is erased in `Threaded`.
+trait Program {
+    /// Run the program on the memory of the context, and return the count of the
+    /// executed instructions, with the address of the failed one and why it
+    /// failed.
+    fn run(&self, context: &mut Context) -> (u64, Option<(usize, MemoryError)>);
+}
+
+struct Compiled<M> {
+    blocks: Vec<Block<M>>,
+}
+
+impl<A, C, O, E> Compiled<Memory<A, C, O, E>>
+where
+    A: AddrStrategy + 'static,
+    C: CellStrategy + 'static,
+    O: OverflowStrategy + 'static,
+    E: EofStrategy + 'static,
+{
+    fn new(instructions: &InstructionList) -> Self {
+        let list = &instructions.instructions;
+        let blocks = Self::blocks(list, 0, list.len());
+        Self { blocks }
+    }
+
+    /// Build the closures of the instructions in `start..end`, where the loops
+    /// are closed.
+    fn blocks(list: &[Instruction], start: usize, end: usize) -> Vec<Block<Memory<A, C, O, E>>> {
+        let mut res = vec![];
+        let mut pc = start;
+
+        while pc < end {
+            match &list[pc] {
+                // It jumps to right after the `Jump` which jumps back to it.
+                Instruction::JumpIfZero { target } => {
+                    res.push(Self::loop_block(Self::blocks(list, pc + 1, target - 1)));
+                    pc = *target;
+                }
+                instruction => {
+                    res.push(Self::block(instruction.clone(), pc));
+                    pc += 1;
+                }
+            }
+        }
+
+        res
+    }
+
+    /// The `JumpIfZero` and the `Jump` of a loop are counted as steps in each
+    /// iteration, like what `Processor` does.
+    fn loop_block(body: Vec<Block<Memory<A, C, O, E>>>) -> Block<Memory<A, C, O, E>> {
+        Box::new(move |machine| loop {
+            machine.steps += 1;
+
+            if machine.memory.get() == 0 {
+                return true;
+            }
+
+            for block in &body {
+                if !block(machine) {
+                    return false;
+                }
+            }
+
+            machine.steps += 1;
+        })
+    }
+
+    fn block(instruction: Instruction, pc: usize) -> Block<Memory<A, C, O, E>> {
+        /// Build a block running `$run` on the machine as `$machine`, which
+        /// fails with the error it returns.
+        macro_rules! block {
+            (|$machine:ident| $run:expr) => {
+                Box::new(move |$machine: &mut Machine<Memory<A, C, O, E>>| {
+                    $machine.steps += 1;
+                    let res: MemoryResult<()> = $run;
+
+                    match res {
+                        Ok(()) => true,
+                        Err(e) => {
+                            $machine.error = Some((pc, e));
+                            false
+                        }
+                    }
+                })
+            };
+        }
+
+        match instruction {
+            Instruction::Add { offset: 0, val } => block!(|machine| machine.memory.add(val)),
+            Instruction::Add { offset, val } => block!(|machine| {
+                let addr = machine.memory.position() + offset;
+                machine.memory.add_at(addr, val)
+            }),
+            Instruction::Seek { offset } => block!(|machine| machine.memory.seek(offset)),
+            Instruction::Clear => block!(|machine| machine.memory.set(0)),
+            Instruction::Set { offset, val } => block!(|machine| {
+                let addr = machine.memory.position() + offset;
+                machine.memory.set_at(addr, val)
+            }),
+            Instruction::AddUntilZero { target, step } => {
+                block!(|machine| Processor::add_until_zero(&target, step, machine.memory))
+            }
+            Instruction::Scan { stride } => block!(|machine| machine.memory.scan(stride)),
+            Instruction::Halt => Box::new(|_| false),
+            Instruction::Jump { .. } | Instruction::JumpIfZero { .. } => unreachable!(),
+            instruction => block!(|machine| Processor::execute_on(
+                &instruction,
+                machine.memory,
+                machine.in_stream,
+                machine.out_stream,
+            )),
+        }
+    }
+}
+
+impl<A, C, O, E> Program for Compiled<Memory<A, C, O, E>>
+where
+    A: AddrStrategy + 'static,
+    C: CellStrategy + 'static,
+    O: OverflowStrategy + 'static,
+    E: EofStrategy + 'static,
+{
+    fn run(&self, context: &mut Context) -> (u64, Option<(usize, MemoryError)>) {
+        let Context {
+            memory,
+            in_stream,
+            out_stream,
+        } = context;
+        let memory = dispatch!(memory, memory => {
+            (memory as &mut dyn Any).downcast_mut::<Memory<A, C, O, E>>()
+        });
+        let mut machine = Machine {
+            memory: memory.expect("the program is compiled for another memory"),
+            in_stream: in_stream.as_mut(),
+            out_stream: out_stream.as_mut(),
+            steps: 0,
+            error: None,
+        };
+
+        for block in &self.blocks {
+            if !block(&mut machine) {
+                break;
+            }
+        }
+
+        (machine.steps, machine.error)
+    }
+}
+
+/// Build the closures for the `Memory` chosen at runtime.
+struct Compile<'a>(&'a InstructionList);
+
+impl MemoryVisitor for Compile<'_> {
+    type Output = Box<dyn Program>;
+
+    fn visit<A, C, O, E>(self) -> Box<dyn Program>
+    where
+        A: AddrStrategy + 'static,
+        C: CellStrategy + 'static,
+        O: OverflowStrategy + 'static,
+        E: EofStrategy + 'static,
+    {
+        Box::new(Compiled::<Memory<A, C, O, E>>::new(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{AddUntilZeroArg, Compiler, Config as CompilerConfig, OutputItem};
+    use crate::execution::memory::config::{Addr, Cell, Config as MemoryConfig, Eof, Overflow};
+    use crate::execution::stream::config::{Config as StreamConfig, Input, Output};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    type Outcome = (ProcessorResult<()>, Vec<i32>, isize, Vec<(isize, i32)>);
+
+    /// Run the instructions with `Processor` or `Threaded`, and return the
+    /// result, the output and the memory.
+    fn run(list: InstructionList, memory: &MemoryConfig, input: &[u8], threaded: bool) -> Outcome {
+        let input = input.iter().map(|&byte| byte as i32).collect();
+        let output = Rc::new(RefCell::new(VecDeque::new()));
+        let stream = StreamConfig {
+            input: Input::Vec(Rc::new(RefCell::new(input))),
+            output: Output::Vec(output.clone()),
+        };
+        let mut context = Context::new(memory.clone(), stream);
+
+        let res = if threaded {
+            Threaded::compile(list, memory).run(&mut context)
+        } else {
+            Processor::new(list).run(&mut context)
+        };
+
+        let cells = context.memory.cells().collect();
+        (res, output.take().into(), context.memory.position(), cells)
+    }
+
+    fn assert_same_list(list: InstructionList, memory: &MemoryConfig, input: &[u8]) {
+        let expected = run(list.clone(), memory, input, false);
+        assert_eq!(run(list, memory, input, true), expected, "{input:?}");
+    }
+
+    fn assert_same(code: &str, memory: &MemoryConfig, input: &[u8]) {
+        let config = CompilerConfig {
+            target: Some(memory.clone()),
+            ..Default::default()
+        };
+        let list = Compiler::with_config(config).compile(code).unwrap();
+        assert_same_list(list, memory, input);
+    }
+
+    fn wrap() -> MemoryConfig {
+        MemoryConfig {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn examples_run_the_same() {
+        let hello = include_str!("../../../../../examples/helloworld.bf");
+        let examples = [
+            hello,
+            include_str!("../../../../../examples/squares.bf"),
+            include_str!("../../../../../examples/hanoi.bf"),
+        ];
+
+        for code in examples {
+            assert_same(code, &wrap(), b"");
+        }
+
+        let memory = MemoryConfig {
+            eof: Eof::Zero,
+            ..wrap()
+        };
+        let code = include_str!("../../../../../examples/self-interpreter.bf");
+        assert_same(code, &memory, format!("{hello}!").as_bytes());
+        // Without wrapping, hanoi fails with an overflow.
+        assert_same(examples[2], &Default::default(), b"");
+    }
+
+    #[test]
+    fn memory_strategies() {
+        let signed = MemoryConfig {
+            len: 10,
+            addr: Addr::Signed,
+            cell: Cell::I32,
+            ..Default::default()
+        };
+        let cases = [
+            (",[->+++<]>.-.", wrap(), "a"),
+            (",[->++<]>.", Default::default(), "a"),
+            (",[->---<]>.", wrap(), "b"),
+            (",[-->+<]>.", wrap(), "d"),
+            (",[--<+>]", wrap(), "e"),
+            (",[-->+<]>.", Default::default(), "e"),
+            ("+[>-<+++]>.", Default::default(), ""),
+            (">,<,>.<.,.", Default::default(), "\u{ff}"),
+            ("<<<<<+[<].", signed.clone(), ""),
+            ("+[>+].", signed.clone(), ""),
+            ("+[->>>>>>+<<<<<<].", signed.clone(), ""),
+            ("<.", Default::default(), ""),
+            ("+++.>.<<.", Default::default(), ""),
+            (
+                ">>+<<,[>>.<<,]",
+                MemoryConfig {
+                    eof: Eof::Zero,
+                    ..signed
+                },
+                "abc",
+            ),
+        ];
+
+        for (code, memory, input) in cases {
+            assert_same(code, &memory, input.as_bytes());
+        }
+    }
+
+    /// `Halt` in the middle, and instructions the compiler doesn't generate for
+    /// the loops above.
+    #[test]
+    fn unusual_instructions() {
+        let memory = MemoryConfig {
+            len: 8,
+            ..Default::default()
+        };
+        let cases = vec![
+            vec![
+                Instruction::Set { offset: 0, val: 1 },
+                Instruction::JumpIfZero { target: 4 },
+                Instruction::Halt,
+                Instruction::Jump { target: 1 },
+                Instruction::Seek { offset: -1 },
+            ],
+            vec![
+                Instruction::Set { offset: 0, val: 7 },
+                Instruction::AddUntilZero {
+                    target: vec![AddUntilZeroArg {
+                        offset: 1,
+                        times: 5,
+                        clear: false,
+                    }],
+                    step: -3,
+                },
+            ],
+            vec![
+                Instruction::Set { offset: 0, val: 1 },
+                Instruction::OutputSeq {
+                    items: vec![OutputItem::Cell { offset: 0 }, OutputItem::Byte { val: 33 }],
+                },
+                Instruction::Add { offset: 9, val: 1 },
+            ],
+        ];
+
+        for mut instructions in cases {
+            instructions.push(Instruction::Halt);
+            assert_same_list(InstructionList::new(instructions), &memory, b"");
+        }
+    }
+
+    #[test]
+    fn unpaired_jumps() {
+        for instructions in [
+            vec![Instruction::Jump { target: 2 }, Instruction::Halt],
+            vec![Instruction::JumpIfZero { target: 1 }, Instruction::Halt],
+        ] {
+            assert!(!Threaded::supports(&InstructionList::new(instructions)));
+        }
+    }
+
+    /// The closures are built once, and run on each context.
+    #[test]
+    fn run_again() {
+        let list = Compiler::new().compile("+++[>++<-]>.<<").unwrap();
+        let (expected, ..) = run(list.clone(), &Default::default(), b"", false);
+        assert!(matches!(expected, Err(ProcessorError::Memory { .. })));
+        let threaded = Threaded::compile(list, &Default::default());
+
+        for _ in 0..2 {
+            let output = Rc::new(RefCell::new(VecDeque::new()));
+            let stream = StreamConfig {
+                input: Input::Null,
+                output: Output::Vec(output.clone()),
+            };
+            let mut context = Context::new(Default::default(), stream);
+
+            assert_eq!(threaded.run(&mut context), expected);
+            assert_eq!(output.take(), VecDeque::from([6]));
+        }
+    }
+
+    #[test]
+    fn empty_program() {
+        let list = InstructionList::new(vec![Instruction::Halt]);
+        let mut context = Context::new(Default::default(), Default::default());
+        assert_eq!(
+            Threaded::compile(list, &Default::default()).run(&mut context),
+            Err(ProcessorError::Empty)
+        );
+    }
+}